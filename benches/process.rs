@@ -0,0 +1,102 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use replicant::Synth;
+
+const NOTE_ON: u8 = 0x90;
+
+/// Parameter indices, and how many choices the stepped ones have.
+const POLYPHONY: i32 = 4;
+const WAVEFORM: i32 = 9;
+const WAVEFORMS: [&str; 6] = ["sine", "saw", "square", "triangle", "wavetable", "additive"];
+const FILTER_TYPE: i32 = 18;
+const FILTER_TYPES: [&str; 3] = ["low pass", "high pass", "band pass"];
+const RESONANCE: i32 = 20;
+const UNISON: i32 = 59;
+
+/// The buffer size the benchmarks use unless they're comparing buffer sizes.
+const BUFFER_SIZE: usize = 512;
+
+/// A synth playing a chord of `voices` notes, each with `unison` oscillators.
+fn playing(voices: u8, unison: usize) -> Synth {
+    let mut synth = Synth::default();
+    synth.set_parameter(POLYPHONY, 1.0);
+    synth.set_parameter(UNISON, (unison - 1) as f32 / 6.0);
+    for i in 0..voices {
+        synth.queue_event(0, [NOTE_ON, 40 + i * 3, 100]);
+    }
+    synth
+}
+
+/// Time processing one buffer after another, as a host would.
+fn bench_buffers(b: &mut criterion::Bencher, synth: &mut Synth, buffer_size: usize) {
+    let (mut left, mut right) = (vec![0.0; buffer_size], vec![0.0; buffer_size]);
+    b.iter(|| synth.process_buffer(&mut left, &mut right));
+}
+
+fn voices(c: &mut Criterion) {
+    let mut group = c.benchmark_group("voices");
+    group.throughput(Throughput::Elements(BUFFER_SIZE as u64));
+    for &voices in &[1, 4, 8, 16] {
+        for &unison in &[1, 7] {
+            let mut synth = playing(voices, unison);
+            let id = BenchmarkId::new(format!("{} unison", unison), voices);
+            group.bench_function(id, |b| bench_buffers(b, &mut synth, BUFFER_SIZE));
+        }
+    }
+    group.finish();
+}
+
+fn buffer_size(c: &mut Criterion) {
+    let mut group = c.benchmark_group("buffer size");
+    for &buffer_size in &[32, 64, 256, 1024] {
+        group.throughput(Throughput::Elements(buffer_size as u64));
+        let mut synth = playing(8, 1);
+        group.bench_function(BenchmarkId::from_parameter(buffer_size), |b| bench_buffers(b, &mut synth, buffer_size));
+    }
+    group.finish();
+}
+
+fn waveform(c: &mut Criterion) {
+    let mut group = c.benchmark_group("waveform");
+    group.throughput(Throughput::Elements(BUFFER_SIZE as u64));
+    for (i, name) in WAVEFORMS.iter().enumerate() {
+        let mut synth = playing(8, 1);
+        synth.set_parameter(WAVEFORM, i as f32 / (WAVEFORMS.len() - 1) as f32);
+        group.bench_function(*name, |b| bench_buffers(b, &mut synth, BUFFER_SIZE));
+    }
+    group.finish();
+}
+
+fn filter(c: &mut Criterion) {
+    let mut group = c.benchmark_group("filter");
+    group.throughput(Throughput::Elements(BUFFER_SIZE as u64));
+    for (i, name) in FILTER_TYPES.iter().enumerate() {
+        for &resonance in &[0.0, 0.9] {
+            let mut synth = playing(8, 1);
+            synth.set_parameter(FILTER_TYPE, i as f32 / (FILTER_TYPES.len() - 1) as f32);
+            synth.set_parameter(RESONANCE, resonance);
+            let id = BenchmarkId::new(*name, format!("resonance {}", resonance));
+            group.bench_function(id, |b| bench_buffers(b, &mut synth, BUFFER_SIZE));
+        }
+    }
+    group.finish();
+}
+
+/// A full chord of resonant unison voices, labelled by whether the `simd` feature's on, so
+/// running it both ways puts the two side by side:
+///
+/// ```sh
+/// cargo bench --bench process -- simd
+/// cargo bench --bench process --features simd -- simd
+/// ```
+fn simd(c: &mut Criterion) {
+    let mut group = c.benchmark_group("simd");
+    group.throughput(Throughput::Elements(BUFFER_SIZE as u64));
+    let mut synth = playing(16, 7);
+    synth.set_parameter(RESONANCE, 0.5);
+    let id = if cfg!(feature = "simd") { "simd" } else { "scalar" };
+    group.bench_function(id, |b| bench_buffers(b, &mut synth, BUFFER_SIZE));
+    group.finish();
+}
+
+criterion_group!(benches, voices, buffer_size, waveform, filter, simd);
+criterion_main!(benches);
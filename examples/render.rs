@@ -0,0 +1,140 @@
+//! Render a midi file to a wav file with the synth, without a host.
+//!
+//!     cargo run --example render -- <input.mid> <output.wav> [--preset <file>] [--sample-rate <hz>] [--tail <seconds>]
+//!
+//! The preset is a file saved with the editor's export, and the tail is how long to keep
+//! rendering after the last event, for the release and effects to ring out.
+
+use std::convert::TryFrom;
+use std::error::Error;
+use std::{env, fs, process};
+
+use midly::{MetaMessage, Smf, Timing, TrackEventKind};
+use replicant::Synth;
+
+const USAGE: &str =
+    "usage: render <input.mid> <output.wav> [--preset <file>] [--sample-rate <hz>] [--tail <seconds>]";
+
+/// The tempo until the file sets one, in microseconds per beat: 120 bpm.
+const DEFAULT_TEMPO: f64 = 500_000.0;
+
+struct Options {
+    input: String,
+    output: String,
+    preset: Option<String>,
+    sample_rate: u32,
+    tail: f64,
+}
+
+fn main() {
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{}\n{}", err, USAGE);
+            process::exit(2);
+        }
+    };
+    if let Err(err) = render(&options) {
+        eprintln!("render: {}", err);
+        process::exit(1);
+    }
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut paths = Vec::new();
+    let mut options = Options {
+        input: String::new(),
+        output: String::new(),
+        preset: None,
+        sample_rate: 44100,
+        tail: 2.0,
+    };
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--preset" => options.preset = Some(value()?),
+            "--sample-rate" => {
+                options.sample_rate = value()?.parse().map_err(|err| format!("bad sample rate: {}", err))?
+            }
+            "--tail" => options.tail = value()?.parse().map_err(|err| format!("bad tail: {}", err))?,
+            _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+            _ => paths.push(arg),
+        }
+    }
+    match <[String; 2]>::try_from(paths) {
+        Ok([input, output]) => Ok(Options { input, output, ..options }),
+        Err(_) => Err("expected an input and an output file".to_string()),
+    }
+}
+
+fn render(options: &Options) -> Result<(), Box<dyn Error>> {
+    let sample_rate = f64::from(options.sample_rate);
+    let events = read_midi(&fs::read(&options.input)?, sample_rate)?;
+    let last_event = events.last().map_or(0, |&(frame, _)| frame as usize);
+    let len = last_event + (options.tail.max(0.0) * sample_rate) as usize;
+
+    let mut synth = Synth::default();
+    synth.set_sample_rate(options.sample_rate as f32);
+    if let Some(preset) = &options.preset {
+        synth.import_preset(&fs::read(preset)?)?;
+    }
+    let output = synth.render_midi(&events, len);
+
+    let spec = hound::WavSpec {
+        channels: 2,
+        sample_rate: options.sample_rate,
+        bits_per_sample: 32,
+        sample_format: hound::SampleFormat::Float,
+    };
+    let mut writer = hound::WavWriter::create(&options.output, spec)?;
+    for sample in output {
+        writer.write_sample(sample)?;
+    }
+    writer.finalize()?;
+    println!("rendered {:.1} seconds to {}", len as f64 / sample_rate, options.output);
+    Ok(())
+}
+
+/// Raw midi messages, each with the sample it falls on.
+type Events = Vec<(u32, [u8; 3])>;
+
+/// The channel messages from every track of a standard midi file, in order.
+fn read_midi(data: &[u8], sample_rate: f64) -> Result<Events, Box<dyn Error>> {
+    let smf = Smf::parse(data)?;
+
+    // every track's events on one timeline, keeping the tracks' order for events on the same tick
+    let mut timeline = Vec::new();
+    for track in &smf.tracks {
+        let mut tick = 0u64;
+        for event in track {
+            tick += u64::from(u32::from(event.delta));
+            timeline.push((tick, event.kind));
+        }
+    }
+    timeline.sort_by_key(|&(tick, _)| tick);
+
+    // tempo changes only matter with beat based timing
+    let seconds_per_tick = |tempo: f64| match smf.header.timing {
+        Timing::Metrical(ticks_per_beat) => tempo / 1e6 / f64::from(u16::from(ticks_per_beat)),
+        Timing::Timecode(fps, subframes) => 1.0 / (f64::from(fps.as_f32()) * f64::from(subframes)),
+    };
+    let mut tempo = DEFAULT_TEMPO;
+    let (mut last_tick, mut seconds) = (0, 0.0);
+    let mut events = Vec::new();
+    for (tick, kind) in timeline {
+        seconds += (tick - last_tick) as f64 * seconds_per_tick(tempo);
+        last_tick = tick;
+        match kind {
+            TrackEventKind::Meta(MetaMessage::Tempo(microseconds)) => tempo = f64::from(u32::from(microseconds)),
+            TrackEventKind::Midi { .. } => {
+                let mut bytes = Vec::with_capacity(3);
+                kind.as_live_event().expect("a channel message").write_std(&mut bytes)?;
+                let mut data = [0; 3];
+                data[..bytes.len()].copy_from_slice(&bytes);
+                events.push(((seconds * sample_rate).round() as u32, data));
+            }
+            _ => (),
+        }
+    }
+    Ok(events)
+}
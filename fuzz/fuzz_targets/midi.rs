@@ -0,0 +1,44 @@
+//! Feeds arbitrary bytes to the synth as raw midi messages, over a few buffers, checking it never
+//! panics or clips, and that it goes quiet once every note on every channel has been let go.
+//!
+//!     cargo fuzz run midi
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use replicant::Synth;
+
+const BUFFER_SIZE: usize = 256;
+
+/// How long the release and effects get to ring out, in buffers of 44100 Hz.
+const TAIL_BUFFERS: usize = 4 * 44100 / BUFFER_SIZE;
+
+const CONTROL_CHANGE: u8 = 0xB0;
+const SUSTAIN_PEDAL: u8 = 64;
+const ALL_NOTES_OFF: u8 = 123;
+
+fuzz_target!(|data: &[u8]| {
+    let mut synth = Synth::default();
+    let (mut left, mut right) = ([0.0; BUFFER_SIZE], [0.0; BUFFER_SIZE]);
+
+    // each event is a sample offset and a message, and a zero offset byte ends the buffer, so the
+    // input decides how the events are split between buffers as well as their order
+    for buffer in data.split(|&byte| byte == 0) {
+        for event in buffer.chunks_exact(5) {
+            let frame = usize::from(u16::from_le_bytes([event[0], event[1]])) % (BUFFER_SIZE * 2);
+            synth.queue_event(frame, [event[2], event[3], event[4]]);
+        }
+        synth.process_buffer(&mut left, &mut right);
+        assert!(left.iter().chain(&right).all(|sample| sample.abs() <= 1.0), "the output clipped");
+    }
+
+    for channel in 0..16 {
+        synth.queue_event(0, [CONTROL_CHANGE | channel, SUSTAIN_PEDAL, 0]);
+        synth.queue_event(0, [CONTROL_CHANGE | channel, ALL_NOTES_OFF, 0]);
+    }
+    for _ in 0..TAIL_BUFFERS {
+        synth.process_buffer(&mut left, &mut right);
+    }
+    let peak = left.iter().chain(&right).fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+    assert!(peak < 1e-3, "still playing after all notes off, at {}", peak);
+});
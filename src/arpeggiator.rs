@@ -0,0 +1,216 @@
+use crate::tempo::NoteDivision;
+
+/// The most octaves the arpeggio can climb through.
+pub const MAX_ARP_OCTAVES: usize = 4;
+
+/// The order the arpeggiator plays the held notes in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ArpMode {
+    Up,
+    Down,
+    /// Up and back down again, without repeating the top and bottom notes.
+    UpDown,
+    Random,
+}
+
+impl ArpMode {
+    pub const ALL: [ArpMode; 4] = [ArpMode::Up, ArpMode::Down, ArpMode::UpDown, ArpMode::Random];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ArpMode::Up => "Up",
+            ArpMode::Down => "Down",
+            ArpMode::UpDown => "Up/Down",
+            ArpMode::Random => "Random",
+        }
+    }
+}
+
+/// A snapshot of the arpeggiator's settings, read from the shared plugin parameters once per
+/// buffer.
+#[derive(Clone, Copy, Debug)]
+pub struct ArpParams {
+    pub mode: ArpMode,
+    /// How many octaves the pattern spans, from 1 to `MAX_ARP_OCTAVES`.
+    pub octaves: usize,
+    /// Steps per second, unless synced.
+    pub rate: f64,
+    /// When set, each step lasts this long instead, following the host tempo.
+    pub division: Option<NoteDivision>,
+    /// How much of each step the note is held for, 0.0 to 1.0.
+    pub gate: f64,
+}
+
+impl ArpParams {
+    /// The length of a step in seconds at `tempo` beats per minute.
+    pub fn step_time(&self, tempo: f64) -> f64 {
+        self.division.map_or(1.0 / self.rate, |division| division.seconds(tempo))
+    }
+}
+
+/// What the arpeggiator wants played on one sample.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ArpEvents {
+    /// The note to release, which comes before the note to play.
+    pub note_off: Option<u8>,
+    /// The note to play, with its velocity.
+    pub note_on: Option<(u8, u8)>,
+}
+
+/// Plays the held keys one at a time in a repeating pattern, standing in for them between the
+/// incoming notes and the voices.
+#[derive(Debug)]
+pub struct Arpeggiator {
+    // the held keys and their velocities, from lowest to highest
+    held: Vec<(u8, u8)>,
+    // the note it's playing, until its gate closes
+    playing: Option<u8>,
+    // how many steps have been played since the first key went down
+    step: usize,
+    // progress through the current step, 0.0 to 1.0
+    phase: f64,
+    rng: u32,
+}
+
+impl Default for Arpeggiator {
+    fn default() -> Arpeggiator {
+        Arpeggiator { held: Vec::with_capacity(128), playing: None, step: 0, phase: 0.0, rng: 0x2545_f491 }
+    }
+}
+
+impl Arpeggiator {
+    pub fn note_on(&mut self, note: u8, velocity: u8) {
+        if self.held.is_empty() {
+            // start the pattern from the beginning, playing straight away
+            self.step = 0;
+            self.phase = 1.0;
+        }
+        self.held.retain(|&(held, _)| held != note);
+        let index = self.held.partition_point(|&(held, _)| held < note);
+        self.held.insert(index, (note, velocity));
+    }
+
+    pub fn note_off(&mut self, note: u8) {
+        self.held.retain(|&(held, _)| held != note);
+    }
+
+    /// Whether any keys are held, or a note is still playing.
+    pub fn is_active(&self) -> bool {
+        !self.held.is_empty() || self.playing.is_some()
+    }
+
+    /// Forget the held keys, returning the note that was playing so it can be released.
+    pub fn reset(&mut self) -> Option<u8> {
+        self.held.clear();
+        self.playing.take()
+    }
+
+    /// Advance by one sample, returning the notes to release and play on it. `step_time` is in
+    /// seconds.
+    pub fn next(&mut self, params: &ArpParams, step_time: f64, time_per_sample: f64) -> ArpEvents {
+        let mut events = ArpEvents::default();
+        if self.held.is_empty() {
+            events.note_off = self.playing.take();
+            return events;
+        }
+
+        if self.phase >= params.gate {
+            events.note_off = self.playing.take();
+        }
+        if self.phase >= 1.0 {
+            self.phase = self.phase.fract();
+            events.note_off = events.note_off.or_else(|| self.playing.take());
+            let (note, velocity) = self.pick(params);
+            events.note_on = Some((note, velocity));
+            self.playing = Some(note);
+            self.step += 1;
+        }
+        self.phase += time_per_sample / step_time.max(time_per_sample);
+        events
+    }
+
+    /// The note for the current step.
+    fn pick(&mut self, params: &ArpParams) -> (u8, u8) {
+        let octaves = params.octaves.clamp(1, MAX_ARP_OCTAVES);
+        let len = self.held.len() * octaves;
+        let index = match params.mode {
+            ArpMode::Up => self.step % len,
+            ArpMode::Down => len - 1 - self.step % len,
+            ArpMode::UpDown => {
+                let period = (2 * len).saturating_sub(2).max(1);
+                let position = self.step % period;
+                if position < len { position } else { period - position }
+            }
+            ArpMode::Random => self.random() as usize % len,
+        };
+        let (note, velocity) = self.held[index % self.held.len()];
+        let octave = (index / self.held.len()) as u8;
+        // notes that would go off the top of the keyboard play in their own octave
+        let transposed = note.checked_add(12 * octave).filter(|&note| note <= 127).unwrap_or(note);
+        (transposed, velocity)
+    }
+
+    fn random(&mut self) -> u32 {
+        let mut x = self.rng;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.rng = x;
+        x
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PARAMS: ArpParams = ArpParams { mode: ArpMode::Up, octaves: 1, rate: 10.0, division: None, gate: 0.5 };
+
+    /// The notes played over `steps` steps of 8 samples.
+    fn play(arp: &mut Arpeggiator, params: ArpParams, steps: usize) -> Vec<u8> {
+        (0..steps * 8).filter_map(|_| arp.next(&params, 1.0, 0.125).note_on.map(|(note, _)| note)).collect()
+    }
+
+    #[test]
+    fn test_modes() {
+        let held = |mode, octaves| {
+            let mut arp = Arpeggiator::default();
+            for &note in &[64, 60, 67] {
+                arp.note_on(note, 100);
+            }
+            play(&mut arp, ArpParams { mode, octaves, ..PARAMS }, 7)
+        };
+        assert_eq!(held(ArpMode::Up, 1), [60, 64, 67, 60, 64, 67, 60]);
+        assert_eq!(held(ArpMode::Down, 1), [67, 64, 60, 67, 64, 60, 67]);
+        assert_eq!(held(ArpMode::UpDown, 1), [60, 64, 67, 64, 60, 64, 67]);
+        assert_eq!(held(ArpMode::Up, 2), [60, 64, 67, 72, 76, 79, 60]);
+
+        let random = held(ArpMode::Random, 2);
+        assert!(random.iter().all(|note| [60, 64, 67, 72, 76, 79].contains(note)));
+        assert!(random.windows(2).any(|pair| pair[0] > pair[1]));
+    }
+
+    #[test]
+    fn test_gate_releases_each_note() {
+        let mut arp = Arpeggiator::default();
+        arp.note_on(60, 90);
+        let events: Vec<_> = (0..12).map(|_| arp.next(&PARAMS, 1.0, 0.125)).collect();
+        assert_eq!(events[0], ArpEvents { note_off: None, note_on: Some((60, 90)) });
+        // released half way through the step, and played again at the start of the next one
+        assert_eq!(events[4].note_off, Some(60));
+        assert_eq!(events[1..8].iter().filter(|events| **events != ArpEvents::default()).count(), 1);
+        assert_eq!(events[8], ArpEvents { note_off: None, note_on: Some((60, 90)) });
+
+        // letting go stops it
+        arp.note_off(60);
+        assert_eq!(arp.next(&PARAMS, 1.0, 0.125).note_off, Some(60));
+        assert!(!arp.is_active());
+    }
+
+    #[test]
+    fn test_synced_step_time_follows_tempo() {
+        let synced = ArpParams { division: Some(NoteDivision::Sixteenth), ..PARAMS };
+        assert_eq!(synced.step_time(120.0), 0.125);
+        assert_eq!(PARAMS.step_time(120.0), 0.1);
+    }
+}
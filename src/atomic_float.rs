@@ -0,0 +1,45 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+
+/// An `f32` that can be shared between threads, as the parameters are between the host, the
+/// editor and the audio thread. Nothing else is ordered around it, so it's read and written with
+/// relaxed ordering.
+#[derive(Debug)]
+pub struct AtomicFloat {
+    bits: AtomicU32,
+}
+
+impl AtomicFloat {
+    pub fn new(value: f32) -> AtomicFloat {
+        AtomicFloat { bits: AtomicU32::new(value.to_bits()) }
+    }
+
+    pub fn get(&self) -> f32 {
+        f32::from_bits(self.bits.load(Ordering::Relaxed))
+    }
+
+    pub fn set(&self, value: f32) {
+        self.bits.store(value.to_bits(), Ordering::Relaxed);
+    }
+}
+
+impl Default for AtomicFloat {
+    fn default() -> AtomicFloat {
+        AtomicFloat::new(0.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let value = AtomicFloat::default();
+        assert_eq!(value.get(), 0.0);
+        for &x in &[1.5, -0.0, f32::MAX, f32::MIN_POSITIVE] {
+            value.set(x);
+            assert_eq!(value.get().to_bits(), x.to_bits());
+        }
+        assert!(AtomicFloat::new(f32::NAN).get().is_nan());
+    }
+}
@@ -0,0 +1,343 @@
+//! Play the synth without a host, from midi inputs to an audio output.
+//!
+//!     cargo run --release --features standalone --bin standalone -- [--config <file>] [--output <device>]
+//!         [--midi <port>] [--sample-rate <hz>] [--buffer-size <frames>] [--list] [--jack]
+//!
+//! The config file is a preset file, as saved with the editor's export, so it sets any of the
+//! parameters by name. It can also pick the devices and stream settings, with `output`, `midi`,
+//! `sample_rate` and `buffer_size` keys alongside the preset's. The flags override it.
+//!
+//! Devices and ports are picked by any part of their name. Without a midi port, every input is
+//! played from.
+//!
+//! With the `jack` feature, `--jack` (or `"jack": true` in the config) runs as a JACK client
+//! instead, which also works with PipeWire. It has a midi input and stereo outputs to patch into
+//! the session's graph, and follows the JACK transport's tempo. The server picks the sample rate
+//! and buffer size, and there are no devices to pick.
+
+use std::error::Error;
+use std::io::{self, BufRead};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::{env, fs, process};
+
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{BufferSize, Device, FromSample, SampleFormat, SizedSample, Stream, StreamConfig};
+use midir::{MidiInput, MidiInputConnection};
+use replicant::Synth;
+use serde::Deserialize;
+
+const USAGE: &str = "usage: standalone [--config <file>] [--output <device>] [--midi <port>] \
+                     [--sample-rate <hz>] [--buffer-size <frames>] [--list] [--jack]";
+
+/// How many midi messages can wait for the audio thread before more are dropped.
+const MIDI_QUEUE_LEN: usize = 1024;
+
+/// The audio callback renders in pieces of this many frames, so it never allocates.
+const RENDER_FRAMES: usize = 256;
+
+/// The settings, from the config file then the flags.
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct Options {
+    #[serde(skip)]
+    config: Option<String>,
+    #[serde(skip)]
+    list: bool,
+    output: Option<String>,
+    midi: Option<String>,
+    sample_rate: Option<u32>,
+    buffer_size: Option<u32>,
+    jack: bool,
+}
+
+fn main() {
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{}\n{}", err, USAGE);
+            process::exit(2);
+        }
+    };
+    let result = if options.list { list() } else { run(options) };
+    if let Err(err) = result {
+        eprintln!("standalone: {}", err);
+        process::exit(1);
+    }
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options::default();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--config" => options.config = Some(value()?),
+            "--output" => options.output = Some(value()?),
+            "--midi" => options.midi = Some(value()?),
+            "--sample-rate" => {
+                options.sample_rate = Some(value()?.parse().map_err(|err| format!("bad sample rate: {}", err))?)
+            }
+            "--buffer-size" => {
+                options.buffer_size = Some(value()?.parse().map_err(|err| format!("bad buffer size: {}", err))?)
+            }
+            "--list" => options.list = true,
+            "--jack" => options.jack = true,
+            _ => return Err(format!("unknown option {}", arg)),
+        }
+    }
+    Ok(options)
+}
+
+/// Print the audio outputs and midi inputs that can be picked.
+fn list() -> Result<(), Box<dyn Error>> {
+    let host = cpal::default_host();
+    println!("audio outputs:");
+    for device in host.output_devices()? {
+        println!("    {}", device);
+    }
+    println!("midi inputs:");
+    match MidiInput::new("Replicant") {
+        Ok(input) => {
+            for port in input.ports() {
+                println!("    {}", input.port_name(&port)?);
+            }
+        }
+        Err(err) => println!("    none: {}", err),
+    }
+    Ok(())
+}
+
+fn run(mut options: Options) -> Result<(), Box<dyn Error>> {
+    let mut synth = Synth::default();
+    if let Some(path) = options.config.take() {
+        let data = fs::read(&path)?;
+        synth.import_preset(&data)?;
+        let config: Options = serde_json::from_slice(&data)?;
+        options = Options {
+            output: options.output.or(config.output),
+            midi: options.midi.or(config.midi),
+            sample_rate: options.sample_rate.or(config.sample_rate),
+            buffer_size: options.buffer_size.or(config.buffer_size),
+            jack: options.jack || config.jack,
+            ..options
+        };
+    }
+    if options.jack {
+        #[cfg(feature = "jack")]
+        return jack_backend::run(synth);
+        #[cfg(not(feature = "jack"))]
+        return Err("JACK needs a build with the jack feature".into());
+    }
+
+    let (sender, receiver) = mpsc::sync_channel(MIDI_QUEUE_LEN);
+    let _connections = connect_midi(options.midi.as_deref(), &sender)?;
+    let _stream = start_audio(&options, synth, receiver)?;
+
+    println!("playing, press enter to quit");
+    io::stdin().lock().read_line(&mut String::new())?;
+    Ok(())
+}
+
+/// Pass the messages from the midi inputs matching `name`, or all of them, on to the audio
+/// thread. They're played while the connections are kept.
+fn connect_midi(
+    name: Option<&str>,
+    sender: &SyncSender<[u8; 3]>,
+) -> Result<Vec<MidiInputConnection<()>>, Box<dyn Error>> {
+    let mut connections = Vec::new();
+    let ports = match MidiInput::new("Replicant") {
+        Ok(input) => input.ports(),
+        Err(err) if name.is_some() => return Err(err.into()),
+        // the synth still plays its audio, which is something to test with
+        Err(err) => {
+            eprintln!("standalone: no midi, so nothing will play: {}", err);
+            return Ok(connections);
+        }
+    };
+    for port in &ports {
+        let input = MidiInput::new("Replicant")?;
+        let port_name = input.port_name(port)?;
+        if name.is_some_and(|name| !port_name.contains(name)) {
+            continue;
+        }
+        let sender = sender.clone();
+        let connection = input.connect(port, "Replicant input", move |_, message, _| {
+            // system exclusive and other long messages aren't played
+            if (1..=3).contains(&message.len()) {
+                let mut data = [0; 3];
+                data[..message.len()].copy_from_slice(message);
+                // the audio thread has fallen behind if this is full, so the message is dropped
+                let _ = sender.try_send(data);
+            }
+        }, ());
+        match connection {
+            Ok(connection) => {
+                println!("midi input: {}", port_name);
+                connections.push(connection);
+            }
+            Err(err) => eprintln!("standalone: can't open midi input {}: {}", port_name, err),
+        }
+    }
+    match name {
+        Some(name) if connections.is_empty() => Err(format!("no midi input named {}", name).into()),
+        None if connections.is_empty() => {
+            eprintln!("standalone: no midi inputs, so nothing will play");
+            Ok(connections)
+        }
+        _ => Ok(connections),
+    }
+}
+
+/// Start the audio output, with the synth rendering into it.
+fn start_audio(options: &Options, mut synth: Synth, midi: Receiver<[u8; 3]>) -> Result<Stream, Box<dyn Error>> {
+    let host = cpal::default_host();
+    let device = match &options.output {
+        Some(name) => host.output_devices()?
+            .find(|device| device.to_string().contains(name.as_str()))
+            .ok_or_else(|| format!("no audio output named {}", name))?,
+        None => host.default_output_device().ok_or("no audio output")?,
+    };
+    let default = device.default_output_config()?;
+    let config = StreamConfig {
+        channels: default.channels(),
+        sample_rate: options.sample_rate.unwrap_or(default.sample_rate()),
+        buffer_size: options.buffer_size.map_or(BufferSize::Default, BufferSize::Fixed),
+    };
+    println!("audio output: {}, {} Hz", device, config.sample_rate);
+    synth.set_sample_rate(config.sample_rate as f32);
+
+    let stream = match default.sample_format() {
+        SampleFormat::F32 => build_stream::<f32>(&device, config, synth, midi),
+        SampleFormat::F64 => build_stream::<f64>(&device, config, synth, midi),
+        SampleFormat::I16 => build_stream::<i16>(&device, config, synth, midi),
+        SampleFormat::I32 => build_stream::<i32>(&device, config, synth, midi),
+        SampleFormat::U16 => build_stream::<u16>(&device, config, synth, midi),
+        format => return Err(format!("unsupported sample format {}", format).into()),
+    }?;
+    stream.play()?;
+    Ok(stream)
+}
+
+fn build_stream<T>(
+    device: &Device,
+    config: StreamConfig,
+    mut synth: Synth,
+    midi: Receiver<[u8; 3]>,
+) -> Result<Stream, cpal::Error>
+where
+    T: SizedSample + FromSample<f32>,
+{
+    let channels = usize::from(config.channels);
+    let (mut left, mut right) = ([0.0; RENDER_FRAMES], [0.0; RENDER_FRAMES]);
+    let callback = move |output: &mut [T], _: &cpal::OutputCallbackInfo| {
+        // messages land at the start of the next buffer, which is as close as a small buffer gets
+        for data in midi.try_iter() {
+            synth.queue_event(0, data);
+        }
+        for frames in output.chunks_mut(RENDER_FRAMES * channels) {
+            let len = frames.len() / channels;
+            let (left, right) = (&mut left[..len], &mut right[..len]);
+            synth.process_buffer(left, right);
+            for (frame, (&left, &right)) in frames.chunks_mut(channels).zip(left.iter().zip(right.iter())) {
+                match frame {
+                    [mono] => *mono = T::from_sample((left + right) * 0.5),
+                    [first, second, rest @ ..] => {
+                        *first = T::from_sample(left);
+                        *second = T::from_sample(right);
+                        rest.iter_mut().for_each(|sample| *sample = T::EQUILIBRIUM);
+                    }
+                    [] => (),
+                }
+            }
+        }
+        // there's no host to record them
+        synth.take_automated().for_each(drop);
+    };
+    device.build_output_stream(config, callback, |err| eprintln!("standalone: audio error: {}", err), None)
+}
+
+#[cfg(feature = "jack")]
+mod jack_backend {
+    use std::error::Error;
+    use std::io::{self, BufRead};
+
+    use jack::{AudioOut, Client, ClientOptions, Control, MidiIn, Port, PortFlags, ProcessHandler, ProcessScope};
+    use jack::{TransportState, TransportStatePosition};
+    use replicant::{Synth, Transport};
+
+    const CLIENT_NAME: &str = "Replicant";
+
+    /// The synth as a JACK client, with its ports.
+    struct JackSynth {
+        synth: Synth,
+        midi: Port<MidiIn>,
+        left: Port<AudioOut>,
+        right: Port<AudioOut>,
+    }
+
+    impl ProcessHandler for JackSynth {
+        fn process(&mut self, client: &Client, scope: &ProcessScope) -> Control {
+            if let Ok(transport) = client.transport().query() {
+                let previous = *self.synth.transport();
+                self.synth.set_transport(transport_of(&transport, &previous));
+            }
+            // JACK's midi comes with the sample it lands on
+            for event in self.midi.iter(scope) {
+                if (1..=3).contains(&event.bytes.len()) {
+                    let mut data = [0; 3];
+                    data[..event.bytes.len()].copy_from_slice(event.bytes);
+                    self.synth.queue_event(event.time as usize, data);
+                }
+            }
+            let left = self.left.as_mut_slice(scope);
+            let right = self.right.as_mut_slice(scope);
+            self.synth.process_buffer(left, right);
+            // there's no host to record them
+            self.synth.take_automated().for_each(drop);
+            Control::Continue
+        }
+    }
+
+    /// The JACK transport's tempo and play position, if something is keeping time. Without a
+    /// timebase, it keeps `previous`'s tempo and stays stopped.
+    fn transport_of(transport: &TransportStatePosition, previous: &Transport) -> Transport {
+        let Some(bbt) = transport.pos.bbt() else {
+            return Transport { position: None, ..*previous };
+        };
+        let rolling = transport.state == TransportState::Rolling;
+        let tempo = if bbt.bpm > 0.0 { bbt.bpm } else { previous.tempo };
+        // beats since the start, supposing the time signature hasn't changed along the way
+        let beats = (bbt.bar.saturating_sub(1) as f64) * f64::from(bbt.sig_num)
+            + bbt.beat.saturating_sub(1) as f64
+            + bbt.tick as f64 / bbt.ticks_per_beat;
+        Transport { tempo, position: if rolling { Some(beats) } else { None } }
+    }
+
+    /// Run until enter is pressed. The outputs start off connected to the speakers, if there
+    /// are any, and the midi input is left for the session to patch.
+    pub fn run(mut synth: Synth) -> Result<(), Box<dyn Error>> {
+        let (client, _) = Client::new(CLIENT_NAME, ClientOptions::NO_START_SERVER)?;
+        synth.set_sample_rate(client.sample_rate() as f32);
+        let midi = client.register_port("midi_in", MidiIn::default())?;
+        let left = client.register_port("out_left", AudioOut::default())?;
+        let right = client.register_port("out_right", AudioOut::default())?;
+        let ports = [midi.name()?, left.name()?, right.name()?];
+        println!("jack client {}, {} Hz", client.name(), client.sample_rate());
+
+        let active = client.activate_async((), JackSynth { synth, midi, left, right })?;
+        let speaker_flags = PortFlags::IS_INPUT | PortFlags::IS_PHYSICAL;
+        let speakers = active.as_client().ports(None, Some(jack::jack_sys::FLOAT_MONO_AUDIO), speaker_flags);
+        for (output, speaker) in ports[1..].iter().zip(&speakers) {
+            if let Err(err) = active.as_client().connect_ports_by_name(output, speaker) {
+                eprintln!("standalone: can't connect {} to {}: {}", output, speaker, err);
+            }
+        }
+        for port in &ports {
+            println!("port: {}", port);
+        }
+
+        println!("playing, press enter to quit");
+        io::stdin().lock().read_line(&mut String::new())?;
+        active.deactivate()?;
+        Ok(())
+    }
+}
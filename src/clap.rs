@@ -0,0 +1,909 @@
+use std::cell::UnsafeCell;
+use std::convert::TryFrom;
+use std::ffi::{c_char, c_void, CStr};
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Arc;
+
+use clap_sys::entry::clap_plugin_entry;
+use clap_sys::events::*;
+use clap_sys::ext::audio_ports::*;
+use clap_sys::ext::note_ports::*;
+use clap_sys::ext::params::*;
+use clap_sys::ext::state::*;
+use clap_sys::ext::tail::*;
+use clap_sys::factory::plugin_factory::{clap_plugin_factory, CLAP_PLUGIN_FACTORY_ID};
+use clap_sys::fixedpoint::CLAP_BEATTIME_FACTOR;
+use clap_sys::host::clap_host;
+use clap_sys::id::{clap_id, CLAP_INVALID_ID};
+use clap_sys::plugin::{clap_plugin, clap_plugin_descriptor};
+use clap_sys::plugin_features::*;
+use clap_sys::process::*;
+use clap_sys::stream::{clap_istream, clap_ostream};
+use clap_sys::version::{clap_version_is_compatible, CLAP_VERSION};
+
+use crate::param_groups::ParamGroup;
+use crate::params::{ReplicantParameters, NUM_PARAMS};
+use crate::presets::Preset;
+use crate::tempo::Transport;
+use crate::voice::NoteExpression;
+use crate::{init_plugin, Synth};
+
+/// A `&'static CStr` from a string literal.
+macro_rules! c_str {
+    ($text:literal) => {
+        match CStr::from_bytes_with_nul(concat!($text, "\0").as_bytes()) {
+            Ok(text) => text,
+            Err(_) => panic!("a nul in a C string"),
+        }
+    };
+}
+
+const NOTE_ON: u8 = 0x90;
+const NOTE_OFF: u8 = 0x80;
+const CONTROL_CHANGE: u8 = 0xB0;
+const ALL_SOUND_OFF: u8 = 120;
+
+/// The raw pointers in the descriptor all point at static strings.
+struct Features([*const c_char; 4]);
+
+unsafe impl Sync for Features {}
+
+static FEATURES: Features = Features([
+    CLAP_PLUGIN_FEATURE_INSTRUMENT.as_ptr(),
+    CLAP_PLUGIN_FEATURE_SYNTHESIZER.as_ptr(),
+    CLAP_PLUGIN_FEATURE_STEREO.as_ptr(),
+    ptr::null(),
+]);
+
+const ID: &CStr = c_str!("io.github.sullyj3.replicant");
+
+static DESCRIPTOR: clap_plugin_descriptor = clap_plugin_descriptor {
+    clap_version: CLAP_VERSION,
+    id: ID.as_ptr(),
+    name: c_str!("Replicant").as_ptr(),
+    vendor: c_str!("James Sully").as_ptr(),
+    url: c_str!("https://github.com/sullyj3/replicant").as_ptr(),
+    manual_url: c_str!("").as_ptr(),
+    support_url: c_str!("").as_ptr(),
+    version: c_str!("0.1.0").as_ptr(),
+    description: c_str!("A polyphonic subtractive and FM synth").as_ptr(),
+    features: &FEATURES.0 as *const _ as *const *const c_char,
+};
+
+/// The CLAP entry point, which hosts look for alongside the VST one.
+#[no_mangle]
+#[allow(non_upper_case_globals)]
+pub static clap_entry: clap_plugin_entry = clap_plugin_entry {
+    clap_version: CLAP_VERSION,
+    init: Some(entry_init),
+    deinit: Some(entry_deinit),
+    get_factory: Some(entry_get_factory),
+};
+
+unsafe extern "C" fn entry_init(_plugin_path: *const c_char) -> bool {
+    true
+}
+
+unsafe extern "C" fn entry_deinit() {}
+
+static FACTORY: clap_plugin_factory = clap_plugin_factory {
+    get_plugin_count: Some(factory_get_plugin_count),
+    get_plugin_descriptor: Some(factory_get_plugin_descriptor),
+    create_plugin: Some(factory_create_plugin),
+};
+
+unsafe extern "C" fn entry_get_factory(factory_id: *const c_char) -> *const c_void {
+    if !factory_id.is_null() && CStr::from_ptr(factory_id) == CLAP_PLUGIN_FACTORY_ID {
+        &FACTORY as *const clap_plugin_factory as *const c_void
+    } else {
+        ptr::null()
+    }
+}
+
+unsafe extern "C" fn factory_get_plugin_count(_factory: *const clap_plugin_factory) -> u32 {
+    1
+}
+
+unsafe extern "C" fn factory_get_plugin_descriptor(
+    _factory: *const clap_plugin_factory,
+    index: u32,
+) -> *const clap_plugin_descriptor {
+    if index == 0 { &DESCRIPTOR } else { ptr::null() }
+}
+
+unsafe extern "C" fn factory_create_plugin(
+    _factory: *const clap_plugin_factory,
+    host: *const clap_host,
+    plugin_id: *const c_char,
+) -> *const clap_plugin {
+    if host.is_null() || !clap_version_is_compatible((*host).clap_version) {
+        return ptr::null();
+    }
+    if plugin_id.is_null() || CStr::from_ptr(plugin_id) != ID {
+        return ptr::null();
+    }
+    let plugin = Box::into_raw(Box::new(ClapReplicant::new(host)));
+    (*plugin).clap.plugin_data = plugin as *mut c_void;
+    &(*plugin).clap
+}
+
+/// The synth as a CLAP plugin. The host gets a pointer to `clap`, whose `plugin_data` points back
+/// here.
+struct ClapReplicant {
+    clap: clap_plugin,
+    host: *const clap_host,
+    // only touched on the audio thread, or on the main thread while the plugin is deactivated, as
+    // CLAP's threading rules promise
+    synth: UnsafeCell<Synth>,
+    params: Arc<ReplicantParameters>,
+    // the host visible parameters' indices, with their defaults
+    param_info: Vec<(clap_id, f64)>,
+    // a spare set of parameters, for showing values other than the current ones
+    display: ReplicantParameters,
+    // set on the audio thread when a program change switched presets, so the main thread can
+    // tell the host every value has changed
+    preset_switched: AtomicBool,
+    // the synth's tail in samples, as of the last buffer
+    tail: AtomicU32,
+}
+
+impl ClapReplicant {
+    fn new(host: *const clap_host) -> ClapReplicant {
+        let synth = Synth::new();
+        let params = Arc::clone(synth.params());
+        let defaults = ReplicantParameters::default();
+        let param_info = (0..NUM_PARAMS as i32).zip(ReplicantParameters::default_values())
+            .filter(|&(index, _)| defaults.value(index).is_some())
+            .map(|(index, default)| (index as clap_id, f64::from(default)))
+            .collect();
+        let tail = AtomicU32::new(synth.tail_samples());
+        ClapReplicant {
+            clap: clap_plugin {
+                desc: &DESCRIPTOR,
+                plugin_data: ptr::null_mut(),
+                init: Some(plugin_init),
+                destroy: Some(plugin_destroy),
+                activate: Some(plugin_activate),
+                deactivate: Some(plugin_deactivate),
+                start_processing: Some(plugin_start_processing),
+                stop_processing: Some(plugin_stop_processing),
+                reset: Some(plugin_reset),
+                process: Some(plugin_process),
+                get_extension: Some(plugin_get_extension),
+                on_main_thread: Some(plugin_on_main_thread),
+            },
+            host,
+            synth: UnsafeCell::new(synth),
+            params,
+            param_info,
+            display: defaults,
+            preset_switched: AtomicBool::new(false),
+            tail,
+        }
+    }
+
+    /// The synth, for the threads CLAP allows to process audio.
+    #[allow(clippy::mut_from_ref)]
+    unsafe fn synth(&self) -> &mut Synth {
+        &mut *self.synth.get()
+    }
+
+    /// Ask the host to call back on the main thread.
+    unsafe fn request_callback(&self) {
+        if let Some(request_callback) = (*self.host).request_callback {
+            request_callback(self.host);
+        }
+    }
+
+    /// One of the host's extensions, if it has it.
+    unsafe fn host_extension<T>(&self, id: &CStr) -> Option<&T> {
+        let get_extension = (*self.host).get_extension?;
+        (get_extension(self.host, id.as_ptr()) as *const T).as_ref()
+    }
+}
+
+unsafe fn instance<'a>(plugin: *const clap_plugin) -> &'a ClapReplicant {
+    &*((*plugin).plugin_data as *const ClapReplicant)
+}
+
+/// Apply the host's events up to the end of `left` and `right`, rendering in between so each
+/// lands on its own sample.
+unsafe fn process_events(synth: &mut Synth, events: &clap_input_events, left: &mut [f32], right: &mut [f32]) {
+    let frames = left.len().min(right.len());
+    let mut start = 0;
+    for index in 0..input_event_count(events) {
+        let Some(header) = input_event(events, index) else { continue };
+        let time = (header.time as usize).clamp(start, frames);
+        match header.type_ {
+            CLAP_EVENT_NOTE_ON | CLAP_EVENT_NOTE_OFF | CLAP_EVENT_MIDI => {
+                if let Some(data) = midi_message(header) {
+                    synth.queue_event(time - start, data);
+                }
+            }
+            // these take effect straight away, so the audio before them is rendered first
+            CLAP_EVENT_NOTE_CHOKE | CLAP_EVENT_NOTE_EXPRESSION | CLAP_EVENT_PARAM_VALUE => {
+                if time > start {
+                    synth.process_buffer(&mut left[start..time], &mut right[start..time]);
+                    start = time;
+                }
+                apply_event(synth, header);
+            }
+            _ => (),
+        }
+    }
+    synth.process_buffer(&mut left[start..frames], &mut right[start..frames]);
+}
+
+/// Apply a parameter change or note expression.
+unsafe fn apply_event(synth: &mut Synth, header: &clap_event_header) {
+    match header.type_ {
+        CLAP_EVENT_PARAM_VALUE => {
+            let event = &*(header as *const _ as *const clap_event_param_value);
+            synth.set_parameter(event.param_id as i32, event.value as f32);
+        }
+        CLAP_EVENT_NOTE_EXPRESSION => {
+            let event = &*(header as *const _ as *const clap_event_note_expression);
+            let expression = match event.expression_id {
+                CLAP_NOTE_EXPRESSION_TUNING => NoteExpression::Tuning(event.value),
+                CLAP_NOTE_EXPRESSION_PRESSURE => NoteExpression::Pressure(event.value),
+                CLAP_NOTE_EXPRESSION_BRIGHTNESS => NoteExpression::Brightness(event.value),
+                _ => return,
+            };
+            if let Some(note) = note_key(event.key) {
+                synth.note_expression(note, expression);
+            }
+        }
+        // midi can only silence a whole channel at once, which is what choking every key does
+        CLAP_EVENT_NOTE_CHOKE => {
+            let note = &*(header as *const _ as *const clap_event_note);
+            match note_key(note.key) {
+                Some(key) => synth.choke(key),
+                None if note.key == -1 => {
+                    let channel = u8::try_from(note.channel).unwrap_or(0) & 0x0F;
+                    synth.queue_event(0, [CONTROL_CHANGE | channel, ALL_SOUND_OFF, 0]);
+                }
+                None => (),
+            }
+        }
+        _ => (),
+    }
+}
+
+/// Tell the host about parameters that mapped midi controllers have moved.
+unsafe fn send_automation(synth: &mut Synth, out: &clap_output_events) {
+    let Some(try_push) = out.try_push else { return };
+    for (index, value) in synth.take_automated() {
+        let event = clap_event_param_value {
+            header: clap_event_header {
+                size: std::mem::size_of::<clap_event_param_value>() as u32,
+                time: 0,
+                space_id: CLAP_CORE_EVENT_SPACE_ID,
+                type_: CLAP_EVENT_PARAM_VALUE,
+                flags: 0,
+            },
+            param_id: index as clap_id,
+            cookie: ptr::null_mut(),
+            note_id: -1,
+            port_index: -1,
+            channel: -1,
+            key: -1,
+            value: f64::from(value),
+        };
+        try_push(out, &event.header);
+    }
+}
+
+unsafe fn input_event_count(events: &clap_input_events) -> u32 {
+    events.size.map_or(0, |size| size(events))
+}
+
+unsafe fn input_event(events: &clap_input_events, index: u32) -> Option<&clap_event_header> {
+    let header = events.get?(events, index).as_ref()?;
+    // other spaces' events are other extensions', which we don't know
+    if header.space_id == CLAP_CORE_EVENT_SPACE_ID { Some(header) } else { None }
+}
+
+/// The midi message a note or midi event stands for. Notes for any key, or one out of range, are
+/// dropped.
+unsafe fn midi_message(header: &clap_event_header) -> Option<[u8; 3]> {
+    if header.type_ == CLAP_EVENT_MIDI {
+        return Some((*(header as *const _ as *const clap_event_midi)).data);
+    }
+    let note = &*(header as *const _ as *const clap_event_note);
+    let channel = u8::try_from(note.channel).unwrap_or(0) & 0x0F;
+    let key = note_key(note.key)?;
+    Some(match header.type_ {
+        // a velocity of 0 would be a note off
+        CLAP_EVENT_NOTE_ON => [NOTE_ON | channel, key, (note.velocity * 127.0).round().clamp(1.0, 127.0) as u8],
+        _ => [NOTE_OFF | channel, key, (note.velocity * 127.0).round().clamp(0.0, 127.0) as u8],
+    })
+}
+
+/// A note event's key, unless it's out of range, or -1 for every key.
+fn note_key(key: i16) -> Option<u8> {
+    u8::try_from(key).ok().filter(|&key| key < 128)
+}
+
+/// The host's transport, keeping `previous`'s tempo if it doesn't give one.
+fn transport(transport: &clap_event_transport, previous: &Transport) -> Transport {
+    let has_tempo = transport.flags & CLAP_TRANSPORT_HAS_TEMPO != 0 && transport.tempo > 0.0;
+    let playing = transport.flags & CLAP_TRANSPORT_IS_PLAYING != 0
+        && transport.flags & CLAP_TRANSPORT_HAS_BEATS_TIMELINE != 0;
+    Transport {
+        tempo: if has_tempo { transport.tempo } else { previous.tempo },
+        position: playing.then(|| transport.song_pos_beats as f64 / CLAP_BEATTIME_FACTOR as f64),
+    }
+}
+
+/// Copy `text` into a C string buffer of `capacity` bytes, cutting it short at a character
+/// boundary if it doesn't fit.
+unsafe fn write_c_str(text: &str, buffer: *mut c_char, capacity: usize) {
+    if buffer.is_null() || capacity == 0 {
+        return;
+    }
+    let mut len = text.len().min(capacity - 1);
+    while !text.is_char_boundary(len) {
+        len -= 1;
+    }
+    ptr::copy_nonoverlapping(text.as_ptr() as *const c_char, buffer, len);
+    *buffer.add(len) = 0;
+}
+
+unsafe extern "C" fn plugin_init(plugin: *const clap_plugin) -> bool {
+    init_plugin(&instance(plugin).params);
+    true
+}
+
+unsafe extern "C" fn plugin_destroy(plugin: *const clap_plugin) {
+    drop(Box::from_raw((*plugin).plugin_data as *mut ClapReplicant));
+}
+
+unsafe extern "C" fn plugin_activate(plugin: *const clap_plugin, sample_rate: f64, _min: u32, _max: u32) -> bool {
+    let this = instance(plugin);
+    let synth = this.synth();
+    synth.set_sample_rate(sample_rate as f32);
+    this.tail.store(synth.tail_samples(), Ordering::Relaxed);
+    true
+}
+
+unsafe extern "C" fn plugin_deactivate(_plugin: *const clap_plugin) {}
+
+unsafe extern "C" fn plugin_start_processing(_plugin: *const clap_plugin) -> bool {
+    true
+}
+
+// hosts stop processing when the plugin is switched off, so nothing should ring on when it's
+// switched back on
+unsafe extern "C" fn plugin_stop_processing(plugin: *const clap_plugin) {
+    instance(plugin).synth().reset();
+}
+
+unsafe extern "C" fn plugin_reset(plugin: *const clap_plugin) {
+    instance(plugin).synth().reset();
+}
+
+unsafe extern "C" fn plugin_process(plugin: *const clap_plugin, process: *const clap_process) -> clap_process_status {
+    let this = instance(plugin);
+    let process = &*process;
+    let synth = this.synth();
+
+    if let Some(host_transport) = process.transport.as_ref() {
+        synth.set_transport(transport(host_transport, synth.transport()));
+    }
+    if this.params.take_preset_switched() {
+        this.preset_switched.store(true, Ordering::Relaxed);
+        this.request_callback();
+    }
+
+    // the one output port is stereo
+    let output = match process.audio_outputs.as_ref() {
+        Some(output) if process.audio_outputs_count == 1 && output.channel_count == 2 && !output.data32.is_null() => {
+            output
+        }
+        _ => return CLAP_PROCESS_ERROR,
+    };
+    let (left, right) = (*output.data32, *output.data32.add(1));
+    if left.is_null() || right.is_null() {
+        return CLAP_PROCESS_ERROR;
+    }
+    let frames = process.frames_count as usize;
+    let left = std::slice::from_raw_parts_mut(left, frames);
+    let right = std::slice::from_raw_parts_mut(right, frames);
+
+    match process.in_events.as_ref() {
+        Some(events) => process_events(synth, events, left, right),
+        None => synth.process_buffer(left, right),
+    }
+    if let Some(out) = process.out_events.as_ref() {
+        send_automation(synth, out);
+    }
+    let tail = synth.tail_samples();
+    if this.tail.swap(tail, Ordering::Relaxed) != tail {
+        let host_tail = this.host_extension::<clap_host_tail>(CLAP_EXT_TAIL);
+        if let Some(changed) = host_tail.and_then(|host_tail| host_tail.changed) {
+            changed(this.host);
+        }
+    }
+    CLAP_PROCESS_CONTINUE
+}
+
+unsafe extern "C" fn plugin_on_main_thread(plugin: *const clap_plugin) {
+    let this = instance(plugin);
+    if this.preset_switched.swap(false, Ordering::Relaxed) {
+        let host_params = this.host_extension::<clap_host_params>(CLAP_EXT_PARAMS);
+        if let Some(rescan) = host_params.and_then(|params| params.rescan) {
+            rescan(this.host, CLAP_PARAM_RESCAN_VALUES | CLAP_PARAM_RESCAN_TEXT);
+        }
+    }
+}
+
+unsafe extern "C" fn plugin_get_extension(_plugin: *const clap_plugin, id: *const c_char) -> *const c_void {
+    if id.is_null() {
+        return ptr::null();
+    }
+    let id = CStr::from_ptr(id);
+    if id == CLAP_EXT_AUDIO_PORTS {
+        &AUDIO_PORTS as *const _ as *const c_void
+    } else if id == CLAP_EXT_NOTE_PORTS {
+        &NOTE_PORTS as *const _ as *const c_void
+    } else if id == CLAP_EXT_PARAMS {
+        &PARAMS as *const _ as *const c_void
+    } else if id == CLAP_EXT_STATE {
+        &STATE as *const _ as *const c_void
+    } else if id == CLAP_EXT_TAIL {
+        &TAIL as *const _ as *const c_void
+    } else {
+        ptr::null()
+    }
+}
+
+static AUDIO_PORTS: clap_plugin_audio_ports = clap_plugin_audio_ports {
+    count: Some(audio_ports_count),
+    get: Some(audio_ports_get),
+};
+
+unsafe extern "C" fn audio_ports_count(_plugin: *const clap_plugin, is_input: bool) -> u32 {
+    if is_input { 0 } else { 1 }
+}
+
+unsafe extern "C" fn audio_ports_get(
+    _plugin: *const clap_plugin,
+    index: u32,
+    is_input: bool,
+    info: *mut clap_audio_port_info,
+) -> bool {
+    if is_input || index != 0 || info.is_null() {
+        return false;
+    }
+    let info = &mut *info;
+    info.id = 0;
+    write_c_str("Output", info.name.as_mut_ptr(), info.name.len());
+    info.flags = CLAP_AUDIO_PORT_IS_MAIN;
+    info.channel_count = 2;
+    info.port_type = CLAP_PORT_STEREO.as_ptr();
+    info.in_place_pair = CLAP_INVALID_ID;
+    true
+}
+
+static NOTE_PORTS: clap_plugin_note_ports = clap_plugin_note_ports {
+    count: Some(note_ports_count),
+    get: Some(note_ports_get),
+};
+
+unsafe extern "C" fn note_ports_count(_plugin: *const clap_plugin, is_input: bool) -> u32 {
+    if is_input { 1 } else { 0 }
+}
+
+unsafe extern "C" fn note_ports_get(
+    _plugin: *const clap_plugin,
+    index: u32,
+    is_input: bool,
+    info: *mut clap_note_port_info,
+) -> bool {
+    if !is_input || index != 0 || info.is_null() {
+        return false;
+    }
+    let info = &mut *info;
+    info.id = 0;
+    info.supported_dialects = CLAP_NOTE_DIALECT_CLAP | CLAP_NOTE_DIALECT_MIDI | CLAP_NOTE_DIALECT_MIDI_MPE;
+    info.preferred_dialect = CLAP_NOTE_DIALECT_CLAP;
+    write_c_str("Notes", info.name.as_mut_ptr(), info.name.len());
+    true
+}
+
+// the parameters are the same as the VST ones, with the same ids and the same 0.0 to 1.0 values,
+// so automation carries over between the two
+static PARAMS: clap_plugin_params = clap_plugin_params {
+    count: Some(params_count),
+    get_info: Some(params_get_info),
+    get_value: Some(params_get_value),
+    value_to_text: Some(params_value_to_text),
+    text_to_value: Some(params_text_to_value),
+    flush: Some(params_flush),
+};
+
+unsafe extern "C" fn params_count(plugin: *const clap_plugin) -> u32 {
+    instance(plugin).param_info.len() as u32
+}
+
+unsafe extern "C" fn params_get_info(plugin: *const clap_plugin, param_index: u32, info: *mut clap_param_info) -> bool {
+    let this = instance(plugin);
+    let Some(&(id, default)) = this.param_info.get(param_index as usize) else { return false };
+    if info.is_null() {
+        return false;
+    }
+    let info = &mut *info;
+    info.id = id;
+    info.flags = CLAP_PARAM_IS_AUTOMATABLE;
+    info.cookie = ptr::null_mut();
+    write_c_str(&this.params.param_name(id as i32), info.name.as_mut_ptr(), info.name.len());
+    let group = ParamGroup::of(id as i32).map_or("", ParamGroup::name);
+    write_c_str(group, info.module.as_mut_ptr(), info.module.len());
+    info.min_value = 0.0;
+    info.max_value = 1.0;
+    info.default_value = default;
+    true
+}
+
+unsafe extern "C" fn params_get_value(plugin: *const clap_plugin, param_id: clap_id, out_value: *mut f64) -> bool {
+    let this = instance(plugin);
+    if out_value.is_null() || this.params.value(param_id as i32).is_none() {
+        return false;
+    }
+    *out_value = f64::from(this.params.normalized(param_id as i32));
+    true
+}
+
+unsafe extern "C" fn params_value_to_text(
+    plugin: *const clap_plugin,
+    param_id: clap_id,
+    value: f64,
+    out_buffer: *mut c_char,
+    out_buffer_capacity: u32,
+) -> bool {
+    let this = instance(plugin);
+    let index = param_id as i32;
+    if this.display.value(index).is_none() {
+        return false;
+    }
+    // the text comes from the parameter's current value, so it's shown through the spare set
+    this.display.set_normalized(index, value as f32);
+    let text = format!("{} {}", this.display.param_text(index), this.display.param_label(index));
+    write_c_str(text.trim_end(), out_buffer, out_buffer_capacity as usize);
+    true
+}
+
+unsafe extern "C" fn params_text_to_value(
+    plugin: *const clap_plugin,
+    param_id: clap_id,
+    param_value_text: *const c_char,
+    out_value: *mut f64,
+) -> bool {
+    let this = instance(plugin);
+    let index = param_id as i32;
+    if param_value_text.is_null() || out_value.is_null() {
+        return false;
+    }
+    let Ok(text) = CStr::from_ptr(param_value_text).to_str() else { return false };
+    // read into the spare set, so the live value is left alone
+    if !this.display.set_param_text(index, text) {
+        return false;
+    }
+    *out_value = f64::from(this.display.normalized(index));
+    true
+}
+
+unsafe extern "C" fn params_flush(
+    plugin: *const clap_plugin,
+    in_: *const clap_input_events,
+    out: *const clap_output_events,
+) {
+    let synth = instance(plugin).synth();
+    if let Some(events) = in_.as_ref() {
+        for index in 0..input_event_count(events) {
+            if let Some(header) = input_event(events, index).filter(|header| header.type_ == CLAP_EVENT_PARAM_VALUE) {
+                apply_event(synth, header);
+            }
+        }
+    }
+    if let Some(out) = out.as_ref() {
+        send_automation(synth, out);
+    }
+}
+
+static STATE: clap_plugin_state = clap_plugin_state {
+    save: Some(state_save),
+    load: Some(state_load),
+};
+
+/// The host saves the current patch, in the same format as the editor's preset export.
+unsafe extern "C" fn state_save(plugin: *const clap_plugin, stream: *const clap_ostream) -> bool {
+    let Some(write) = stream.as_ref().and_then(|stream| stream.write) else { return false };
+    let data = instance(plugin).params.current_preset().to_bytes();
+    let mut written = 0;
+    while written < data.len() {
+        let remaining = &data[written..];
+        match write(stream, remaining.as_ptr() as *const c_void, remaining.len() as u64) {
+            len if len > 0 => written += len as usize,
+            _ => return false,
+        }
+    }
+    true
+}
+
+unsafe extern "C" fn state_load(plugin: *const clap_plugin, stream: *const clap_istream) -> bool {
+    let Some(read) = stream.as_ref().and_then(|stream| stream.read) else { return false };
+    let mut data = Vec::new();
+    let mut chunk = [0u8; 4096];
+    loop {
+        match read(stream, chunk.as_mut_ptr() as *mut c_void, chunk.len() as u64) {
+            0 => break,
+            len if len > 0 => data.extend_from_slice(&chunk[..len as usize]),
+            _ => return false,
+        }
+    }
+    match Preset::from_bytes(&data) {
+        Ok(preset) => {
+            instance(plugin).params.load_preset(preset);
+            true
+        }
+        Err(err) => {
+            log::warn!("ignoring invalid state: {}", err);
+            false
+        }
+    }
+}
+
+static TAIL: clap_plugin_tail = clap_plugin_tail { get: Some(tail_get) };
+
+/// How long released notes and the effects ring on, so hosts render all of it when bouncing.
+unsafe extern "C" fn tail_get(plugin: *const clap_plugin) -> u32 {
+    instance(plugin).tail.load(Ordering::Relaxed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap_sys::audio_buffer::clap_audio_buffer;
+    use std::mem;
+
+    const HOST: clap_host = clap_host {
+        clap_version: CLAP_VERSION,
+        host_data: ptr::null_mut(),
+        name: ptr::null(),
+        vendor: ptr::null(),
+        url: ptr::null(),
+        version: ptr::null(),
+        get_extension: None,
+        request_restart: None,
+        request_process: None,
+        request_callback: None,
+    };
+
+    fn header<T>(time: u32, type_: clap_event_type) -> clap_event_header {
+        let size = mem::size_of::<T>() as u32;
+        clap_event_header { size, time, space_id: CLAP_CORE_EVENT_SPACE_ID, type_, flags: 0 }
+    }
+
+    fn note(time: u32, type_: clap_event_type, key: i16) -> clap_event_note {
+        let header = header::<clap_event_note>(time, type_);
+        clap_event_note { header, note_id: -1, port_index: 0, channel: 0, key, velocity: 1.0 }
+    }
+
+    /// An input event list over events the test owns.
+    struct Events(Vec<*const clap_event_header>);
+
+    unsafe extern "C" fn events_size(list: *const clap_input_events) -> u32 {
+        (&*((*list).ctx as *const Events)).0.len() as u32
+    }
+
+    unsafe extern "C" fn events_get(list: *const clap_input_events, index: u32) -> *const clap_event_header {
+        (&*((*list).ctx as *const Events)).0[index as usize]
+    }
+
+    fn create() -> *const clap_plugin {
+        unsafe {
+            let factory = &*(entry_get_factory(CLAP_PLUGIN_FACTORY_ID.as_ptr()) as *const clap_plugin_factory);
+            let descriptor = &*factory.get_plugin_descriptor.unwrap()(factory, 0);
+            let plugin = factory.create_plugin.unwrap()(factory, &HOST, descriptor.id);
+            assert!((*plugin).init.unwrap()(plugin));
+            assert!((*plugin).activate.unwrap()(plugin, 44100.0, 1, 512));
+            plugin
+        }
+    }
+
+    /// Render a buffer of `len` samples with `events`, returning the left channel.
+    fn process(plugin: *const clap_plugin, events: &[*const clap_event_header], len: usize) -> Vec<f32> {
+        let (mut left, mut right) = (vec![0.0f32; len], vec![0.0f32; len]);
+        let mut channels = [left.as_mut_ptr(), right.as_mut_ptr()];
+        let mut output = clap_audio_buffer {
+            data32: channels.as_mut_ptr(),
+            data64: ptr::null_mut(),
+            channel_count: 2,
+            latency: 0,
+            constant_mask: 0,
+        };
+        let events = Events(events.to_vec());
+        let in_events = clap_input_events {
+            ctx: &events as *const Events as *mut c_void,
+            size: Some(events_size),
+            get: Some(events_get),
+        };
+        let process = clap_process {
+            steady_time: -1,
+            frames_count: len as u32,
+            transport: ptr::null(),
+            audio_inputs: ptr::null(),
+            audio_outputs: &mut output,
+            audio_inputs_count: 0,
+            audio_outputs_count: 1,
+            in_events: &in_events,
+            out_events: ptr::null(),
+        };
+        unsafe {
+            assert_eq!((*plugin).process.unwrap()(plugin, &process), CLAP_PROCESS_CONTINUE);
+        }
+        left
+    }
+
+    fn destroy(plugin: *const clap_plugin) {
+        unsafe { (*plugin).destroy.unwrap()(plugin) }
+    }
+
+    #[test]
+    fn test_notes_are_sample_accurate() {
+        let plugin = create();
+        // the DC blocker would still be settling after the choke
+        unsafe { instance(plugin).params.dc_blocker.set(0.0) };
+        let on = note(100, CLAP_EVENT_NOTE_ON, 60);
+        let left = process(plugin, &[&on.header], 256);
+        assert!(left[..100].iter().all(|&sample| sample == 0.0));
+        assert!(left[100..].iter().any(|&sample| sample != 0.0));
+
+        // choking every key silences the channel straight away
+        let choke = note(0, CLAP_EVENT_NOTE_CHOKE, -1);
+        let left = process(plugin, &[&choke.header], 256);
+        assert!(left.iter().all(|&sample| sample == 0.0));
+        destroy(plugin);
+    }
+
+    #[test]
+    fn test_choking_a_key_silences_it() {
+        let plugin = create();
+        unsafe {
+            instance(plugin).params.dc_blocker.set(0.0);
+            // the amp release, at its longest, which a note off would ring out through
+            instance(plugin).params.set_normalized(8, 1.0);
+        }
+        let on = note(0, CLAP_EVENT_NOTE_ON, 60);
+        process(plugin, &[&on.header], 256);
+        let choke = note(100, CLAP_EVENT_NOTE_CHOKE, 60);
+        let left = process(plugin, &[&choke.header], 256);
+        assert!(left[..100].iter().any(|&sample| sample != 0.0));
+        assert!(left[100..].iter().all(|&sample| sample == 0.0));
+
+        // keys out of range are dropped, rather than playing the top note
+        let on = note(0, CLAP_EVENT_NOTE_ON, 200);
+        let left = process(plugin, &[&on.header], 256);
+        assert!(left.iter().all(|&sample| sample == 0.0));
+        destroy(plugin);
+    }
+
+    #[test]
+    fn test_tail_follows_the_release() {
+        let plugin = create();
+        unsafe {
+            let tail = &*(plugin_get_extension(plugin, CLAP_EXT_TAIL.as_ptr()) as *const clap_plugin_tail);
+            let short = tail.get.unwrap()(plugin);
+            // the amp release, at its longest of 5 seconds
+            instance(plugin).params.set_normalized(8, 1.0);
+            process(plugin, &[], 64);
+            let long = tail.get.unwrap()(plugin);
+            assert!(long > short);
+            assert!(long >= 5 * 44100);
+        }
+        destroy(plugin);
+    }
+
+    #[test]
+    fn test_params() {
+        let plugin = create();
+        unsafe {
+            let params = &*(plugin_get_extension(plugin, CLAP_EXT_PARAMS.as_ptr()) as *const clap_plugin_params);
+            let count = params.count.unwrap()(plugin);
+            assert!(count > 0 && count as usize <= NUM_PARAMS);
+
+            let mut info: clap_param_info = mem::zeroed();
+            let found = (0..count).any(|index| {
+                params.get_info.unwrap()(plugin, index, &mut info);
+                CStr::from_ptr(info.name.as_ptr()).to_str() == Ok("Cutoff")
+            });
+            assert!(found);
+            assert_eq!(info.id, 19);
+            assert_eq!(CStr::from_ptr(info.module.as_ptr()).to_str(), Ok("Filter"));
+            assert!(!params.get_info.unwrap()(plugin, count, &mut info));
+
+            // values from the host land on their sample, and the text follows any value
+            let mut change = clap_event_param_value {
+                header: header::<clap_event_param_value>(10, CLAP_EVENT_PARAM_VALUE),
+                param_id: 19,
+                cookie: ptr::null_mut(),
+                note_id: -1,
+                port_index: -1,
+                channel: -1,
+                key: -1,
+                value: 1.0,
+            };
+            process(plugin, &[&change.header], 64);
+            let mut value = 0.0;
+            assert!(params.get_value.unwrap()(plugin, 19, &mut value));
+            assert_eq!(value, 1.0);
+
+            let mut text = [0 as c_char; 32];
+            assert!(params.value_to_text.unwrap()(plugin, 19, 1.0, text.as_mut_ptr(), 32));
+            assert_eq!(CStr::from_ptr(text.as_ptr()).to_str(), Ok("20.00 kHz"));
+            // a short buffer cuts the text short
+            assert!(params.value_to_text.unwrap()(plugin, 19, 1.0, text.as_mut_ptr(), 4));
+            assert_eq!(CStr::from_ptr(text.as_ptr()).to_str(), Ok("20."));
+
+            // and typed in text reads back in
+            let mut value = 0.0;
+            assert!(params.text_to_value.unwrap()(plugin, 19, c_str!("20 kHz").as_ptr(), &mut value));
+            assert_eq!(value, 1.0);
+            assert!(!params.text_to_value.unwrap()(plugin, 19, c_str!("loud").as_ptr(), &mut value));
+
+            // flushing applies changes without processing
+            change.value = 0.0;
+            let events = Events(vec![&change.header]);
+            let in_events = clap_input_events {
+                ctx: &events as *const Events as *mut c_void,
+                size: Some(events_size),
+                get: Some(events_get),
+            };
+            params.flush.unwrap()(plugin, &in_events, ptr::null());
+            assert!(params.get_value.unwrap()(plugin, 19, &mut value));
+            assert_eq!(value, 0.0);
+        }
+        destroy(plugin);
+    }
+
+    unsafe extern "C" fn write_vec(stream: *const clap_ostream, buffer: *const c_void, size: u64) -> i64 {
+        // a few bytes at a time, as streams are allowed to take
+        let size = size.min(7) as usize;
+        (*((*stream).ctx as *mut Vec<u8>)).extend_from_slice(std::slice::from_raw_parts(buffer as *const u8, size));
+        size as i64
+    }
+
+    unsafe extern "C" fn read_slice(stream: *const clap_istream, buffer: *mut c_void, size: u64) -> i64 {
+        let data = &mut *((*stream).ctx as *mut &[u8]);
+        let size = (size as usize).min(data.len());
+        ptr::copy_nonoverlapping(data.as_ptr(), buffer as *mut u8, size);
+        *data = &data[size..];
+        size as i64
+    }
+
+    #[test]
+    fn test_state_round_trip() {
+        let plugin = create();
+        let mut saved = Vec::new();
+        unsafe {
+            let state = &*(plugin_get_extension(plugin, CLAP_EXT_STATE.as_ptr()) as *const clap_plugin_state);
+            instance(plugin).params.set_normalized(20, 0.75);
+            let stream = clap_ostream { ctx: &mut saved as *mut Vec<u8> as *mut c_void, write: Some(write_vec) };
+            assert!(state.save.unwrap()(plugin, &stream));
+
+            let loaded = create();
+            let mut data = &saved[..];
+            let stream = clap_istream { ctx: &mut data as *mut &[u8] as *mut c_void, read: Some(read_slice) };
+            assert!(state.load.unwrap()(loaded, &stream));
+            assert_eq!(instance(loaded).params.normalized(20), 0.75);
+
+            let mut data = &b"not a preset"[..];
+            let stream = clap_istream { ctx: &mut data as *mut &[u8] as *mut c_void, read: Some(read_slice) };
+            assert!(!state.load.unwrap()(loaded, &stream));
+            destroy(loaded);
+        }
+        destroy(plugin);
+    }
+}
@@ -0,0 +1,115 @@
+use crate::simd::F64x2;
+
+/// Added to values in feedback paths and taken away again, which rounds away anything small enough
+/// to be on its way to becoming denormal.
+const ANTI_DENORMAL: f64 = 1e-18;
+
+/// Round values too quiet to hear down to zero, before they become denormal.
+///
+/// As a signal decays to silence through a feedback path, it eventually becomes denormal, which
+/// is very slow to calculate with on some CPUs. `FlushDenormals` has the FPU round those to zero,
+/// but not every CPU can, so the filter, delay and limiter states also pass through this.
+pub fn flush_denormal(value: f64) -> f64 {
+    value + ANTI_DENORMAL - ANTI_DENORMAL
+}
+
+/// `flush_denormal` for both of a pair at once.
+pub fn flush_denormals(values: F64x2) -> F64x2 {
+    values + F64x2::splat(ANTI_DENORMAL) - F64x2::splat(ANTI_DENORMAL)
+}
+
+/// Has the FPU treat denormals as zero until it's dropped, when the host's setting is restored.
+///
+/// On x86 this sets the flush to zero and denormals are zero flags, and on ARM the flush to zero
+/// flag. Elsewhere it does nothing, leaving it to `flush_denormal`.
+pub struct FlushDenormals {
+    previous: usize,
+}
+
+#[cfg(any(target_arch = "x86_64", all(target_arch = "x86", target_feature = "sse")))]
+mod fpu {
+    use std::arch::asm;
+
+    // MXCSR's flush to zero and denormals are zero bits
+    pub const FLUSH_DENORMALS: usize = 0x8040;
+
+    pub fn control() -> usize {
+        let mut csr = 0u32;
+        unsafe { asm!("stmxcsr [{}]", in(reg) &mut csr, options(nostack, preserves_flags)) };
+        csr as usize
+    }
+
+    pub fn set_control(csr: usize) {
+        let csr = csr as u32;
+        unsafe { asm!("ldmxcsr [{}]", in(reg) &csr, options(nostack, readonly, preserves_flags)) };
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod fpu {
+    use std::arch::asm;
+
+    // FPCR's flush to zero bit
+    pub const FLUSH_DENORMALS: usize = 1 << 24;
+
+    pub fn control() -> usize {
+        let fpcr: usize;
+        unsafe { asm!("mrs {}, fpcr", out(reg) fpcr, options(nomem, nostack, preserves_flags)) };
+        fpcr
+    }
+
+    pub fn set_control(fpcr: usize) {
+        unsafe { asm!("msr fpcr, {}", in(reg) fpcr, options(nomem, nostack, preserves_flags)) };
+    }
+}
+
+#[cfg(not(any(target_arch = "x86_64", all(target_arch = "x86", target_feature = "sse"), target_arch = "aarch64")))]
+mod fpu {
+    pub const FLUSH_DENORMALS: usize = 0;
+
+    pub fn control() -> usize {
+        0
+    }
+
+    pub fn set_control(_control: usize) {}
+}
+
+impl FlushDenormals {
+    pub fn new() -> FlushDenormals {
+        let previous = fpu::control();
+        fpu::set_control(previous | fpu::FLUSH_DENORMALS);
+        FlushDenormals { previous }
+    }
+}
+
+impl Drop for FlushDenormals {
+    fn drop(&mut self) {
+        fpu::set_control(self.previous);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::hint::black_box;
+
+    #[test]
+    fn test_flush_denormal() {
+        assert_eq!(flush_denormal(1e-300), 0.0);
+        assert_eq!(flush_denormal(-1e-40), 0.0);
+        assert_eq!(flush_denormal(0.5), 0.5);
+        assert!((flush_denormal(1e-12) - 1e-12).abs() < 1e-16);
+    }
+
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    #[test]
+    fn test_flush_denormals_is_restored() {
+        let tiny = f64::MIN_POSITIVE;
+        assert!(black_box(tiny) / 4.0 > 0.0);
+        {
+            let _flush = FlushDenormals::new();
+            assert_eq!(black_box(tiny) / 4.0, 0.0);
+        }
+        assert!(black_box(tiny) / 4.0 > 0.0);
+    }
+}
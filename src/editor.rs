@@ -0,0 +1,519 @@
+use std::ffi::c_void;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use egui::{Button, CentralPanel, ComboBox, Key, KeyboardShortcut, Modifiers, ProgressBar, Sense, Shape, Slider, Stroke, Panel};
+use egui_baseview::baseview::dpi::LogicalSize;
+use egui_baseview::baseview::Window;
+use egui_baseview::{EguiWindow, EguiWindowSettings, Frame};
+use log::warn;
+use raw_window_handle::{HandleError, HasWindowHandle, RawWindowHandle, WindowHandle};
+use vst::editor::Editor;
+use vst::host::Host;
+use vst::plugin::HostCallback;
+
+use crate::arpeggiator::ArpMode;
+use crate::envelope::{sample_shape, EnvelopeMode};
+use crate::filter::FilterType;
+use crate::loader::LoadStatus;
+use crate::meter::{Meter, SCOPE_LEN};
+use crate::oscillator::{NoiseColor, SubWaveform, Waveform};
+use crate::oversampling::Oversampling;
+use crate::morph::Slot;
+use crate::params::{ReplicantParameters, MORPH_PARAM};
+use crate::saturation::Saturation;
+use crate::stereo::StereoMode;
+use crate::tempo::NoteDivision;
+use crate::tuning::TuningFiles;
+use crate::voice::{PanMode, Retrigger};
+use crate::wavetable::NUM_HARMONICS;
+
+const WIDTH: i32 = 1480;
+const HEIGHT: i32 = 500;
+
+/// How many points the envelope's shape is drawn through.
+const ENVELOPE_POINTS: usize = 200;
+
+/// How often the controls are redrawn to follow host automation, even without any input.
+const REFRESH_INTERVAL: Duration = Duration::from_millis(33);
+
+/// A control for one parameter, by host index.
+enum Control {
+    /// A slider over the parameter's normalized range, showing its display text.
+    Slider(i32),
+    /// A drop down list of named options.
+    Choice(i32, Vec<&'static str>),
+}
+
+/// The plugin's editor window, which edits the shared parameters directly so it always agrees with
+/// the host.
+pub struct ReplicantEditor {
+    params: Arc<ReplicantParameters>,
+    meter: Arc<Meter>,
+    host: HostCallback,
+    window: Option<Window>,
+}
+
+impl ReplicantEditor {
+    pub fn new(params: Arc<ReplicantParameters>, meter: Arc<Meter>, host: HostCallback) -> ReplicantEditor {
+        ReplicantEditor { params, meter, host, window: None }
+    }
+}
+
+impl Editor for ReplicantEditor {
+    fn size(&self) -> (i32, i32) {
+        (WIDTH, HEIGHT)
+    }
+
+    fn position(&self) -> (i32, i32) {
+        (0, 0)
+    }
+
+    fn open(&mut self, parent: *mut c_void) -> bool {
+        if self.window.is_some() {
+            return true;
+        }
+
+        let parent = ParentWindow(parent);
+        let settings = EguiWindowSettings::new()
+            .with_title("Replicant")
+            .with_size(LogicalSize { width: f64::from(WIDTH), height: f64::from(HEIGHT) })
+            .with_parent(&parent);
+        let app = EditorApp::new(Arc::clone(&self.params), Arc::clone(&self.meter), self.host);
+
+        match EguiWindow::create(settings, app).and_then(|window| window.show().map(|_| window)) {
+            Ok(window) => {
+                self.window = Some(window);
+                true
+            }
+            Err(err) => {
+                warn!("couldn't open the editor: {}", err);
+                false
+            }
+        }
+    }
+
+    fn is_open(&mut self) -> bool {
+        self.window.is_some()
+    }
+
+    fn close(&mut self) {
+        if let Some(window) = self.window.take() {
+            window.close();
+        }
+    }
+}
+
+/// The window the host gives us to embed the editor in.
+struct ParentWindow(*mut c_void);
+
+impl HasWindowHandle for ParentWindow {
+    fn window_handle(&self) -> Result<WindowHandle<'_>, HandleError> {
+        #[cfg(target_os = "windows")]
+        let raw = {
+            let hwnd = std::num::NonZeroIsize::new(self.0 as isize).ok_or(HandleError::Unavailable)?;
+            RawWindowHandle::Win32(raw_window_handle::Win32WindowHandle::new(hwnd))
+        };
+        #[cfg(target_os = "macos")]
+        let raw = {
+            let view = std::ptr::NonNull::new(self.0).ok_or(HandleError::Unavailable)?;
+            RawWindowHandle::AppKit(raw_window_handle::AppKitWindowHandle::new(view))
+        };
+        // on X11 the "pointer" is really the parent's window id
+        #[cfg(not(any(target_os = "windows", target_os = "macos")))]
+        let raw = RawWindowHandle::Xlib(raw_window_handle::XlibWindowHandle::new(self.0 as _));
+
+        // the host keeps the parent window alive for as long as the editor is open
+        Ok(unsafe { WindowHandle::borrow_raw(raw) })
+    }
+}
+
+struct EditorApp {
+    params: Arc<ReplicantParameters>,
+    meter: Arc<Meter>,
+    host: HostCallback,
+    sections: Vec<(&'static str, Vec<Control>)>,
+    // reused for each frame's copy of the scope, and for the envelope's shape
+    scope: Vec<f32>,
+    envelope: Vec<f64>,
+    // while on, touching a control gets it ready to be mapped to the next midi CC
+    learn_mode: bool,
+    // the Scala file paths being typed in, and how loading the last ones went
+    scale_path: String,
+    mapping_path: String,
+    tuning_status: String,
+    // the wavetable file path being typed in, which loads on the loader thread
+    wavetable_path: String,
+}
+
+impl EditorApp {
+    fn new(params: Arc<ReplicantParameters>, meter: Arc<Meter>, host: HostCallback) -> EditorApp {
+        let waveforms = Waveform::ALL.iter().map(|waveform| waveform.name()).collect();
+        let sub_waveforms = SubWaveform::ALL.iter().map(|waveform| waveform.name()).collect();
+        let noise_colors = NoiseColor::ALL.iter().map(|color| color.name()).collect();
+        let filter_types = FilterType::ALL.iter().map(|filter_type| filter_type.name()).collect();
+        let stereo_modes = StereoMode::ALL.iter().map(|mode| mode.name()).collect();
+        let saturation_modes = Saturation::ALL.iter().map(|saturation| saturation.name()).collect();
+        let oversampling = Oversampling::ALL.iter().map(|oversampling| oversampling.name()).collect();
+        let envelope_modes: Vec<_> = EnvelopeMode::ALL.iter().map(|mode| mode.name()).collect();
+        let retrigger_modes = Retrigger::ALL.iter().map(|retrigger| retrigger.name()).collect();
+        let pan_modes = PanMode::ALL.iter().map(|mode| mode.name()).collect();
+        let arp_modes = ArpMode::ALL.iter().map(|mode| mode.name()).collect();
+        let divisions: Vec<_> = std::iter::once("Off")
+            .chain(NoteDivision::ALL.iter().map(|division| division.name()))
+            .collect();
+        let sections = vec![
+            ("Oscillator", vec![
+                Control::Choice(9, waveforms),
+                Control::Slider(123),
+                Control::Slider(91),
+                Control::Slider(59),
+                Control::Slider(0),
+                Control::Slider(60),
+                Control::Slider(1),
+                Control::Slider(2),
+                Control::Slider(78),
+                Control::Slider(79),
+                Control::Slider(96),
+                Control::Slider(105),
+                Control::Choice(106, oversampling),
+            ]),
+            ("Additive", (0..NUM_HARMONICS as i32).map(|harmonic| Control::Slider(124 + harmonic)).collect()),
+            ("Mixer", vec![
+                Control::Slider(85),
+                Control::Slider(92),
+                Control::Slider(86),
+                Control::Slider(87),
+                Control::Choice(88, sub_waveforms),
+                Control::Slider(89),
+                Control::Choice(90, noise_colors),
+            ]),
+            ("Envelope", vec![
+                Control::Choice(109, envelope_modes.clone()),
+                Control::Slider(5),
+                Control::Slider(108),
+                Control::Slider(6),
+                Control::Slider(7),
+                Control::Slider(8),
+                Control::Slider(61),
+                Control::Choice(107, retrigger_modes),
+                Control::Slider(132),
+                Control::Slider(133),
+            ]),
+            ("Filter", vec![
+                Control::Choice(18, filter_types),
+                Control::Slider(19),
+                Control::Slider(20),
+                Control::Slider(21),
+                Control::Choice(111, envelope_modes),
+                Control::Slider(22),
+                Control::Slider(110),
+                Control::Slider(23),
+                Control::Slider(24),
+                Control::Slider(25),
+                Control::Slider(62),
+            ]),
+            ("Output", vec![
+                Control::Slider(64),
+                Control::Slider(65),
+                Control::Choice(118, pan_modes),
+                Control::Slider(119),
+                Control::Choice(139, stereo_modes),
+                Control::Slider(66),
+                Control::Choice(67, saturation_modes),
+                Control::Slider(68),
+                Control::Slider(143),
+            ]),
+            ("Effects", vec![
+                Control::Slider(69),
+                Control::Slider(70),
+                Control::Slider(71),
+                Control::Slider(72),
+                Control::Choice(77, divisions.clone()),
+                Control::Slider(73),
+                Control::Slider(74),
+                Control::Slider(140),
+                Control::Slider(141),
+                Control::Slider(142),
+            ]),
+            ("Arpeggiator", vec![
+                Control::Slider(99),
+                Control::Choice(100, arp_modes),
+                Control::Slider(101),
+                Control::Slider(102),
+                Control::Choice(103, divisions),
+                Control::Slider(104),
+            ]),
+        ];
+        let files = params.tuning_files();
+        let (scale_path, mapping_path) = match &files {
+            Some(files) => (
+                files.scale.display().to_string(),
+                files.mapping.as_ref().map_or_else(String::new, |path| path.display().to_string()),
+            ),
+            None => (String::new(), String::new()),
+        };
+        let tuning_status = if files.is_some() { "Scala" } else { "12-TET" }.to_string();
+        let wavetable_path = params.wavetable_file().map_or_else(String::new, |path| path.display().to_string());
+        EditorApp {
+            params,
+            meter,
+            host,
+            sections,
+            scope: vec![0.0; SCOPE_LEN],
+            envelope: vec![0.0; ENVELOPE_POINTS],
+            learn_mode: false,
+            scale_path,
+            mapping_path,
+            tuning_status,
+            wavetable_path,
+        }
+    }
+
+    /// Set a parameter from the editor, and tell the host so it can record automation.
+    /// `continuing` joins it onto the last undo step, for a drag that's already started.
+    fn set_parameter(&self, index: i32, value: f32, continuing: bool) {
+        self.params.edit_parameter(index, value, continuing);
+        self.host.automate(index, self.params.get_parameter(index));
+    }
+
+    fn control(&self, ui: &mut egui::Ui, control: &Control) {
+        match *control {
+            Control::Slider(index) => {
+                self.label(ui, index);
+                self.slider(ui, index);
+            }
+            Control::Choice(index, ref options) => {
+                let steps = (options.len() - 1).max(1) as f32;
+                let mut selected = (self.params.get_parameter(index) * steps).round() as usize;
+                self.label(ui, index);
+                let response = ComboBox::from_id_salt(index)
+                    .selected_text(options.get(selected).copied().unwrap_or_default())
+                    .show_ui(ui, |ui| {
+                        for (option, name) in options.iter().enumerate() {
+                            ui.selectable_value(&mut selected, option, *name);
+                        }
+                    });
+                if response.inner.is_some() && selected as f32 / steps != self.params.get_parameter(index) {
+                    self.set_parameter(index, selected as f32 / steps, false);
+                }
+                if self.learn_mode && response.response.clicked() {
+                    self.params.midi_map.learn(Some(index));
+                }
+            }
+        }
+        ui.end_row();
+    }
+
+    /// A slider over the parameter's normalized range, showing its display text.
+    fn slider(&self, ui: &mut egui::Ui, index: i32) {
+        let mut value = self.params.get_parameter(index);
+        let text = format!(
+            "{} {}",
+            self.params.get_parameter_text(index),
+            self.params.get_parameter_label(index)
+        );
+        let response = ui.add(Slider::new(&mut value, 0.0..=1.0).show_value(false).text(text));
+        if response.changed() {
+            self.set_parameter(index, value, response.dragged() && !response.drag_started());
+        }
+        if self.learn_mode && (response.clicked() || response.drag_started()) {
+            self.params.midi_map.learn(Some(index));
+        }
+    }
+
+    /// A control's name, with the midi CC it's mapped to in learn mode.
+    fn label(&self, ui: &mut egui::Ui, index: i32) {
+        let name = self.params.get_parameter_name(index);
+        if !self.learn_mode {
+            ui.label(name);
+        } else if self.params.midi_map.learning() == Some(index) {
+            ui.strong(format!("{} [CC ?]", name));
+        } else if let Some(cc) = self.params.midi_map.cc(index) {
+            ui.label(format!("{} [CC {}]", name, cc));
+        } else {
+            ui.label(name);
+        }
+    }
+
+    /// Reset to the init patch, or make a random one. Either moves most of the parameters, so the
+    /// host is told to fetch them all again. Undo and redo go back through the editor's changes,
+    /// from the buttons or with Ctrl+Z and Ctrl+Shift+Z.
+    fn patch(&mut self, ui: &mut egui::Ui) {
+        // redo first, as the undo shortcut would take it too
+        let redo = KeyboardShortcut::new(Modifiers::COMMAND | Modifiers::SHIFT, Key::Z);
+        let redo = ui.input_mut(|input| input.consume_shortcut(&redo));
+        let undo = ui.input_mut(|input| input.consume_shortcut(&KeyboardShortcut::new(Modifiers::COMMAND, Key::Z)));
+        ui.horizontal(|ui| {
+            let undo = ui.add_enabled(self.params.can_undo(), Button::new("Undo")).clicked() || undo;
+            let redo = ui.add_enabled(self.params.can_redo(), Button::new("Redo")).clicked() || redo;
+            let moved = match (undo, redo) {
+                (true, _) => self.params.undo(),
+                (_, true) => self.params.redo(),
+                _ => Vec::new(),
+            };
+            for index in moved {
+                self.host.automate(index, self.params.get_parameter(index));
+            }
+            if ui.button("Init Patch").clicked() {
+                self.params.init_patch();
+                self.host.update_display();
+            }
+            if ui.button("Random Patch").clicked() {
+                self.params.randomize_patch();
+                self.host.update_display();
+            }
+        });
+    }
+
+    /// The A and B patches: store the current patch in either, go back to one to compare, or morph
+    /// between the two. Going back moves most of the parameters, so the host fetches them again.
+    fn ab(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            for slot in Slot::ALL {
+                if ui.button(format!("Store {}", slot.name())).clicked() {
+                    self.params.store_ab(slot);
+                }
+            }
+            for slot in Slot::ALL {
+                if ui.add_enabled(self.params.ab.is_stored(slot), Button::new(slot.name())).clicked() {
+                    self.params.recall_ab(slot);
+                    self.host.update_display();
+                }
+            }
+            self.label(ui, MORPH_PARAM);
+            self.slider(ui, MORPH_PARAM);
+        });
+    }
+
+    /// The midi learn switch. While it's on, touching a control and then moving a knob on the
+    /// midi controller maps one to the other.
+    fn midi_learn(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            if ui.toggle_value(&mut self.learn_mode, "MIDI Learn").changed() && !self.learn_mode {
+                self.params.midi_map.learn(None);
+            }
+            if !self.learn_mode {
+                return;
+            }
+            match self.params.midi_map.learning() {
+                Some(index) => {
+                    ui.label(format!("Move a controller for {}", self.params.get_parameter_name(index)));
+                    if ui.button("Forget").clicked() {
+                        self.params.midi_map.forget(index);
+                        self.params.midi_map.learn(None);
+                    }
+                }
+                None => {
+                    ui.label("Touch a control to map it");
+                }
+            }
+        });
+    }
+
+    /// The Scala scale and keyboard mapping files. An empty scale path goes back to 12-TET, and an
+    /// empty mapping path uses the default layout.
+    fn tuning(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label("Scale (.scl)");
+            ui.text_edit_singleline(&mut self.scale_path);
+            ui.label("Mapping (.kbm)");
+            ui.text_edit_singleline(&mut self.mapping_path);
+            if ui.button("Load").clicked() {
+                let scale = self.scale_path.trim();
+                let mapping = self.mapping_path.trim();
+                let files = Some(scale).filter(|scale| !scale.is_empty()).map(|scale| TuningFiles {
+                    scale: PathBuf::from(scale),
+                    mapping: Some(mapping).filter(|mapping| !mapping.is_empty()).map(PathBuf::from),
+                });
+                let loaded = files.is_some();
+                self.tuning_status = match self.params.set_tuning_files(files) {
+                    Ok(()) if loaded => "Scala".to_string(),
+                    Ok(()) => "12-TET".to_string(),
+                    Err(err) => format!("12-TET, couldn't load: {}", err),
+                };
+            }
+            ui.label(&self.tuning_status);
+        });
+    }
+
+    /// The wavetable file. An empty path goes back to the built in table.
+    fn wavetable(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label("Wavetable (.wav)");
+            ui.text_edit_singleline(&mut self.wavetable_path);
+            if ui.button("Load").clicked() {
+                let file = Some(self.wavetable_path.trim()).filter(|path| !path.is_empty()).map(PathBuf::from);
+                self.params.request_wavetable_file(file);
+            }
+            let status = match (self.params.wavetable_status(), self.params.wavetable_file()) {
+                (LoadStatus::Loading, _) => "Loading...".to_string(),
+                (LoadStatus::Loaded, Some(_)) => "Loaded".to_string(),
+                (LoadStatus::Loaded, None) => "Built in".to_string(),
+                (LoadStatus::Failed(err), _) => format!("Built in, couldn't load: {}", err),
+            };
+            ui.label(status);
+        });
+    }
+
+    /// The output level meters, and the oscilloscope beside the amp envelope's shape.
+    fn meters(&mut self, ui: &mut egui::Ui) {
+        let levels = self.meter.levels();
+        for (name, peak, rms) in [("L", levels.peak.0, levels.rms.0), ("R", levels.peak.1, levels.rms.1)] {
+            ui.horizontal(|ui| {
+                ui.label(name);
+                ui.add(ProgressBar::new(peak.min(1.0)).desired_height(6.0).text(format!("RMS {:.2}", rms)));
+            });
+        }
+
+        self.meter.scope(&mut self.scope);
+        let adsr = self.params.adsr();
+        // long enough to see the sustain level, in proportion with the rest
+        let sustain_time = (adsr.attack + adsr.hold + adsr.decay + adsr.release) / 4.0;
+        sample_shape(&adsr, sustain_time, &mut self.envelope);
+        ui.horizontal(|ui| {
+            let width = (ui.available_width() - ui.spacing().item_spacing.x) / 2.0;
+            plot(ui, width, self.scope.iter().copied());
+            plot(ui, width, self.envelope.iter().map(|&level| level as f32 * 2.0 - 1.0));
+        });
+    }
+}
+
+/// Draw a line through `values`, evenly spaced across a box `width` wide, from -1.0 at the bottom
+/// to 1.0 at the top.
+fn plot(ui: &mut egui::Ui, width: f32, values: impl ExactSizeIterator<Item = f32>) {
+    let (rect, _) = ui.allocate_exact_size(egui::vec2(width, 80.0), Sense::hover());
+    let step = rect.width() / (values.len().max(2) - 1) as f32;
+    let points = values.enumerate()
+        .map(|(i, value)| egui::pos2(rect.left() + i as f32 * step, rect.center().y - value.clamp(-1.0, 1.0) * rect.height() / 2.0))
+        .collect();
+    ui.painter().rect_filled(rect, 2.0, ui.visuals().extreme_bg_color);
+    ui.painter().add(Shape::line(points, Stroke::new(1.0, ui.visuals().text_color())));
+}
+
+impl egui_baseview::App for EditorApp {
+    fn ui(&mut self, ui: &mut egui::Ui, _frame: &mut Frame) {
+        Panel::bottom("meters").show(ui, |ui| {
+            self.patch(ui);
+            self.ab(ui);
+            self.midi_learn(ui);
+            self.tuning(ui);
+            self.wavetable(ui);
+            self.meters(ui);
+        });
+        CentralPanel::default().show(ui, |ui| {
+            ui.columns(self.sections.len(), |columns| {
+                for ((title, controls), ui) in self.sections.iter().zip(columns) {
+                    ui.heading(*title);
+                    egui::Grid::new(title).num_columns(2).show(ui, |ui| {
+                        for control in controls {
+                            self.control(ui, control);
+                        }
+                    });
+                }
+            });
+        });
+        ui.ctx().request_repaint_after(REFRESH_INTERVAL);
+    }
+}
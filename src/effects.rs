@@ -0,0 +1,383 @@
+use crate::denormal::flush_denormal;
+use crate::params::{note_division, ReplicantParameters};
+use crate::reverb::Reverb;
+use crate::smoothing::SmoothedParam;
+use crate::tempo::Transport;
+use crate::TAU;
+
+/// The longest delay time, in seconds.
+pub const MAX_DELAY_TIME: f64 = 2.0;
+
+/// The chorus delay at the centre of its sweep, and the furthest it sweeps either side at full
+/// depth, in seconds.
+const CHORUS_DELAY: f64 = 0.012;
+const MAX_CHORUS_SWEEP: f64 = 0.005;
+
+/// How long delay time changes take to glide, in seconds. The echoes bend in pitch while it
+/// glides, so this is slower than other parameters.
+const DELAY_TIME_SMOOTHING: f64 = 0.1;
+
+/// The most delay feedback allowed, so the echoes always die away.
+pub const MAX_DELAY_FEEDBACK: f64 = 0.95;
+
+/// How quiet an echo has to be before the delay's tail counts as over: -80 dB.
+const TAIL_LEVEL: f64 = 1e-4;
+
+/// A stereo effect on the synth's output.
+pub trait Effect: Send {
+    /// Read the effect's settings, and the host tempo for anything synced to it. Called once per
+    /// buffer.
+    fn update(&mut self, params: &ReplicantParameters, transport: &Transport);
+
+    /// Prepare for a new sample rate. This may allocate, so it mustn't be called from
+    /// `process()`.
+    fn set_time_per_sample(&mut self, time_per_sample: f64);
+
+    /// Forget everything heard so far, silencing any tail.
+    fn reset(&mut self);
+
+    /// How long the effect rings on once its input falls silent, in seconds, with these
+    /// settings.
+    fn tail(&self, params: &ReplicantParameters, transport: &Transport) -> f64;
+
+    /// Process one sample, returning the left and right outputs.
+    fn process(&mut self, left: f64, right: f64) -> (f64, f64);
+}
+
+/// Effects applied one after another, in order.
+pub struct EffectsChain {
+    effects: Vec<Box<dyn Effect>>,
+}
+
+impl EffectsChain {
+    /// The built in chain: chorus, then delay, then reverb.
+    pub fn new(time_per_sample: f64) -> EffectsChain {
+        EffectsChain {
+            effects: vec![
+                Box::new(Chorus::new(time_per_sample)),
+                Box::new(Delay::new(time_per_sample)),
+                Box::new(Reverb::new(time_per_sample)),
+            ],
+        }
+    }
+}
+
+impl Effect for EffectsChain {
+    fn update(&mut self, params: &ReplicantParameters, transport: &Transport) {
+        for effect in &mut self.effects {
+            effect.update(params, transport);
+        }
+    }
+
+    fn set_time_per_sample(&mut self, time_per_sample: f64) {
+        for effect in &mut self.effects {
+            effect.set_time_per_sample(time_per_sample);
+        }
+    }
+
+    fn reset(&mut self) {
+        for effect in &mut self.effects {
+            effect.reset();
+        }
+    }
+
+    fn tail(&self, params: &ReplicantParameters, transport: &Transport) -> f64 {
+        self.effects.iter().map(|effect| effect.tail(params, transport)).sum()
+    }
+
+    fn process(&mut self, left: f64, right: f64) -> (f64, f64) {
+        self.effects.iter_mut().fold((left, right), |(left, right), effect| effect.process(left, right))
+    }
+}
+
+/// A ring buffer of past samples, read back at fractional delays.
+#[derive(Debug)]
+pub(crate) struct DelayLine {
+    buffer: Vec<f64>,
+    // where the next sample is written, which is also the oldest one
+    write_pos: usize,
+}
+
+impl DelayLine {
+    /// A delay line able to hold `max_delay` seconds.
+    pub(crate) fn new(max_delay: f64, time_per_sample: f64) -> DelayLine {
+        // room for interpolating past the longest delay
+        let len = (max_delay / time_per_sample).ceil() as usize + 2;
+        DelayLine { buffer: vec![0.0; len], write_pos: 0 }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.buffer.iter_mut().for_each(|sample| *sample = 0.0);
+    }
+
+    /// The input from `delay` samples ago, interpolated linearly. The delay is kept to at least
+    /// one sample, and no longer than the line holds.
+    pub(crate) fn read(&self, delay: f64) -> f64 {
+        let len = self.buffer.len();
+        let delay = delay.clamp(1.0, (len - 2) as f64);
+        let pos = self.write_pos as f64 + len as f64 - delay;
+        let index = pos.floor();
+        let frac = pos - index;
+        let a = self.buffer[index as usize % len];
+        let b = self.buffer[(index as usize + 1) % len];
+        a + (b - a) * frac
+    }
+
+    pub(crate) fn write(&mut self, sample: f64) {
+        self.buffer[self.write_pos] = sample;
+        self.write_pos = (self.write_pos + 1) % self.buffer.len();
+    }
+}
+
+/// Thickens the sound by mixing in a copy whose delay is swept by an LFO. The channels are swept
+/// a quarter cycle apart, for width.
+pub struct Chorus {
+    lines: [DelayLine; 2],
+    // in cycles, 0.0 to 1.0
+    phase: f64,
+    time_per_sample: f64,
+    /// Sweep rate, in Hz.
+    rate: f64,
+    /// How far the delay is swept, 0.0 to 1.0.
+    depth: f64,
+    /// How much of the swept copy is heard, 0.0 (dry) to 1.0 (wet).
+    mix: f64,
+}
+
+impl Chorus {
+    pub fn new(time_per_sample: f64) -> Chorus {
+        let max_delay = CHORUS_DELAY + MAX_CHORUS_SWEEP;
+        Chorus {
+            lines: [DelayLine::new(max_delay, time_per_sample), DelayLine::new(max_delay, time_per_sample)],
+            phase: 0.0,
+            time_per_sample,
+            rate: 0.0,
+            depth: 0.0,
+            mix: 0.0,
+        }
+    }
+}
+
+impl Effect for Chorus {
+    fn update(&mut self, params: &ReplicantParameters, _transport: &Transport) {
+        self.rate = f64::from(params.chorus_rate.get());
+        self.depth = f64::from(params.chorus_depth.get()).clamp(0.0, 1.0);
+        self.mix = f64::from(params.chorus_mix.get()).clamp(0.0, 1.0);
+    }
+
+    fn set_time_per_sample(&mut self, time_per_sample: f64) {
+        *self = Chorus { rate: self.rate, depth: self.depth, mix: self.mix, ..Chorus::new(time_per_sample) };
+    }
+
+    fn reset(&mut self) {
+        self.lines.iter_mut().for_each(DelayLine::clear);
+        self.phase = 0.0;
+    }
+
+    fn tail(&self, params: &ReplicantParameters, _transport: &Transport) -> f64 {
+        if params.chorus_mix.get() > 0.0 { CHORUS_DELAY + MAX_CHORUS_SWEEP } else { 0.0 }
+    }
+
+    fn process(&mut self, left: f64, right: f64) -> (f64, f64) {
+        let sweep = self.depth * MAX_CHORUS_SWEEP;
+        let mut output = [left, right];
+        for (channel, (line, sample)) in self.lines.iter_mut().zip(&mut output).enumerate() {
+            let lfo = ((self.phase + channel as f64 * 0.25) * TAU).sin();
+            let wet = line.read((CHORUS_DELAY + sweep * lfo) / self.time_per_sample);
+            line.write(*sample);
+            *sample += (wet - *sample) * self.mix;
+        }
+        self.phase = (self.phase + self.rate * self.time_per_sample).fract();
+        (output[0], output[1])
+    }
+}
+
+/// A stereo echo, with each channel fed back into itself.
+pub struct Delay {
+    lines: [DelayLine; 2],
+    time_per_sample: f64,
+    /// The delay time, in seconds.
+    time: SmoothedParam,
+    /// How much of each echo is fed back into the next, 0.0 up to `MAX_DELAY_FEEDBACK`.
+    feedback: f64,
+    /// How loud the echoes are, 0.0 to 1.0, mixed on top of the dry signal.
+    mix: f64,
+    // how long the lines have been fed nothing but silence, in samples
+    silent_samples: usize,
+}
+
+impl Delay {
+    pub fn new(time_per_sample: f64) -> Delay {
+        let line = || DelayLine::new(MAX_DELAY_TIME, time_per_sample);
+        let lines = [line(), line()];
+        Delay {
+            silent_samples: lines[0].buffer.len(),
+            lines,
+            time_per_sample,
+            time: SmoothedParam::new(0.0, DELAY_TIME_SMOOTHING, time_per_sample),
+            feedback: 0.0,
+            mix: 0.0,
+        }
+    }
+
+    /// Whether the lines hold nothing but silence, so the delay time can change without being
+    /// heard.
+    fn is_silent(&self) -> bool {
+        self.silent_samples >= self.lines[0].buffer.len()
+    }
+}
+
+/// The delay time in seconds, from the tempo if it's synced.
+fn delay_time(params: &ReplicantParameters, transport: &Transport) -> f64 {
+    let time = match note_division(&params.delay_sync) {
+        Some(division) => division.seconds(transport.tempo),
+        None => f64::from(params.delay_time.get()),
+    };
+    time.clamp(0.0, MAX_DELAY_TIME)
+}
+
+impl Effect for Delay {
+    fn update(&mut self, params: &ReplicantParameters, transport: &Transport) {
+        let time = delay_time(params, transport);
+        if self.is_silent() {
+            self.time.reset(time);
+        } else {
+            self.time.set_target(time);
+        }
+        self.feedback = f64::from(params.delay_feedback.get()).clamp(0.0, MAX_DELAY_FEEDBACK);
+        self.mix = f64::from(params.delay_mix.get()).clamp(0.0, 1.0);
+    }
+
+    fn set_time_per_sample(&mut self, time_per_sample: f64) {
+        let mut time = self.time;
+        time.set_time_per_sample(time_per_sample);
+        *self = Delay { time, feedback: self.feedback, mix: self.mix, ..Delay::new(time_per_sample) };
+    }
+
+    fn reset(&mut self) {
+        self.lines.iter_mut().for_each(DelayLine::clear);
+        self.silent_samples = self.lines[0].buffer.len();
+    }
+
+    fn tail(&self, params: &ReplicantParameters, transport: &Transport) -> f64 {
+        let mix = f64::from(params.delay_mix.get()).clamp(0.0, 1.0);
+        if mix <= TAIL_LEVEL {
+            return 0.0;
+        }
+        // each echo is quieter than the last by the feedback, so count those above TAIL_LEVEL
+        let feedback = f64::from(params.delay_feedback.get()).clamp(0.0, MAX_DELAY_FEEDBACK);
+        let echoes = if feedback > 0.0 { 1.0 + ((TAIL_LEVEL / mix).ln() / feedback.ln()).floor() } else { 1.0 };
+        delay_time(params, transport) * echoes
+    }
+
+    fn process(&mut self, left: f64, right: f64) -> (f64, f64) {
+        let delay = self.time.next() / self.time_per_sample;
+        let mut output = [left, right];
+        let mut silent = true;
+        for (line, sample) in self.lines.iter_mut().zip(&mut output) {
+            let echo = line.read(delay);
+            let input = flush_denormal(*sample + echo * self.feedback);
+            line.write(input);
+            silent &= input == 0.0;
+            *sample += echo * self.mix;
+        }
+        self.silent_samples = if silent { self.silent_samples.saturating_add(1) } else { 0 };
+        (output[0], output[1])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tempo::NoteDivision;
+
+    const TIME_PER_SAMPLE: f64 = 1.0 / 1000.0;
+
+    #[test]
+    fn test_delay_line_interpolates() {
+        let mut line = DelayLine::new(0.01, TIME_PER_SAMPLE);
+        for i in 0..20 {
+            line.write(f64::from(i));
+        }
+        assert_eq!(line.read(1.0), 19.0);
+        assert_eq!(line.read(3.0), 17.0);
+        assert_eq!(line.read(2.5), 17.5);
+    }
+
+    #[test]
+    fn test_delay_echoes_and_dies_away() {
+        let params = ReplicantParameters::init();
+        params.delay_time.set(0.01);
+        params.delay_feedback.set(0.5);
+        params.delay_mix.set(1.0);
+
+        let mut delay = Delay::new(TIME_PER_SAMPLE);
+        delay.update(&params, &Transport::default());
+        let output: Vec<f64> = (0..40).map(|i| delay.process(if i == 0 { 1.0 } else { 0.0 }, 0.0).0).collect();
+
+        // an impulse repeats every 10 samples, halving each time. the f32 parameters make the
+        // delay a hair off 10 samples.
+        for &(i, expected) in &[(0, 1.0), (10, 1.0), (20, 0.5), (30, 0.25), (5, 0.0)] {
+            assert!((output[i] - expected).abs() < 1e-5, "sample {} was {}", i, output[i]);
+        }
+    }
+
+    #[test]
+    fn test_delay_tail_lasts_until_the_echoes_fade() {
+        let params = ReplicantParameters::init();
+        let transport = Transport::default();
+        params.delay_time.set(0.01);
+        params.delay_feedback.set(0.5);
+        assert_eq!(Delay::new(TIME_PER_SAMPLE).tail(&params, &transport), 0.0);
+
+        params.delay_mix.set(0.5);
+        let mut delay = Delay::new(TIME_PER_SAMPLE);
+        delay.update(&params, &transport);
+        let tail = (delay.tail(&params, &transport) / TIME_PER_SAMPLE).round() as usize;
+        let output: Vec<f64> = (0..tail + 20).map(|i| delay.process(if i == 0 { 1.0 } else { 0.0 }, 0.0).0).collect();
+        // the last echo in the tail is still above the threshold, and everything after is below it
+        assert!(output[tail - 10..=tail].iter().any(|&sample| sample >= TAIL_LEVEL));
+        assert!(output[tail + 1..].iter().all(|&sample| sample.abs() < TAIL_LEVEL));
+    }
+
+    #[test]
+    fn test_synced_delay_follows_tempo() {
+        let params = ReplicantParameters::init();
+        let eighth = NoteDivision::ALL.iter().position(|&d| d == NoteDivision::Eighth).unwrap();
+        params.delay_sync.set((eighth + 1) as f32);
+
+        let mut delay = Delay::new(TIME_PER_SAMPLE);
+        delay.update(&params, &Transport { tempo: 150.0, position: None });
+        assert_eq!(delay.time.get(), 0.2);
+        // too slow to fit, so it's as long as it can be
+        delay.reset();
+        delay.update(&params, &Transport { tempo: 10.0, position: None });
+        assert_eq!(delay.time.get(), MAX_DELAY_TIME);
+    }
+
+    #[test]
+    fn test_dry_chain_passes_through() {
+        let params = ReplicantParameters::init();
+        let mut chain = EffectsChain::new(TIME_PER_SAMPLE);
+        chain.update(&params, &Transport::default());
+        for i in 0..100 {
+            let input = (f64::from(i) * 0.3).sin();
+            assert_eq!(chain.process(input, -input), (input, -input));
+        }
+    }
+
+    #[test]
+    fn test_chorus_mixes_in_delayed_copy() {
+        let params = ReplicantParameters::init();
+        params.chorus_mix.set(1.0);
+        params.chorus_depth.set(0.0);
+
+        // fully wet with no sweep, it's a plain delay
+        let mut chorus = Chorus::new(TIME_PER_SAMPLE);
+        chorus.update(&params, &Transport::default());
+        let delay = (CHORUS_DELAY / TIME_PER_SAMPLE).round() as usize;
+        let output: Vec<f64> = (0..50).map(|i| chorus.process(if i == 0 { 1.0 } else { 0.0 }, 0.0).0).collect();
+        assert!((output[delay] - 1.0).abs() < 1e-9);
+        assert_eq!(output[0], 0.0);
+    }
+}
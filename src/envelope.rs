@@ -1,152 +1,717 @@
-use std::fmt;
-use vst::util::AtomicFloat;
-use std::sync::Arc;
-
-#[derive(PartialEq, Debug)]
-pub enum ADSRPhase {
-    Attack,
-    Decay,
-    Sustain,
-    Release,
-}
-
-#[derive(Debug)]
-pub struct ADSREnvelope {
-    pub current_phase: ADSRPhase,
-    pub phase_elapsed: f64,
-
-    note_on_volume: f64,
-    note_off_volume: f64,
-}
-
-impl fmt::Debug for ADSRParams {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let (a,d,s,r) = (self.attack.get(), self.decay.get(), self.sustain.get(), self.release.get());
-        write!(f, "ADSRParams({}, {}, {}, {})", a,d,s,r)
-    }
-}
-
-impl ADSRParams {
-    fn new(attack: f32, decay: f32, sustain: f32, release: f32) -> ADSRParams {
-        ADSRParams {
-            attack: AtomicFloat::new(attack),
-            decay: AtomicFloat::new(decay),
-            sustain: AtomicFloat::new(sustain), // 0.0 to 1.0
-            release: AtomicFloat::new(release),
-        }
-    }
-}
-
-impl ADSREnvelope {
-    pub fn new() -> ADSREnvelope {
-        let (attack, decay, sustain, release) = (0.0005, 0.0005, 1.0, 0.0005);
-
-        ADSREnvelope {
-
-            // we begin at the "end of the release phase" - nothing plays.
-            current_phase: ADSRPhase::Release,
-            phase_elapsed: release.into(),
-
-            note_on_volume: 0.0,
-
-            // todo: Arc
-            params: Arc::new(ADSRParams::new(attack, decay, sustain, release)),
-
-            // this shouldn't be used before being set by note_off()
-            note_off_volume: sustain.into(),
-        }
-    }
-
-    pub fn note_on(&mut self, note_on_volume: f64) {
-        // note_on_volume exists for the case where there is still audio playing - we don't want to
-        // jump to 0 and click, we want to maintain the current volume
-        self.note_on_volume = note_on_volume;
-        self.current_phase = ADSRPhase::Attack;
-        self.phase_elapsed = 0.0;
-    }
-
-    pub fn note_off(&mut self) {
-        // if we're in the sustain phase, note_off_volume is just the sustain 
-        // level. if we're in the attack or decay phase, during release we'll 
-        // interpolate down from note_off_volume instead.
-        self.note_off_volume = self.alpha();
-        self.current_phase = ADSRPhase::Release;
-        self.phase_elapsed = 0.0;
-    }
-
-    pub fn inc_timer(&mut self, dt: f64, attack: f64, decay: f64, sustain: f64, release: f64) {
-        self.phase_elapsed += dt;
-
-        // TODO potential bug if dt exceeds the duration of a phase
-        if self.current_phase == ADSRPhase::Attack {
-            if self.phase_elapsed > params.attack.get().into() {
-                self.current_phase = ADSRPhase::Decay;
-                let attack: f64 = params.attack.get().into();
-                self.phase_elapsed %= attack;
-            }
-        }
-
-        // theoretically, could go straight from attack to sustain in one 
-        // inc_time() call if dt is large
-        if self.current_phase == ADSRPhase::Decay {
-            if self.phase_elapsed > params.decay.get().into() {
-                self.current_phase = ADSRPhase::Sustain;
-                let decay: f64 = params.decay.get().into();
-                self.phase_elapsed %= decay;
-            }
-        }
-
-        // don't need to do anything for sustain or release
-    }
-
-    // for now we just lerp. TODO: learn decibels and best curve shapes
-    pub fn alpha(&self, params: &ReplicantParameters) -> f64 {
-        match self.current_phase {
-            ADSRPhase::Attack  => {
-                let attack: f64 = params.attack.get().into();
-                lerp(self.note_on_volume, 1.0, self.phase_elapsed / attack)
-            },
-            ADSRPhase::Decay   => {
-                let decay: f64 = params.decay.get().into();
-                let sustain: f64 = params.sustain.get().into();
-                lerp_down(1.0, sustain, self.phase_elapsed / decay)
-            },
-            ADSRPhase::Sustain => params.sustain.get().into(),
-            ADSRPhase::Release => {
-                let release: f64 = params.release.get().into();
-                let alpha = lerp_down(self.note_off_volume,
-                                      0.0,
-                                      self.phase_elapsed / release);
-                
-                // if phase_elapsed is longer than release, clamp to 0 rather than returning a
-                // negative value
-                clamp(0.0, alpha, 1.0)
-                // don't need to do this for other phases, as inc_timer should ensure a phase
-                // transition and reset of phase_elapsed whenever the phase_elapsed exceeds that
-                // phase's length.
-            },
-
-        }
-    }
-}
-
-fn clamp(a: f64, x: f64, b: f64) -> f64 {
-    a.max(x.min(b))
-}
-
-// the lerp functions will return values outside a..b for t outside 0..1
-fn lerp(a: f64, b:f64, t:f64) -> f64 {
-    let result = a + (b - a) * t;
-    result
-}
-
-fn lerp_down(b: f64, a:f64, t:f64) -> f64 {
-    let result = b - (b - a) * t;
-    result
-}
-
-#[derive(PartialEq)]
-pub enum IsDone {
-    Continue,
-    Done
-}
+/// How strongly a curve of 1.0 bends the envelope stages.
+const MAX_CURVATURE: f64 = 6.0;
+
+#[derive(PartialEq, Debug)]
+pub enum ADSRPhase {
+    Attack,
+    /// Staying at the peak, between the attack and the decay.
+    Hold,
+    Decay,
+    Sustain,
+    Release,
+}
+
+#[derive(Debug)]
+pub struct ADSREnvelope {
+    pub current_phase: ADSRPhase,
+    pub phase_elapsed: f64,
+
+    note_on_volume: f64,
+    note_off_volume: f64,
+    // the release time the release is running at, which only follows the setting as the timer
+    // moves on, so the level never jumps
+    release: f64,
+}
+
+/// Whether an envelope waits for the note off.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EnvelopeMode {
+    /// Attack, hold and decay, then sustain until the note off, then release.
+    Sustain,
+    /// Attack, hold and decay all the way to silence, ignoring the note off, as for percussion.
+    OneShot,
+}
+
+impl EnvelopeMode {
+    pub const ALL: [EnvelopeMode; 2] = [EnvelopeMode::Sustain, EnvelopeMode::OneShot];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            EnvelopeMode::Sustain => "Sustain",
+            EnvelopeMode::OneShot => "One Shot",
+        }
+    }
+}
+
+/// A snapshot of the envelope settings, read from the shared plugin parameters once per buffer.
+///
+/// Times are in seconds, sustain is a level from 0.0 to 1.0.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ADSRParams {
+    pub attack: f64,
+    /// How long the level stays at the peak before the decay.
+    pub hold: f64,
+    pub decay: f64,
+    pub sustain: f64,
+    pub release: f64,
+    /// The shape of every stage, from -1.0 to 1.0. At 0.0 the level moves linearly. Above that it
+    /// moves quickly at first and eases into its target, like an analog envelope; below, it starts
+    /// slowly and speeds up.
+    pub curve: f64,
+    pub mode: EnvelopeMode,
+}
+
+impl ADSRParams {
+    /// Where the decay falls to.
+    fn decay_target(&self) -> f64 {
+        match self.mode {
+            EnvelopeMode::Sustain => self.sustain,
+            EnvelopeMode::OneShot => 0.0,
+        }
+    }
+}
+
+impl ADSREnvelope {
+    pub fn new() -> ADSREnvelope {
+        ADSREnvelope {
+
+            // we begin at the "end of the release phase" from silence - nothing plays.
+            current_phase: ADSRPhase::Release,
+            phase_elapsed: 0.0,
+
+            note_on_volume: 0.0,
+
+            // set by note_off()
+            note_off_volume: 0.0,
+            release: 0.0,
+        }
+    }
+
+    pub fn note_on(&mut self, note_on_volume: f64) {
+        // note_on_volume exists for the case where there is still audio playing - we don't want to
+        // jump to 0 and click, we want to maintain the current volume
+        self.note_on_volume = note_on_volume;
+        self.current_phase = ADSRPhase::Attack;
+        self.phase_elapsed = 0.0;
+    }
+
+    pub fn note_off(&mut self, params: &ADSRParams) {
+        // a one shot envelope plays out the same however long the note is held
+        if params.mode == EnvelopeMode::OneShot {
+            return;
+        }
+        // the release falls from wherever the level is, whichever phase it's in, even another
+        // release. the sustain level is only where it starts from if the note got that far.
+        self.note_off_volume = self.alpha(params);
+        self.current_phase = ADSRPhase::Release;
+        self.phase_elapsed = 0.0;
+        self.release = params.release;
+    }
+
+    /// Move the envelope on by `dt` seconds. Returns `IsDone::Done` once the release has fully
+    /// elapsed, or a one shot envelope's decay, and the envelope will stay silent until the next
+    /// note on.
+    pub fn inc_timer(&mut self, dt: f64, params: &ADSRParams) -> IsDone {
+        self.phase_elapsed += dt;
+
+        // a large dt can carry us through several phases at once, with whatever's left over
+        // counting towards the next one
+        loop {
+            let (duration, next_phase) = match self.current_phase {
+                ADSRPhase::Attack => (params.attack, ADSRPhase::Hold),
+                ADSRPhase::Hold => (params.hold, ADSRPhase::Decay),
+                // a one shot envelope has decayed to silence, and is finished as if released
+                ADSRPhase::Decay if params.mode == EnvelopeMode::OneShot && self.phase_elapsed > params.decay => {
+                    self.note_off_volume = 0.0;
+                    self.current_phase = ADSRPhase::Release;
+                    self.phase_elapsed = params.release;
+                    self.release = params.release;
+                    return IsDone::Done;
+                }
+                ADSRPhase::Decay => (params.decay, ADSRPhase::Sustain),
+                // sustain lasts until note off, and release until it's silent
+                ADSRPhase::Sustain => return IsDone::Continue,
+                ADSRPhase::Release => {
+                    self.follow_release(params.release);
+                    return if self.phase_elapsed >= self.release { IsDone::Done } else { IsDone::Continue };
+                }
+            };
+            if self.phase_elapsed <= duration {
+                return IsDone::Continue;
+            }
+            self.phase_elapsed -= duration;
+            self.current_phase = next_phase;
+        }
+    }
+
+    /// Change the release time mid-release, keeping how far through it the envelope is, so the
+    /// level carries on from where it was rather than jumping.
+    fn follow_release(&mut self, release: f64) {
+        if release != self.release {
+            self.phase_elapsed = progress(self.phase_elapsed, self.release).min(1.0) * release;
+            self.release = release;
+        }
+    }
+
+    /// Fill `out` with the level at each of the next `out.len()` samples, `dt` seconds apart,
+    /// moving the envelope on past them. The same as calling `alpha()` then `inc_timer()` for
+    /// each sample, returning whether the envelope had finished by the end of the block.
+    pub fn process_block(&mut self, dt: f64, params: &ADSRParams, out: &mut [f64]) -> IsDone {
+        let mut done = IsDone::Continue;
+        for alpha in out {
+            *alpha = self.alpha(params);
+            done = self.inc_timer(dt, params);
+        }
+        done
+    }
+
+    pub fn alpha(&self, params: &ADSRParams) -> f64 {
+        let shaped = |duration| shape(progress(self.phase_elapsed, duration), params.curve);
+        match self.current_phase {
+            ADSRPhase::Attack  => lerp(self.note_on_volume, 1.0, shaped(params.attack)),
+            ADSRPhase::Hold    => 1.0,
+            ADSRPhase::Decay   => lerp_down(1.0, params.decay_target(), shaped(params.decay)),
+            ADSRPhase::Sustain => params.sustain,
+            ADSRPhase::Release => {
+                let alpha = lerp_down(self.note_off_volume, 0.0, shaped(self.release));
+                
+                // if phase_elapsed is longer than release, clamp to 0 rather than returning a
+                // negative value
+                clamp(0.0, alpha, 1.0)
+                // don't need to do this for other phases, as inc_timer should ensure a phase
+                // transition and reset of phase_elapsed whenever the phase_elapsed exceeds that
+                // phase's length.
+            },
+
+        }
+    }
+}
+
+/// Sample the envelope's whole shape from a note on at silence into `out`, at evenly spaced times
+/// from the note on to the end of the release, holding the sustain for `sustain_time` seconds in
+/// between. One shot envelopes end with their decay. The envelope is run just as it is for a
+/// voice, so what's drawn is what plays.
+#[cfg_attr(not(feature = "gui"), allow(dead_code))]
+pub fn sample_shape(params: &ADSRParams, sustain_time: f64, out: &mut [f64]) {
+    let (note_off, release) = match params.mode {
+        EnvelopeMode::Sustain => (params.attack + params.hold + params.decay + sustain_time.max(0.0), params.release),
+        EnvelopeMode::OneShot => (params.attack + params.hold + params.decay, 0.0),
+    };
+    let dt = (note_off + release) / out.len().saturating_sub(1).max(1) as f64;
+    let mut env = ADSREnvelope::new();
+    env.note_on(0.0);
+    let mut elapsed = 0.0;
+    let mut released = false;
+    for (i, level) in out.iter_mut().enumerate() {
+        let time = i as f64 * dt;
+        if !released && time >= note_off {
+            env.inc_timer(note_off - elapsed, params);
+            env.note_off(params);
+            elapsed = note_off;
+            released = true;
+        }
+        env.inc_timer(time - elapsed, params);
+        elapsed = time;
+        *level = env.alpha(params);
+    }
+}
+
+/// How far through a phase of the given duration we are, where 1.0 is the end of the phase.
+///
+/// A zero-length phase is instantaneous: we're always at its end, rather than dividing by zero.
+fn progress(elapsed: f64, duration: f64) -> f64 {
+    if duration > 0.0 {
+        elapsed / duration
+    } else {
+        1.0
+    }
+}
+
+/// Bend a stage's progress into how far the level has moved towards the stage's target, by the
+/// envelope's curve. Progress from 0.0 to 1.0 always maps onto 0.0 to 1.0, rising throughout.
+pub(crate) fn shape(progress: f64, curve: f64) -> f64 {
+    let t = clamp(0.0, progress, 1.0);
+    let k = curve * MAX_CURVATURE;
+    // the exponential below approaches linear as k goes to 0, but divides by 0 on the way
+    if k.abs() < 1e-6 {
+        t
+    } else {
+        (1.0 - (-k * t).exp()) / (1.0 - (-k).exp())
+    }
+}
+
+fn clamp(a: f64, x: f64, b: f64) -> f64 {
+    a.max(x.min(b))
+}
+
+// the lerp functions will return values outside a..b for t outside 0..1
+fn lerp(a: f64, b:f64, t:f64) -> f64 {
+    a + (b - a) * t
+}
+
+fn lerp_down(b: f64, a:f64, t:f64) -> f64 {
+    b - (b - a) * t
+}
+
+/// Whether an envelope has finished its release.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IsDone {
+    Continue,
+    Done
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INSTANT: ADSRParams = ADSRParams {
+        attack: 0.0,
+        hold: 0.0,
+        decay: 0.0,
+        sustain: 0.5,
+        release: 0.0,
+        curve: 0.0,
+        mode: EnvelopeMode::Sustain,
+    };
+    const CURVES: [f64; 5] = [-1.0, -0.5, 0.0, 0.5, 1.0];
+
+    #[test]
+    fn test_shape_endpoints_and_monotonicity() {
+        for &curve in &CURVES {
+            assert_eq!(shape(0.0, curve), 0.0);
+            assert!((shape(1.0, curve) - 1.0).abs() < 1e-12);
+            let steps: Vec<f64> = (0..=100).map(|i| shape(f64::from(i) / 100.0, curve)).collect();
+            assert!(steps.windows(2).all(|w| w[1] > w[0]), "curve {} isn't rising", curve);
+        }
+
+        // positive curves move fastest at the start, negative ones at the end
+        assert!(shape(0.5, 0.5) > 0.5);
+        assert!(shape(0.5, -0.5) < 0.5);
+    }
+
+    #[test]
+    fn test_curved_stages_are_monotonic() {
+        for &curve in &CURVES {
+            let params = ADSRParams {
+                attack: 0.1, hold: 0.0, decay: 0.1, sustain: 0.5,
+                release: 0.1, curve, mode: EnvelopeMode::Sustain,
+            };
+            let mut env = ADSREnvelope::new();
+            env.note_on(0.0);
+            assert_eq!(env.alpha(&params), 0.0);
+
+            // attack rises to the peak, then decay falls to the sustain level
+            let mut levels = vec![];
+            while env.current_phase != ADSRPhase::Sustain {
+                levels.push(env.alpha(&params));
+                env.inc_timer(0.001, &params);
+            }
+            let peak = levels.iter().cloned().fold(0.0, f64::max);
+            let peak = levels.iter().position(|&level| level == peak).unwrap();
+            assert!(levels[..=peak].windows(2).all(|w| w[1] >= w[0]));
+            assert!(levels[peak..].windows(2).all(|w| w[1] <= w[0]));
+            assert!((levels[peak] - 1.0).abs() < 0.01);
+            assert_eq!(env.alpha(&params), 0.5);
+
+            // release falls from the sustain level to silence
+            env.note_off(&params);
+            let mut previous = env.alpha(&params);
+            assert_eq!(previous, 0.5);
+            for _ in 0..150 {
+                env.inc_timer(0.001, &params);
+                let level = env.alpha(&params);
+                assert!(level <= previous);
+                previous = level;
+            }
+            assert_eq!(previous, 0.0);
+        }
+    }
+
+    #[test]
+    fn test_zero_length_stages_jump_to_target() {
+        let mut env = ADSREnvelope::new();
+        assert_eq!(env.alpha(&INSTANT), 0.0);
+
+        env.note_on(0.0);
+        assert_eq!(env.alpha(&INSTANT), 1.0);
+
+        env.inc_timer(0.001, &INSTANT);
+        assert_eq!(env.current_phase, ADSRPhase::Sustain);
+        assert_eq!(env.alpha(&INSTANT), 0.5);
+
+        env.note_off(&INSTANT);
+        assert_eq!(env.alpha(&INSTANT), 0.0);
+        assert_eq!(env.inc_timer(0.001, &INSTANT), IsDone::Done);
+        assert_eq!(env.alpha(&INSTANT), 0.0);
+    }
+
+    #[test]
+    fn test_done_once_release_elapses() {
+        let params = ADSRParams {
+            attack: 0.01, hold: 0.0, decay: 0.01, sustain: 0.5,
+            release: 0.1, curve: 0.0, mode: EnvelopeMode::Sustain,
+        };
+        let mut env = ADSREnvelope::new();
+        env.note_on(0.0);
+        // held notes never finish, however long they're held
+        assert_eq!(env.inc_timer(10.0, &params), IsDone::Continue);
+
+        env.note_off(&params);
+        assert_eq!(env.inc_timer(0.05, &params), IsDone::Continue);
+        assert_eq!(env.inc_timer(0.05, &params), IsDone::Done);
+        assert_eq!(env.alpha(&params), 0.0);
+
+        // retriggering starts it over
+        env.note_on(0.0);
+        assert_eq!(env.inc_timer(0.001, &params), IsDone::Continue);
+
+        let mut block = [0.0; 200];
+        env.note_off(&params);
+        assert_eq!(env.process_block(0.001, &params, &mut block[..50]), IsDone::Continue);
+        assert_eq!(env.process_block(0.001, &params, &mut block), IsDone::Done);
+    }
+
+    #[test]
+    fn test_large_steps_cross_several_phases() {
+        let params = ADSRParams {
+            attack: 0.1, hold: 0.0, decay: 0.1, sustain: 0.5,
+            release: 0.1, curve: 0.0, mode: EnvelopeMode::Sustain,
+        };
+
+        let mut env = ADSREnvelope::new();
+        env.note_on(0.0);
+        env.inc_timer(0.15, &params);
+        assert_eq!(env.current_phase, ADSRPhase::Decay);
+        assert!((env.phase_elapsed - 0.05).abs() < 1e-12);
+
+        let mut env = ADSREnvelope::new();
+        env.note_on(0.0);
+        env.inc_timer(10.0, &params);
+        assert_eq!(env.current_phase, ADSRPhase::Sustain);
+        assert_eq!(env.alpha(&params), 0.5);
+    }
+
+    #[test]
+    fn test_process_block_matches_per_sample() {
+        let params = ADSRParams {
+            attack: 0.01, hold: 0.0, decay: 0.02, sustain: 0.3,
+            release: 0.01, curve: 0.5, mode: EnvelopeMode::Sustain,
+        };
+        let mut block_env = ADSREnvelope::new();
+        let mut sample_env = ADSREnvelope::new();
+        block_env.note_on(0.0);
+        sample_env.note_on(0.0);
+
+        let mut block = [0.0; 64];
+        for _ in 0..10 {
+            block_env.process_block(0.001, &params, &mut block);
+            for &alpha in &block {
+                assert_eq!(alpha, sample_env.alpha(&params));
+                sample_env.inc_timer(0.001, &params);
+            }
+        }
+        assert_eq!(block_env.current_phase, ADSRPhase::Sustain);
+    }
+
+    #[test]
+    fn test_hold_stays_at_the_peak() {
+        let params = ADSRParams {
+            attack: 0.01, hold: 0.1, decay: 0.01, sustain: 0.5,
+            release: 0.01, curve: 0.0, mode: EnvelopeMode::Sustain,
+        };
+        let mut env = ADSREnvelope::new();
+        env.note_on(0.0);
+        env.inc_timer(0.02, &params);
+        assert_eq!(env.current_phase, ADSRPhase::Hold);
+        assert_eq!(env.alpha(&params), 1.0);
+        env.inc_timer(0.085, &params);
+        assert_eq!(env.current_phase, ADSRPhase::Hold);
+        assert_eq!(env.alpha(&params), 1.0);
+
+        env.inc_timer(0.01, &params);
+        assert_eq!(env.current_phase, ADSRPhase::Decay);
+        assert!(env.alpha(&params) < 1.0);
+        env.inc_timer(0.01, &params);
+        assert_eq!(env.current_phase, ADSRPhase::Sustain);
+    }
+
+    #[test]
+    fn test_one_shot_ignores_note_off() {
+        let params = ADSRParams {
+            attack: 0.01, hold: 0.02, decay: 0.1, sustain: 0.5,
+            release: 0.01, curve: 0.0, mode: EnvelopeMode::OneShot,
+        };
+        let mut env = ADSREnvelope::new();
+        env.note_on(0.0);
+        env.inc_timer(0.02, &params);
+        env.note_off(&params);
+        assert_eq!(env.current_phase, ADSRPhase::Hold);
+
+        // the decay falls past the sustain level all the way to silence, then it's finished
+        assert_eq!(env.inc_timer(0.08, &params), IsDone::Continue);
+        assert!(env.alpha(&params) < 0.5 && env.alpha(&params) > 0.0);
+        assert_eq!(env.inc_timer(0.04, &params), IsDone::Done);
+        assert_eq!(env.alpha(&params), 0.0);
+        assert_eq!(env.inc_timer(0.001, &params), IsDone::Done);
+    }
+
+    #[test]
+    fn test_release_starts_at_the_level_from_every_phase() {
+        const SAMPLE: f64 = 1.0 / 44100.0;
+        for &curve in &CURVES {
+            for &release in &[0.01, 1.0, 5.0] {
+                let params = ADSRParams {
+                    attack: 0.1, hold: 0.05, decay: 0.1, sustain: 0.3,
+                    release, curve, mode: EnvelopeMode::Sustain,
+                };
+                // from the start, middle and end of each phase, and from partway through a release
+                let times = [0.0, 0.03, 0.1, 0.12, 0.15, 0.2, 0.25, 0.4, 1.0];
+                for &held in &times {
+                    for &rereleased in &[None, Some(0.002)] {
+                        let mut env = ADSREnvelope::new();
+                        env.note_on(0.2);
+                        env.inc_timer(held, &params);
+                        if let Some(time) = rereleased {
+                            env.note_off(&params);
+                            env.inc_timer(time, &params);
+                        }
+                        let phase = format!(
+                            "{:?} at {}s, curve {}, release {}", env.current_phase, held, curve, release
+                        );
+                        let before = env.alpha(&params);
+                        env.note_off(&params);
+                        assert_eq!(env.current_phase, ADSRPhase::Release);
+                        assert_eq!(env.alpha(&params), before, "{}", phase);
+
+                        // and falls from there no faster than the curve's steepest
+                        env.inc_timer(SAMPLE, &params);
+                        let fall = before - env.alpha(&params);
+                        let steepest = MAX_CURVATURE / (1.0 - (-MAX_CURVATURE).exp());
+                        assert!(fall >= 0.0 && fall <= before * steepest * SAMPLE / release + 1e-12, "{}", phase);
+
+                        assert_eq!(env.inc_timer(release, &params), IsDone::Done, "{}", phase);
+                        assert_eq!(env.alpha(&params), 0.0);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_changing_the_release_time_mid_release() {
+        let params = ADSRParams {
+            attack: 0.0, hold: 0.0, decay: 0.0, sustain: 0.8,
+            release: 1.0, curve: 0.5, mode: EnvelopeMode::Sustain,
+        };
+        let mut env = ADSREnvelope::new();
+        env.note_on(0.0);
+        env.inc_timer(0.01, &params);
+        env.note_off(&params);
+        env.inc_timer(0.5, &params);
+        let level = env.alpha(&params);
+
+        // shorter, then longer: the level carries on from where it was either way
+        for &release in &[0.1, 4.0] {
+            let changed = ADSRParams { release, ..params };
+            assert_eq!(env.alpha(&changed), level);
+            env.inc_timer(0.0, &changed);
+            assert!((env.alpha(&changed) - level).abs() < 1e-12);
+        }
+        assert_eq!(env.inc_timer(2.0, &ADSRParams { release: 4.0, ..params }), IsDone::Done);
+    }
+
+    #[test]
+    fn test_sample_shape() {
+        let params = ADSRParams {
+            attack: 0.1, hold: 0.1, decay: 0.1, sustain: 0.5,
+            release: 0.1, curve: 0.5, mode: EnvelopeMode::Sustain,
+        };
+        // a point every 10ms
+        let mut shape = [0.0; 51];
+        sample_shape(&params, 0.1, &mut shape);
+        assert_eq!(shape[0], 0.0);
+        assert_eq!(&shape[10..=20], &[1.0; 11]);
+        assert!(shape[30..=40].iter().all(|&level| (level - 0.5).abs() < 1e-9));
+        assert_eq!(shape[50], 0.0);
+
+        // the same as playing it
+        let mut env = ADSREnvelope::new();
+        env.note_on(0.0);
+        for (i, &level) in shape.iter().enumerate().take(40) {
+            assert!((level - env.alpha(&params)).abs() < 1e-9, "point {}", i);
+            env.inc_timer(0.01, &params);
+        }
+
+        let one_shot = ADSRParams { mode: EnvelopeMode::OneShot, ..params };
+        sample_shape(&one_shot, 0.1, &mut shape);
+        assert_eq!(shape[25], 1.0);
+        assert!(shape[41] < 0.5);
+        assert_eq!(shape[50], 0.0);
+
+        // zero length stages and tiny buffers don't divide by zero
+        for len in 0..3 {
+            let mut shape = vec![1.0; len];
+            sample_shape(&INSTANT, 0.0, &mut shape);
+            assert!(shape.iter().all(|level| level.is_finite()));
+        }
+    }
+
+    #[test]
+    fn test_alpha_is_finite_with_zero_length_stages() {
+        let mut env = ADSREnvelope::new();
+        env.note_on(0.0);
+        for _ in 0..10 {
+            env.inc_timer(0.0, &INSTANT);
+            assert!(env.alpha(&INSTANT).is_finite());
+        }
+        env.note_off(&INSTANT);
+        for _ in 0..10 {
+            env.inc_timer(0.001, &INSTANT);
+            assert!(env.alpha(&INSTANT).is_finite());
+        }
+    }
+
+    mod properties {
+        use super::*;
+        use proptest::prelude::*;
+
+        /// Anything the envelope settings can be set to, including zero length stages.
+        fn params() -> impl Strategy<Value = ADSRParams> {
+            (0.0..=5.0, 0.0..=5.0, 0.0..=5.0, 0.0..=1.0, 0.0..=5.0, -1.0..=1.0).prop_map(
+                |(attack, hold, decay, sustain, release, curve)| ADSRParams {
+                    attack,
+                    hold,
+                    decay,
+                    sustain,
+                    release,
+                    curve,
+                    mode: EnvelopeMode::Sustain,
+                }
+            )
+        }
+
+        /// Steps from a single sample at a high rate up to longer than any stage.
+        fn dt() -> impl Strategy<Value = f64> {
+            prop_oneof![1e-6..=1e-3, 1e-3..=0.1, 0.1..=20.0]
+        }
+
+        #[derive(Clone, Debug)]
+        enum Event {
+            NoteOn,
+            NoteOff,
+            Step(f64),
+        }
+
+        fn events() -> impl Strategy<Value = Vec<Event>> {
+            let event = prop_oneof![
+                1 => Just(Event::NoteOn),
+                1 => Just(Event::NoteOff),
+                8 => dt().prop_map(Event::Step),
+            ];
+            prop::collection::vec(event, 1..100)
+        }
+
+        fn phase_order(phase: &ADSRPhase) -> u8 {
+            match phase {
+                ADSRPhase::Attack => 0,
+                ADSRPhase::Hold => 1,
+                ADSRPhase::Decay => 2,
+                ADSRPhase::Sustain => 3,
+                ADSRPhase::Release => 4,
+            }
+        }
+
+        proptest! {
+            #[test]
+            fn alpha_stays_in_range(params in params(), events in events()) {
+                let mut env = ADSREnvelope::new();
+                for event in events {
+                    match event {
+                        // retriggered from wherever it was, as the voices do
+                        Event::NoteOn => env.note_on(env.alpha(&params)),
+                        Event::NoteOff => env.note_off(&params),
+                        Event::Step(dt) => { env.inc_timer(dt, &params); }
+                    }
+                    let alpha = env.alpha(&params);
+                    prop_assert!((0.0..=1.0).contains(&alpha), "alpha was {}", alpha);
+                }
+            }
+
+            #[test]
+            fn attack_never_falls(params in params(), from in 0.0..=1.0, steps in prop::collection::vec(dt(), 1..50)) {
+                let mut env = ADSREnvelope::new();
+                env.note_on(from);
+                let mut previous = env.alpha(&params);
+                for dt in steps {
+                    env.inc_timer(dt, &params);
+                    if env.current_phase != ADSRPhase::Attack {
+                        break;
+                    }
+                    let alpha = env.alpha(&params);
+                    prop_assert!(alpha >= previous, "fell from {} to {}", previous, alpha);
+                    previous = alpha;
+                }
+            }
+
+            #[test]
+            fn release_never_rises(params in params(), held in dt(), steps in prop::collection::vec(dt(), 1..50)) {
+                let mut env = ADSREnvelope::new();
+                env.note_on(0.0);
+                env.inc_timer(held, &params);
+                env.note_off(&params);
+                let mut previous = env.alpha(&params);
+                for dt in steps {
+                    let done = env.inc_timer(dt, &params);
+                    prop_assert_eq!(&env.current_phase, &ADSRPhase::Release);
+                    let alpha = env.alpha(&params);
+                    prop_assert!(alpha <= previous, "rose from {} to {}", previous, alpha);
+                    prop_assert_eq!(done == IsDone::Done, env.phase_elapsed >= params.release);
+                    if done == IsDone::Done {
+                        prop_assert_eq!(alpha, 0.0);
+                    }
+                    previous = alpha;
+                }
+            }
+
+            #[test]
+            fn phases_only_move_forwards(params in params(), steps in prop::collection::vec(dt(), 1..50)) {
+                let mut env = ADSREnvelope::new();
+                env.note_on(0.0);
+                let mut elapsed = 0.0;
+                for dt in steps {
+                    let before = phase_order(&env.current_phase);
+                    env.inc_timer(dt, &params);
+                    elapsed += dt;
+                    prop_assert!(phase_order(&env.current_phase) >= before);
+                    prop_assert!(env.current_phase != ADSRPhase::Release);
+
+                    // however big the steps, the phase is the one the total time since the note
+                    // on falls in, with the time left over counted towards it
+                    let (attack, hold, decay) = (params.attack, params.hold, params.decay);
+                    let tolerance = 1e-9 * elapsed.max(1.0);
+                    match env.current_phase {
+                        ADSRPhase::Attack => {
+                            prop_assert!(elapsed <= attack + tolerance);
+                            prop_assert!((env.phase_elapsed - elapsed).abs() <= tolerance);
+                        }
+                        ADSRPhase::Hold => {
+                            prop_assert!(elapsed >= attack - tolerance && elapsed <= attack + hold + tolerance);
+                            prop_assert!((env.phase_elapsed - (elapsed - attack)).abs() <= tolerance);
+                            prop_assert_eq!(env.alpha(&params), 1.0);
+                        }
+                        ADSRPhase::Decay => {
+                            let start = attack + hold;
+                            prop_assert!(elapsed >= start - tolerance && elapsed <= start + decay + tolerance);
+                            prop_assert!((env.phase_elapsed - (elapsed - start)).abs() <= tolerance);
+                        }
+                        ADSRPhase::Sustain => {
+                            prop_assert!(elapsed >= attack + hold + decay - tolerance);
+                            prop_assert_eq!(env.alpha(&params), params.sustain);
+                        }
+                        ADSRPhase::Release => unreachable!(),
+                    }
+                }
+            }
+        }
+    }
+}
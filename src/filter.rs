@@ -0,0 +1,191 @@
+use std::f64::consts::PI;
+
+use crate::denormal::{flush_denormal, flush_denormals};
+use crate::simd::F64x2;
+use crate::TAU;
+
+/// The DC blocker's cutoff, in Hz. Low enough to leave the lowest notes alone.
+const DC_BLOCKER_CUTOFF: f64 = 5.0;
+
+/// Which band of the signal the filter lets through.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FilterType {
+    Lowpass,
+    Highpass,
+    Bandpass,
+}
+
+impl FilterType {
+    pub const ALL: [FilterType; 3] = [FilterType::Lowpass, FilterType::Highpass, FilterType::Bandpass];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            FilterType::Lowpass => "Low Pass",
+            FilterType::Highpass => "High Pass",
+            FilterType::Bandpass => "Band Pass",
+        }
+    }
+}
+
+/// A resonant state variable filter, in the trapezoidal-integration form described by Andrew
+/// Simper. It stays stable with the cutoff modulated every sample.
+///
+/// It filters a left and right channel together, at the same cutoff and resonance, so the
+/// coefficients are only worked out once, and with the `simd` feature both channels go through
+/// in the same instructions.
+#[derive(Debug)]
+pub struct StateVariableFilter {
+    ic1eq: F64x2,
+    ic2eq: F64x2,
+}
+
+impl Default for StateVariableFilter {
+    fn default() -> StateVariableFilter {
+        StateVariableFilter { ic1eq: F64x2::splat(0.0), ic2eq: F64x2::splat(0.0) }
+    }
+}
+
+impl StateVariableFilter {
+    pub fn new() -> StateVariableFilter {
+        StateVariableFilter::default()
+    }
+
+    /// Filter one sample of each channel.
+    ///
+    /// `cutoff` is in Hz, and is kept safely below Nyquist. `resonance` goes from 0.0 (none) to
+    /// 1.0 (on the verge of self-oscillation).
+    pub fn process(&mut self, input: F64x2, filter_type: FilterType, cutoff: f64, resonance: f64,
+                   time_per_sample: f64) -> F64x2
+    {
+        let normalized_cutoff = (cutoff * time_per_sample).clamp(0.0, 0.49);
+        let g = (PI * normalized_cutoff).tan();
+        let k = 2.0 - 1.98 * resonance.clamp(0.0, 1.0);
+
+        let a1 = 1.0 / (1.0 + g * (g + k));
+        let a2 = g * a1;
+        let a3 = g * a2;
+        let [a1, a2, a3, k, two] = [a1, a2, a3, k, 2.0].map(F64x2::splat);
+
+        let v3 = input - self.ic2eq;
+        let v1 = a1 * self.ic1eq + a2 * v3;
+        let v2 = self.ic2eq + a2 * self.ic1eq + a3 * v3;
+        self.ic1eq = flush_denormals(two * v1 - self.ic1eq);
+        self.ic2eq = flush_denormals(two * v2 - self.ic2eq);
+
+        match filter_type {
+            FilterType::Lowpass => v2,
+            FilterType::Bandpass => v1,
+            FilterType::Highpass => input - k * v1 - v2,
+        }
+    }
+}
+
+/// A one pole high pass filter just above DC, which takes out any offset the oscillators,
+/// filter or effects build up, so it doesn't eat into the headroom or thump when playing stops.
+#[derive(Debug)]
+pub struct DcBlocker {
+    // the last input and output
+    input: f64,
+    output: f64,
+    coeff: f64,
+}
+
+impl DcBlocker {
+    pub fn new(time_per_sample: f64) -> DcBlocker {
+        let mut blocker = DcBlocker { input: 0.0, output: 0.0, coeff: 0.0 };
+        blocker.set_time_per_sample(time_per_sample);
+        blocker
+    }
+
+    pub fn set_time_per_sample(&mut self, time_per_sample: f64) {
+        self.coeff = (-TAU * DC_BLOCKER_CUTOFF * time_per_sample).exp();
+    }
+
+    pub fn reset(&mut self) {
+        self.input = 0.0;
+        self.output = 0.0;
+    }
+
+    pub fn process(&mut self, input: f64) -> f64 {
+        self.output = flush_denormal(input - self.input + self.coeff * self.output);
+        self.input = input;
+        self.output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Peak output level for a sine input, once the filter has settled.
+    fn response(filter_type: FilterType, freq: f64, cutoff: f64, resonance: f64) -> f64 {
+        let mut filter = StateVariableFilter::new();
+        let time_per_sample = 1.0 / 44100.0;
+        (0..44100)
+            .map(|i| {
+                let input = (f64::from(i) * freq * time_per_sample * TAU).sin();
+                let [left, right] = filter.process(F64x2::splat(input), filter_type, cutoff, resonance, time_per_sample)
+                    .to_array();
+                // both channels see the same filter
+                assert_eq!(left, right);
+                left
+            })
+            .skip(22050)
+            .fold(0.0, |peak, sample| f64::max(peak, sample.abs()))
+    }
+
+    #[test]
+    fn test_low_pass() {
+        assert!(response(FilterType::Lowpass, 100.0, 1000.0, 0.0) > 0.95);
+        assert!(response(FilterType::Lowpass, 10000.0, 1000.0, 0.0) < 0.02);
+    }
+
+    #[test]
+    fn test_high_pass() {
+        assert!(response(FilterType::Highpass, 100.0, 1000.0, 0.0) < 0.02);
+        assert!(response(FilterType::Highpass, 10000.0, 1000.0, 0.0) > 0.95);
+    }
+
+    #[test]
+    fn test_band_pass() {
+        let at_cutoff = response(FilterType::Bandpass, 1000.0, 1000.0, 0.0);
+        assert!(at_cutoff > 0.45);
+        assert!(response(FilterType::Bandpass, 100.0, 1000.0, 0.0) < at_cutoff / 4.0);
+        assert!(response(FilterType::Bandpass, 10000.0, 1000.0, 0.0) < at_cutoff / 4.0);
+    }
+
+    #[test]
+    fn test_resonance_boosts_cutoff() {
+        let flat = response(FilterType::Lowpass, 1000.0, 1000.0, 0.0);
+        let resonant = response(FilterType::Lowpass, 1000.0, 1000.0, 1.0);
+        assert!(resonant > flat * 10.0);
+        assert!(resonant.is_finite());
+    }
+
+    #[test]
+    fn test_decays_to_silence_without_denormals() {
+        let mut filter = StateVariableFilter::new();
+        let states = |filter: &StateVariableFilter| [filter.ic1eq.to_array(), filter.ic2eq.to_array()].concat();
+        filter.process(F64x2::new(1.0, -0.5), FilterType::Lowpass, 1000.0, 0.5, 1.0 / 44100.0);
+        for _ in 0..441000 {
+            filter.process(F64x2::splat(0.0), FilterType::Lowpass, 1000.0, 0.5, 1.0 / 44100.0);
+            assert!(states(&filter).iter().all(|state| !state.is_subnormal()));
+        }
+        // it settles on a level far too quiet to hear
+        assert!(states(&filter).iter().all(|state| state.abs() < 1e-30));
+    }
+
+    #[test]
+    fn test_dc_blocker() {
+        let time_per_sample = 1.0 / 44100.0;
+        let mut blocker = DcBlocker::new(time_per_sample);
+        // an offset dies away within a second, while a low note goes through
+        let output: Vec<f64> = (0..44100)
+            .map(|i| blocker.process(0.5 + (f64::from(i) * 40.0 * time_per_sample * TAU).sin()))
+            .collect();
+        let mean = output[22050..].iter().sum::<f64>() / 22050.0;
+        assert!(mean.abs() < 0.01, "the offset was still {}", mean);
+        let peak = output[22050..].iter().fold(0.0, |peak, sample| f64::max(peak, sample.abs()));
+        assert!(peak > 0.95 && peak < 1.05);
+    }
+}
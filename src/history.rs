@@ -0,0 +1,131 @@
+use std::collections::VecDeque;
+
+/// How many steps can be undone. The oldest are forgotten after that.
+const MAX_STEPS: usize = 100;
+
+/// A change to one parameter, with its normalized value before and after.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Change {
+    pub index: i32,
+    pub before: f32,
+    pub after: f32,
+}
+
+/// The undo and redo stacks for changes made in the editor. Hosts record automation from the
+/// editor, but they don't undo a plugin's own edits, so this does.
+///
+/// Each step is a group of changes that are undone together, like all the parameters a new patch
+/// moved.
+#[derive(Debug, Default)]
+pub struct History {
+    undo: VecDeque<Vec<Change>>,
+    redo: Vec<Vec<Change>>,
+}
+
+impl History {
+    /// Record a step, which can't be redone past. With `continuing`, a change to the same single
+    /// parameter as the last step joins it, so a whole slider drag undoes at once.
+    pub fn record(&mut self, changes: Vec<Change>, continuing: bool) {
+        self.redo.clear();
+        if let ([change], Some([last])) = (&changes[..], self.undo.back_mut().map(Vec::as_mut_slice)) {
+            if continuing && last.index == change.index {
+                last.after = change.after;
+                return;
+            }
+        }
+        if changes.is_empty() {
+            return;
+        }
+        if self.undo.len() == MAX_STEPS {
+            self.undo.pop_front();
+        }
+        self.undo.push_back(changes);
+    }
+
+    /// Take the last step off the undo stack and onto the redo stack, returning its changes to be
+    /// reverted.
+    pub fn undo(&mut self) -> Option<&[Change]> {
+        let step = self.undo.pop_back()?;
+        self.redo.push(step);
+        self.redo.last().map(Vec::as_slice)
+    }
+
+    /// Take the last undone step back onto the undo stack, returning its changes to be made again.
+    pub fn redo(&mut self) -> Option<&[Change]> {
+        let step = self.redo.pop()?;
+        self.undo.push_back(step);
+        self.undo.back().map(Vec::as_slice)
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    /// Forget every step, for when the parameters they'd restore belong to another preset.
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn change(index: i32, before: f32, after: f32) -> Vec<Change> {
+        vec![Change { index, before, after }]
+    }
+
+    #[test]
+    fn test_drags_are_one_step() {
+        let mut history = History::default();
+        history.record(change(3, 0.0, 0.1), false);
+        history.record(change(3, 0.1, 0.2), true);
+        history.record(change(3, 0.2, 0.3), true);
+        // a different parameter, then a new drag of the first, start steps of their own
+        history.record(change(4, 0.5, 0.6), true);
+        history.record(change(4, 0.6, 0.7), false);
+
+        assert_eq!(history.undo(), Some(&change(4, 0.6, 0.7)[..]));
+        assert_eq!(history.undo(), Some(&change(4, 0.5, 0.6)[..]));
+        assert_eq!(history.undo(), Some(&change(3, 0.0, 0.3)[..]));
+        assert_eq!(history.undo(), None);
+        assert!(history.can_redo());
+    }
+
+    #[test]
+    fn test_redo() {
+        let mut history = History::default();
+        history.record(change(1, 0.0, 1.0), false);
+        let patch = vec![Change { index: 2, before: 0.0, after: 0.5 }, Change { index: 3, before: 1.0, after: 0.0 }];
+        history.record(patch, false);
+        history.undo();
+        history.undo();
+        assert_eq!(history.redo(), Some(&change(1, 0.0, 1.0)[..]));
+        assert_eq!(history.redo().map(<[Change]>::len), Some(2));
+        assert_eq!(history.redo(), None);
+
+        // a new change can't be redone past
+        history.undo();
+        history.record(change(5, 0.0, 0.5), false);
+        assert!(!history.can_redo());
+    }
+
+    #[test]
+    fn test_oldest_steps_are_forgotten() {
+        let mut history = History::default();
+        for index in 0..MAX_STEPS as i32 + 10 {
+            history.record(change(index, 0.0, 1.0), false);
+        }
+        let mut undone = 0;
+        while let Some(step) = history.undo() {
+            undone += 1;
+            assert!(step[0].index >= 10);
+        }
+        assert_eq!(undone, MAX_STEPS);
+    }
+}
@@ -0,0 +1,314 @@
+use crate::tempo::NoteDivision;
+use crate::TAU;
+
+/// The number of general purpose LFOs.
+pub const NUM_LFOS: usize = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LfoShape {
+    Sine,
+    Triangle,
+    Square,
+    /// A new random value every cycle.
+    SampleAndHold,
+}
+
+impl LfoShape {
+    pub const ALL: [LfoShape; 4] = [LfoShape::Sine, LfoShape::Triangle, LfoShape::Square, LfoShape::SampleAndHold];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            LfoShape::Sine => "Sine",
+            LfoShape::Triangle => "Triangle",
+            LfoShape::Square => "Square",
+            LfoShape::SampleAndHold => "S&H",
+        }
+    }
+}
+
+/// A snapshot of one LFO's settings, read from the shared plugin parameters once per buffer.
+#[derive(Clone, Copy, Debug)]
+pub struct LfoParams {
+    pub shape: LfoShape,
+    /// In Hz, unless synced.
+    pub rate: f64,
+    /// When set, one cycle lasts this long instead, following the host tempo.
+    pub division: Option<NoteDivision>,
+}
+
+impl LfoParams {
+    /// The rate in Hz at `tempo` beats per minute.
+    pub fn rate_at(&self, tempo: f64) -> f64 {
+        self.division.map_or(self.rate, |division| 1.0 / division.seconds(tempo))
+    }
+}
+
+/// A snapshot of the random source's settings, read from the shared plugin parameters once per
+/// buffer.
+#[derive(Clone, Copy, Debug)]
+pub struct RandomParams {
+    /// Steps per second, unless synced.
+    pub rate: f64,
+    /// When set, each step lasts this long instead, following the host tempo.
+    pub division: Option<NoteDivision>,
+    /// How much of each step is spent gliding to the new value, from 0.0 (jumping straight there)
+    /// to 1.0 (gliding the whole way).
+    pub smooth: f64,
+}
+
+impl RandomParams {
+    /// The rate in steps per second at `tempo` beats per minute.
+    pub fn rate_at(&self, tempo: f64) -> f64 {
+        self.division.map_or(self.rate, |division| 1.0 / division.seconds(tempo))
+    }
+}
+
+/// A free running low frequency oscillator, with output from -1.0 to 1.0.
+#[derive(Debug)]
+pub struct Lfo {
+    // in cycles, 0.0 to 1.0
+    phase: f64,
+    // the current sample and hold value
+    held: f64,
+    rng: XorShift,
+}
+
+impl Lfo {
+    /// `seed` picks the sequence of sample and hold values, and must not be 0.
+    pub fn new(seed: u32) -> Lfo {
+        let mut rng = XorShift(seed);
+        Lfo {
+            phase: 0.0,
+            held: rng.next_bipolar(),
+            rng,
+        }
+    }
+
+    /// Move on by `time` seconds, and return the new output. `rate` is in Hz.
+    pub fn advance(&mut self, shape: LfoShape, rate: f64, time: f64) -> f64 {
+        self.phase += rate * time;
+        if self.phase >= 1.0 {
+            self.phase = self.phase.fract();
+            self.held = self.rng.next_bipolar();
+        }
+        self.get(shape)
+    }
+
+    /// The current output.
+    pub fn get(&self, shape: LfoShape) -> f64 {
+        match shape {
+            LfoShape::Sine => (self.phase * TAU).sin(),
+            LfoShape::Triangle => 1.0 - 4.0 * (self.phase - 0.5).abs(),
+            LfoShape::Square => if self.phase < 0.5 { 1.0 } else { -1.0 },
+            LfoShape::SampleAndHold => self.held,
+        }
+    }
+
+    /// Jump to `phase`, in cycles. Only the fractional part is used.
+    pub fn set_phase(&mut self, phase: f64) {
+        self.phase = phase.rem_euclid(1.0);
+    }
+
+    pub fn reset(&mut self) {
+        self.phase = 0.0;
+    }
+}
+
+/// Sample and hold on random values from -1.0 to 1.0, which can glide from one value to the next
+/// rather than jumping.
+#[derive(Debug)]
+pub struct RandomSource {
+    // in steps, 0.0 to 1.0
+    phase: f64,
+    // the value this step glides from, and the one it settles on
+    from: f64,
+    to: f64,
+    rng: XorShift,
+}
+
+impl RandomSource {
+    /// `seed` picks the sequence of values, and must not be 0.
+    pub fn new(seed: u32) -> RandomSource {
+        let mut rng = XorShift(seed);
+        let value = rng.next_bipolar();
+        RandomSource { phase: 0.0, from: value, to: value, rng }
+    }
+
+    /// Move on by `time` seconds, and return the new output. `rate` is in steps per second.
+    pub fn advance(&mut self, rate: f64, smooth: f64, time: f64) -> f64 {
+        self.phase += rate * time;
+        if self.phase >= 1.0 {
+            self.phase = self.phase.fract();
+            self.from = self.to;
+            self.to = self.rng.next_bipolar();
+        }
+        self.get(smooth)
+    }
+
+    /// The current output.
+    pub fn get(&self, smooth: f64) -> f64 {
+        if smooth <= 0.0 {
+            return self.to;
+        }
+        // eased in and out, so the glide has no corners
+        let progress = (self.phase / smooth).min(1.0);
+        self.from + (self.to - self.from) * progress * progress * (3.0 - 2.0 * progress)
+    }
+
+    /// Jump to `phase`, in steps. Only the fractional part is used.
+    pub fn set_phase(&mut self, phase: f64) {
+        self.phase = phase.rem_euclid(1.0);
+    }
+
+    pub fn reset(&mut self) {
+        self.phase = 0.0;
+        self.from = self.to;
+    }
+}
+
+/// How often the drift picks a new direction to wander in, in seconds, and how long it takes to
+/// get most of the way there.
+const DRIFT_INTERVAL: f64 = 0.5;
+const DRIFT_SMOOTHING: f64 = 0.4;
+
+/// How much of the drift is a fixed detune, with the rest wandering.
+const DRIFT_STATIC: f64 = 0.3;
+
+/// The pitch instability of an analog oscillator: a small fixed detune, plus a slow random walk
+/// around it. Output is from -1.0 to 1.0, to be scaled by the drift amount.
+#[derive(Debug)]
+pub struct Drift {
+    offset: f64,
+    // the walk heads towards the target, which moves on every interval
+    target: f64,
+    value: f64,
+    elapsed: f64,
+    rng: XorShift,
+}
+
+impl Drift {
+    /// `seed` picks the detune and the walk, and must not be 0.
+    pub fn new(seed: u32) -> Drift {
+        let mut rng = XorShift(seed);
+        let offset = rng.next_bipolar();
+        let value = rng.next_bipolar();
+        Drift { offset, target: value, value, elapsed: 0.0, rng }
+    }
+
+    /// Move on by `time` seconds, and return the new output.
+    pub fn advance(&mut self, time: f64) -> f64 {
+        self.elapsed += time;
+        if self.elapsed >= DRIFT_INTERVAL {
+            self.elapsed -= DRIFT_INTERVAL;
+            self.target = (self.target + self.rng.next_bipolar() * 0.5).clamp(-1.0, 1.0);
+        }
+        self.value += (self.target - self.value) * (time / DRIFT_SMOOTHING).min(1.0);
+        self.get()
+    }
+
+    pub fn get(&self) -> f64 {
+        DRIFT_STATIC * self.offset + (1.0 - DRIFT_STATIC) * self.value
+    }
+}
+
+/// A tiny pseudo-random generator for sample and hold, which is fine for modulation and safe to
+/// call on the audio thread. The seed must not be 0.
+#[derive(Debug)]
+pub(crate) struct XorShift(pub(crate) u32);
+
+impl XorShift {
+    /// A value from -1.0 to 1.0.
+    pub(crate) fn next_bipolar(&mut self) -> f64 {
+        let mut x = self.0;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.0 = x;
+        f64::from(x) / f64::from(u32::MAX) * 2.0 - 1.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_synced_rate_follows_tempo() {
+        let synced = LfoParams { shape: LfoShape::Sine, rate: 5.0, division: Some(NoteDivision::Quarter) };
+        assert_eq!(synced.rate_at(120.0), 2.0);
+        assert_eq!(synced.rate_at(60.0), 1.0);
+
+        let free = LfoParams { division: None, ..synced };
+        assert_eq!(free.rate_at(120.0), 5.0);
+    }
+
+    #[test]
+    fn test_set_phase_wraps() {
+        let mut lfo = Lfo::new(1);
+        lfo.set_phase(2.25);
+        assert_eq!(lfo.get(LfoShape::Triangle), 0.0);
+        lfo.set_phase(-0.5);
+        assert_eq!(lfo.get(LfoShape::Triangle), 1.0);
+    }
+
+    #[test]
+    fn test_lfo_shapes_in_range() {
+        for &shape in &LfoShape::ALL {
+            let mut lfo = Lfo::new(1);
+            for _ in 0..1000 {
+                let value = lfo.advance(shape, 7.0, 1.0 / 100.0);
+                assert!((-1.0..=1.0).contains(&value), "{:?}", shape);
+            }
+        }
+    }
+
+    #[test]
+    fn test_random_source_steps_and_glides() {
+        // stepped, it holds each value for a whole step
+        let mut random = RandomSource::new(3);
+        let first = random.advance(10.0, 0.0, 0.01);
+        assert_eq!(random.advance(10.0, 0.0, 0.08), first);
+        let second = random.advance(10.0, 0.0, 0.02);
+        assert_ne!(second, first);
+        assert!((-1.0..=1.0).contains(&second));
+
+        // smoothed over half the step, it's halfway there a quarter of the way in, then holds
+        let mut random = RandomSource::new(3);
+        random.advance(10.0, 0.5, 0.1);
+        let (from, to) = (random.from, random.to);
+        assert_eq!(random.get(0.5), from);
+        assert!((random.advance(10.0, 0.5, 0.025) - (from + to) / 2.0).abs() < 1e-9);
+        assert_eq!(random.advance(10.0, 0.5, 0.025), to);
+        assert_eq!(random.advance(10.0, 0.5, 0.04), to);
+    }
+
+    #[test]
+    fn test_drift_wanders_slowly() {
+        let values: Vec<f64> = {
+            let mut drift = Drift::new(7);
+            (0..20 * 1000).map(|_| drift.advance(1.0 / 1000.0)).collect()
+        };
+        assert!(values.iter().all(|value| (-1.0..=1.0).contains(value)));
+        // it moves, but never far in a millisecond
+        assert!(values.iter().any(|&value| (value - values[0]).abs() > 0.05));
+        assert!(values.windows(2).all(|pair| (pair[1] - pair[0]).abs() < 0.01));
+
+        // different voices drift differently
+        assert_ne!(Drift::new(7).advance(0.001), Drift::new(8).advance(0.001));
+    }
+
+    #[test]
+    fn test_sample_and_hold_holds_for_a_cycle() {
+        let mut lfo = Lfo::new(1);
+        // exactly 8 samples per cycle
+        let first = lfo.get(LfoShape::SampleAndHold);
+        let values: Vec<f64> = std::iter::once(first)
+            .chain((1..24).map(|_| lfo.advance(LfoShape::SampleAndHold, 8.0, 1.0 / 64.0)))
+            .collect();
+        for cycle in values.chunks(8) {
+            assert!(cycle.iter().all(|&value| value == cycle[0]));
+        }
+        assert_ne!(values[0], values[8]);
+        assert_ne!(values[8], values[16]);
+    }
+}
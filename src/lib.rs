@@ -1,235 +1,159 @@
-// Adapted from the sine-synth example from vst-rs
-// author: Rob Saunders <hello@robsaunders.io>
-
-#[macro_use]
-extern crate vst;
-
-use vst::api::{Events, Supported};
-use vst::buffer::AudioBuffer;
-use vst::event::Event;
-use vst::plugin::{CanDo, Category, Info, Plugin};
+// without a plugin format this is just the synth engine, for the standalone binary, benchmarks and
+// rendering, and what's only there for hosts goes unused
+#![cfg_attr(not(any(feature = "vst2", feature = "clap", feature = "vst3")), allow(dead_code))]
 
 use std::f64::consts::PI;
-use log::{LevelFilter, debug};
 
+#[cfg(test)]
+mod alloc_guard;
+mod arpeggiator;
+mod atomic_float;
+#[cfg(feature = "clap")]
+mod clap;
+mod denormal;
+#[cfg(feature = "gui")]
+mod editor;
+mod effects;
 mod envelope;
-
-use envelope::ADSREnvelope;
+mod filter;
+mod history;
+mod lfo;
+mod loader;
+mod logging;
+mod meter;
+mod midi_cc;
+mod midi_map;
+mod modulation;
+mod morph;
+mod mts;
+mod multi;
+mod oscillator;
+mod oversampling;
+mod param_groups;
+mod params;
+mod presets;
+mod reverb;
+mod saturation;
+mod simd;
+mod smoothing;
+mod stereo;
+mod synth;
+mod tempo;
+mod tuning;
+mod voice;
+#[cfg(feature = "vst2")]
+mod vst2;
+#[cfg(feature = "vst3")]
+mod vst3;
+mod wavetable;
+
+use params::ReplicantParameters;
+pub use synth::Synth;
+pub use tempo::Transport;
+pub use voice::NoteExpression;
+#[cfg(feature = "vst2")]
+pub use vst2::Replicant;
 
 /// Convert the midi note's pitch into the equivalent frequency.
 ///
-/// This function assumes A4 is 440hz.
-fn midi_pitch_to_freq(pitch: u8) -> f64 {
-    const A4_PITCH: i8 = 69;
+/// The pitch can be fractional, eg. during a glide between notes. This function assumes A4 is
+/// 440hz.
+fn midi_pitch_to_freq(pitch: f64) -> f64 {
+    const A4_PITCH: f64 = 69.0;
     const A4_FREQ: f64 = 440.0;
 
     // Midi notes can be 0-127
-    ((f64::from(pitch as i8 - A4_PITCH)) / 12.).exp2() * A4_FREQ
+    ((pitch - A4_PITCH) / 12.).exp2() * A4_FREQ
 }
 
-// struct PolyNote {
-//     note: u8,
-//     envelope: ADSREnvelope,
-// }
-// 
-// struct Polyreplicant {
-//     sample_rate: f64,
-//     time: f64,
-//     notes: Vec<Voice>,
-//     envelope: ADSREnvelope,
-// }
-
-struct MonoReplicant {
-    sample_rate: f64,
-    time: f64,
-    note: u8,
-    envelope: ADSREnvelope,
-    params: ReplicantParameters,
+/// Convert a midi velocity into the 0.0 to 1.0 range.
+fn velocity_amount(velocity: u8) -> f64 {
+    f64::from(velocity.min(127)) / 127.0
 }
 
-struct ReplicantParameters {
-    attack: AtomicFloat,
-    decay: AtomicFloat,
-    sustain: AtomicFloat, // 0.0 to 1.0
-    release: AtomicFloat,
-}
-
-impl MonoReplicant {
-    fn time_per_sample(&self) -> f64 {
-        1.0 / self.sample_rate
-    }
-
-    /// Process an incoming midi event.
-    ///
-    /// The midi data is split up like so:
-    ///
-    /// `data[0]`: Contains the status and the channel. Source: [source]
-    /// `data[1]`: Contains the supplemental data for the message - so, if this was a NoteOn then
-    ///            this would contain the note.
-    /// `data[2]`: Further supplemental data. Would be velocity in the case of a NoteOn message.
-    ///
-    /// [source]: http://www.midimountain.com/midi/midi_status.htm
-    fn process_midi_event(&mut self, data: [u8; 3]) {
-        match data[0] {
-            128 => self.note_off(data[1]),
-            144 => self.note_on(data[1]),
-            _ => (),
-        }
-    }
-
-    // TODO eliminate pops on note change
-    fn note_on(&mut self, note: u8) {
-        self.envelope.note_on(self.envelope.alpha());
-        self.note = note;
-    }
-
-    fn note_off(&mut self, _note: u8) {
-        self.envelope.note_off();
+/// Decode a pitch bend message's 14-bit value into the range -1.0 to 1.0, with 0.0 at the centre.
+fn decode_pitch_bend(lsb: u8, msb: u8) -> f64 {
+    let value = (i32::from(msb & 0x7F) << 7) | i32::from(lsb & 0x7F);
+    // the centre is 8192, and there's one less step above it than below
+    let offset = value - 8192;
+    if offset >= 0 {
+        f64::from(offset) / 8191.0
+    } else {
+        f64::from(offset) / 8192.0
     }
 }
 
-pub const TAU: f64 = PI * 2.0;
-
-impl Default for MonoReplicant {
-    fn default() -> MonoReplicant {
-        MonoReplicant {
-            sample_rate: 44100.0,
-            time: 0.0,
-            envelope: ADSREnvelope::new(),
-            note: 0, // this should never be audible before it is set to something else by note_on()
-        }
-    }
+/// Convert a pitch offset in cents into a frequency multiplier.
+fn cents_to_ratio(cents: f64) -> f64 {
+    (cents / 1200.).exp2()
 }
 
-impl Plugin for MonoReplicant {
-    fn get_info(&self) -> Info {
-        Info {
-            name: "Replicant".to_string(),
-            vendor: "James Sully".to_string(),
-            unique_id: 144_153_144,
-            category: Category::Synth,
-            inputs: 0,
-            outputs: 2,
-            parameters: 0,
-            initial_delay: 0,
-            ..Info::default()
-        }
-    }
-
-    fn init(&mut self) {
-        simple_logging::log_to_file("C:/Users/James/Desktop/replicant.log", LevelFilter::Off);
-    }
-
-    #[allow(unused_variables)]
-    #[allow(clippy::single_match)]
-    fn process_events(&mut self, events: &Events) {
-        for event in events.events() {
-            match event {
-                Event::Midi(ev) => self.process_midi_event(ev.data),
-                // More events can be handled here.
-                _ => (),
-            }
-        }
-    }
-
-    fn set_sample_rate(&mut self, rate: f32) {
-        self.sample_rate = f64::from(rate);
-    }
-
-    fn process(&mut self, buffer: &mut AudioBuffer<f32>) {
-        let samples = buffer.samples();
-        let (_, mut outputs) = buffer.split();
-        let output_count = outputs.len();
-        let time_per_sample = self.time_per_sample();
-        let mut output_sample_left;
-        let mut output_sample_right;
-
-        for sample_idx in 0..samples {
-            let time = self.time;
-
-            let note = self.note;
-
-            // simple stereo effect
-            let signal_left = (time+0.01 * midi_pitch_to_freq(note)*0.99 * TAU).sin();
-            let signal_right = (time * midi_pitch_to_freq(note)*1.01 * TAU).sin();
-
-            debug!("calling envelope.alpha()");
-
-            // should be 0.0 if release phase is over
-            let alpha = self.envelope.alpha();
-            debug!("phase: {:?}, phase_elapsed: {:?}, alpha: {:?}",
-                   self.envelope.current_phase, self.envelope.phase_elapsed, alpha);
-
-            output_sample_left = (signal_left * alpha) as f32;
-            output_sample_right = (signal_right * alpha) as f32;
-
-            self.time += time_per_sample;
-            self.envelope.inc_timer(time_per_sample);
-
-            let buff_left = outputs.get_mut(0);
-            let buff_right = outputs.get_mut(1);
-
-            buff_left[sample_idx] = output_sample_left;
-            buff_right[sample_idx] = output_sample_right;
-        }
-    }
+/// Convert a level in dB into an amplitude multiplier.
+fn db_to_gain(db: f64) -> f64 {
+    10f64.powf(db / 20.0)
+}
 
-    fn can_do(&self, can_do: CanDo) -> Supported {
-        match can_do {
-            CanDo::ReceiveMidiEvent => Supported::Yes,
-            _ => Supported::Maybe,
-        }
-    }
+/// The point `t` of the way from `a` to `b`.
+fn lerp(a: f64, b: f64, t: f64) -> f64 {
+    a + (b - a) * t
 }
 
-impl PluginParameters for ReplicantParameters {
-    // the `get_parameter` function reads the value of a parameter.
-    fn get_parameter(&self, index: i32) -> f32 {
-        match index {
-            0 => self.amplitude.get(),
-            _ => 0.0,
-        }
-    }
+/// Time constant of the pitch bend smoothing, in seconds.
+const PITCH_BEND_SMOOTHING: f64 = 0.005;
 
-    // the `set_parameter` function sets the value of a parameter.
-    fn set_parameter(&self, index: i32, val: f32) {
-        #[allow(clippy::single_match)]
-        match index {
-            0 => self.amplitude.set(val),
-            _ => (),
-        }
-    }
+/// Start logging, and load the user's presets. Every plugin format does this once the host has
+/// set the plugin up.
+fn init_plugin(params: &ReplicantParameters) {
+    logging::init();
 
-    // This is what will display underneath our control.  We can
-    // format it into a string that makes the most since.
-    fn get_parameter_text(&self, index: i32) -> String {
-        match index {
-            0 => format!("{:.2}", (self.amplitude.get() - 0.5) * 2f32),
-            _ => "".to_string(),
-        }
+    if let Some(dir) = presets::user_preset_dir() {
+        params.request_preset_dir(dir);
     }
+}
 
-    // This shows the control's name.
-    fn get_parameter_name(&self, index: i32) -> String {
-        match index {
-            0 => "Amplitude",
-            _ => "",
-        }
-        .to_string()
-    }
+/// Render midi with the default patch at `sample_rate`, without a host. See
+/// `Synth::render_midi`.
+pub fn render_midi_to_buffer(events: &[(u32, [u8; 3])], sample_rate: f32, len: usize) -> Vec<f32> {
+    let mut synth = Synth::default();
+    synth.set_sample_rate(sample_rate);
+    synth.render_midi(events, len)
 }
 
-plugin_main!(MonoReplicant);
+pub const TAU: f64 = PI * 2.0;
 
 #[cfg(test)]
 mod tests {
-    use midi_pitch_to_freq;
+    use super::*;
 
     #[test]
     fn test_midi_pitch_to_freq() {
         for i in 0..127 {
             // expect no panics
-            midi_pitch_to_freq(i);
+            midi_pitch_to_freq(f64::from(i));
         }
     }
+
+    #[test]
+    fn test_cents_to_ratio() {
+        assert_eq!(cents_to_ratio(0.0), 1.0);
+        assert!((cents_to_ratio(10.0) - 1.00578).abs() < 1e-4);
+        assert!((cents_to_ratio(1200.0) - 2.0).abs() < 1e-12);
+
+        // symmetric detune: the left and right ratios are reciprocals
+        assert!((cents_to_ratio(-17.0) * cents_to_ratio(17.0) - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_decode_pitch_bend() {
+        assert_eq!(decode_pitch_bend(0, 64), 0.0);
+        assert_eq!(decode_pitch_bend(0, 0), -1.0);
+        assert_eq!(decode_pitch_bend(127, 127), 1.0);
+    }
+
+    #[test]
+    fn test_db_to_gain() {
+        assert_eq!(db_to_gain(0.0), 1.0);
+        assert!((db_to_gain(-6.0) - 0.501).abs() < 0.001);
+        assert!((db_to_gain(20.0) - 10.0).abs() < 1e-9);
+    }
 }
@@ -0,0 +1,141 @@
+use std::path::PathBuf;
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::sync::OnceLock;
+use std::thread;
+#[cfg(test)]
+use std::time::Duration;
+
+use log::debug;
+
+/// How many loads can wait for the loader thread. The audio thread's loads are dropped while it's
+/// full, and the others wait for room.
+const QUEUE_LEN: usize = 64;
+
+/// Work that takes locks, allocates or reads files, so it's done on the loader thread rather than
+/// the audio thread, or the host's UI thread when it could take a while.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Load {
+    /// A wavetable file, or the built in table with `None`.
+    Wavetable(Option<PathBuf>),
+    /// A directory of preset files, for the user preset slots.
+    PresetDir(PathBuf),
+    /// A switch to a preset in the bank, asked for by a program change.
+    Preset(i32),
+    /// Multi-timbral mode's parts, built at a sample rate.
+    BuildParts(f32),
+    /// A preset from the bank for one of multi-timbral mode's parts, asked for by a program change
+    /// on its channel.
+    PartPreset { part: usize, preset: i32 },
+}
+
+/// How loading a file is going, for the editor to show.
+#[derive(Clone, Debug, PartialEq)]
+pub enum LoadStatus {
+    Loading,
+    Loaded,
+    Failed(String),
+}
+
+/// The queue to the loader thread, which does the loads sent to it one at a time, in the order
+/// they were sent, and sleeps while there are none.
+#[derive(Default)]
+pub struct Loader {
+    // set once the thread's started. the queue's bounded, so sending never allocates
+    sender: OnceLock<SyncSender<Load>>,
+}
+
+impl Loader {
+    /// Start the thread, which hands each load sent to it to `load`. It stops once `load` returns
+    /// false or the loader is dropped.
+    pub fn spawn(&self, mut load: impl FnMut(Load) -> bool + Send + 'static) {
+        let (sender, receiver) = mpsc::sync_channel(QUEUE_LEN);
+        let spawned = thread::Builder::new().name("replicant-loader".to_string()).spawn(move || {
+            for request in receiver {
+                if !load(request) {
+                    break;
+                }
+            }
+        });
+        match spawned {
+            Ok(_) => {
+                // a second thread's queue is dropped along with its sender, which stops it
+                let _ = self.sender.set(sender);
+            }
+            Err(err) => debug!("can't start the loader thread, so files load on the thread asking: {}", err),
+        }
+    }
+
+    /// Send a load to the thread, waiting for room if the queue's full, or give it back if there's
+    /// no thread to do it, for the caller to do itself.
+    pub fn send(&self, load: Load) -> Result<(), Load> {
+        match self.sender.get() {
+            Some(sender) => sender.send(load).map_err(|err| err.0),
+            None => Err(load),
+        }
+    }
+
+    /// Send a load to the thread without waiting or allocating, so it's safe on the audio thread.
+    /// It's given back if the queue's full or there's no thread.
+    pub fn try_send(&self, load: Load) -> Result<(), Load> {
+        match self.sender.get() {
+            Some(sender) => sender.try_send(load).map_err(|err| match err {
+                TrySendError::Full(load) | TrySendError::Disconnected(load) => load,
+            }),
+            None => Err(load),
+        }
+    }
+}
+
+/// Wait for the loader thread to do something, failing the test if it takes too long.
+#[cfg(test)]
+pub fn wait_for(mut done: impl FnMut() -> bool) {
+    for _ in 0..500 {
+        if done() {
+            return;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    panic!("the loader thread never finished");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Barrier};
+
+    use crate::alloc_guard;
+
+    #[test]
+    fn test_loads_arrive_in_order() {
+        let loader = Loader::default();
+        let load = Load::PresetDir(PathBuf::from("presets"));
+        // without a thread the load comes back
+        assert_eq!(loader.send(load.clone()), Err(load.clone()));
+        assert_eq!(loader.try_send(Load::Preset(1)), Err(Load::Preset(1)));
+
+        let (done, received) = mpsc::channel();
+        loader.spawn(move |load| done.send(load).is_ok());
+        assert_eq!(loader.send(Load::Wavetable(None)), Ok(()));
+        assert_eq!(loader.send(load.clone()), Ok(()));
+        assert_eq!(alloc_guard::assert_no_alloc(|| loader.try_send(Load::Preset(2))), Ok(()));
+        let wait = || received.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(wait(), Load::Wavetable(None));
+        assert_eq!(wait(), load);
+        assert_eq!(wait(), Load::Preset(2));
+    }
+
+    #[test]
+    fn test_full_queue_gives_loads_back() {
+        let loader = Loader::default();
+        let busy = Arc::new(Barrier::new(2));
+        let thread_busy = Arc::clone(&busy);
+        loader.spawn(move |_| {
+            thread_busy.wait();
+            true
+        });
+        // the thread's held up on the first, so the rest fill the queue
+        let sent = (0..QUEUE_LEN + 2).filter(|&preset| loader.try_send(Load::Preset(preset as i32)).is_ok()).count();
+        assert!(sent <= QUEUE_LEN + 1);
+        busy.wait();
+    }
+}
@@ -0,0 +1,258 @@
+use std::cell::UnsafeCell;
+use std::fmt::{self, Write as _};
+use std::fs::OpenOptions;
+use std::io::Write as _;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Once;
+use std::time::{Duration, Instant};
+use std::{env, thread};
+
+use log::{LevelFilter, Log, Metadata, Record};
+
+/// The file to log to. Logging is off unless it's set.
+pub const LOG_FILE_VAR: &str = "REPLICANT_LOG";
+/// How much to log, eg. `debug`. Defaults to `info`.
+pub const LOG_LEVEL_VAR: &str = "REPLICANT_LOG_LEVEL";
+
+/// How many messages can wait to be written, and how long each can be. Messages beyond either
+/// are dropped or cut short rather than holding up the thread that logged them.
+const QUEUE_LEN: usize = 256;
+const MESSAGE_LEN: usize = 256;
+
+/// How often the background thread writes out waiting messages.
+const DRAIN_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Start logging to the file named by `$REPLICANT_LOG`, if it's set.
+///
+/// Logging a message never allocates, locks or touches the file, so it's safe anywhere, even on
+/// the audio thread. Messages are formatted into a fixed size slot of a lock-free queue, and a
+/// background thread writes them out.
+pub fn init() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        let Some(path) = env::var_os(LOG_FILE_VAR) else { return };
+        let level = env::var(LOG_LEVEL_VAR).ok()
+            .and_then(|level| level.parse().ok())
+            .unwrap_or(LevelFilter::Info);
+        let mut file = match OpenOptions::new().create(true).append(true).open(&path) {
+            Ok(file) => file,
+            // there's nowhere to log the problem to, and the host's console is the best we have
+            Err(err) => return eprintln!("replicant: can't log to {:?}: {}", path, err),
+        };
+
+        let logger: &'static QueueLogger = Box::leak(Box::new(QueueLogger {
+            queue: Queue::new(QUEUE_LEN),
+            start: Instant::now(),
+        }));
+        let spawned = thread::Builder::new().name("replicant-log".to_string()).spawn(move || {
+            let mut line = Vec::with_capacity(MESSAGE_LEN);
+            loop {
+                while logger.queue.pop(&mut line) {
+                    line.push(b'\n');
+                    let _ = file.write_all(&line);
+                }
+                let _ = file.flush();
+                thread::sleep(DRAIN_INTERVAL);
+            }
+        });
+        if spawned.is_ok() && log::set_logger(logger).is_ok() {
+            log::set_max_level(level);
+        }
+    });
+}
+
+struct QueueLogger {
+    queue: Queue,
+    start: Instant,
+}
+
+impl Log for QueueLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let mut message = Truncated { text: [0; MESSAGE_LEN], len: 0 };
+        let elapsed = self.start.elapsed();
+        let _ = write!(
+            message,
+            "{}.{:03} {} {}: {}",
+            elapsed.as_secs(),
+            elapsed.subsec_millis(),
+            record.level(),
+            record.target(),
+            record.args()
+        );
+        self.queue.push(&message.text[..message.len]);
+    }
+
+    fn flush(&self) {}
+}
+
+/// A message formatted on the stack, cut short if it doesn't fit.
+struct Truncated {
+    text: [u8; MESSAGE_LEN],
+    len: usize,
+}
+
+impl fmt::Write for Truncated {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let room = MESSAGE_LEN - self.len;
+        // only cut at a character boundary, so the message stays valid UTF-8
+        let mut end = s.len().min(room);
+        while !s.is_char_boundary(end) {
+            end -= 1;
+        }
+        self.text[self.len..self.len + end].copy_from_slice(&s.as_bytes()[..end]);
+        self.len += end;
+        Ok(())
+    }
+}
+
+/// A bounded lock-free queue of messages, for any number of threads to push to and one to pop
+/// from.
+///
+/// Each slot's sequence number says whose turn it is: a pusher claims the slot at `sequence ==
+/// position`, and the popper takes it at `sequence == position + 1`. (Dmitry Vyukov's bounded
+/// queue.)
+struct Queue {
+    slots: Box<[Slot]>,
+    push_position: AtomicUsize,
+    pop_position: AtomicUsize,
+}
+
+struct Slot {
+    sequence: AtomicUsize,
+    len: UnsafeCell<usize>,
+    text: UnsafeCell<[u8; MESSAGE_LEN]>,
+}
+
+// a slot's contents are only touched by the thread whose turn it is, as given by its sequence
+unsafe impl Sync for Queue {}
+
+impl Queue {
+    fn new(len: usize) -> Queue {
+        Queue {
+            slots: (0..len)
+                .map(|i| Slot {
+                    sequence: AtomicUsize::new(i),
+                    len: UnsafeCell::new(0),
+                    text: UnsafeCell::new([0; MESSAGE_LEN]),
+                })
+                .collect(),
+            push_position: AtomicUsize::new(0),
+            pop_position: AtomicUsize::new(0),
+        }
+    }
+
+    /// Add a message, returning false and dropping it if the queue is full.
+    fn push(&self, text: &[u8]) -> bool {
+        let mut position = self.push_position.load(Ordering::Relaxed);
+        loop {
+            let slot = &self.slots[position % self.slots.len()];
+            let sequence = slot.sequence.load(Ordering::Acquire);
+            if sequence == position {
+                match self.push_position.compare_exchange_weak(
+                    position,
+                    position.wrapping_add(1),
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        let len = text.len().min(MESSAGE_LEN);
+                        unsafe {
+                            (&mut *slot.text.get())[..len].copy_from_slice(&text[..len]);
+                            *slot.len.get() = len;
+                        }
+                        slot.sequence.store(position.wrapping_add(1), Ordering::Release);
+                        return true;
+                    }
+                    Err(current) => position = current,
+                }
+            } else if (sequence.wrapping_sub(position) as isize) < 0 {
+                // the popper hasn't freed this slot from the last time round
+                return false;
+            } else {
+                // another pusher got here first
+                position = self.push_position.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Take the oldest message into `text`, returning false if there aren't any. Only one thread
+    /// may pop.
+    fn pop(&self, text: &mut Vec<u8>) -> bool {
+        let position = self.pop_position.load(Ordering::Relaxed);
+        let slot = &self.slots[position % self.slots.len()];
+        if slot.sequence.load(Ordering::Acquire) != position.wrapping_add(1) {
+            return false;
+        }
+        text.clear();
+        unsafe {
+            text.extend_from_slice(&(&*slot.text.get())[..*slot.len.get()]);
+        }
+        self.pop_position.store(position.wrapping_add(1), Ordering::Relaxed);
+        slot.sequence.store(position.wrapping_add(self.slots.len()), Ordering::Release);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_queue_keeps_order_and_drops_when_full() {
+        let queue = Queue::new(4);
+        let mut text = Vec::new();
+        assert!(!queue.pop(&mut text));
+
+        // round a few times, so positions wrap around the slots
+        for round in 0..3u8 {
+            for i in 0..4 {
+                assert!(queue.push(&[round, i]));
+            }
+            assert!(!queue.push(b"dropped"));
+            for i in 0..4 {
+                assert!(queue.pop(&mut text));
+                assert_eq!(text, [round, i]);
+            }
+            assert!(!queue.pop(&mut text));
+        }
+    }
+
+    #[test]
+    fn test_queue_from_many_threads() {
+        let queue = Queue::new(QUEUE_LEN);
+        thread::scope(|scope| {
+            for thread in 0..4u8 {
+                let queue = &queue;
+                scope.spawn(move || {
+                    for i in 0..50 {
+                        assert!(queue.push(&[thread, i]));
+                    }
+                });
+            }
+        });
+
+        let mut text = Vec::new();
+        let mut next = [0; 4];
+        while queue.pop(&mut text) {
+            // each thread's messages come out in the order it pushed them
+            assert_eq!(text[1], next[usize::from(text[0])]);
+            next[usize::from(text[0])] += 1;
+        }
+        assert_eq!(next, [50; 4]);
+    }
+
+    #[test]
+    fn test_long_messages_are_truncated() {
+        let mut message = Truncated { text: [0; MESSAGE_LEN], len: 0 };
+        let _ = write!(message, "{}", "é".repeat(MESSAGE_LEN));
+        assert_eq!(message.len, MESSAGE_LEN);
+        assert!(std::str::from_utf8(&message.text[..message.len]).is_ok());
+    }
+}
@@ -0,0 +1,119 @@
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+use crate::atomic_float::AtomicFloat;
+
+/// How many of the most recent output samples the scope keeps.
+pub const SCOPE_LEN: usize = 2048;
+
+/// Output levels for the most recent buffer.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Levels {
+    /// Left and right peak levels.
+    pub peak: (f32, f32),
+    /// Left and right RMS levels.
+    pub rms: (f32, f32),
+}
+
+/// Output levels and a short waveform history, written by the audio thread and read from anywhere
+/// without locking.
+///
+/// There's a single writer, `process()`. Readers never block it, at the cost of the occasional
+/// scope snapshot catching a buffer half written, which is harmless for display.
+pub struct Meter {
+    peak: [AtomicFloat; 2],
+    rms: [AtomicFloat; 2],
+    // the mono output as f32 bits, written round in a circle
+    scope: [AtomicU32; SCOPE_LEN],
+    // how many samples have ever been written, so the newest is just before this
+    scope_end: AtomicUsize,
+}
+
+impl Meter {
+    pub fn new() -> Meter {
+        Meter {
+            peak: [AtomicFloat::new(0.0), AtomicFloat::new(0.0)],
+            rms: [AtomicFloat::new(0.0), AtomicFloat::new(0.0)],
+            scope: std::array::from_fn(|_| AtomicU32::new(0)),
+            scope_end: AtomicUsize::new(0),
+        }
+    }
+
+    /// Measure a buffer of output. Only the audio thread should call this.
+    pub fn update(&self, left: &[f32], right: &[f32]) {
+        for (channel, samples) in [left, right].iter().enumerate() {
+            let peak = samples.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+            let sum_squares: f32 = samples.iter().map(|s| s * s).sum();
+            let rms = if samples.is_empty() { 0.0 } else { (sum_squares / samples.len() as f32).sqrt() };
+            self.peak[channel].set(peak);
+            self.rms[channel].set(rms);
+        }
+
+        let end = self.scope_end.load(Ordering::Relaxed);
+        for (i, (l, r)) in left.iter().zip(right).enumerate() {
+            let mono = (l + r) / 2.0;
+            self.scope[(end + i) % SCOPE_LEN].store(mono.to_bits(), Ordering::Relaxed);
+        }
+        // publish the new samples only once they're all written
+        self.scope_end.store(end.wrapping_add(left.len().min(right.len())), Ordering::Release);
+    }
+
+    pub fn levels(&self) -> Levels {
+        Levels {
+            peak: (self.peak[0].get(), self.peak[1].get()),
+            rms: (self.rms[0].get(), self.rms[1].get()),
+        }
+    }
+
+    /// Fill `out` with the most recent mono output samples, oldest first. At most `SCOPE_LEN`
+    /// samples are available; any more at the start of `out` are left alone.
+    pub fn scope(&self, out: &mut [f32]) {
+        let end = self.scope_end.load(Ordering::Acquire);
+        let len = out.len().min(SCOPE_LEN);
+        let start = out.len() - len;
+        for (i, sample) in out[start..].iter_mut().enumerate() {
+            let index = end.wrapping_sub(len).wrapping_add(i) % SCOPE_LEN;
+            *sample = f32::from_bits(self.scope[index].load(Ordering::Relaxed));
+        }
+    }
+}
+
+impl Default for Meter {
+    fn default() -> Meter {
+        Meter::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_levels() {
+        let meter = Meter::new();
+        meter.update(&[0.5, -1.0, 0.5, -1.0], &[0.0; 4]);
+
+        let levels = meter.levels();
+        assert_eq!(levels.peak, (1.0, 0.0));
+        assert!((levels.rms.0 - 0.625f32.sqrt()).abs() < 1e-6);
+        assert_eq!(levels.rms.1, 0.0);
+    }
+
+    #[test]
+    fn test_scope_keeps_the_latest_samples_in_order() {
+        let meter = Meter::new();
+        // write enough to wrap round the ring more than once, in uneven buffers
+        let samples: Vec<f32> = (0..SCOPE_LEN * 2 + 100).map(|i| i as f32).collect();
+        for buffer in samples.chunks(300) {
+            meter.update(buffer, buffer);
+        }
+
+        let mut out = [0.0; 10];
+        meter.scope(&mut out);
+        assert_eq!(&out[..], &samples[samples.len() - 10..]);
+
+        let mut all = vec![-1.0; SCOPE_LEN + 1];
+        meter.scope(&mut all);
+        assert_eq!(all[0], -1.0);
+        assert_eq!(&all[1..], &samples[samples.len() - SCOPE_LEN..]);
+    }
+}
@@ -0,0 +1,185 @@
+/// Controllers 0 to 31 can each be paired with the one this far above, which carries the least
+/// significant 7 bits of a 14-bit value.
+const LSB_OFFSET: u8 = 32;
+
+const DATA_ENTRY: u8 = 6;
+const DATA_ENTRY_LSB: u8 = DATA_ENTRY + LSB_OFFSET;
+const DATA_INCREMENT: u8 = 96;
+const DATA_DECREMENT: u8 = 97;
+const NRPN_LSB: u8 = 98;
+const NRPN_MSB: u8 = 99;
+const RPN_LSB: u8 = 100;
+const RPN_MSB: u8 = 101;
+
+/// The largest 14-bit value.
+const MAX_14_BIT: u16 = 0x3FFF;
+
+/// A controller change, decoded from one or more CC messages.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Control {
+    /// A controller moved, to a value from 0.0 to 1.0. Pairs sending both halves of a 14-bit value
+    /// are combined onto the first of the pair.
+    Cc(u8, f64),
+    /// An NRPN's data entry changed, to a value from 0.0 to 1.0, by parameter number.
+    Nrpn(u16, f64),
+}
+
+/// The parameter number the data entry controllers change.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Selected {
+    None,
+    Nrpn,
+    // registered parameters aren't used, but their data entry mustn't change the NRPN
+    Rpn,
+}
+
+/// Combines the halves of 14-bit controllers and NRPNs, so controllers with high resolution
+/// encoders move parameters smoothly rather than in 128 steps.
+///
+/// Controllers only sending the most significant half, as most do, still reach 1.0 at 127. Once
+/// one has sent its least significant half it's scaled as 14 bits from then on.
+#[derive(Clone, Debug)]
+pub struct CcDecoder {
+    // the most significant half last sent by each of the pairable controllers
+    msb: [Option<u8>; LSB_OFFSET as usize],
+    // a bit for each pairable controller that's sent its least significant half
+    high_res: u32,
+    selected: Selected,
+    // the parameter number, as its two halves arrive
+    number: u16,
+    // the data entry value, and whether its least significant half has ever been sent
+    data: u16,
+    data_high_res: bool,
+}
+
+impl Default for CcDecoder {
+    fn default() -> CcDecoder {
+        CcDecoder {
+            msb: [None; LSB_OFFSET as usize],
+            high_res: 0,
+            selected: Selected::None,
+            number: 0,
+            data: 0,
+            data_high_res: false,
+        }
+    }
+}
+
+impl CcDecoder {
+    /// Decode a control change message, returning the change it completes, if any. The messages
+    /// selecting an NRPN and their data entry are used up here, but every other controller is
+    /// returned.
+    pub fn decode(&mut self, controller: u8, value: u8) -> Option<Control> {
+        let value = value & 0x7F;
+        match controller {
+            NRPN_MSB | RPN_MSB => self.select(controller, (self.number & 0x7F) | u16::from(value) << 7),
+            NRPN_LSB | RPN_LSB => self.select(controller, (self.number & !0x7F) | u16::from(value)),
+            DATA_ENTRY | DATA_ENTRY_LSB | DATA_INCREMENT | DATA_DECREMENT if self.selected != Selected::None => {
+                self.data_entry(controller, value)
+            }
+            _ if controller < LSB_OFFSET => {
+                self.msb[usize::from(controller)] = Some(value);
+                let high_res = self.high_res & 1 << controller != 0;
+                Some(Control::Cc(controller, scale(value, 0, high_res)))
+            }
+            _ if controller < LSB_OFFSET * 2 => {
+                let paired = controller - LSB_OFFSET;
+                match self.msb[usize::from(paired)] {
+                    Some(msb) => {
+                        self.high_res |= 1 << paired;
+                        Some(Control::Cc(paired, scale(msb, value, true)))
+                    }
+                    // without its other half, it's an ordinary controller
+                    None => Some(Control::Cc(controller, scale(value, 0, false))),
+                }
+            }
+            _ => Some(Control::Cc(controller, scale(value, 0, false))),
+        }
+    }
+
+    /// Half of a parameter number arrived. The RPN number 127, 127 is the null one, which stops
+    /// data entry changing anything.
+    fn select(&mut self, controller: u8, number: u16) -> Option<Control> {
+        self.number = number;
+        self.selected = match controller {
+            NRPN_MSB | NRPN_LSB => Selected::Nrpn,
+            _ if number == MAX_14_BIT => Selected::None,
+            _ => Selected::Rpn,
+        };
+        None
+    }
+
+    fn data_entry(&mut self, controller: u8, value: u8) -> Option<Control> {
+        // a step of the lowest bit that's in use
+        let step = if self.data_high_res { 1 } else { 1 << 7 };
+        self.data = match controller {
+            // the most significant half starts a new value
+            DATA_ENTRY => u16::from(value) << 7,
+            DATA_ENTRY_LSB => {
+                self.data_high_res = true;
+                (self.data & !0x7F) | u16::from(value)
+            }
+            DATA_INCREMENT => self.data.saturating_add(step).min(MAX_14_BIT),
+            _ => self.data.saturating_sub(step),
+        };
+        let value = if self.data_high_res {
+            f64::from(self.data) / f64::from(MAX_14_BIT)
+        } else {
+            scale((self.data >> 7) as u8, 0, false)
+        };
+        (self.selected == Selected::Nrpn).then_some(Control::Nrpn(self.number, value))
+    }
+}
+
+/// A controller's value from 0.0 to 1.0, from its halves, or just the most significant one.
+fn scale(msb: u8, lsb: u8, high_res: bool) -> f64 {
+    if high_res {
+        f64::from(u16::from(msb) << 7 | u16::from(lsb)) / f64::from(MAX_14_BIT)
+    } else {
+        f64::from(msb) / 127.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_14_bit_pairs() {
+        let mut decoder = CcDecoder::default();
+        assert_eq!(decoder.decode(1, 127), Some(Control::Cc(1, 1.0)));
+        // an unpaired controller in the upper range is an ordinary one
+        assert_eq!(decoder.decode(40, 127), Some(Control::Cc(40, 1.0)));
+
+        decoder.decode(7, 64);
+        assert_eq!(decoder.decode(39, 1), Some(Control::Cc(7, f64::from(8193) / 16383.0)));
+        // from now on the most significant half is part of a 14-bit value
+        assert_eq!(decoder.decode(7, 64), Some(Control::Cc(7, f64::from(8192) / 16383.0)));
+        assert_eq!(decoder.decode(39, 127), Some(Control::Cc(7, f64::from(8319) / 16383.0)));
+        assert_eq!(decoder.decode(1, 127), Some(Control::Cc(1, 1.0)));
+    }
+
+    #[test]
+    fn test_nrpn() {
+        let mut decoder = CcDecoder::default();
+        assert_eq!(decoder.decode(DATA_ENTRY, 64), Some(Control::Cc(DATA_ENTRY, 64.0 / 127.0)));
+
+        assert_eq!(decoder.decode(NRPN_MSB, 1), None);
+        assert_eq!(decoder.decode(NRPN_LSB, 2), None);
+        assert_eq!(decoder.decode(DATA_ENTRY, 127), Some(Control::Nrpn(130, 1.0)));
+        assert_eq!(decoder.decode(DATA_DECREMENT, 0), Some(Control::Nrpn(130, 126.0 / 127.0)));
+        assert_eq!(decoder.decode(DATA_ENTRY, 64), Some(Control::Nrpn(130, 64.0 / 127.0)));
+        assert_eq!(decoder.decode(DATA_ENTRY_LSB, 1), Some(Control::Nrpn(130, 8193.0 / 16383.0)));
+        assert_eq!(decoder.decode(DATA_INCREMENT, 0), Some(Control::Nrpn(130, 8194.0 / 16383.0)));
+
+        // data entry for a registered parameter doesn't change the NRPN
+        decoder.decode(RPN_MSB, 0);
+        decoder.decode(RPN_LSB, 0);
+        assert_eq!(decoder.decode(DATA_ENTRY, 2), None);
+
+        // and after the null one, it's an ordinary controller again
+        decoder.decode(RPN_MSB, 127);
+        decoder.decode(RPN_LSB, 127);
+        assert_eq!(decoder.decode(DATA_ENTRY, 127), Some(Control::Cc(DATA_ENTRY, 1.0)));
+    }
+}
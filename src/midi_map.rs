@@ -0,0 +1,132 @@
+use std::sync::atomic::{AtomicI32, Ordering};
+
+/// Controllers from here up are channel mode messages, like all notes off, so they can't be
+/// mapped.
+const FIRST_CHANNEL_MODE_CC: u8 = 120;
+
+/// Marks a controller that isn't mapped, or that nothing is being learned.
+const NONE: i32 = -1;
+
+/// Which parameter each midi CC controls, as learned from the user's controller.
+///
+/// The audio thread reads it as controllers move while the editor changes it, so it's all atomics
+/// rather than a lock.
+pub struct MidiMap {
+    // parameter indices by CC number
+    params: [AtomicI32; FIRST_CHANNEL_MODE_CC as usize],
+    // the parameter waiting for the next CC to be mapped to it
+    learning: AtomicI32,
+}
+
+impl Default for MidiMap {
+    fn default() -> MidiMap {
+        MidiMap {
+            params: std::array::from_fn(|_| AtomicI32::new(NONE)),
+            learning: AtomicI32::new(NONE),
+        }
+    }
+}
+
+impl MidiMap {
+    /// The parameter a controller is mapped to.
+    pub fn param(&self, cc: u8) -> Option<i32> {
+        let index = self.params.get(usize::from(cc))?.load(Ordering::Relaxed);
+        (index != NONE).then_some(index)
+    }
+
+    /// The controller a parameter is mapped to.
+    #[cfg_attr(not(feature = "gui"), allow(dead_code))]
+    pub fn cc(&self, param: i32) -> Option<u8> {
+        (0..FIRST_CHANNEL_MODE_CC).find(|&cc| self.param(cc) == Some(param))
+    }
+
+    /// Map the next controller that moves to a parameter, or stop waiting for one with `None`.
+    #[cfg_attr(not(feature = "gui"), allow(dead_code))]
+    pub fn learn(&self, param: Option<i32>) {
+        self.learning.store(param.unwrap_or(NONE), Ordering::Relaxed);
+    }
+
+    /// The parameter waiting to be mapped to a controller.
+    #[cfg_attr(not(feature = "gui"), allow(dead_code))]
+    pub fn learning(&self) -> Option<i32> {
+        let index = self.learning.load(Ordering::Relaxed);
+        (index != NONE).then_some(index)
+    }
+
+    /// A controller moved: map it if a parameter is being learned, and return the parameter it
+    /// controls, if any.
+    pub fn control(&self, cc: u8) -> Option<i32> {
+        if cc >= FIRST_CHANNEL_MODE_CC {
+            return None;
+        }
+        let learning = self.learning.swap(NONE, Ordering::Relaxed);
+        if learning != NONE {
+            // each parameter follows one controller
+            self.forget(learning);
+            self.params[usize::from(cc)].store(learning, Ordering::Relaxed);
+        }
+        self.param(cc)
+    }
+
+    /// Unmap every controller from a parameter.
+    pub fn forget(&self, param: i32) {
+        for mapped in &self.params {
+            let _ = mapped.compare_exchange(param, NONE, Ordering::Relaxed, Ordering::Relaxed);
+        }
+    }
+
+    /// Every mapped controller, with the parameter it controls, in CC order.
+    pub fn mappings(&self) -> Vec<(u8, i32)> {
+        (0..FIRST_CHANNEL_MODE_CC).filter_map(|cc| Some((cc, self.param(cc)?))).collect()
+    }
+
+    /// Replace all of the mappings. Controllers that can't be mapped are skipped.
+    pub fn set_mappings(&self, mappings: &[(u8, i32)]) {
+        for mapped in &self.params {
+            mapped.store(NONE, Ordering::Relaxed);
+        }
+        for &(cc, param) in mappings {
+            if let Some(mapped) = self.params.get(usize::from(cc)) {
+                mapped.store(param, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_learn() {
+        let map = MidiMap::default();
+        assert_eq!(map.control(74), None);
+
+        map.learn(Some(19));
+        assert_eq!(map.learning(), Some(19));
+        assert_eq!(map.control(74), Some(19));
+        assert_eq!(map.learning(), None);
+        assert_eq!(map.control(74), Some(19));
+        assert_eq!(map.control(71), None);
+
+        // learning another controller for the same parameter moves it
+        map.learn(Some(19));
+        map.control(71);
+        assert_eq!(map.mappings(), [(71, 19)]);
+        assert_eq!(map.cc(19), Some(71));
+
+        // channel mode messages can't be learned, and leave learn mode waiting
+        map.learn(Some(5));
+        assert_eq!(map.control(123), None);
+        assert_eq!(map.learning(), Some(5));
+    }
+
+    #[test]
+    fn test_set_mappings() {
+        let map = MidiMap::default();
+        map.set_mappings(&[(1, 19), (127, 20), (7, 64)]);
+        assert_eq!(map.mappings(), [(1, 19), (7, 64)]);
+        map.forget(19);
+        assert_eq!(map.mappings(), [(7, 64)]);
+    }
+}
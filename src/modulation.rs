@@ -0,0 +1,248 @@
+use crate::lfo::NUM_LFOS;
+
+/// The number of modulation matrix slots.
+pub const NUM_MOD_SLOTS: usize = 8;
+
+/// How far each destination is moved by a source at full value and depth.
+const MAX_PITCH_DEPTH: f64 = 12.0; // semitones either way
+const MAX_CUTOFF_DEPTH: f64 = 8.0; // octaves either way
+const MAX_PULSE_WIDTH_DEPTH: f64 = 0.45; // fraction of the cycle either way
+const MAX_SYNC_RATIO_DEPTH: f64 = 3.0; // octaves either way
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ModSource {
+    Lfo1,
+    Lfo2,
+    /// The per-voice modulation envelope.
+    ModEnv,
+    Velocity,
+    /// 0.0 at middle C, moving 1/8 per octave, so that full depth to cutoff tracks the keyboard
+    /// exactly.
+    KeyTrack,
+    ModWheel,
+    /// Channel pressure, or each note's own pressure for MPE notes.
+    Aftertouch,
+    /// CC74, or each note's own CC74 for MPE notes.
+    Timbre,
+    /// Polyphonic key pressure, which each note has its own of.
+    PolyAftertouch,
+    /// Sample and hold on random values, shared by every voice.
+    Random,
+}
+
+impl ModSource {
+    pub const ALL: [ModSource; 10] = [
+        ModSource::Lfo1,
+        ModSource::Lfo2,
+        ModSource::ModEnv,
+        ModSource::Velocity,
+        ModSource::KeyTrack,
+        ModSource::ModWheel,
+        ModSource::Aftertouch,
+        ModSource::Timbre,
+        ModSource::PolyAftertouch,
+        ModSource::Random,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ModSource::Lfo1 => "LFO 1",
+            ModSource::Lfo2 => "LFO 2",
+            ModSource::ModEnv => "Mod Env",
+            ModSource::Velocity => "Velocity",
+            ModSource::KeyTrack => "Key Track",
+            ModSource::ModWheel => "Mod Wheel",
+            ModSource::Aftertouch => "Aftertouch",
+            ModSource::Timbre => "Timbre",
+            ModSource::PolyAftertouch => "Poly AT",
+            ModSource::Random => "Random",
+        }
+    }
+
+    /// Whether the source swings either side of 0.0, rather than going from 0.0 to 1.0.
+    fn is_bipolar(self) -> bool {
+        matches!(self, ModSource::Lfo1 | ModSource::Lfo2 | ModSource::KeyTrack | ModSource::Random)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ModDestination {
+    Pitch,
+    Amplitude,
+    Cutoff,
+    Pan,
+    /// Crossfades from the oscillator we'd normally hear to the other one of the sync pair.
+    OscMix,
+    PulseWidth,
+    /// The sync pair's frequency ratio, for sync sweeps.
+    SyncRatio,
+    /// How far through the wavetable the oscillators read.
+    WavetablePosition,
+}
+
+impl ModDestination {
+    pub const ALL: [ModDestination; 8] = [
+        ModDestination::Pitch,
+        ModDestination::Amplitude,
+        ModDestination::Cutoff,
+        ModDestination::Pan,
+        ModDestination::OscMix,
+        ModDestination::PulseWidth,
+        ModDestination::SyncRatio,
+        ModDestination::WavetablePosition,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ModDestination::Pitch => "Pitch",
+            ModDestination::Amplitude => "Amplitude",
+            ModDestination::Cutoff => "Cutoff",
+            ModDestination::Pan => "Pan",
+            ModDestination::OscMix => "Osc Mix",
+            ModDestination::PulseWidth => "Pulse Width",
+            ModDestination::SyncRatio => "Sync Ratio",
+            ModDestination::WavetablePosition => "WT Pos",
+        }
+    }
+}
+
+/// One routing in the matrix, read from the shared plugin parameters once per buffer.
+#[derive(Clone, Copy, Debug)]
+pub struct ModSlot {
+    pub source: ModSource,
+    pub destination: ModDestination,
+    /// -1.0 to 1.0, where 0.0 turns the slot off.
+    pub depth: f64,
+}
+
+/// Modulation shared by every voice, updated once per block.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Modulation {
+    /// A frequency multiplier, eg. from pitch bend and vibrato.
+    pub pitch_ratio: f64,
+    /// Each LFO's output, -1.0 to 1.0.
+    pub lfos: [f64; NUM_LFOS],
+    /// The random source's output, -1.0 to 1.0.
+    pub random: f64,
+    /// 0.0 to 1.0.
+    pub mod_wheel: f64,
+    /// 0.0 to 1.0.
+    pub aftertouch: f64,
+    /// 0.0 to 1.0.
+    pub timbre: f64,
+}
+
+/// The sum of the matrix's modulation of each destination, in the units each one uses.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Targets {
+    pub semitones: f64,
+    pub cutoff_octaves: f64,
+    /// Multiplies the level, 0.0 to 1.0.
+    pub gain: f64,
+    /// -1.0 (left) to 1.0 (right).
+    pub pan: f64,
+    /// 0.0 to 1.0.
+    pub osc_mix: f64,
+    /// Added to the pulse width, as a fraction of the cycle.
+    pub pulse_width: f64,
+    /// How far the sync ratio is raised or lowered, in octaves.
+    pub sync_octaves: f64,
+    /// Added to the wavetable position, from 0.0 at the first frame to 1.0 at the last.
+    pub wavetable_position: f64,
+}
+
+impl Targets {
+    /// Sum up the matrix, reading each source's current value from `value`.
+    pub fn evaluate(slots: &[ModSlot], value: impl Fn(ModSource) -> f64) -> Targets {
+        let mut targets = Targets {
+            semitones: 0.0,
+            cutoff_octaves: 0.0,
+            gain: 1.0,
+            pan: 0.0,
+            osc_mix: 0.0,
+            pulse_width: 0.0,
+            sync_octaves: 0.0,
+            wavetable_position: 0.0,
+        };
+        for slot in slots.iter().filter(|slot| slot.depth != 0.0) {
+            let source = value(slot.source);
+            let amount = source * slot.depth;
+            match slot.destination {
+                ModDestination::Pitch => targets.semitones += amount * MAX_PITCH_DEPTH,
+                ModDestination::Cutoff => targets.cutoff_octaves += amount * MAX_CUTOFF_DEPTH,
+                ModDestination::Pan => targets.pan += amount,
+                ModDestination::OscMix => targets.osc_mix += amount,
+                ModDestination::PulseWidth => targets.pulse_width += amount * MAX_PULSE_WIDTH_DEPTH,
+                ModDestination::SyncRatio => targets.sync_octaves += amount * MAX_SYNC_RATIO_DEPTH,
+                ModDestination::WavetablePosition => targets.wavetable_position += amount,
+                ModDestination::Amplitude => {
+                    // level only ever dips below full, so modulation can't clip. a positive depth
+                    // is quietest when the source is lowest, and a negative depth the reverse.
+                    let unipolar = if slot.source.is_bipolar() { (source + 1.0) / 2.0 } else { source };
+                    let unipolar = if slot.depth > 0.0 { unipolar } else { 1.0 - unipolar };
+                    targets.gain *= 1.0 - slot.depth.abs() * (1.0 - unipolar.clamp(0.0, 1.0));
+                }
+            }
+        }
+        targets.pan = targets.pan.clamp(-1.0, 1.0);
+        targets.osc_mix = targets.osc_mix.clamp(0.0, 1.0);
+        targets
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn slot(source: ModSource, destination: ModDestination, depth: f64) -> ModSlot {
+        ModSlot { source, destination, depth }
+    }
+
+    #[test]
+    fn test_empty_matrix_does_nothing() {
+        let slots = [slot(ModSource::Lfo1, ModDestination::Pitch, 0.0); NUM_MOD_SLOTS];
+        let targets = Targets::evaluate(&slots, |_| 1.0);
+        assert_eq!(
+            targets,
+            Targets {
+                semitones: 0.0,
+                cutoff_octaves: 0.0,
+                gain: 1.0,
+                pan: 0.0,
+                osc_mix: 0.0,
+                pulse_width: 0.0,
+                sync_octaves: 0.0,
+                wavetable_position: 0.0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_slots_sum() {
+        let slots = [
+            slot(ModSource::Lfo1, ModDestination::Cutoff, 0.5),
+            slot(ModSource::Lfo2, ModDestination::Cutoff, -0.25),
+            slot(ModSource::ModWheel, ModDestination::Pan, 1.0),
+            slot(ModSource::Aftertouch, ModDestination::Pan, 1.0),
+        ];
+        let targets = Targets::evaluate(&slots, |_| 1.0);
+        assert_eq!(targets.cutoff_octaves, 0.25 * MAX_CUTOFF_DEPTH);
+        // clamped to hard right
+        assert_eq!(targets.pan, 1.0);
+    }
+
+    #[test]
+    fn test_amplitude_depth() {
+        let velocity = |depth, value| {
+            Targets::evaluate(&[slot(ModSource::Velocity, ModDestination::Amplitude, depth)], |_| value).gain
+        };
+        assert_eq!(velocity(1.0, 0.25), 0.25);
+        assert_eq!(velocity(0.5, 0.0), 0.5);
+        assert_eq!(velocity(-1.0, 0.25), 0.75);
+
+        // bipolar sources dip to silence at their lowest
+        let lfo = |value| Targets::evaluate(&[slot(ModSource::Lfo1, ModDestination::Amplitude, 1.0)], |_| value).gain;
+        assert_eq!(lfo(-1.0), 0.0);
+        assert_eq!(lfo(1.0), 1.0);
+    }
+}
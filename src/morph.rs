@@ -0,0 +1,92 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::atomic_float::AtomicFloat;
+use crate::lerp;
+
+/// One of the two patches kept for comparing and morphing between.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Slot {
+    A,
+    B,
+}
+
+#[cfg_attr(not(feature = "gui"), allow(dead_code))]
+impl Slot {
+    pub const ALL: [Slot; 2] = [Slot::A, Slot::B];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Slot::A => "A",
+            Slot::B => "B",
+        }
+    }
+}
+
+/// The A and B patches, as every parameter's normalized value by host index.
+///
+/// The morph between them can be automated, which moves the parameters on the audio thread, so
+/// they're kept in atomics rather than behind a lock.
+pub struct AbSlots {
+    values: [Vec<AtomicFloat>; 2],
+    stored: [AtomicBool; 2],
+}
+
+impl AbSlots {
+    pub fn new(len: usize) -> AbSlots {
+        AbSlots {
+            values: [(); 2].map(|_| (0..len).map(|_| AtomicFloat::new(0.0)).collect()),
+            stored: [AtomicBool::new(false), AtomicBool::new(false)],
+        }
+    }
+
+    /// Keep the patch's values in a slot, replacing what was there.
+    pub fn store(&self, slot: Slot, values: impl IntoIterator<Item = f32>) {
+        for (stored, value) in self.values[slot as usize].iter().zip(values) {
+            stored.set(value);
+        }
+        self.stored[slot as usize].store(true, Ordering::Release);
+    }
+
+    /// Whether a patch has been stored in the slot.
+    pub fn is_stored(&self, slot: Slot) -> bool {
+        self.stored[slot as usize].load(Ordering::Acquire)
+    }
+
+    /// A parameter's value in a slot, if one's been stored.
+    pub fn value(&self, slot: Slot, index: usize) -> Option<f32> {
+        Some(self.values[slot as usize].get(index)?.get()).filter(|_| self.is_stored(slot))
+    }
+
+    /// A parameter's value `amount` of the way from A to B, or `None` if the two don't differ,
+    /// or either hasn't been stored. `stepped` parameters can't be in between, so they switch from
+    /// A to B halfway.
+    pub fn morphed(&self, index: usize, amount: f32, stepped: bool) -> Option<f32> {
+        let (a, b) = (self.value(Slot::A, index)?, self.value(Slot::B, index)?);
+        if a == b {
+            None
+        } else if stepped {
+            Some(if amount < 0.5 { a } else { b })
+        } else {
+            Some(lerp(f64::from(a), f64::from(b), f64::from(amount)) as f32)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_morph_between_slots() {
+        let slots = AbSlots::new(3);
+        slots.store(Slot::A, vec![0.0, 0.5, 0.0]);
+        assert_eq!(slots.morphed(0, 0.5, false), None);
+
+        slots.store(Slot::B, vec![1.0, 0.5, 1.0]);
+        assert_eq!(slots.morphed(0, 0.25, false), Some(0.25));
+        assert_eq!(slots.morphed(1, 0.25, false), None);
+        assert_eq!(slots.morphed(2, 0.25, true), Some(0.0));
+        assert_eq!(slots.morphed(2, 0.75, true), Some(1.0));
+        assert_eq!(slots.morphed(3, 0.75, false), None);
+    }
+}
@@ -0,0 +1,91 @@
+use std::os::raw::c_char;
+use std::path::PathBuf;
+
+use libloading::Library;
+use log::{debug, info};
+
+use crate::tuning::Tuning;
+
+/// A connection to MTS-ESP, which lets a master plugin elsewhere in the session retune all of its
+/// clients.
+///
+/// The master and its clients share their tuning through the MTS-ESP library, which is installed
+/// separately. Without it, or until a master is added to the session, we keep our own tuning.
+pub struct MtsClient {
+    has_master: unsafe extern "C" fn() -> bool,
+    should_filter_note: unsafe extern "C" fn(c_char, c_char) -> bool,
+    get_tuning_table: unsafe extern "C" fn() -> *const f64,
+    deregister: unsafe extern "C" fn(),
+    // the functions above point into the library, so it has to outlive them
+    _library: Library,
+}
+
+impl MtsClient {
+    /// Load the MTS-ESP library and register as a client, or `None` if it isn't installed.
+    pub fn connect() -> Option<MtsClient> {
+        let path = library_path()?;
+        // loading runs the library's initialisers, which for MTS-ESP only set up its shared memory
+        let library = match unsafe { Library::new(&path) } {
+            Ok(library) => library,
+            Err(err) => {
+                debug!("MTS-ESP isn't available from {}: {}", path.display(), err);
+                return None;
+            }
+        };
+
+        // the signatures are those of the MTS-ESP client library
+        let client = unsafe {
+            let register = *library.get::<unsafe extern "C" fn()>(b"MTS_RegisterClient\0").ok()?;
+            let client = MtsClient {
+                has_master: *library.get(b"MTS_HasMaster\0").ok()?,
+                should_filter_note: *library.get(b"MTS_ShouldFilterNote\0").ok()?,
+                get_tuning_table: *library.get(b"MTS_GetTuningTable\0").ok()?,
+                deregister: *library.get(b"MTS_DeregisterClient\0").ok()?,
+                _library: library,
+            };
+            register();
+            client
+        };
+        info!("registered as an MTS-ESP client");
+        Some(client)
+    }
+
+    /// The master's tuning, or `None` if there's no master in the session.
+    ///
+    /// Notes the master filters out are left unmapped, so they don't play. Safe to call from the
+    /// audio thread, as MTS-ESP shares the table without locking.
+    pub fn tuning(&self) -> Option<Tuning> {
+        unsafe {
+            if !(self.has_master)() {
+                return None;
+            }
+            let table = (self.get_tuning_table)();
+            if table.is_null() {
+                return None;
+            }
+            // the table always has a frequency for each of the 128 midi notes
+            let freqs = *(table as *const [f64; 128]);
+            // we only take notes on channel 1
+            let mapped = std::array::from_fn(|note| !(self.should_filter_note)(note as c_char, 0));
+            Some(Tuning::from_table(freqs, mapped))
+        }
+    }
+}
+
+impl Drop for MtsClient {
+    fn drop(&mut self) {
+        unsafe { (self.deregister)() }
+    }
+}
+
+/// Where the MTS-ESP installer puts the library.
+fn library_path() -> Option<PathBuf> {
+    if cfg!(target_os = "windows") {
+        let common = std::env::var_os("CommonProgramFiles")?;
+        Some(PathBuf::from(common).join("MTS-ESP").join("LIBMTS.dll"))
+    } else if cfg!(target_os = "macos") {
+        Some(PathBuf::from("/Library/Application Support/MTS-ESP/libMTS.dylib"))
+    } else {
+        Some(PathBuf::from("/usr/local/lib/libMTS.so"))
+    }
+}
@@ -0,0 +1,144 @@
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use crate::atomic_float::AtomicFloat;
+use crate::params::ReplicantParameters;
+use crate::presets::Preset;
+use crate::synth::Synth;
+
+/// How many parts multi-timbral mode plays: one for each midi channel but the first, which plays
+/// the instance's own patch.
+pub const NUM_PARTS: usize = 15;
+
+/// Builds multi-timbral mode's parts, and loads the presets their program changes ask for.
+///
+/// Each part is a whole synth, with effects and delay lines of its own, so they're only built once
+/// the mode is first switched on, and on the loader thread rather than the audio thread. The audio
+/// thread asks for them with `take` until they're ready.
+#[derive(Default)]
+pub struct PartBuilder {
+    // the sample rate the parts were last asked for at, so they're only asked for once
+    asked: AtomicFloat,
+    // the parts, once built, and the sample rate they were built at
+    built: Mutex<Option<(f32, Vec<Synth>)>>,
+    // each part's parameters, and the sample rate they were built at, kept for loading presets
+    // once the audio thread has taken the parts themselves
+    part_params: Mutex<(f32, Vec<Arc<ReplicantParameters>>)>,
+}
+
+impl PartBuilder {
+    /// Ask `params`' loader thread for the parts at `sample_rate`, returning them once they've
+    /// been built. This never waits on the lock, so it's safe on the audio thread.
+    pub fn take(&self, sample_rate: f64, params: &ReplicantParameters) -> Option<Vec<Synth>> {
+        let sample_rate = sample_rate as f32;
+        if self.asked.get() != sample_rate && params.request_parts(sample_rate) {
+            self.asked.set(sample_rate);
+        }
+        let mut built = self.built.try_lock().ok()?;
+        // parts built before the rate changed are put back, for the loader to replace
+        match built.take() {
+            Some((rate, parts)) if rate == sample_rate => Some(parts),
+            other => {
+                *built = other;
+                None
+            }
+        }
+    }
+
+    /// Build the parts at `rate`, unless they already have been, returning whether they were. This
+    /// allocates, so it mustn't be called on the audio thread.
+    pub fn build(&self, rate: f32) -> bool {
+        let mut part_params = self.part_params();
+        if part_params.0 == rate {
+            return false;
+        }
+        let parts: Vec<Synth> = (0..NUM_PARTS)
+            .map(|_| {
+                let mut part = Synth::part(Arc::new(ReplicantParameters::init()));
+                part.set_sample_rate(rate);
+                part
+            })
+            .collect();
+        *part_params = (rate, parts.iter().map(|part| Arc::clone(part.params())).collect());
+        *self.built.lock().unwrap_or_else(PoisonError::into_inner) = Some((rate, parts));
+        true
+    }
+
+    /// Load a preset into one of the parts, if they've been built.
+    pub fn load_preset(&self, part: usize, preset: &Preset) {
+        if let Some(part) = self.part_params().1.get(part) {
+            load_part_preset(part, preset);
+        }
+    }
+
+    fn part_params(&self) -> MutexGuard<'_, (f32, Vec<Arc<ReplicantParameters>>)> {
+        self.part_params.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Load a preset into a part. The volume and pan its channel set are kept, and so is the setup
+/// that makes it play its channel's midi, which always arrives on the first channel.
+pub fn load_part_preset(part: &ReplicantParameters, preset: &Preset) {
+    let (gain, pan) = (part.master_gain.get(), part.master_pan.get());
+    preset.apply(part);
+    part.master_gain.set(gain);
+    part.master_pan.set(pan);
+    part.mpe.set(0.0);
+    part.midi_channel.set(0.0);
+    part.multitimbral.set(0.0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader::wait_for;
+
+    #[test]
+    fn test_parts_are_built_at_the_wanted_rate() {
+        let builder = PartBuilder::default();
+        // without a loader thread nothing builds them
+        let params = ReplicantParameters::init();
+        assert!(builder.take(48000.0, &params).is_none());
+        assert!(builder.build(48000.0));
+        // parts for an old rate aren't handed over
+        assert!(builder.take(96000.0, &params).is_none());
+        assert!(builder.build(96000.0));
+        assert_eq!(builder.take(96000.0, &params).map(|parts| parts.len()), Some(NUM_PARTS));
+        // and once they have been, no more are built
+        assert!(!builder.build(96000.0));
+    }
+
+    #[test]
+    fn test_loader_builds_parts_and_loads_their_presets() {
+        let builder = Arc::new(PartBuilder::default());
+        let params = Arc::new(ReplicantParameters::default());
+        ReplicantParameters::spawn_loader_thread(&params, Arc::downgrade(&builder));
+        let mut parts = None;
+        wait_for(|| {
+            parts = builder.take(44100.0, &params);
+            parts.is_some()
+        });
+        let parts = parts.unwrap();
+        assert_eq!(parts.len(), NUM_PARTS);
+
+        let preset = params.preset(5).unwrap();
+        let expected = ReplicantParameters::init();
+        load_part_preset(&expected, &preset);
+        params.request_part_preset(2, 5);
+        wait_for(|| parts[2].params().normalized_values() == expected.normalized_values());
+    }
+
+    #[test]
+    fn test_part_presets_keep_the_part_setup() {
+        let part = ReplicantParameters::init();
+        part.master_gain.set(-12.0);
+        let preset_params = ReplicantParameters::init();
+        preset_params.mpe.set(1.0);
+        preset_params.midi_channel.set(3.0);
+        preset_params.cutoff.set(1234.0);
+        load_part_preset(&part, &Preset::capture("Preset", &preset_params));
+        assert!((part.cutoff.get() - 1234.0).abs() < 0.01);
+        assert_eq!(part.master_gain.get(), -12.0);
+        assert!(!part.mpe());
+        assert_eq!(part.midi_channel(), None);
+    }
+}
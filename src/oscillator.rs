@@ -0,0 +1,495 @@
+use std::f64::consts::PI;
+
+const TAU: f64 = PI * 2.0;
+
+/// The narrowest and widest the square wave's pulse can be, as a fraction of the cycle. Any
+/// further and it thins out to nothing.
+pub const MIN_PULSE_WIDTH: f64 = 0.05;
+pub const MAX_PULSE_WIDTH: f64 = 0.95;
+
+/// The shape of an oscillator's output over one cycle.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Waveform {
+    Sine,
+    Saw,
+    Square,
+    Triangle,
+    /// Reads the loaded wavetable, at the wavetable position.
+    Wavetable,
+    /// Sine harmonics mixed at the harmonic levels, like an organ's drawbars.
+    Additive,
+}
+
+impl Waveform {
+    pub const ALL: [Waveform; 6] = [
+        Waveform::Sine,
+        Waveform::Saw,
+        Waveform::Square,
+        Waveform::Triangle,
+        Waveform::Wavetable,
+        Waveform::Additive,
+    ];
+
+    /// The waveform's value at `phase`, in cycles from 0.0 to 1.0.
+    ///
+    /// `pulse_width` is the fraction of the cycle the square wave spends high, where 0.5 is a
+    /// true square. The other waveforms ignore it. The voice reads `Wavetable` and `Additive`
+    /// from their own tables, so here they're the sine the built in table and the default
+    /// harmonic levels start on.
+    pub fn sample(self, phase: f64, pulse_width: f64) -> f64 {
+        match self {
+            Waveform::Sine | Waveform::Wavetable | Waveform::Additive => (phase * TAU).sin(),
+            Waveform::Saw => 2.0 * phase - 1.0,
+            Waveform::Square => if phase < pulse_width { 1.0 } else { -1.0 },
+            Waveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+        }
+    }
+
+    /// Like `sample()`, but with the discontinuities smoothed by PolyBLEP to reduce aliasing.
+    ///
+    /// `phase_inc` is the oscillator's frequency divided by the sample rate. The triangle's
+    /// harmonics already fall off quickly enough that it's left uncorrected.
+    pub fn sample_bandlimited(self, phase: f64, phase_inc: f64, pulse_width: f64) -> f64 {
+        match self {
+            Waveform::Saw => self.sample(phase, pulse_width) - poly_blep(phase, phase_inc),
+            // a rising edge at the start of the cycle and a falling one at the pulse width
+            Waveform::Square => {
+                self.sample(phase, pulse_width)
+                    + poly_blep(phase, phase_inc)
+                    - poly_blep((phase + 1.0 - pulse_width).fract(), phase_inc)
+            },
+            Waveform::Sine | Waveform::Triangle | Waveform::Wavetable | Waveform::Additive => {
+                self.sample(phase, pulse_width)
+            }
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Waveform::Sine => "Sine",
+            Waveform::Saw => "Saw",
+            Waveform::Square => "Square",
+            Waveform::Triangle => "Triangle",
+            Waveform::Wavetable => "Wavetable",
+            Waveform::Additive => "Additive",
+        }
+    }
+}
+
+/// Something an oscillator can play, with its value at each point in the cycle.
+pub trait Shape {
+    /// The value at `phase`, in cycles from 0.0 to 1.0.
+    fn sample(&self, phase: f64) -> f64;
+
+    /// The value at `phase`, with nothing that would alias at `phase_inc`, the oscillator's
+    /// frequency divided by the sample rate.
+    fn sample_bandlimited(&self, phase: f64, phase_inc: f64) -> f64;
+}
+
+/// One of the basic waveforms, with its pulse width.
+struct Basic(Waveform, f64);
+
+impl Shape for Basic {
+    fn sample(&self, phase: f64) -> f64 {
+        self.0.sample(phase, self.1)
+    }
+
+    fn sample_bandlimited(&self, phase: f64, phase_inc: f64) -> f64 {
+        self.0.sample_bandlimited(phase, phase_inc, self.1)
+    }
+}
+
+/// Polynomial approximation of a band-limited step, to be added at each discontinuity of a naive
+/// waveform.
+///
+/// `phase` is the position in the cycle, with the discontinuity at 0.0, and `phase_inc` the phase
+/// advance per sample. Only the samples within one `phase_inc` either side of the step are
+/// affected.
+fn poly_blep(phase: f64, phase_inc: f64) -> f64 {
+    if phase_inc <= 0.0 {
+        0.0
+    } else if phase < phase_inc {
+        let t = phase / phase_inc;
+        2.0 * t - t * t - 1.0
+    } else if phase > 1.0 - phase_inc {
+        let t = (phase - 1.0) / phase_inc;
+        t * t + 2.0 * t + 1.0
+    } else {
+        0.0
+    }
+}
+
+/// A master/slave pair of oscillators.
+///
+/// Phases are kept in cycles (0.0 to 1.0) rather than radians so that wrapping is exact. When
+/// hard sync is enabled, the slave's phase is reset every time the master completes a cycle, and
+/// the jump this makes in the slave's waveform is smoothed by PolyBLEP like its other edges.
+#[derive(Debug, Default)]
+pub struct SyncedOscillator {
+    master_phase: f64,
+    slave_phase: f64,
+    // added to the next slave sample, to finish smoothing a reset that happened before it
+    sync_correction: f64,
+}
+
+impl SyncedOscillator {
+    /// An oscillator pair starting `phase` cycles in, from 0.0 to 1.0.
+    pub fn with_phase(phase: f64) -> SyncedOscillator {
+        SyncedOscillator { master_phase: phase, slave_phase: phase, sync_correction: 0.0 }
+    }
+
+    /// Advance both oscillators by one sample, returning the `(master, slave)` outputs.
+    ///
+    /// `phase_inc` is the master frequency divided by the sample rate. The slave runs at
+    /// `sync_ratio` times the master frequency. `phase_offset`, in cycles, shifts where both
+    /// waveforms are read for phase modulation, without changing the phases themselves. The sync
+    /// edge smoothing doesn't allow for it, so it's only approximate under FM.
+    pub fn next_sample(
+        &mut self,
+        waveform: Waveform,
+        pulse_width: f64,
+        phase_inc: f64,
+        sync_ratio: f64,
+        sync: bool,
+        phase_offset: f64,
+    ) -> (f64, f64) {
+        self.next_shape_sample(&Basic(waveform, pulse_width), phase_inc, sync_ratio, sync, phase_offset)
+    }
+
+    /// Like `next_sample()`, but playing any shape, such as a point in a wavetable.
+    pub fn next_shape_sample<S: Shape>(
+        &mut self,
+        shape: &S,
+        phase_inc: f64,
+        sync_ratio: f64,
+        sync: bool,
+        phase_offset: f64,
+    ) -> (f64, f64) {
+        let slave_inc = phase_inc * sync_ratio;
+        let master = shape.sample_bandlimited((self.master_phase + phase_offset).rem_euclid(1.0), phase_inc);
+        let mut slave = shape.sample_bandlimited((self.slave_phase + phase_offset).rem_euclid(1.0), slave_inc)
+            + self.sync_correction;
+        self.sync_correction = 0.0;
+
+        if sync && self.master_phase + phase_inc >= 1.0 {
+            // the master wraps `until_reset` samples from now, cutting the slave off at
+            // `reset_from`. smooth the step on this sample, and the next one after the reset.
+            let until_reset = (1.0 - self.master_phase) / phase_inc;
+            let reset_from = (self.slave_phase + slave_inc * until_reset).fract();
+            let step = shape.sample(0.0) - shape.sample(reset_from);
+            slave += step / 2.0 * poly_blep(1.0 - until_reset * slave_inc, slave_inc);
+            // after the reset the slave's phase is just past 0.0, where sample_bandlimited()
+            // already smooths the step it would take from the very end of its cycle, so only
+            // the difference from that is left
+            let natural_end = shape.sample(1.0);
+            let after_reset = (1.0 - until_reset) * slave_inc;
+            self.sync_correction = (natural_end - shape.sample(reset_from)) / 2.0
+                * poly_blep(after_reset, slave_inc);
+        }
+
+        self.master_phase += phase_inc;
+        self.slave_phase += slave_inc;
+
+        if self.master_phase >= 1.0 {
+            self.master_phase = self.master_phase.fract();
+            if sync {
+                // restart from where the slave would be now if it had restarted with the master
+                self.slave_phase = self.master_phase * sync_ratio;
+            }
+        }
+        self.slave_phase = self.slave_phase.fract();
+
+        (master, slave)
+    }
+}
+
+/// A sine oscillator that phase modulates another, for FM.
+#[derive(Debug, Default)]
+pub struct Modulator {
+    // in cycles, 0.0 to 1.0
+    phase: f64,
+}
+
+impl Modulator {
+    /// Return the current output and advance by one sample. `phase_inc` is the frequency divided
+    /// by the sample rate.
+    pub fn next_sample(&mut self, phase_inc: f64) -> f64 {
+        let output = (self.phase * TAU).sin();
+        self.phase = (self.phase + phase_inc).fract();
+        output
+    }
+}
+
+/// The sub-oscillator's shape.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SubWaveform {
+    Sine,
+    Square,
+}
+
+impl SubWaveform {
+    pub const ALL: [SubWaveform; 2] = [SubWaveform::Sine, SubWaveform::Square];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            SubWaveform::Sine => "Sine",
+            SubWaveform::Square => "Square",
+        }
+    }
+}
+
+/// A simple oscillator an octave or two below the note, to fill out the low end.
+#[derive(Debug, Default)]
+pub struct SubOscillator {
+    // in cycles, 0.0 to 1.0
+    phase: f64,
+}
+
+impl SubOscillator {
+    /// Return the current output and advance by one sample. `phase_inc` is the frequency divided
+    /// by the sample rate.
+    pub fn next_sample(&mut self, waveform: SubWaveform, phase_inc: f64) -> f64 {
+        let output = match waveform {
+            SubWaveform::Sine => Waveform::Sine.sample(self.phase, 0.5),
+            SubWaveform::Square => Waveform::Square.sample_bandlimited(self.phase, phase_inc, 0.5),
+        };
+        self.phase = (self.phase + phase_inc).fract();
+        output
+    }
+}
+
+/// The noise source's spectrum.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NoiseColor {
+    /// Equal energy at every frequency.
+    White,
+    /// Equal energy in every octave, so it sounds darker and fuller than white noise.
+    Pink,
+}
+
+impl NoiseColor {
+    pub const ALL: [NoiseColor; 2] = [NoiseColor::White, NoiseColor::Pink];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            NoiseColor::White => "White",
+            NoiseColor::Pink => "Pink",
+        }
+    }
+}
+
+/// Scales the pinking filter's output down near full scale. Its peaks are much higher relative
+/// to its rms than white noise's, so it ends up a few dB quieter.
+const PINK_GAIN: f64 = 0.25;
+
+/// A noise generator, from a xorshift random number generator.
+#[derive(Debug)]
+pub struct Noise {
+    // never zero, or the generator gets stuck there
+    state: u32,
+    // the pinking filter's state
+    pink: [f64; 3],
+}
+
+impl Noise {
+    /// Noise from a sequence picked by `seed`, so different voices can be given different noise.
+    pub fn new(seed: u32) -> Noise {
+        Noise { state: seed.max(1), pink: [0.0; 3] }
+    }
+
+    /// The next sample, from -1.0 to 1.0.
+    pub fn next_sample(&mut self, color: NoiseColor) -> f64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+        let white = f64::from(self.state) / f64::from(u32::MAX) * 2.0 - 1.0;
+
+        // Paul Kellet's economy pinking filter, which is within 0.6dB of -3dB per octave. it's
+        // kept running even while white noise is picked, so switching colors doesn't click.
+        let [b0, b1, b2] = &mut self.pink;
+        *b0 = 0.99765 * *b0 + white * 0.099_046;
+        *b1 = 0.963 * *b1 + white * 0.296_516_4;
+        *b2 = 0.57 * *b2 + white * 1.052_691_3;
+        match color {
+            NoiseColor::White => white,
+            NoiseColor::Pink => (*b0 + *b1 + *b2 + white * 0.1848) * PINK_GAIN,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render_slave(sync: bool, periods: usize) -> Vec<f64> {
+        let mut osc = SyncedOscillator::default();
+        // exactly 64 samples per master cycle
+        (0..64 * periods)
+            .map(|_| osc.next_sample(Waveform::Sine, 0.5, 1.0 / 64.0, 2.5, sync, 0.0).1)
+            .collect()
+    }
+
+    #[test]
+    fn test_hard_sync_restarts_slave_with_master() {
+        let slave = render_slave(true, 4);
+        let first_period = &slave[..64];
+        // the same but for rounding, as the reset smoothing rounds the sine's zero crossing
+        for period in slave.chunks(64) {
+            assert!(period.iter().zip(first_period).all(|(a, b)| (a - b).abs() < 1e-12));
+        }
+    }
+
+    #[test]
+    fn test_sync_reset_is_smoothed() {
+        // the master wraps halfway between two samples, while the slave saw is halfway up its
+        // ramp at 0.1, so it drops by 1.1 to -1.0
+        let mut osc = SyncedOscillator { master_phase: 0.95, slave_phase: 0.5, sync_correction: 0.0 };
+        let (_, before) = osc.next_sample(Waveform::Saw, 0.5, 0.1, 1.0, true, 0.0);
+        let (_, after) = osc.next_sample(Waveform::Saw, 0.5, 0.1, 1.0, true, 0.0);
+        assert!((osc.slave_phase - 0.15).abs() < 1e-12);
+
+        // the samples either side are pulled towards each other by the same amount
+        let (naive_before, naive_after) = (Waveform::Saw.sample(0.5, 0.5), Waveform::Saw.sample(0.05, 0.5));
+        assert!((before - (naive_before - 1.1 / 8.0)).abs() < 1e-9, "before was {}", before);
+        assert!((after - (naive_after + 1.1 / 8.0)).abs() < 1e-9, "after was {}", after);
+    }
+
+    #[test]
+    fn test_free_running_slave_drifts_from_master() {
+        let slave = render_slave(false, 2);
+        assert!(slave[..64].iter().zip(&slave[64..]).any(|(a, b)| (a - b).abs() > 1e-6));
+    }
+
+    #[test]
+    fn test_phase_stays_wrapped_over_long_runs() {
+        let mut osc = SyncedOscillator::default();
+        // a frequency that doesn't divide the sample rate evenly, for a minute at 44.1kHz
+        let phase_inc = 261.63 / 44100.0;
+        for _ in 0..44100 * 60 {
+            osc.next_sample(Waveform::Saw, 0.5, phase_inc, 1.5, false, 0.0);
+        }
+        assert!((0.0..1.0).contains(&osc.master_phase));
+        assert!((0.0..1.0).contains(&osc.slave_phase));
+
+        // the accumulated phase matches the exact one to well within a sample
+        let exact = (phase_inc * (44100 * 60) as f64).fract();
+        assert!((osc.master_phase - exact).abs() < phase_inc / 100.0);
+    }
+
+    #[test]
+    fn test_phase_offset_shifts_the_waveform() {
+        let mut osc = SyncedOscillator::default();
+        let mut shifted = SyncedOscillator::default();
+        for _ in 0..100 {
+            let (sine, _) = osc.next_sample(Waveform::Sine, 0.5, 0.01, 1.0, false, 0.0);
+            // a quarter cycle back turns sine into -cosine, and a whole cycle changes nothing
+            let (behind, _) = shifted.next_sample(Waveform::Sine, 0.5, 0.01, 1.0, false, -1.25);
+            let phase = (osc.master_phase - 0.01).rem_euclid(1.0);
+            assert!((sine - (phase * TAU).sin()).abs() < 1e-9);
+            assert!((behind + (phase * TAU).cos()).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_sub_oscillator_shapes() {
+        let mut sine = SubOscillator::default();
+        let mut square = SubOscillator::default();
+        for i in 0..64 {
+            let phase = f64::from(i) / 64.0;
+            assert!((sine.next_sample(SubWaveform::Sine, 1.0 / 64.0) - (phase * TAU).sin()).abs() < 1e-9);
+            // away from the edges the band-limited square is a plain square
+            let sample = square.next_sample(SubWaveform::Square, 1.0 / 64.0);
+            if i % 32 != 0 && i % 32 != 31 {
+                assert_eq!(sample, if phase < 0.5 { 1.0 } else { -1.0 });
+            }
+        }
+    }
+
+    #[test]
+    fn test_noise_colors() {
+        let render = |color| {
+            let mut noise = Noise::new(1234);
+            (0..44100).map(|_| noise.next_sample(color)).collect::<Vec<f64>>()
+        };
+        let rms = |samples: &[f64]| (samples.iter().map(|s| s * s).sum::<f64>() / samples.len() as f64).sqrt();
+        // how much the noise changes from one sample to the next, which is mostly its highs
+        let roughness = |samples: &[f64]| {
+            let steps: Vec<f64> = samples.windows(2).map(|pair| pair[1] - pair[0]).collect();
+            rms(&steps) / rms(samples)
+        };
+
+        let (white, pink) = (render(NoiseColor::White), render(NoiseColor::Pink));
+        assert!(white.iter().all(|sample| sample.abs() <= 1.0));
+        // uniform noise from -1.0 to 1.0 has an rms of 1/sqrt(3), and pink is a little quieter
+        assert!((rms(&white) - 3.0f64.sqrt().recip()).abs() < 0.01);
+        assert!((0.6..1.0).contains(&(rms(&pink) / rms(&white))), "pink rms was {}", rms(&pink));
+        // consecutive white noise samples are unrelated, while pink noise is much smoother
+        assert!((roughness(&white) - 2.0f64.sqrt()).abs() < 0.05);
+        assert!(roughness(&pink) < 0.5 * roughness(&white));
+    }
+
+    #[test]
+    fn test_waveform_shapes() {
+        for &waveform in &Waveform::ALL {
+            for i in 0..64 {
+                let sample = waveform.sample(f64::from(i) / 64.0, 0.5);
+                assert!((-1.0..=1.0).contains(&sample), "{:?}", waveform);
+            }
+        }
+
+        assert_eq!(Waveform::Saw.sample(0.0, 0.5), -1.0);
+        assert_eq!(Waveform::Saw.sample(0.5, 0.5), 0.0);
+        assert_eq!(Waveform::Square.sample(0.25, 0.5), 1.0);
+        assert_eq!(Waveform::Square.sample(0.75, 0.5), -1.0);
+        assert_eq!(Waveform::Triangle.sample(0.0, 0.5), -1.0);
+        assert_eq!(Waveform::Triangle.sample(0.5, 0.5), 1.0);
+        assert_eq!(Waveform::Triangle.sample(0.25, 0.5), 0.0);
+    }
+
+    #[test]
+    fn test_pulse_width() {
+        // a quarter of the cycle high, and three quarters low, so it averages out at -0.5
+        let pulse: Vec<f64> = (0..64).map(|i| Waveform::Square.sample(f64::from(i) / 64.0, 0.25)).collect();
+        assert!(pulse[..16].iter().all(|&sample| sample == 1.0));
+        assert!(pulse[16..].iter().all(|&sample| sample == -1.0));
+        assert_eq!(pulse.iter().sum::<f64>() / 64.0, -0.5);
+    }
+
+    #[test]
+    fn test_bandlimited_matches_naive_away_from_edges() {
+        for &waveform in &Waveform::ALL {
+            for &phase in &[0.1, 0.3, 0.7, 0.9] {
+                assert_eq!(waveform.sample_bandlimited(phase, 0.01, 0.5), waveform.sample(phase, 0.5));
+            }
+        }
+        for &phase in &[0.1, 0.3, 0.5, 0.9] {
+            assert_eq!(Waveform::Square.sample_bandlimited(phase, 0.01, 0.2), Waveform::Square.sample(phase, 0.2));
+        }
+    }
+
+    #[test]
+    fn test_bandlimited_saw_smooths_the_reset() {
+        // the samples either side of the reset are pulled towards the middle of the step
+        let phase_inc = 0.1;
+        let before = Waveform::Saw.sample_bandlimited(0.95, phase_inc, 0.5);
+        let after = Waveform::Saw.sample_bandlimited(0.05, phase_inc, 0.5);
+        assert!(before < Waveform::Saw.sample(0.95, 0.5));
+        assert!(after > Waveform::Saw.sample(0.05, 0.5));
+        assert!((before + after).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_bandlimited_pulse_smooths_both_edges() {
+        let phase_inc = 0.1;
+        for &width in &[0.3, 0.5, 0.7] {
+            // pulled towards the middle of the rising edge at the start of the cycle...
+            let before = Waveform::Square.sample_bandlimited(0.95, phase_inc, width);
+            let after = Waveform::Square.sample_bandlimited(0.05, phase_inc, width);
+            assert!((before + after).abs() < 1e-9 && after < 1.0);
+            // ...and the falling edge at the pulse width
+            let before = Waveform::Square.sample_bandlimited(width - 0.05, phase_inc, width);
+            let after = Waveform::Square.sample_bandlimited(width + 0.05, phase_inc, width);
+            assert!((before + after).abs() < 1e-9 && before < 1.0);
+        }
+    }
+}
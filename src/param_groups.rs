@@ -0,0 +1,250 @@
+use std::convert::TryFrom;
+
+use crate::params::NUM_PARAMS;
+
+/// The sections the parameters are grouped into, for hosts that show them organized, in the
+/// order they're shown. VST2 has no way to tell hosts about them.
+#[cfg_attr(not(any(feature = "clap", feature = "vst3")), allow(dead_code))]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ParamGroup {
+    Oscillator,
+    Fm,
+    AmpEnvelope,
+    Filter,
+    ModEnvelope,
+    Pitch,
+    Voicing,
+    Lfos,
+    ModMatrix,
+    Arpeggiator,
+    Effects,
+    Master,
+    Midi,
+}
+
+#[cfg_attr(not(any(feature = "clap", feature = "vst3")), allow(dead_code))]
+impl ParamGroup {
+    // only VST3 lists the groups themselves, as units
+    #[cfg_attr(not(feature = "vst3"), allow(dead_code))]
+    pub const ALL: [ParamGroup; 13] = [
+        ParamGroup::Oscillator,
+        ParamGroup::Fm,
+        ParamGroup::AmpEnvelope,
+        ParamGroup::Filter,
+        ParamGroup::ModEnvelope,
+        ParamGroup::Pitch,
+        ParamGroup::Voicing,
+        ParamGroup::Lfos,
+        ParamGroup::ModMatrix,
+        ParamGroup::Arpeggiator,
+        ParamGroup::Effects,
+        ParamGroup::Master,
+        ParamGroup::Midi,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ParamGroup::Oscillator => "Oscillator",
+            ParamGroup::Fm => "FM",
+            ParamGroup::AmpEnvelope => "Amp Envelope",
+            ParamGroup::Filter => "Filter",
+            ParamGroup::ModEnvelope => "Mod Envelope",
+            ParamGroup::Pitch => "Pitch",
+            ParamGroup::Voicing => "Voicing",
+            ParamGroup::Lfos => "LFOs",
+            ParamGroup::ModMatrix => "Mod Matrix",
+            ParamGroup::Arpeggiator => "Arpeggiator",
+            ParamGroup::Effects => "Effects",
+            ParamGroup::Master => "Master",
+            ParamGroup::Midi => "MIDI",
+        }
+    }
+
+    /// The group a parameter is in, by host index.
+    pub fn of(index: i32) -> Option<ParamGroup> {
+        usize::try_from(index).ok().and_then(|index| GROUPS.get(index)).copied()
+    }
+}
+
+/// Every parameter's group, by host index.
+///
+/// The host indices are the parameters' ids in every plugin format, and presets store their
+/// values by them, so they never change: a new parameter always goes on the end, with its group
+/// here. The table's length is checked against `NUM_PARAMS`, so one can't be added without it.
+#[cfg_attr(not(any(feature = "clap", feature = "vst3")), allow(dead_code))]
+const GROUPS: [ParamGroup; NUM_PARAMS] = [
+    ParamGroup::Oscillator, // 0 Unison Detune
+    ParamGroup::Oscillator, // 1 Osc Sync
+    ParamGroup::Oscillator, // 2 Sync Ratio
+    ParamGroup::AmpEnvelope, // 3 Vel > Attack
+    ParamGroup::Voicing, // 4 Polyphony
+    ParamGroup::AmpEnvelope, // 5 Attack
+    ParamGroup::AmpEnvelope, // 6 Decay
+    ParamGroup::AmpEnvelope, // 7 Sustain
+    ParamGroup::AmpEnvelope, // 8 Release
+    ParamGroup::Oscillator, // 9 Waveform
+    ParamGroup::Midi, // 10 Vel Curve
+    ParamGroup::Midi, // 11 Bend Range
+    ParamGroup::Pitch, // 12 Vibrato Rate
+    ParamGroup::Pitch, // 13 Vibrato Depth
+    ParamGroup::Voicing, // 14 Voice Steal
+    ParamGroup::Voicing, // 15 Note Priority
+    ParamGroup::Voicing, // 16 Legato
+    ParamGroup::Voicing, // 17 Glide
+    ParamGroup::Filter, // 18 Filter Type
+    ParamGroup::Filter, // 19 Cutoff
+    ParamGroup::Filter, // 20 Resonance
+    ParamGroup::Filter, // 21 Filter Env Amount
+    ParamGroup::Filter, // 22 Filter Attack
+    ParamGroup::Filter, // 23 Filter Decay
+    ParamGroup::Filter, // 24 Filter Sustain
+    ParamGroup::Filter, // 25 Filter Release
+    ParamGroup::Filter, // 26 Vel > Cutoff
+    ParamGroup::ModEnvelope, // 27 Mod Attack
+    ParamGroup::ModEnvelope, // 28 Mod Decay
+    ParamGroup::ModEnvelope, // 29 Mod Sustain
+    ParamGroup::ModEnvelope, // 30 Mod Release
+    ParamGroup::Lfos, // 31 LFO 1 Shape
+    ParamGroup::Lfos, // 32 LFO 1 Rate
+    ParamGroup::Lfos, // 33 LFO 2 Shape
+    ParamGroup::Lfos, // 34 LFO 2 Rate
+    ParamGroup::ModMatrix, // 35 Mod 1 Source
+    ParamGroup::ModMatrix, // 36 Mod 1 Dest
+    ParamGroup::ModMatrix, // 37 Mod 1 Depth
+    ParamGroup::ModMatrix, // 38 Mod 2 Source
+    ParamGroup::ModMatrix, // 39 Mod 2 Dest
+    ParamGroup::ModMatrix, // 40 Mod 2 Depth
+    ParamGroup::ModMatrix, // 41 Mod 3 Source
+    ParamGroup::ModMatrix, // 42 Mod 3 Dest
+    ParamGroup::ModMatrix, // 43 Mod 3 Depth
+    ParamGroup::ModMatrix, // 44 Mod 4 Source
+    ParamGroup::ModMatrix, // 45 Mod 4 Dest
+    ParamGroup::ModMatrix, // 46 Mod 4 Depth
+    ParamGroup::ModMatrix, // 47 Mod 5 Source
+    ParamGroup::ModMatrix, // 48 Mod 5 Dest
+    ParamGroup::ModMatrix, // 49 Mod 5 Depth
+    ParamGroup::ModMatrix, // 50 Mod 6 Source
+    ParamGroup::ModMatrix, // 51 Mod 6 Dest
+    ParamGroup::ModMatrix, // 52 Mod 6 Depth
+    ParamGroup::ModMatrix, // 53 Mod 7 Source
+    ParamGroup::ModMatrix, // 54 Mod 7 Dest
+    ParamGroup::ModMatrix, // 55 Mod 7 Depth
+    ParamGroup::ModMatrix, // 56 Mod 8 Source
+    ParamGroup::ModMatrix, // 57 Mod 8 Dest
+    ParamGroup::ModMatrix, // 58 Mod 8 Depth
+    ParamGroup::Oscillator, // 59 Unison
+    ParamGroup::Oscillator, // 60 Unison Spread
+    ParamGroup::AmpEnvelope, // 61 Curve
+    ParamGroup::Filter, // 62 Filter Curve
+    ParamGroup::ModEnvelope, // 63 Mod Curve
+    ParamGroup::Master, // 64 Master Gain
+    ParamGroup::Master, // 65 Master Pan
+    ParamGroup::Master, // 66 Stereo Width
+    ParamGroup::Master, // 67 Saturation
+    ParamGroup::Master, // 68 Drive
+    ParamGroup::Effects, // 69 Chorus Rate
+    ParamGroup::Effects, // 70 Chorus Depth
+    ParamGroup::Effects, // 71 Chorus Mix
+    ParamGroup::Effects, // 72 Delay Time
+    ParamGroup::Effects, // 73 Delay Feedback
+    ParamGroup::Effects, // 74 Delay Mix
+    ParamGroup::Lfos, // 75 LFO 1 Sync
+    ParamGroup::Lfos, // 76 LFO 2 Sync
+    ParamGroup::Effects, // 77 Delay Sync
+    ParamGroup::Fm, // 78 FM Ratio
+    ParamGroup::Fm, // 79 FM Index
+    ParamGroup::Fm, // 80 FM Attack
+    ParamGroup::Fm, // 81 FM Decay
+    ParamGroup::Fm, // 82 FM Sustain
+    ParamGroup::Fm, // 83 FM Release
+    ParamGroup::Fm, // 84 FM Curve
+    ParamGroup::Oscillator, // 85 Osc Level
+    ParamGroup::Oscillator, // 86 Sub Level
+    ParamGroup::Oscillator, // 87 Sub Octave
+    ParamGroup::Oscillator, // 88 Sub Wave
+    ParamGroup::Oscillator, // 89 Noise Level
+    ParamGroup::Oscillator, // 90 Noise Color
+    ParamGroup::Oscillator, // 91 Pulse Width
+    ParamGroup::Oscillator, // 92 Ring Mod
+    ParamGroup::Midi, // 93 MPE
+    ParamGroup::Midi, // 94 MPE Bend Range
+    ParamGroup::Pitch, // 95 AT > Vibrato
+    ParamGroup::Pitch, // 96 Reference Pitch
+    ParamGroup::Midi, // 97 MIDI Channel
+    ParamGroup::Midi, // 98 Program Change
+    ParamGroup::Arpeggiator, // 99 Arpeggiator
+    ParamGroup::Arpeggiator, // 100 Arp Mode
+    ParamGroup::Arpeggiator, // 101 Arp Octaves
+    ParamGroup::Arpeggiator, // 102 Arp Rate
+    ParamGroup::Arpeggiator, // 103 Arp Sync
+    ParamGroup::Arpeggiator, // 104 Arp Gate
+    ParamGroup::Oscillator, // 105 Drift
+    ParamGroup::Oscillator, // 106 Oversampling
+    ParamGroup::Voicing, // 107 Retrigger
+    ParamGroup::AmpEnvelope, // 108 Hold
+    ParamGroup::AmpEnvelope, // 109 Env Mode
+    ParamGroup::Filter, // 110 Filter Hold
+    ParamGroup::Filter, // 111 Filter Env Mode
+    ParamGroup::ModEnvelope, // 112 Mod Hold
+    ParamGroup::ModEnvelope, // 113 Mod Env Mode
+    ParamGroup::Fm, // 114 FM Hold
+    ParamGroup::Fm, // 115 FM Env Mode
+    ParamGroup::Voicing, // 116 Glide Mode
+    ParamGroup::Voicing, // 117 Glide Curve
+    ParamGroup::Voicing, // 118 Pan Mode
+    ParamGroup::Voicing, // 119 Pan Width
+    ParamGroup::Lfos, // 120 Random Rate
+    ParamGroup::Lfos, // 121 Random Sync
+    ParamGroup::Lfos, // 122 Random Smooth
+    ParamGroup::Oscillator, // 123 WT Position
+    ParamGroup::Oscillator, // 124 Harmonic 1
+    ParamGroup::Oscillator, // 125 Harmonic 2
+    ParamGroup::Oscillator, // 126 Harmonic 3
+    ParamGroup::Oscillator, // 127 Harmonic 4
+    ParamGroup::Oscillator, // 128 Harmonic 5
+    ParamGroup::Oscillator, // 129 Harmonic 6
+    ParamGroup::Oscillator, // 130 Harmonic 7
+    ParamGroup::Oscillator, // 131 Harmonic 8
+    ParamGroup::Pitch, // 132 Pitch Env Amount
+    ParamGroup::Pitch, // 133 Pitch Env Decay
+    ParamGroup::Midi, // 134 Key Min
+    ParamGroup::Midi, // 135 Key Max
+    ParamGroup::Midi, // 136 Velocity Min
+    ParamGroup::Midi, // 137 Velocity Max
+    ParamGroup::Master, // 138 A/B Morph
+    ParamGroup::Master, // 139 Stereo Mode
+    ParamGroup::Effects, // 140 Reverb Size
+    ParamGroup::Effects, // 141 Reverb Damping
+    ParamGroup::Effects, // 142 Reverb Mix
+    ParamGroup::Master, // 143 DC Blocker
+    ParamGroup::Midi, // 144 On Stop/Loop
+    ParamGroup::Midi, // 145 Multi-Timbral
+    ParamGroup::Master, // 146 Voice Outputs
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::params::ReplicantParameters;
+
+    #[test]
+    fn test_every_group_has_parameters() {
+        for group in ParamGroup::ALL {
+            assert!(GROUPS.contains(&group), "{} is empty", group.name());
+        }
+        assert_eq!(ParamGroup::of(19), Some(ParamGroup::Filter));
+        assert_eq!(ParamGroup::of(-1), None);
+        assert_eq!(ParamGroup::of(NUM_PARAMS as i32), None);
+    }
+
+    #[test]
+    fn test_parameters_are_named_within_their_groups() {
+        // a few parameters from each end, so moving them about is caught
+        let params = ReplicantParameters::init();
+        let grouped = |index| (params.param_name(index), ParamGroup::of(index).map(ParamGroup::name));
+        assert_eq!(grouped(0), ("Unison Detune".to_string(), Some("Oscillator")));
+        assert_eq!(grouped(64), ("Master Gain".to_string(), Some("Master")));
+        assert_eq!(grouped(140), ("Reverb Size".to_string(), Some("Effects")));
+        assert_eq!(grouped(146), ("Voice Outputs".to_string(), Some("Master")));
+    }
+}
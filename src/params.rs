@@ -0,0 +1,2388 @@
+use std::convert::TryFrom;
+use std::io;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, TryLockError, Weak};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::{debug, warn};
+
+use crate::arpeggiator::{ArpMode, ArpParams, MAX_ARP_OCTAVES};
+use crate::atomic_float::AtomicFloat;
+use crate::effects::{MAX_DELAY_FEEDBACK, MAX_DELAY_TIME};
+use crate::envelope::{ADSRParams, EnvelopeMode};
+use crate::filter::FilterType;
+use crate::history::{Change, History};
+use crate::lfo::{LfoParams, LfoShape, RandomParams, NUM_LFOS};
+use crate::loader::{Load, LoadStatus, Loader};
+use crate::midi_map::MidiMap;
+use crate::morph::{AbSlots, Slot};
+use crate::modulation::{ModDestination, ModSlot, ModSource, NUM_MOD_SLOTS};
+use crate::multi::PartBuilder;
+use crate::oscillator::{NoiseColor, SubWaveform, Waveform, MAX_PULSE_WIDTH, MIN_PULSE_WIDTH};
+use crate::oversampling::Oversampling;
+use crate::presets::{Bank, Preset, RANDOM_PRESET, SETUP_PARAMS};
+use crate::saturation::Saturation;
+use crate::stereo::StereoMode;
+use crate::tempo::{NoteDivision, StopMode};
+use crate::tuning::{Tuning, TuningError, TuningFiles, STANDARD_PITCH};
+use crate::voice::{
+    GlideCurve, GlideMode, NotePriority, OutputRouting, PanMode, Retrigger, StealPolicy, VelocityCurve,
+    MAX_UNISON,
+};
+use crate::wavetable::{Wavetable, WavetableError, NUM_HARMONICS};
+use crate::db_to_gain;
+
+/// The number of parameters exposed to the host.
+pub const NUM_PARAMS: usize = 147;
+
+/// The morph between the A and B patches, which moves the other parameters as it's set.
+pub const MORPH_PARAM: i32 = 138;
+
+/// Maximum length of each envelope stage, in seconds.
+const MAX_ENVELOPE_TIME: f32 = 5.0;
+
+/// Maximum pitch spread between the outermost unison oscillators, in cents.
+const MAX_UNISON_DETUNE: f32 = 100.0;
+
+/// The most voices that can play at once.
+pub const MAX_POLYPHONY: usize = 16;
+
+/// Filter cutoff range in Hz, and the furthest the filter envelope and velocity can move it, in
+/// octaves.
+const MIN_CUTOFF: f32 = 20.0;
+const MAX_CUTOFF: f32 = 20000.0;
+const MAX_FILTER_ENV_AMOUNT: f32 = 8.0;
+const MAX_VEL_TO_CUTOFF: f32 = 4.0;
+
+/// The longest mono glide, in seconds.
+const MAX_GLIDE_TIME: f32 = 2.0;
+
+/// The widest pitch bend range, in semitones either way.
+const MAX_BEND_RANGE: f32 = 24.0;
+const MAX_MPE_BEND_RANGE: f32 = 96.0;
+
+/// The furthest the pitch envelope bends the note, in semitones either way.
+const MAX_PITCH_ENV_AMOUNT: f32 = 48.0;
+
+/// The number of midi channels there are to listen to.
+const NUM_MIDI_CHANNELS: f32 = 16.0;
+
+/// Vibrato rate range in Hz, and the most vibrato the mod wheel can add, in cents.
+const MIN_VIBRATO_RATE: f32 = 0.1;
+const MAX_VIBRATO_RATE: f32 = 20.0;
+const MAX_VIBRATO_DEPTH: f32 = 100.0;
+
+/// How far A4 can be retuned either side of 440 Hz.
+const MIN_REFERENCE_PITCH: f32 = 400.0;
+const MAX_REFERENCE_PITCH: f32 = 480.0;
+
+/// How far voices drift at most with the drift amount all the way up, in cents.
+const MAX_DRIFT: f32 = 10.0;
+
+/// Arpeggiator rate range, in steps per second, and the shortest gate.
+const MIN_ARP_RATE: f32 = 0.5;
+const MAX_ARP_RATE: f32 = 30.0;
+const MIN_ARP_GATE: f32 = 0.05;
+
+/// LFO rate range, in Hz.
+const MIN_LFO_RATE: f32 = 0.05;
+const MAX_LFO_RATE: f32 = 20.0;
+
+/// Master output level range, in dB. The minimum is silence.
+pub const MIN_MASTER_GAIN: f32 = -60.0;
+const MAX_MASTER_GAIN: f32 = 6.0;
+
+/// The widest stereo width, where 1.0 leaves the stereo image untouched.
+const MAX_STEREO_WIDTH: f32 = 2.0;
+
+/// The most the output can be driven into the saturation stage, in dB.
+const MAX_DRIVE: f32 = 24.0;
+
+/// FM modulator frequency range, as a multiple of the note's, and the largest modulation index.
+const MIN_FM_RATIO: f32 = 0.5;
+const MAX_FM_RATIO: f32 = 16.0;
+const MAX_FM_INDEX: f32 = 10.0;
+
+/// Chorus sweep rate range, in Hz.
+const MIN_CHORUS_RATE: f32 = 0.05;
+const MAX_CHORUS_RATE: f32 = 5.0;
+
+/// The LFO and modulation matrix settings are laid out in blocks of parameters per LFO and per
+/// slot. Parameters added since come after them, so saved presets keep their indices.
+const FIRST_LFO_PARAM: i32 = 31;
+const PARAMS_PER_LFO: usize = 2;
+const FIRST_MOD_SLOT_PARAM: i32 = FIRST_LFO_PARAM + (NUM_LFOS * PARAMS_PER_LFO) as i32;
+const PARAMS_PER_MOD_SLOT: usize = 3;
+
+/// Each LFO's tempo sync, one per LFO, added after the blocks above.
+const FIRST_LFO_SYNC_PARAM: i32 = 75;
+
+/// The additive waveform's harmonic levels, one per harmonic, added after those above.
+const FIRST_HARMONIC_PARAM: i32 = 124;
+
+/// Range of the hard sync slave oscillator's frequency, as a multiple of the master's.
+const MIN_SYNC_RATIO: f32 = 1.0;
+const MAX_SYNC_RATIO: f32 = 8.0;
+
+/// Parameters shared between the host and the audio thread.
+///
+/// Values are stored in their real units (seconds, cents, ...). Conversion to and from the
+/// host's normalized 0.0 to 1.0 range happens in the `PluginParameters` impl.
+pub struct ReplicantParameters {
+    pub attack: AtomicFloat,
+    /// How long the level stays at the peak between the attack and decay, in seconds.
+    pub hold: AtomicFloat,
+    pub decay: AtomicFloat,
+    pub sustain: AtomicFloat, // 0.0 to 1.0
+    pub release: AtomicFloat,
+    /// Index into `EnvelopeMode::ALL`, for whether the envelope waits for the note off.
+    pub env_mode: AtomicFloat,
+    /// The shape of the envelope stages, from -1.0 (slow then fast) through 0.0 (linear) to 1.0
+    /// (fast then slow).
+    pub curve: AtomicFloat,
+    /// Index into `Retrigger::ALL`, for how the envelopes restart on a sounding voice.
+    pub retrigger: AtomicFloat,
+
+    /// How many oscillators play each note, from 1 to `MAX_UNISON`.
+    pub unison: AtomicFloat,
+    /// Total pitch spread between the outermost unison oscillators, in cents.
+    pub unison_detune: AtomicFloat,
+    /// How far the unison oscillators are panned apart, 0.0 (all centred) to 1.0 (hard left and
+    /// right).
+    pub unison_spread: AtomicFloat,
+
+    /// Hard sync toggle, 0.0 (off) or 1.0 (on).
+    pub osc_sync: AtomicFloat,
+    /// Slave oscillator frequency as a multiple of the master's.
+    pub sync_ratio: AtomicFloat,
+
+    /// How much note velocity shortens the attack, 0.0 to 1.0.
+    pub vel_to_attack: AtomicFloat,
+    /// Index into `VelocityCurve::ALL`.
+    pub velocity_curve: AtomicFloat,
+
+    /// How many voices can play at once, from 1 (mono) to `MAX_POLYPHONY`.
+    pub polyphony: AtomicFloat,
+    /// Index into `StealPolicy::ALL`.
+    pub steal_policy: AtomicFloat,
+    /// Index into `PanMode::ALL`, for where each poly voice sits.
+    pub pan_mode: AtomicFloat,
+    /// How far the voices are panned by the pan mode, 0.0 (all centred) to 1.0 (hard left and
+    /// right).
+    pub pan_width: AtomicFloat,
+
+    /// Index into `NotePriority::ALL`, for mono mode.
+    pub note_priority: AtomicFloat,
+    /// Mono legato toggle, 0.0 (off) or 1.0 (on).
+    pub legato: AtomicFloat,
+    /// Mono portamento time, in seconds, for the whole glide or for each octave of it.
+    pub glide_time: AtomicFloat,
+    /// Index into `GlideMode::ALL`.
+    pub glide_mode: AtomicFloat,
+    /// Index into `GlideCurve::ALL`.
+    pub glide_curve: AtomicFloat,
+
+    /// Index into `Waveform::ALL`.
+    pub waveform: AtomicFloat,
+    /// The fraction of the cycle the square wave spends high.
+    pub pulse_width: AtomicFloat,
+    /// How far through the wavetable the oscillators read, from 0.0 at the first frame to 1.0 at
+    /// the last.
+    pub wavetable_position: AtomicFloat,
+    /// The additive waveform's level of each harmonic, from the fundamental up, 0.0 to 1.0.
+    pub harmonics: [AtomicFloat; NUM_HARMONICS],
+    /// How far the pitch envelope bends each note at its start, in semitones.
+    pub pitch_env_amount: AtomicFloat,
+    /// How long the pitch envelope takes to settle back onto the note, in seconds.
+    pub pitch_env_decay: AtomicFloat,
+    /// How far the patch is morphed from A, at 0.0, to B, at 1.0.
+    pub morph: AtomicFloat,
+    /// How much each voice's pitch wanders like an analog oscillator's, 0.0 to 1.0.
+    pub drift: AtomicFloat,
+    /// Index into `Oversampling::ALL`.
+    pub oversampling: AtomicFloat,
+
+    /// How far a full pitch bend goes, in semitones.
+    pub bend_range: AtomicFloat,
+    /// Treats midi channels 2 to 16 as MPE note channels, each carrying one note's pitch bend,
+    /// pressure and CC74.
+    pub mpe: AtomicFloat,
+    /// How far the note channels' pitch bend reaches, in semitones.
+    pub mpe_bend_range: AtomicFloat,
+    /// The midi channel to listen to, from 1 to 16, or 0 for all of them. MPE always uses them
+    /// all.
+    pub midi_channel: AtomicFloat,
+    /// The lowest and highest keys played, as midi note numbers. Notes outside them are ignored,
+    /// for splitting a keyboard between instances.
+    pub key_min: AtomicFloat,
+    pub key_max: AtomicFloat,
+    /// The softest and hardest note velocities played, from 1 to 127, for layering instances by
+    /// how hard the keys are struck.
+    pub velocity_min: AtomicFloat,
+    pub velocity_max: AtomicFloat,
+    /// Whether midi program changes switch presets, 0.0 (off) or 1.0 (on).
+    pub program_change: AtomicFloat,
+    /// Index into `StopMode::ALL`.
+    pub stop_mode: AtomicFloat,
+    /// Whether midi channels 2 to 16 each play a part with a patch of its own, 0.0 (off) or 1.0
+    /// (on).
+    pub multitimbral: AtomicFloat,
+    /// Index into `OutputRouting::ALL`.
+    pub output_routing: AtomicFloat,
+
+    /// Arpeggiator toggle, 0.0 (off) or 1.0 (on).
+    pub arp: AtomicFloat,
+    /// Index into `ArpMode::ALL`.
+    pub arp_mode: AtomicFloat,
+    /// How many octaves the arpeggio spans, from 1 to `MAX_ARP_OCTAVES`.
+    pub arp_octaves: AtomicFloat,
+    /// Arpeggiator steps per second.
+    pub arp_rate: AtomicFloat,
+    /// The note length each step follows instead, as for `LfoParameters::sync`.
+    pub arp_sync: AtomicFloat,
+    /// How much of each step the notes are held for.
+    pub arp_gate: AtomicFloat,
+
+    /// Vibrato LFO rate, in Hz.
+    pub vibrato_rate: AtomicFloat,
+    /// Vibrato depth with the mod wheel fully up, in cents.
+    pub vibrato_depth: AtomicFloat,
+    /// How much of the vibrato depth full channel pressure adds, 0.0 to 1.0.
+    pub aftertouch_vibrato: AtomicFloat,
+
+    /// The frequency A4 is tuned to, in Hz. Scala and MTS-ESP tunings are moved along with it.
+    pub reference_pitch: AtomicFloat,
+
+    /// Index into `FilterType::ALL`.
+    pub filter_type: AtomicFloat,
+    /// Filter cutoff in Hz, before modulation.
+    pub cutoff: AtomicFloat,
+    pub resonance: AtomicFloat, // 0.0 to 1.0
+    /// How far the filter envelope moves the cutoff, in octaves, either way.
+    pub filter_env_amount: AtomicFloat,
+    pub filter_attack: AtomicFloat,
+    pub filter_hold: AtomicFloat,
+    pub filter_decay: AtomicFloat,
+    pub filter_sustain: AtomicFloat, // 0.0 to 1.0
+    pub filter_release: AtomicFloat,
+    pub filter_curve: AtomicFloat,
+    pub filter_env_mode: AtomicFloat,
+    /// How far a full velocity note raises the cutoff, in octaves.
+    pub vel_to_cutoff: AtomicFloat,
+
+    pub mod_attack: AtomicFloat,
+    pub mod_hold: AtomicFloat,
+    pub mod_decay: AtomicFloat,
+    pub mod_sustain: AtomicFloat, // 0.0 to 1.0
+    pub mod_release: AtomicFloat,
+    pub mod_curve: AtomicFloat,
+    pub mod_env_mode: AtomicFloat,
+
+    /// The FM modulator's frequency, as a multiple of the note's.
+    pub fm_ratio: AtomicFloat,
+    /// The FM modulation index at the peak of the FM envelope, in radians. 0.0 turns FM off.
+    pub fm_index: AtomicFloat,
+    pub fm_attack: AtomicFloat,
+    pub fm_hold: AtomicFloat,
+    pub fm_decay: AtomicFloat,
+    pub fm_sustain: AtomicFloat, // 0.0 to 1.0
+    pub fm_release: AtomicFloat,
+    pub fm_curve: AtomicFloat,
+    pub fm_env_mode: AtomicFloat,
+
+    /// The mixer levels of the main oscillators, the sub-oscillator and the noise, 0.0 to 1.0.
+    pub osc_level: AtomicFloat,
+    /// How much of the main oscillators is replaced by the sync pair multiplied together, 0.0 to
+    /// 1.0.
+    pub ring_mod: AtomicFloat,
+    pub sub_level: AtomicFloat,
+    pub noise_level: AtomicFloat,
+    /// How many octaves below the note the sub-oscillator plays, 1 or 2.
+    pub sub_octave: AtomicFloat,
+    pub sub_waveform: AtomicFloat,
+    pub noise_color: AtomicFloat,
+
+    /// Output level, in dB.
+    pub master_gain: AtomicFloat,
+    /// Output balance, -1.0 (left) to 1.0 (right).
+    pub master_pan: AtomicFloat,
+    /// Index into `StereoMode::ALL`.
+    pub stereo_mode: AtomicFloat,
+    /// How far the stereo mode widens the output, from 0.0 to `MAX_STEREO_WIDTH`. In mid/side mode
+    /// 0.0 is mono, and in the others it's how far the mode's spread is taken.
+    pub stereo_width: AtomicFloat,
+    /// Keeps the output from going over full scale.
+    pub saturation: AtomicFloat,
+    /// Gain into the saturation stage, in dB.
+    pub drive: AtomicFloat,
+    /// Whether any DC offset is filtered out of the output, 0.0 (off) or 1.0 (on).
+    pub dc_blocker: AtomicFloat,
+
+    /// Chorus sweep rate, in Hz.
+    pub chorus_rate: AtomicFloat,
+    pub chorus_depth: AtomicFloat, // 0.0 to 1.0
+    pub chorus_mix: AtomicFloat, // 0.0 to 1.0
+    /// Delay time, in seconds.
+    pub delay_time: AtomicFloat,
+    /// The note length the delay time follows instead, as for `LfoParameters::sync`.
+    pub delay_sync: AtomicFloat,
+    pub delay_feedback: AtomicFloat, // 0.0 to MAX_DELAY_FEEDBACK
+    pub delay_mix: AtomicFloat, // 0.0 to 1.0
+    /// How long the reverb rings, 0.0 to 1.0.
+    pub reverb_size: AtomicFloat,
+    /// How quickly the reverb's high frequencies die away, 0.0 to 1.0.
+    pub reverb_damping: AtomicFloat,
+    pub reverb_mix: AtomicFloat, // 0.0 to 1.0
+
+    pub lfos: [LfoParameters; NUM_LFOS],
+    /// The random modulation source's rate, in steps per second.
+    pub random_rate: AtomicFloat,
+    /// The note length each random step lasts instead, as for `LfoParameters::sync`.
+    pub random_sync: AtomicFloat,
+    /// How much of each random step is spent gliding to the new value, 0.0 to 1.0.
+    pub random_smooth: AtomicFloat,
+    pub mod_slots: [ModSlotParameters; NUM_MOD_SLOTS],
+
+    /// The parameters the user's midi controllers are mapped to.
+    pub midi_map: MidiMap,
+
+    // only touched by the host's preset calls and the loader thread, never on the audio thread
+    bank: Mutex<Bank>,
+    // the audio thread only ever tries the lock, so loading a tuning can't hold it up
+    tuning: Mutex<TuningState>,
+    // the same goes for the wavetable
+    wavetable: Mutex<WavetableState>,
+    // set when the loader thread has switched presets, or filled the user preset slots,
+    // until the audio thread tells the host
+    preset_switched: AtomicBool,
+    // set once the host has loaded a bank, which the user presets shouldn't then overwrite
+    bank_loaded: AtomicBool,
+    // wavetables, preset directories, preset switches and multi-timbral mode's parts, loaded off
+    // both the audio and the host's UI threads
+    loader: Loader,
+    // the editor's changes, for undo
+    history: Mutex<History>,
+    /// The patches to compare and morph between.
+    pub ab: AbSlots,
+}
+
+/// The Scala files the tuning was loaded from, if any, and the tuning table built from them.
+#[derive(Default)]
+struct TuningState {
+    files: Option<TuningFiles>,
+    table: Tuning,
+}
+
+/// The file the wavetable was loaded from, if any, the table, and how loading it went.
+struct WavetableState {
+    file: Option<PathBuf>,
+    table: Arc<Wavetable>,
+    status: LoadStatus,
+    // tables replaced while the audio thread may still hold them, so it never has to free one
+    retired: Vec<Arc<Wavetable>>,
+}
+
+impl Default for WavetableState {
+    fn default() -> WavetableState {
+        WavetableState { file: None, table: Wavetable::basic(), status: LoadStatus::Loaded, retired: Vec::new() }
+    }
+}
+
+/// The settings for one of the general purpose LFOs.
+pub struct LfoParameters {
+    /// Index into `LfoShape::ALL`.
+    pub shape: AtomicFloat,
+    /// In Hz.
+    pub rate: AtomicFloat,
+    /// 0 for the free running rate, otherwise a note length to follow the host tempo, as an
+    /// index into `NoteDivision::ALL` plus one.
+    pub sync: AtomicFloat,
+}
+
+// the enum variants below are declared in the same order as their `ALL` lists, so they double as
+// indices for the defaults
+
+impl LfoParameters {
+    fn new(shape: LfoShape, rate: f32) -> LfoParameters {
+        LfoParameters {
+            shape: AtomicFloat::new(shape as usize as f32),
+            rate: AtomicFloat::new(rate),
+            sync: AtomicFloat::new(0.0),
+        }
+    }
+
+    pub fn shape(&self) -> LfoShape {
+        LfoShape::ALL[choice(&self.shape, LfoShape::ALL.len())]
+    }
+
+    /// A snapshot of the settings, clamped to valid values.
+    pub fn snapshot(&self) -> LfoParams {
+        LfoParams {
+            shape: self.shape(),
+            rate: f64::from(sanitize(self.rate.get(), 0.0, MAX_LFO_RATE)),
+            division: note_division(&self.sync),
+        }
+    }
+}
+
+/// The settings for one slot of the modulation matrix.
+pub struct ModSlotParameters {
+    /// Index into `ModSource::ALL`.
+    pub source: AtomicFloat,
+    /// Index into `ModDestination::ALL`.
+    pub destination: AtomicFloat,
+    /// -1.0 to 1.0.
+    pub depth: AtomicFloat,
+}
+
+impl ModSlotParameters {
+    /// An empty slot, which will route `source` to `destination` once it's given some depth.
+    fn new(source: ModSource, destination: ModDestination) -> ModSlotParameters {
+        ModSlotParameters {
+            source: AtomicFloat::new(source as usize as f32),
+            destination: AtomicFloat::new(destination as usize as f32),
+            depth: AtomicFloat::new(0.0),
+        }
+    }
+
+    pub fn source(&self) -> ModSource {
+        ModSource::ALL[choice(&self.source, ModSource::ALL.len())]
+    }
+
+    pub fn destination(&self) -> ModDestination {
+        ModDestination::ALL[choice(&self.destination, ModDestination::ALL.len())]
+    }
+
+    /// A snapshot of the settings, clamped to valid values.
+    pub fn snapshot(&self) -> ModSlot {
+        ModSlot {
+            source: self.source(),
+            destination: self.destination(),
+            depth: f64::from(sanitize(self.depth.get(), -1.0, 1.0)),
+        }
+    }
+}
+
+impl ReplicantParameters {
+    /// A snapshot of the envelope settings, clamped to valid values.
+    pub fn adsr(&self) -> ADSRParams {
+        adsr_params(
+            [&self.attack, &self.hold, &self.decay, &self.sustain, &self.release, &self.curve],
+            &self.env_mode,
+        )
+    }
+
+    /// A snapshot of the filter envelope settings, clamped to valid values.
+    pub fn filter_adsr(&self) -> ADSRParams {
+        adsr_params(
+            [
+                &self.filter_attack,
+                &self.filter_hold,
+                &self.filter_decay,
+                &self.filter_sustain,
+                &self.filter_release,
+                &self.filter_curve,
+            ],
+            &self.filter_env_mode,
+        )
+    }
+
+    /// A snapshot of the modulation envelope settings, clamped to valid values.
+    pub fn mod_adsr(&self) -> ADSRParams {
+        adsr_params(
+            [&self.mod_attack, &self.mod_hold, &self.mod_decay, &self.mod_sustain, &self.mod_release, &self.mod_curve],
+            &self.mod_env_mode,
+        )
+    }
+
+    /// A snapshot of the FM envelope settings, clamped to valid values.
+    pub fn fm_adsr(&self) -> ADSRParams {
+        adsr_params(
+            [&self.fm_attack, &self.fm_hold, &self.fm_decay, &self.fm_sustain, &self.fm_release, &self.fm_curve],
+            &self.fm_env_mode,
+        )
+    }
+
+    pub fn mod_matrix(&self) -> [ModSlot; NUM_MOD_SLOTS] {
+        self.mod_slots.each_ref().map(ModSlotParameters::snapshot)
+    }
+
+    pub fn filter_type(&self) -> FilterType {
+        FilterType::ALL[choice(&self.filter_type, FilterType::ALL.len())]
+    }
+
+    pub fn osc_sync(&self) -> bool {
+        self.osc_sync.get() >= 0.5
+    }
+
+    /// The output level as an amplitude, with the bottom of the range silent.
+    pub fn master_gain(&self) -> f64 {
+        let db = sanitize(self.master_gain.get(), MIN_MASTER_GAIN, MAX_MASTER_GAIN);
+        if db <= MIN_MASTER_GAIN {
+            0.0
+        } else {
+            db_to_gain(f64::from(db))
+        }
+    }
+
+    pub fn unison(&self) -> usize {
+        (self.unison.get().round() as usize).clamp(1, MAX_UNISON)
+    }
+
+    pub fn polyphony(&self) -> usize {
+        (self.polyphony.get().round() as usize).clamp(1, MAX_POLYPHONY)
+    }
+
+    pub fn steal_policy(&self) -> StealPolicy {
+        StealPolicy::ALL[choice(&self.steal_policy, StealPolicy::ALL.len())]
+    }
+
+    pub fn retrigger(&self) -> Retrigger {
+        Retrigger::ALL[choice(&self.retrigger, Retrigger::ALL.len())]
+    }
+
+    pub fn glide_mode(&self) -> GlideMode {
+        GlideMode::ALL[choice(&self.glide_mode, GlideMode::ALL.len())]
+    }
+
+    pub fn glide_curve(&self) -> GlideCurve {
+        GlideCurve::ALL[choice(&self.glide_curve, GlideCurve::ALL.len())]
+    }
+
+    pub fn pan_mode(&self) -> PanMode {
+        PanMode::ALL[choice(&self.pan_mode, PanMode::ALL.len())]
+    }
+
+    /// A snapshot of the random source's settings, clamped to valid values.
+    pub fn random(&self) -> RandomParams {
+        RandomParams {
+            rate: f64::from(sanitize(self.random_rate.get(), 0.0, MAX_LFO_RATE)),
+            division: note_division(&self.random_sync),
+            smooth: f64::from(sanitize(self.random_smooth.get(), 0.0, 1.0)),
+        }
+    }
+
+    pub fn note_priority(&self) -> NotePriority {
+        NotePriority::ALL[choice(&self.note_priority, NotePriority::ALL.len())]
+    }
+
+    pub fn mpe(&self) -> bool {
+        self.mpe.get() >= 0.5
+    }
+
+    /// The most each voice's pitch drifts, in cents.
+    pub fn drift_cents(&self) -> f64 {
+        f64::from(sanitize(self.drift.get(), 0.0, 1.0) * MAX_DRIFT)
+    }
+
+    pub fn arp(&self) -> bool {
+        self.arp.get() >= 0.5
+    }
+
+    pub fn arp_mode(&self) -> ArpMode {
+        ArpMode::ALL[choice(&self.arp_mode, ArpMode::ALL.len())]
+    }
+
+    /// A snapshot of the arpeggiator settings, clamped to valid values.
+    pub fn arp_params(&self) -> ArpParams {
+        ArpParams {
+            mode: self.arp_mode(),
+            octaves: self.arp_octaves.get().round().clamp(1.0, MAX_ARP_OCTAVES as f32) as usize,
+            rate: f64::from(sanitize(self.arp_rate.get(), MIN_ARP_RATE, MAX_ARP_RATE)),
+            division: note_division(&self.arp_sync),
+            gate: f64::from(sanitize(self.arp_gate.get(), MIN_ARP_GATE, 1.0)),
+        }
+    }
+
+    pub fn program_change(&self) -> bool {
+        self.program_change.get() >= 0.5
+    }
+
+    pub fn multitimbral(&self) -> bool {
+        self.multitimbral.get() >= 0.5
+    }
+
+    pub fn stop_mode(&self) -> StopMode {
+        StopMode::ALL[choice(&self.stop_mode, StopMode::ALL.len())]
+    }
+
+    /// The midi channel to listen to, counting from 0, or `None` for omni.
+    pub fn midi_channel(&self) -> Option<u8> {
+        match choice(&self.midi_channel, NUM_MIDI_CHANNELS as usize + 1) {
+            0 => None,
+            channel => Some(channel as u8 - 1),
+        }
+    }
+
+    /// Whether a note is inside the key and velocity zone, and should be played.
+    pub fn in_zone(&self, note: u8, velocity: u8) -> bool {
+        let velocities = midi_value(&self.velocity_min, 1.0)..=midi_value(&self.velocity_max, 1.0);
+        self.key_zone().contains(&note) && velocities.contains(&velocity)
+    }
+
+    /// The keys played, from the lowest to the highest.
+    pub fn key_zone(&self) -> RangeInclusive<u8> {
+        midi_value(&self.key_min, 0.0)..=midi_value(&self.key_max, 0.0)
+    }
+
+    pub fn output_routing(&self) -> OutputRouting {
+        OutputRouting::ALL[choice(&self.output_routing, OutputRouting::ALL.len())]
+    }
+
+    pub fn legato(&self) -> bool {
+        self.legato.get() >= 0.5
+    }
+
+    pub fn waveform(&self) -> Waveform {
+        Waveform::ALL[choice(&self.waveform, Waveform::ALL.len())]
+    }
+
+    pub fn oversampling(&self) -> Oversampling {
+        Oversampling::ALL[choice(&self.oversampling, Oversampling::ALL.len())]
+    }
+
+    pub fn velocity_curve(&self) -> VelocityCurve {
+        VelocityCurve::ALL[choice(&self.velocity_curve, VelocityCurve::ALL.len())]
+    }
+
+    /// How many octaves below the note the sub-oscillator plays.
+    pub fn sub_octave(&self) -> i32 {
+        sanitize(self.sub_octave.get(), 1.0, 2.0).round() as i32
+    }
+
+    pub fn sub_waveform(&self) -> SubWaveform {
+        SubWaveform::ALL[choice(&self.sub_waveform, SubWaveform::ALL.len())]
+    }
+
+    pub fn noise_color(&self) -> NoiseColor {
+        NoiseColor::ALL[choice(&self.noise_color, NoiseColor::ALL.len())]
+    }
+
+    pub fn stereo_mode(&self) -> StereoMode {
+        StereoMode::ALL[choice(&self.stereo_mode, StereoMode::ALL.len())]
+    }
+
+    pub fn saturation(&self) -> Saturation {
+        Saturation::ALL[choice(&self.saturation, Saturation::ALL.len())]
+    }
+
+    pub fn dc_blocker(&self) -> bool {
+        self.dc_blocker.get() >= 0.5
+    }
+
+    /// The gain into the saturation stage, as an amplitude.
+    pub fn drive(&self) -> f64 {
+        db_to_gain(f64::from(sanitize(self.drive.get(), 0.0, MAX_DRIVE)))
+    }
+}
+
+/// Read an envelope's attack, hold, decay, sustain, release, curve and mode parameters.
+fn adsr_params(
+    [attack, hold, decay, sustain, release, curve]: [&AtomicFloat; 6],
+    mode: &AtomicFloat,
+) -> ADSRParams {
+    // f64::max() discards NaN, so bad values end up as 0.0
+    let time = |param: &AtomicFloat| f64::from(param.get()).max(0.0);
+    ADSRParams {
+        attack: time(attack),
+        hold: time(hold),
+        decay: time(decay),
+        sustain: f64::from(sanitize(sustain.get(), 0.0, 1.0)),
+        release: time(release),
+        curve: f64::from(sanitize(curve.get(), -1.0, 1.0)),
+        mode: EnvelopeMode::ALL[choice(mode, EnvelopeMode::ALL.len())],
+    }
+}
+
+/// Read a tempo sync parameter, which is off at 0 and otherwise picks a note length.
+pub fn note_division(param: &AtomicFloat) -> Option<NoteDivision> {
+    match choice(param, NoteDivision::ALL.len() + 1) {
+        0 => None,
+        index => Some(NoteDivision::ALL[index - 1]),
+    }
+}
+
+fn format_note_division(param: &AtomicFloat) -> String {
+    note_division(param).map_or("Off", NoteDivision::name).to_string()
+}
+
+/// A midi note number's name, with middle C (60) as C4.
+fn format_note(param: &AtomicFloat) -> String {
+    const NAMES: [&str; 12] = ["C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B"];
+    let note = choice(param, 128) as i32;
+    format!("{}{}", NAMES[note as usize % 12], note / 12 - 1)
+}
+
+/// Read a parameter that picks one of `count` options, as an index.
+fn choice(param: &AtomicFloat, count: usize) -> usize {
+    (param.get().max(0.0).round() as usize).min(count - 1)
+}
+
+/// Whether a parameter is part of the sound that A/B compares and morphs, rather than the setup or
+/// the morph itself.
+fn is_sound_param(index: i32) -> bool {
+    index != MORPH_PARAM && !SETUP_PARAMS.contains(&index)
+}
+
+/// A seed for a random patch, from the clock.
+fn random_seed() -> u32 {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.subsec_nanos());
+    nanos | 1
+}
+
+/// Clamp a parameter value into `min..=max`, mapping NaN to `min`.
+/// A parameter holding a midi note number or velocity, from `min` up to 127.
+fn midi_value(param: &AtomicFloat, min: f32) -> u8 {
+    sanitize(param.get(), min, 127.0).round() as u8
+}
+
+fn sanitize(val: f32, min: f32, max: f32) -> f32 {
+    if val.is_nan() {
+        min
+    } else {
+        val.clamp(min, max)
+    }
+}
+
+impl Default for ReplicantParameters {
+    /// The init patch, with the factory presets loaded.
+    fn default() -> ReplicantParameters {
+        let params = ReplicantParameters::init();
+        *params.bank() = Bank::factory();
+        params
+    }
+}
+
+impl ReplicantParameters {
+    /// The init patch, with an empty preset bank.
+    pub fn init() -> ReplicantParameters {
+        ReplicantParameters {
+            attack: AtomicFloat::new(0.0005),
+            hold: AtomicFloat::new(0.0),
+            decay: AtomicFloat::new(0.0005),
+            sustain: AtomicFloat::new(1.0),
+            release: AtomicFloat::new(0.0005),
+            env_mode: AtomicFloat::new(EnvelopeMode::Sustain as usize as f32),
+            curve: AtomicFloat::new(0.0),
+            retrigger: AtomicFloat::new(Retrigger::FromCurrent as usize as f32),
+
+            // a detuned pair panned hard left and right, like the original hard-coded 0.99/1.01
+            // left/right skew
+            unison: AtomicFloat::new(2.0),
+            unison_detune: AtomicFloat::new(34.0),
+            unison_spread: AtomicFloat::new(1.0),
+
+            osc_sync: AtomicFloat::new(0.0),
+            sync_ratio: AtomicFloat::new(2.0),
+
+            vel_to_attack: AtomicFloat::new(0.0),
+            velocity_curve: AtomicFloat::new(0.0),
+
+            polyphony: AtomicFloat::new(8.0),
+            steal_policy: AtomicFloat::new(0.0),
+
+            note_priority: AtomicFloat::new(0.0),
+            legato: AtomicFloat::new(0.0),
+            glide_time: AtomicFloat::new(0.0),
+            glide_mode: AtomicFloat::new(GlideMode::ConstantTime as usize as f32),
+            glide_curve: AtomicFloat::new(GlideCurve::Linear as usize as f32),
+            pan_mode: AtomicFloat::new(PanMode::Centre as usize as f32),
+            pan_width: AtomicFloat::new(0.5),
+
+            // wide open, so the filter is inaudible until it's set up
+            filter_type: AtomicFloat::new(0.0),
+            cutoff: AtomicFloat::new(MAX_CUTOFF),
+            resonance: AtomicFloat::new(0.0),
+            filter_env_amount: AtomicFloat::new(0.0),
+            filter_attack: AtomicFloat::new(0.0005),
+            filter_hold: AtomicFloat::new(0.0),
+            filter_decay: AtomicFloat::new(0.2),
+            filter_sustain: AtomicFloat::new(0.0),
+            filter_release: AtomicFloat::new(0.2),
+            filter_curve: AtomicFloat::new(0.0),
+            filter_env_mode: AtomicFloat::new(EnvelopeMode::Sustain as usize as f32),
+            vel_to_cutoff: AtomicFloat::new(0.0),
+
+            waveform: AtomicFloat::new(0.0),
+            pulse_width: AtomicFloat::new(0.5),
+            wavetable_position: AtomicFloat::new(0.0),
+            // just the fundamental, so it starts out as a sine
+            harmonics: std::array::from_fn(|harmonic| AtomicFloat::new(if harmonic == 0 { 1.0 } else { 0.0 })),
+            pitch_env_amount: AtomicFloat::new(0.0),
+            pitch_env_decay: AtomicFloat::new(0.1),
+            morph: AtomicFloat::new(0.0),
+            drift: AtomicFloat::new(0.0),
+            oversampling: AtomicFloat::new(Oversampling::Off as usize as f32),
+
+            bend_range: AtomicFloat::new(2.0),
+            mpe: AtomicFloat::new(0.0),
+            // the MPE spec's default
+            mpe_bend_range: AtomicFloat::new(48.0),
+            midi_channel: AtomicFloat::new(0.0),
+            key_min: AtomicFloat::new(0.0),
+            key_max: AtomicFloat::new(127.0),
+            velocity_min: AtomicFloat::new(1.0),
+            velocity_max: AtomicFloat::new(127.0),
+            program_change: AtomicFloat::new(1.0),
+            stop_mode: AtomicFloat::new(StopMode::Release as usize as f32),
+            multitimbral: AtomicFloat::new(0.0),
+            output_routing: AtomicFloat::new(OutputRouting::Main as usize as f32),
+
+            arp: AtomicFloat::new(0.0),
+            arp_mode: AtomicFloat::new(ArpMode::Up as usize as f32),
+            arp_octaves: AtomicFloat::new(1.0),
+            arp_rate: AtomicFloat::new(8.0),
+            // sixteenth notes
+            arp_sync: AtomicFloat::new(11.0),
+            arp_gate: AtomicFloat::new(0.5),
+
+            vibrato_rate: AtomicFloat::new(5.0),
+            vibrato_depth: AtomicFloat::new(50.0),
+            aftertouch_vibrato: AtomicFloat::new(0.0),
+
+            reference_pitch: AtomicFloat::new(STANDARD_PITCH as f32),
+
+            mod_attack: AtomicFloat::new(0.0005),
+            mod_hold: AtomicFloat::new(0.0),
+            mod_decay: AtomicFloat::new(0.2),
+            mod_sustain: AtomicFloat::new(0.0),
+            mod_release: AtomicFloat::new(0.2),
+            mod_curve: AtomicFloat::new(0.0),
+            mod_env_mode: AtomicFloat::new(EnvelopeMode::Sustain as usize as f32),
+
+            fm_ratio: AtomicFloat::new(1.0),
+            fm_index: AtomicFloat::new(0.0),
+            fm_attack: AtomicFloat::new(0.0),
+            fm_hold: AtomicFloat::new(0.0),
+            fm_decay: AtomicFloat::new(0.5),
+            fm_sustain: AtomicFloat::new(1.0),
+            fm_release: AtomicFloat::new(0.5),
+            fm_curve: AtomicFloat::new(0.0),
+            fm_env_mode: AtomicFloat::new(EnvelopeMode::Sustain as usize as f32),
+
+            // only the main oscillators until the others are mixed in
+            osc_level: AtomicFloat::new(1.0),
+            ring_mod: AtomicFloat::new(0.0),
+            sub_level: AtomicFloat::new(0.0),
+            noise_level: AtomicFloat::new(0.0),
+            sub_octave: AtomicFloat::new(1.0),
+            sub_waveform: AtomicFloat::new(SubWaveform::Sine as usize as f32),
+            noise_color: AtomicFloat::new(NoiseColor::White as usize as f32),
+
+            master_gain: AtomicFloat::new(0.0),
+            master_pan: AtomicFloat::new(0.0),
+            stereo_mode: AtomicFloat::new(StereoMode::MidSide as usize as f32),
+            stereo_width: AtomicFloat::new(1.0),
+            saturation: AtomicFloat::new(Saturation::SoftClip as usize as f32),
+            drive: AtomicFloat::new(0.0),
+            dc_blocker: AtomicFloat::new(1.0),
+
+            // the effects are off until they're mixed in
+            chorus_rate: AtomicFloat::new(0.8),
+            chorus_depth: AtomicFloat::new(0.5),
+            chorus_mix: AtomicFloat::new(0.0),
+            delay_time: AtomicFloat::new(0.3),
+            delay_sync: AtomicFloat::new(0.0),
+            delay_feedback: AtomicFloat::new(0.35),
+            delay_mix: AtomicFloat::new(0.0),
+            reverb_size: AtomicFloat::new(0.5),
+            reverb_damping: AtomicFloat::new(0.5),
+            reverb_mix: AtomicFloat::new(0.0),
+
+            lfos: [LfoParameters::new(LfoShape::Sine, 2.0), LfoParameters::new(LfoShape::Triangle, 0.5)],
+            random_rate: AtomicFloat::new(4.0),
+            random_sync: AtomicFloat::new(0.0),
+            random_smooth: AtomicFloat::new(0.0),
+            // common routings, all switched off until they're given some depth
+            mod_slots: [
+                ModSlotParameters::new(ModSource::Lfo1, ModDestination::Pitch),
+                ModSlotParameters::new(ModSource::Lfo2, ModDestination::Cutoff),
+                ModSlotParameters::new(ModSource::ModEnv, ModDestination::Cutoff),
+                ModSlotParameters::new(ModSource::Velocity, ModDestination::Amplitude),
+                ModSlotParameters::new(ModSource::KeyTrack, ModDestination::Cutoff),
+                ModSlotParameters::new(ModSource::ModWheel, ModDestination::Cutoff),
+                ModSlotParameters::new(ModSource::Aftertouch, ModDestination::Cutoff),
+                ModSlotParameters::new(ModSource::Lfo1, ModDestination::Pan),
+            ],
+
+            midi_map: MidiMap::default(),
+
+            bank: Mutex::new(Bank { current: 0, presets: Vec::new() }),
+            tuning: Mutex::new(TuningState::default()),
+            wavetable: Mutex::new(WavetableState::default()),
+            preset_switched: AtomicBool::new(false),
+            bank_loaded: AtomicBool::new(false),
+            loader: Loader::default(),
+            history: Mutex::new(History::default()),
+            ab: AbSlots::new(NUM_PARAMS),
+        }
+    }
+
+    fn bank(&self) -> MutexGuard<'_, Bank> {
+        // a panic while holding the lock can't leave the bank half written, so carry on with it
+        self.bank.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn history(&self) -> MutexGuard<'_, History> {
+        self.history.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn tuning(&self) -> MutexGuard<'_, TuningState> {
+        self.tuning.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Load a Scala tuning, or go back to 12-TET with `None`.
+    ///
+    /// If the files can't be loaded the tuning falls back to 12-TET, but they're still
+    /// remembered so presets saved afterwards keep referring to them.
+    pub fn set_tuning_files(&self, files: Option<TuningFiles>) -> Result<(), TuningError> {
+        let loaded = files.as_ref().map_or(Ok(Tuning::default()), Tuning::load);
+        let mut tuning = self.tuning();
+        tuning.files = files;
+        tuning.table = *loaded.as_ref().unwrap_or(&Tuning::default());
+        loaded.map(|_| ())
+    }
+
+    /// The Scala files the tuning was loaded from, or `None` for 12-TET.
+    pub fn tuning_files(&self) -> Option<TuningFiles> {
+        self.tuning().files.clone()
+    }
+
+    /// The tuning table, or `None` if it's being changed right now. For the audio thread, which
+    /// can carry on with the table it had.
+    pub fn try_tuning(&self) -> Option<Tuning> {
+        match self.tuning.try_lock() {
+            Ok(tuning) => Some(tuning.table),
+            Err(TryLockError::Poisoned(poisoned)) => Some(poisoned.into_inner().table),
+            Err(TryLockError::WouldBlock) => None,
+        }
+    }
+
+    fn wavetable(&self) -> MutexGuard<'_, WavetableState> {
+        self.wavetable.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Load a wavetable on the loader thread, or go back to the built in one with `None`, without
+    /// waiting for it. Without a loader thread, as when rendering, it's loaded before this returns.
+    ///
+    /// The file's remembered straight away, for presets saved before it loads. If it can't be
+    /// loaded the built in table is used instead, but it's still remembered so presets saved
+    /// afterwards keep referring to it.
+    pub fn request_wavetable_file(&self, file: Option<PathBuf>) {
+        {
+            let mut wavetable = self.wavetable();
+            wavetable.file = file.clone();
+            wavetable.status = LoadStatus::Loading;
+        }
+        self.load_in_background(Load::Wavetable(file));
+    }
+
+    /// Load the wavetable file asked for and swap it in, unless another's been asked for since.
+    fn load_wavetable(&self, file: Option<PathBuf>) -> Result<(), WavetableError> {
+        // the file's read before the lock's taken, so the audio thread isn't kept from the table
+        let loaded = file.as_deref().map_or(Ok(Wavetable::basic()), |file| Wavetable::load(file).map(Arc::new));
+        let mut wavetable = self.wavetable();
+        if wavetable.file != file {
+            return Ok(());
+        }
+        wavetable.status = match &loaded {
+            Ok(_) => LoadStatus::Loaded,
+            Err(err) => LoadStatus::Failed(err.to_string()),
+        };
+        let table = loaded.as_ref().map_or_else(|_| Wavetable::basic(), Arc::clone);
+        let old = std::mem::replace(&mut wavetable.table, table);
+        // the synth lets go of the old table on its next buffer, so it's freed on a later load
+        wavetable.retired.retain(|table| Arc::strong_count(table) > 1);
+        wavetable.retired.push(old);
+        loaded.map(|_| ())
+    }
+
+    /// The file the wavetable was loaded from, or `None` for the built in one.
+    pub fn wavetable_file(&self) -> Option<PathBuf> {
+        self.wavetable().file.clone()
+    }
+
+    /// How loading the wavetable file is going.
+    #[cfg_attr(not(feature = "gui"), allow(dead_code))]
+    pub fn wavetable_status(&self) -> LoadStatus {
+        self.wavetable().status.clone()
+    }
+
+    /// The wavetable, or `None` if it's being changed right now. For the audio thread, which can
+    /// carry on with the table it had.
+    pub fn try_wavetable(&self) -> Option<Arc<Wavetable>> {
+        match self.wavetable.try_lock() {
+            Ok(wavetable) => Some(Arc::clone(&wavetable.table)),
+            Err(TryLockError::Poisoned(poisoned)) => Some(Arc::clone(&poisoned.into_inner().table)),
+            Err(TryLockError::WouldBlock) => None,
+        }
+    }
+
+    /// How far through the wavetable the oscillators read, 0.0 to 1.0.
+    pub fn wavetable_position(&self) -> f64 {
+        f64::from(sanitize(self.wavetable_position.get(), 0.0, 1.0))
+    }
+
+    /// The additive waveform's harmonic levels, 0.0 to 1.0.
+    pub fn harmonics(&self) -> [f64; NUM_HARMONICS] {
+        self.harmonics.each_ref().map(|level| f64::from(sanitize(level.get(), 0.0, 1.0)))
+    }
+
+    /// The frequency A4 is tuned to, in Hz.
+    pub fn reference_pitch(&self) -> f64 {
+        f64::from(sanitize(self.reference_pitch.get(), MIN_REFERENCE_PITCH, MAX_REFERENCE_PITCH))
+    }
+
+    /// A parameter's value in its real units.
+    pub fn value(&self, index: i32) -> Option<f32> {
+        self.param(index).map(|(param, _)| param.get())
+    }
+
+    /// Set a parameter in its real units, clamped to its range.
+    pub fn set_value(&self, index: i32, value: f32) {
+        if let Some((param, range)) = self.param(index) {
+            param.set(range.denormalize(range.normalize(value)));
+        }
+        if index == MORPH_PARAM {
+            self.morph();
+        }
+    }
+
+    /// A parameter's value from 0.0 to 1.0 across its range, as the host sees it.
+    pub fn normalized(&self, index: i32) -> f32 {
+        self.param(index).map_or(0.0, |(param, range)| range.normalize(param.get()))
+    }
+
+    /// Set a parameter from 0.0 to 1.0 across its range, as the host would.
+    pub fn set_normalized(&self, index: i32, value: f32) {
+        if let Some((param, range)) = self.param(index) {
+            param.set(range.denormalize(sanitize(value, 0.0, 1.0)));
+        }
+        if index == MORPH_PARAM {
+            self.morph();
+        }
+    }
+
+    /// Every parameter's value from 0.0 to 1.0 across its range, by host index, as presets and the
+    /// A/B slots keep them.
+    pub fn normalized_values(&self) -> Vec<f32> {
+        (0..NUM_PARAMS as i32).map(|index| self.normalized(index)).collect()
+    }
+
+    /// Set the parameters from `values`, by host index, as `normalized_values` gives them. Ones
+    /// past the end of `values`, as in presets saved before they were added, are left as they
+    /// were. So is the morph, which would move everything else off the values.
+    pub fn set_normalized_values(&self, values: &[f32]) {
+        for (index, &value) in (0..NUM_PARAMS as i32).zip(values) {
+            if index != MORPH_PARAM {
+                self.set_normalized(index, value);
+            }
+        }
+    }
+
+    /// Every parameter's default from 0.0 to 1.0 across its range, by host index: its value in
+    /// the init patch.
+    #[cfg_attr(not(any(feature = "clap", feature = "vst3")), allow(dead_code))]
+    pub fn default_values() -> Vec<f32> {
+        ReplicantParameters::init().normalized_values()
+    }
+
+    /// Move every parameter the A and B patches differ in to the morph's point between them. The
+    /// ones they agree on, and the setup, are left alone.
+    fn morph(&self) {
+        let amount = sanitize(self.morph.get(), 0.0, 1.0);
+        for index in (0..NUM_PARAMS as i32).filter(|&index| is_sound_param(index)) {
+            if let Some((param, range)) = self.param(index) {
+                let stepped = matches!(range, Range::Stepped(..));
+                if let Some(value) = self.ab.morphed(index as usize, amount, stepped) {
+                    param.set(range.denormalize(value));
+                }
+            }
+        }
+    }
+
+    /// Keep the current patch in the A or B slot.
+    #[cfg_attr(not(feature = "gui"), allow(dead_code))]
+    pub fn store_ab(&self, slot: Slot) {
+        self.ab.store(slot, self.normalized_values());
+    }
+
+    /// Go back to the patch in the A or B slot, as one step that can be undone. The setup and the
+    /// morph stay as they are.
+    #[cfg_attr(not(feature = "gui"), allow(dead_code))]
+    pub fn recall_ab(&self, slot: Slot) {
+        self.record_patch(|| {
+            for index in (0..NUM_PARAMS as i32).filter(|&index| is_sound_param(index)) {
+                if let Some(value) = self.ab.value(slot, index as usize) {
+                    self.set_parameter(index, value);
+                }
+            }
+        });
+    }
+
+    /// The live parameter values, named after the selected preset.
+    pub fn current_preset(&self) -> Preset {
+        let bank = self.bank();
+        let name = bank.presets.get(bank.current).map_or("", |preset| &preset.name);
+        Preset::capture(name, self)
+    }
+
+    /// Load a preset into the selected slot.
+    pub fn load_preset(&self, preset: Preset) {
+        preset.apply(self);
+        let mut bank = self.bank();
+        let current = bank.current;
+        if let Some(slot) = bank.presets.get_mut(current) {
+            *slot = preset;
+        }
+    }
+
+    /// Reset every parameter to the init patch, keeping the midi and tuning setup.
+    #[cfg_attr(not(feature = "gui"), allow(dead_code))]
+    pub fn init_patch(&self) {
+        self.record_patch(|| Preset::init(self).apply(self));
+    }
+
+    /// Make a new random patch, keeping the midi and tuning setup and the output level.
+    #[cfg_attr(not(feature = "gui"), allow(dead_code))]
+    pub fn randomize_patch(&self) {
+        self.record_patch(|| Preset::random(self, random_seed()).apply(self));
+    }
+
+    /// Change the patch from the editor, as one step that can be undone. The tuning and wavetable
+    /// aren't part of the history.
+    fn record_patch(&self, change: impl FnOnce()) {
+        let before = self.normalized_values();
+        change();
+        let changes = before.into_iter().zip(0..)
+            .map(|(before, index)| Change { index, before, after: self.get_parameter(index) })
+            .filter(|change| change.before != change.after)
+            .collect();
+        self.history().record(changes, false);
+    }
+
+    /// Set a parameter from the editor, recording it to be undone. With `continuing`, it joins
+    /// the last step if that only changed the same parameter, as every move of a drag after the
+    /// first does.
+    #[cfg_attr(not(feature = "gui"), allow(dead_code))]
+    pub fn edit_parameter(&self, index: i32, value: f32, continuing: bool) {
+        let before = self.get_parameter(index);
+        self.set_parameter(index, value);
+        let change = Change { index, before, after: self.get_parameter(index) };
+        self.history().record(vec![change], continuing);
+    }
+
+    /// Undo the editor's last step, returning the parameters it moved.
+    #[cfg_attr(not(feature = "gui"), allow(dead_code))]
+    pub fn undo(&self) -> Vec<i32> {
+        let mut history = self.history();
+        let changes = history.undo().unwrap_or_default();
+        // backwards, in case a step ever changes a parameter twice
+        for change in changes.iter().rev() {
+            self.set_parameter(change.index, change.before);
+        }
+        changes.iter().map(|change| change.index).collect()
+    }
+
+    /// Redo the last step undone, returning the parameters it moved.
+    #[cfg_attr(not(feature = "gui"), allow(dead_code))]
+    pub fn redo(&self) -> Vec<i32> {
+        let mut history = self.history();
+        let changes = history.redo().unwrap_or_default();
+        for change in changes {
+            self.set_parameter(change.index, change.after);
+        }
+        changes.iter().map(|change| change.index).collect()
+    }
+
+    #[cfg_attr(not(feature = "gui"), allow(dead_code))]
+    pub fn can_undo(&self) -> bool {
+        self.history().can_undo()
+    }
+
+    #[cfg_attr(not(feature = "gui"), allow(dead_code))]
+    pub fn can_redo(&self) -> bool {
+        self.history().can_redo()
+    }
+
+    /// Fill the user preset slots from a directory of preset files on the loader thread, without
+    /// waiting for it. Without a loader thread it's done before this returns.
+    pub fn request_preset_dir(&self, dir: PathBuf) {
+        self.load_in_background(Load::PresetDir(dir));
+    }
+
+    /// Fill the user preset slots from a directory of preset files, returning how many loaded.
+    /// The files are read before the bank's locked, so the host's preset calls aren't kept
+    /// waiting, and a bank the host has loaded in the meantime is left alone.
+    fn load_preset_dir(&self, dir: &Path) -> io::Result<usize> {
+        let presets = Bank::read_dir(dir)?;
+        let mut bank = self.bank();
+        if self.bank_loaded.load(Ordering::Acquire) {
+            return Ok(0);
+        }
+        let count = bank.set_user_presets(presets);
+        // so the host asks for the presets' names again
+        self.preset_switched.store(true, Ordering::Release);
+        Ok(count)
+    }
+
+    /// Do a load on the loader thread if there is one, or right away if not.
+    fn load_in_background(&self, load: Load) {
+        if let Err(load) = self.loader.send(load) {
+            self.load(load);
+        }
+    }
+
+    fn load(&self, load: Load) {
+        match load {
+            Load::Wavetable(file) => {
+                if let (Err(err), Some(file)) = (self.load_wavetable(file.clone()), file) {
+                    warn!("couldn't load the wavetable {}: {}", file.display(), err);
+                }
+            }
+            Load::PresetDir(dir) => match self.load_preset_dir(&dir) {
+                Ok(count) => debug!("loaded {} presets from {}", count, dir.display()),
+                Err(err) => debug!("no presets loaded from {}: {}", dir.display(), err),
+            },
+            Load::Preset(preset) => {
+                self.switch_preset(preset);
+            }
+            // only the loader thread has the parts
+            Load::BuildParts(_) | Load::PartPreset { .. } => (),
+        }
+    }
+
+    /// Start the thread that loads wavetables and preset directories, switches presets, and builds
+    /// `parts` and loads their presets. It stops once the parameters are dropped.
+    pub fn spawn_loader_thread(params: &Arc<ReplicantParameters>, parts: Weak<PartBuilder>) {
+        let weak = Arc::downgrade(params);
+        params.loader.spawn(move |load| {
+            let Some(params) = weak.upgrade() else {
+                return false;
+            };
+            match load {
+                Load::BuildParts(sample_rate) => {
+                    if let Some(parts) = parts.upgrade() {
+                        parts.build(sample_rate);
+                    }
+                }
+                Load::PartPreset { part, preset } => {
+                    let preset = usize::try_from(preset).ok().and_then(|index| params.preset(index));
+                    if let (Some(parts), Some(preset)) = (parts.upgrade(), preset) {
+                        parts.load_preset(part, &preset);
+                    }
+                }
+                load => params.load(load),
+            }
+            true
+        });
+    }
+
+    /// Ask for a switch to another preset without waiting for it, as the audio thread can't take
+    /// the bank's lock. The loader thread switches soon after. It's dropped if the loader's too
+    /// busy to take it, or there's no loader thread, as when rendering.
+    pub fn request_preset(&self, preset: i32) {
+        let _ = self.loader.try_send(Load::Preset(preset));
+    }
+
+    /// Ask for multi-timbral mode's parts at `sample_rate` without waiting for them, returning
+    /// whether the loader thread took the request.
+    pub fn request_parts(&self, sample_rate: f32) -> bool {
+        self.loader.try_send(Load::BuildParts(sample_rate)).is_ok()
+    }
+
+    /// Ask for a preset from the bank to be loaded into one of multi-timbral mode's parts, without
+    /// waiting for it, as with `request_preset`.
+    pub fn request_part_preset(&self, part: usize, preset: i32) {
+        let _ = self.loader.try_send(Load::PartPreset { part, preset });
+    }
+
+    /// Switch to a preset, returning whether the preset changed.
+    pub fn switch_preset(&self, preset: i32) -> bool {
+        if preset == self.get_preset_num() {
+            return false;
+        }
+        self.change_preset(preset);
+        let switched = self.get_preset_num() == preset;
+        if switched {
+            self.preset_switched.store(true, Ordering::Release);
+        }
+        switched
+    }
+
+    /// A copy of the preset in a slot of the bank. The selected one has the live values, as its
+    /// slot only catches up when another is selected.
+    pub fn preset(&self, index: usize) -> Option<Preset> {
+        let bank = self.bank();
+        if index == bank.current {
+            let name = bank.presets.get(index)?.name.clone();
+            drop(bank);
+            return Some(Preset::capture(&name, self));
+        }
+        bank.presets.get(index).cloned()
+    }
+
+    /// Whether the loader thread has switched presets, or filled the user preset slots, since this
+    /// was last asked.
+    pub fn take_preset_switched(&self) -> bool {
+        self.preset_switched.swap(false, Ordering::Acquire)
+    }
+
+    /// Store the live parameter values into the selected preset.
+    fn save_current_preset(&self, bank: &mut Bank) {
+        let current = bank.current;
+        if let Some(preset) = bank.presets.get_mut(current) {
+            *preset = Preset::capture(&preset.name, self);
+        }
+    }
+}
+
+impl ReplicantParameters {
+    /// The parameter at a host index, and the range it covers.
+    fn param(&self, index: i32) -> Option<(&AtomicFloat, Range)> {
+        Some(match index {
+            0 => (&self.unison_detune, Range::Linear(0.0, MAX_UNISON_DETUNE)),
+            1 => (&self.osc_sync, Range::Stepped(0.0, 1.0)),
+            2 => (&self.sync_ratio, Range::Linear(MIN_SYNC_RATIO, MAX_SYNC_RATIO)),
+            3 => (&self.vel_to_attack, Range::Linear(0.0, 1.0)),
+            4 => (&self.polyphony, Range::Stepped(1.0, MAX_POLYPHONY as f32)),
+            5 => (&self.attack, Range::Quadratic(MAX_ENVELOPE_TIME)),
+            6 => (&self.decay, Range::Quadratic(MAX_ENVELOPE_TIME)),
+            7 => (&self.sustain, Range::Linear(0.0, 1.0)),
+            8 => (&self.release, Range::Quadratic(MAX_ENVELOPE_TIME)),
+            9 => (&self.waveform, Range::Stepped(0.0, (Waveform::ALL.len() - 1) as f32)),
+            10 => (&self.velocity_curve, Range::Stepped(0.0, (VelocityCurve::ALL.len() - 1) as f32)),
+            11 => (&self.bend_range, Range::Stepped(1.0, MAX_BEND_RANGE)),
+            12 => (&self.vibrato_rate, Range::Logarithmic(MIN_VIBRATO_RATE, MAX_VIBRATO_RATE)),
+            13 => (&self.vibrato_depth, Range::Linear(0.0, MAX_VIBRATO_DEPTH)),
+            14 => (&self.steal_policy, Range::Stepped(0.0, (StealPolicy::ALL.len() - 1) as f32)),
+            15 => (&self.note_priority, Range::Stepped(0.0, (NotePriority::ALL.len() - 1) as f32)),
+            16 => (&self.legato, Range::Stepped(0.0, 1.0)),
+            17 => (&self.glide_time, Range::Quadratic(MAX_GLIDE_TIME)),
+            18 => (&self.filter_type, Range::Stepped(0.0, (FilterType::ALL.len() - 1) as f32)),
+            19 => (&self.cutoff, Range::Logarithmic(MIN_CUTOFF, MAX_CUTOFF)),
+            20 => (&self.resonance, Range::Linear(0.0, 1.0)),
+            21 => (&self.filter_env_amount, Range::Linear(-MAX_FILTER_ENV_AMOUNT, MAX_FILTER_ENV_AMOUNT)),
+            22 => (&self.filter_attack, Range::Quadratic(MAX_ENVELOPE_TIME)),
+            23 => (&self.filter_decay, Range::Quadratic(MAX_ENVELOPE_TIME)),
+            24 => (&self.filter_sustain, Range::Linear(0.0, 1.0)),
+            25 => (&self.filter_release, Range::Quadratic(MAX_ENVELOPE_TIME)),
+            26 => (&self.vel_to_cutoff, Range::Linear(0.0, MAX_VEL_TO_CUTOFF)),
+            27 => (&self.mod_attack, Range::Quadratic(MAX_ENVELOPE_TIME)),
+            28 => (&self.mod_decay, Range::Quadratic(MAX_ENVELOPE_TIME)),
+            29 => (&self.mod_sustain, Range::Linear(0.0, 1.0)),
+            30 => (&self.mod_release, Range::Quadratic(MAX_ENVELOPE_TIME)),
+            59 => (&self.unison, Range::Stepped(1.0, MAX_UNISON as f32)),
+            60 => (&self.unison_spread, Range::Linear(0.0, 1.0)),
+            61 => (&self.curve, Range::Linear(-1.0, 1.0)),
+            62 => (&self.filter_curve, Range::Linear(-1.0, 1.0)),
+            63 => (&self.mod_curve, Range::Linear(-1.0, 1.0)),
+            64 => (&self.master_gain, Range::Linear(MIN_MASTER_GAIN, MAX_MASTER_GAIN)),
+            65 => (&self.master_pan, Range::Linear(-1.0, 1.0)),
+            66 => (&self.stereo_width, Range::Linear(0.0, MAX_STEREO_WIDTH)),
+            67 => (&self.saturation, Range::Stepped(0.0, (Saturation::ALL.len() - 1) as f32)),
+            68 => (&self.drive, Range::Linear(0.0, MAX_DRIVE)),
+            69 => (&self.chorus_rate, Range::Logarithmic(MIN_CHORUS_RATE, MAX_CHORUS_RATE)),
+            70 => (&self.chorus_depth, Range::Linear(0.0, 1.0)),
+            71 => (&self.chorus_mix, Range::Linear(0.0, 1.0)),
+            72 => (&self.delay_time, Range::Quadratic(MAX_DELAY_TIME as f32)),
+            73 => (&self.delay_feedback, Range::Linear(0.0, MAX_DELAY_FEEDBACK as f32)),
+            74 => (&self.delay_mix, Range::Linear(0.0, 1.0)),
+            77 => (&self.delay_sync, Range::Stepped(0.0, NoteDivision::ALL.len() as f32)),
+            78 => (&self.fm_ratio, Range::Linear(MIN_FM_RATIO, MAX_FM_RATIO)),
+            79 => (&self.fm_index, Range::Quadratic(MAX_FM_INDEX)),
+            80 => (&self.fm_attack, Range::Quadratic(MAX_ENVELOPE_TIME)),
+            81 => (&self.fm_decay, Range::Quadratic(MAX_ENVELOPE_TIME)),
+            82 => (&self.fm_sustain, Range::Linear(0.0, 1.0)),
+            83 => (&self.fm_release, Range::Quadratic(MAX_ENVELOPE_TIME)),
+            84 => (&self.fm_curve, Range::Linear(-1.0, 1.0)),
+            85 => (&self.osc_level, Range::Linear(0.0, 1.0)),
+            86 => (&self.sub_level, Range::Linear(0.0, 1.0)),
+            87 => (&self.sub_octave, Range::Stepped(1.0, 2.0)),
+            88 => (&self.sub_waveform, Range::Stepped(0.0, (SubWaveform::ALL.len() - 1) as f32)),
+            89 => (&self.noise_level, Range::Linear(0.0, 1.0)),
+            90 => (&self.noise_color, Range::Stepped(0.0, (NoiseColor::ALL.len() - 1) as f32)),
+            91 => (&self.pulse_width, Range::Linear(MIN_PULSE_WIDTH as f32, MAX_PULSE_WIDTH as f32)),
+            92 => (&self.ring_mod, Range::Linear(0.0, 1.0)),
+            93 => (&self.mpe, Range::Stepped(0.0, 1.0)),
+            94 => (&self.mpe_bend_range, Range::Stepped(0.0, MAX_MPE_BEND_RANGE)),
+            95 => (&self.aftertouch_vibrato, Range::Linear(0.0, 1.0)),
+            96 => (&self.reference_pitch, Range::Linear(MIN_REFERENCE_PITCH, MAX_REFERENCE_PITCH)),
+            97 => (&self.midi_channel, Range::Stepped(0.0, NUM_MIDI_CHANNELS)),
+            98 => (&self.program_change, Range::Stepped(0.0, 1.0)),
+            99 => (&self.arp, Range::Stepped(0.0, 1.0)),
+            100 => (&self.arp_mode, Range::Stepped(0.0, (ArpMode::ALL.len() - 1) as f32)),
+            101 => (&self.arp_octaves, Range::Stepped(1.0, MAX_ARP_OCTAVES as f32)),
+            102 => (&self.arp_rate, Range::Logarithmic(MIN_ARP_RATE, MAX_ARP_RATE)),
+            103 => (&self.arp_sync, Range::Stepped(0.0, NoteDivision::ALL.len() as f32)),
+            104 => (&self.arp_gate, Range::Linear(MIN_ARP_GATE, 1.0)),
+            105 => (&self.drift, Range::Linear(0.0, 1.0)),
+            106 => (&self.oversampling, Range::Stepped(0.0, (Oversampling::ALL.len() - 1) as f32)),
+            107 => (&self.retrigger, Range::Stepped(0.0, (Retrigger::ALL.len() - 1) as f32)),
+            108 => (&self.hold, Range::Quadratic(MAX_ENVELOPE_TIME)),
+            109 => (&self.env_mode, Range::Stepped(0.0, (EnvelopeMode::ALL.len() - 1) as f32)),
+            110 => (&self.filter_hold, Range::Quadratic(MAX_ENVELOPE_TIME)),
+            111 => (&self.filter_env_mode, Range::Stepped(0.0, (EnvelopeMode::ALL.len() - 1) as f32)),
+            112 => (&self.mod_hold, Range::Quadratic(MAX_ENVELOPE_TIME)),
+            113 => (&self.mod_env_mode, Range::Stepped(0.0, (EnvelopeMode::ALL.len() - 1) as f32)),
+            114 => (&self.fm_hold, Range::Quadratic(MAX_ENVELOPE_TIME)),
+            115 => (&self.fm_env_mode, Range::Stepped(0.0, (EnvelopeMode::ALL.len() - 1) as f32)),
+            116 => (&self.glide_mode, Range::Stepped(0.0, (GlideMode::ALL.len() - 1) as f32)),
+            117 => (&self.glide_curve, Range::Stepped(0.0, (GlideCurve::ALL.len() - 1) as f32)),
+            118 => (&self.pan_mode, Range::Stepped(0.0, (PanMode::ALL.len() - 1) as f32)),
+            119 => (&self.pan_width, Range::Linear(0.0, 1.0)),
+            120 => (&self.random_rate, Range::Logarithmic(MIN_LFO_RATE, MAX_LFO_RATE)),
+            121 => (&self.random_sync, Range::Stepped(0.0, NoteDivision::ALL.len() as f32)),
+            122 => (&self.random_smooth, Range::Linear(0.0, 1.0)),
+            123 => (&self.wavetable_position, Range::Linear(0.0, 1.0)),
+            132 => (&self.pitch_env_amount, Range::Linear(-MAX_PITCH_ENV_AMOUNT, MAX_PITCH_ENV_AMOUNT)),
+            133 => (&self.pitch_env_decay, Range::Quadratic(MAX_ENVELOPE_TIME)),
+            MORPH_PARAM => (&self.morph, Range::Linear(0.0, 1.0)),
+            134 => (&self.key_min, Range::Stepped(0.0, 127.0)),
+            135 => (&self.key_max, Range::Stepped(0.0, 127.0)),
+            136 => (&self.velocity_min, Range::Stepped(1.0, 127.0)),
+            137 => (&self.velocity_max, Range::Stepped(1.0, 127.0)),
+            139 => (&self.stereo_mode, Range::Stepped(0.0, (StereoMode::ALL.len() - 1) as f32)),
+            140 => (&self.reverb_size, Range::Linear(0.0, 1.0)),
+            141 => (&self.reverb_damping, Range::Linear(0.0, 1.0)),
+            142 => (&self.reverb_mix, Range::Linear(0.0, 1.0)),
+            143 => (&self.dc_blocker, Range::Stepped(0.0, 1.0)),
+            144 => (&self.stop_mode, Range::Stepped(0.0, (StopMode::ALL.len() - 1) as f32)),
+            145 => (&self.multitimbral, Range::Stepped(0.0, 1.0)),
+            146 => (&self.output_routing, Range::Stepped(0.0, (OutputRouting::ALL.len() - 1) as f32)),
+            _ => {
+                if let Some(lfo) = lfo_sync_param(index) {
+                    (&self.lfos[lfo].sync, Range::Stepped(0.0, NoteDivision::ALL.len() as f32))
+                } else if let Some(harmonic) = harmonic_param(index) {
+                    (&self.harmonics[harmonic], Range::Linear(0.0, 1.0))
+                } else if let Some((lfo, setting)) = lfo_param(index) {
+                    let lfo = &self.lfos[lfo];
+                    match setting {
+                        0 => (&lfo.shape, Range::Stepped(0.0, (LfoShape::ALL.len() - 1) as f32)),
+                        _ => (&lfo.rate, Range::Logarithmic(MIN_LFO_RATE, MAX_LFO_RATE)),
+                    }
+                } else {
+                    let (slot, setting) = mod_slot_param(index)?;
+                    let slot = &self.mod_slots[slot];
+                    match setting {
+                        0 => (&slot.source, Range::Stepped(0.0, (ModSource::ALL.len() - 1) as f32)),
+                        1 => (&slot.destination, Range::Stepped(0.0, (ModDestination::ALL.len() - 1) as f32)),
+                        _ => (&slot.depth, Range::Linear(-1.0, 1.0)),
+                    }
+                }
+            }
+        })
+    }
+}
+
+/// Which LFO's tempo sync a host index is.
+fn lfo_sync_param(index: i32) -> Option<usize> {
+    block_param(index, FIRST_LFO_SYNC_PARAM, NUM_LFOS, 1).map(|(lfo, _)| lfo)
+}
+
+/// Which harmonic's level a host index is.
+fn harmonic_param(index: i32) -> Option<usize> {
+    block_param(index, FIRST_HARMONIC_PARAM, NUM_HARMONICS, 1).map(|(harmonic, _)| harmonic)
+}
+
+/// Which LFO a host index belongs to, and which of its settings it is.
+fn lfo_param(index: i32) -> Option<(usize, usize)> {
+    block_param(index, FIRST_LFO_PARAM, NUM_LFOS, PARAMS_PER_LFO)
+}
+
+/// Which modulation matrix slot a host index belongs to, and which of its settings it is.
+fn mod_slot_param(index: i32) -> Option<(usize, usize)> {
+    block_param(index, FIRST_MOD_SLOT_PARAM, NUM_MOD_SLOTS, PARAMS_PER_MOD_SLOT)
+}
+
+/// Find a host index in `count` consecutive groups of `size` parameters starting at `first`,
+/// returning the group and the position within it.
+fn block_param(index: i32, first: i32, count: usize, size: usize) -> Option<(usize, usize)> {
+    if index < first {
+        return None;
+    }
+    let offset = (index - first) as usize;
+    let group = offset / size;
+    if group < count { Some((group, offset % size)) } else { None }
+}
+
+/// The real values a parameter covers, and how they map onto the host's normalized 0.0 to 1.0
+/// range.
+#[derive(Clone, Copy, Debug)]
+enum Range {
+    Linear(f32, f32),
+    /// 0.0 to the given maximum, with more resolution towards 0.0.
+    Quadratic(f32),
+    /// Evenly spaced ratios from min to max, eg. the same distance per octave. Both must be
+    /// positive.
+    Logarithmic(f32, f32),
+    /// Whole numbers from min to max.
+    Stepped(f32, f32),
+}
+
+impl Range {
+    fn normalize(self, value: f32) -> f32 {
+        let normalized = match self {
+            Range::Linear(min, max) | Range::Stepped(min, max) => (value - min) / (max - min),
+            Range::Quadratic(max) => (value / max).max(0.0).sqrt(),
+            Range::Logarithmic(min, max) => (value / min).ln() / (max / min).ln(),
+        };
+        sanitize(normalized, 0.0, 1.0)
+    }
+
+    fn denormalize(self, normalized: f32) -> f32 {
+        match self {
+            Range::Linear(min, max) => min + normalized * (max - min),
+            Range::Stepped(min, max) => (min + normalized * (max - min)).round(),
+            Range::Quadratic(max) => normalized * normalized * max,
+            Range::Logarithmic(min, max) => min * (max / min).powf(normalized),
+        }
+    }
+}
+
+/// Split a frequency in Hz into display text and a unit label.
+fn format_freq(hz: f32) -> (String, &'static str) {
+    if hz < 1000.0 {
+        (format!("{:.0}", hz), "Hz")
+    } else {
+        (format!("{:.2}", hz / 1000.0), "kHz")
+    }
+}
+
+/// Display a level in dB, where the bottom of the range is silent.
+fn format_gain(db: f32) -> String {
+    if db <= MIN_MASTER_GAIN {
+        "-inf".to_string()
+    } else {
+        format!("{:.1}", db)
+    }
+}
+
+/// Display an amplitude from 0.0 to 1.0 in dB.
+fn format_level(amplitude: f32) -> String {
+    if amplitude <= 0.0 {
+        "-inf".to_string()
+    } else {
+        format!("{:.1}", 20.0 * amplitude.log10())
+    }
+}
+
+/// Display a balance as how far it leans to either side.
+fn format_pan(pan: f32) -> String {
+    let amount = (pan.abs() * 100.0).round();
+    if amount == 0.0 {
+        "C".to_string()
+    } else if pan < 0.0 {
+        format!("L {:.0}", amount)
+    } else {
+        format!("R {:.0}", amount)
+    }
+}
+
+/// Describe an envelope curve by which way it bends.
+fn format_curve(curve: f32) -> String {
+    let amount = (curve.abs() * 100.0).round();
+    if amount == 0.0 {
+        "Linear".to_string()
+    } else if curve > 0.0 {
+        format!("Exp {:.0}%", amount)
+    } else {
+        format!("Log {:.0}%", amount)
+    }
+}
+
+/// Split a time in seconds into display text and a unit label.
+fn format_time(seconds: f32) -> (String, &'static str) {
+    if seconds < 1.0 {
+        (format!("{:.1}", seconds * 1000.0), "ms")
+    } else {
+        (format!("{:.2}", seconds), "s")
+    }
+}
+
+/// Split typed in text into a number and whatever unit follows it, eg. "2.5 kHz" or "-6dB".
+fn parse_number(text: &str) -> Option<(f32, &str)> {
+    let end = text.find(|c: char| c.is_alphabetic() || c == '%').unwrap_or(text.len());
+    let number = text[..end].trim().trim_start_matches('±').parse().ok()?;
+    Some((number, text[end..].trim()))
+}
+
+/// Read a balance as `format_pan()` shows it, or as a plain number from -1.0 to 1.0.
+fn parse_pan(text: &str) -> Option<f32> {
+    let lower = text.to_ascii_lowercase();
+    if lower == "c" {
+        return Some(0.0);
+    }
+    let (sign, amount) = match (lower.strip_prefix('l'), lower.strip_prefix('r')) {
+        (Some(amount), _) => (-1.0, amount),
+        (_, Some(amount)) => (1.0, amount),
+        _ => return parse_number(text).map(|(pan, _)| pan),
+    };
+    parse_number(amount).map(|(amount, _)| sign * amount / 100.0)
+}
+
+/// Read an envelope curve as `format_curve()` shows it, or as a plain number from -1.0 to 1.0.
+fn parse_curve(text: &str) -> Option<f32> {
+    let lower = text.to_ascii_lowercase();
+    if lower == "linear" {
+        return Some(0.0);
+    }
+    let (sign, amount) = match (lower.strip_prefix("exp"), lower.strip_prefix("log")) {
+        (Some(amount), _) => (1.0, amount),
+        (_, Some(amount)) => (-1.0, amount),
+        _ => return parse_number(text).map(|(curve, _)| curve),
+    };
+    parse_number(amount).map(|(amount, _)| sign * amount / 100.0)
+}
+
+impl ReplicantParameters {
+    /// Set a parameter from text typed into the host, as `param_text()` shows it, with or without
+    /// the unit from `param_label()`. Times can be given in either ms or s, and frequencies in Hz
+    /// or kHz. Returns whether the text made sense for the parameter.
+    pub fn set_param_text(&self, index: i32, text: &str) -> bool {
+        let text = text.trim();
+        let value = match self.param(index) {
+            Some((_, Range::Stepped(min, max))) => {
+                // the names are found by showing each step on a spare set, so the live value
+                // doesn't flicker through them
+                let spare = ReplicantParameters::init();
+                let named = (min as i32..=max as i32).map(|step| step as f32).find(|&step| {
+                    spare.set_value(index, step);
+                    let shown = spare.param_text(index);
+                    let with_unit = format!("{} {}", shown, spare.param_label(index));
+                    text.eq_ignore_ascii_case(&shown) || text.eq_ignore_ascii_case(with_unit.trim_end())
+                });
+                // or the step itself, for the ones shown as something else
+                let whole = |step: &f32| step.fract() == 0.0 && (min..=max).contains(step);
+                named.or_else(|| parse_number(text).map(|(step, _)| step).filter(whole))
+            }
+            Some(_) => self.parse_value(index, text),
+            None => None,
+        };
+        match value {
+            Some(value) if value.is_finite() => {
+                self.set_value(index, value);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Read typed in text as a continuous parameter's real value, undoing the scaling its
+    /// display applies.
+    fn parse_value(&self, index: i32, text: &str) -> Option<f32> {
+        match index {
+            65 => return parse_pan(text),
+            61..=63 | 84 => return parse_curve(text),
+            _ => (),
+        }
+        let level = matches!(index, 85 | 86 | 89);
+        if text.split_whitespace().next() == Some("-inf") {
+            return match index {
+                64 => Some(MIN_MASTER_GAIN),
+                _ if level => Some(0.0),
+                _ => None,
+            };
+        }
+        let (number, unit) = parse_number(text)?;
+        let label = self.param_label(index);
+        let unit = if unit.is_empty() { label.as_str() } else { unit };
+        Some(match unit.to_ascii_lowercase().as_str() {
+            "ms" | "ms/oct" => number / 1000.0,
+            "s" | "s/oct" => number,
+            "khz" => number * 1000.0,
+            "hz" => number,
+            "%" => number / 100.0,
+            "db" if level => 10.0_f32.powf(number / 20.0),
+            unit if unit.eq_ignore_ascii_case(&label) => number,
+            _ => return None,
+        })
+    }
+
+    /// A parameter's value as the host shows it, without its unit.
+    pub fn param_text(&self, index: i32) -> String {
+        if let Some(lfo) = lfo_sync_param(index) {
+            return format_note_division(&self.lfos[lfo].sync);
+        }
+        if let Some(harmonic) = harmonic_param(index) {
+            return format!("{:.0}", self.harmonics[harmonic].get() * 100.0);
+        }
+        match index {
+            0 => format!("{:.1}", self.unison_detune.get()),
+            1 => if self.osc_sync() { "On" } else { "Off" }.to_string(),
+            2 => format!("{:.2}", self.sync_ratio.get()),
+            3 => format!("{:.0}", self.vel_to_attack.get() * 100.0),
+            4 => match self.polyphony() {
+                1 => "Mono".to_string(),
+                voices => format!("{}", voices),
+            },
+            5 => format_time(self.attack.get()).0,
+            6 => format_time(self.decay.get()).0,
+            7 => format!("{:.0}", self.sustain.get() * 100.0),
+            8 => format_time(self.release.get()).0,
+            9 => self.waveform().name().to_string(),
+            10 => self.velocity_curve().name().to_string(),
+            11 => format!("±{}", self.bend_range.get()),
+            12 => format!("{:.2}", self.vibrato_rate.get()),
+            13 => format!("{:.1}", self.vibrato_depth.get()),
+            14 => self.steal_policy().name().to_string(),
+            15 => self.note_priority().name().to_string(),
+            16 => if self.legato() { "On" } else { "Off" }.to_string(),
+            17 => format_time(self.glide_time.get()).0,
+            18 => self.filter_type().name().to_string(),
+            19 => format_freq(self.cutoff.get()).0,
+            20 => format!("{:.0}", self.resonance.get() * 100.0),
+            21 => format!("{:+.2}", self.filter_env_amount.get()),
+            22 => format_time(self.filter_attack.get()).0,
+            23 => format_time(self.filter_decay.get()).0,
+            24 => format!("{:.0}", self.filter_sustain.get() * 100.0),
+            25 => format_time(self.filter_release.get()).0,
+            26 => format!("{:.2}", self.vel_to_cutoff.get()),
+            27 => format_time(self.mod_attack.get()).0,
+            28 => format_time(self.mod_decay.get()).0,
+            29 => format!("{:.0}", self.mod_sustain.get() * 100.0),
+            30 => format_time(self.mod_release.get()).0,
+            59 => format!("{}", self.unison()),
+            60 => format!("{:.0}", self.unison_spread.get() * 100.0),
+            61 => format_curve(self.curve.get()),
+            62 => format_curve(self.filter_curve.get()),
+            63 => format_curve(self.mod_curve.get()),
+            64 => format_gain(self.master_gain.get()),
+            65 => format_pan(self.master_pan.get()),
+            66 => format!("{:.0}", self.stereo_width.get() * 100.0),
+            67 => self.saturation().name().to_string(),
+            68 => format!("{:.1}", self.drive.get()),
+            69 => format!("{:.2}", self.chorus_rate.get()),
+            70 => format!("{:.0}", self.chorus_depth.get() * 100.0),
+            71 => format!("{:.0}", self.chorus_mix.get() * 100.0),
+            72 => format_time(self.delay_time.get()).0,
+            73 => format!("{:.0}", self.delay_feedback.get() * 100.0),
+            74 => format!("{:.0}", self.delay_mix.get() * 100.0),
+            77 => format_note_division(&self.delay_sync),
+            78 => format!("{:.2}", self.fm_ratio.get()),
+            79 => format!("{:.2}", self.fm_index.get()),
+            80 => format_time(self.fm_attack.get()).0,
+            81 => format_time(self.fm_decay.get()).0,
+            82 => format!("{:.0}", self.fm_sustain.get() * 100.0),
+            83 => format_time(self.fm_release.get()).0,
+            84 => format_curve(self.fm_curve.get()),
+            85 => format_level(self.osc_level.get()),
+            86 => format_level(self.sub_level.get()),
+            87 => format!("-{}", self.sub_octave()),
+            88 => self.sub_waveform().name().to_string(),
+            89 => format_level(self.noise_level.get()),
+            90 => self.noise_color().name().to_string(),
+            91 => format!("{:.0}", self.pulse_width.get() * 100.0),
+            92 => format!("{:.0}", self.ring_mod.get() * 100.0),
+            93 => if self.mpe() { "On" } else { "Off" }.to_string(),
+            94 => format!("±{}", self.mpe_bend_range.get()),
+            95 => format!("{:.0}", self.aftertouch_vibrato.get() * 100.0),
+            96 => format!("{:.1}", self.reference_pitch.get()),
+            97 => self.midi_channel().map_or_else(|| "Omni".to_string(), |channel| (channel + 1).to_string()),
+            98 => if self.program_change() { "On" } else { "Off" }.to_string(),
+            99 => if self.arp() { "On" } else { "Off" }.to_string(),
+            100 => self.arp_mode().name().to_string(),
+            101 => format!("{}", self.arp_octaves.get()),
+            102 => format!("{:.2}", self.arp_rate.get()),
+            103 => format_note_division(&self.arp_sync),
+            104 => format!("{:.0}", self.arp_gate.get() * 100.0),
+            105 => format!("{:.0}", self.drift.get() * 100.0),
+            106 => self.oversampling().name().to_string(),
+            107 => self.retrigger().name().to_string(),
+            108 => format_time(self.hold.get()).0,
+            110 => format_time(self.filter_hold.get()).0,
+            112 => format_time(self.mod_hold.get()).0,
+            114 => format_time(self.fm_hold.get()).0,
+            109 => self.adsr().mode.name().to_string(),
+            111 => self.filter_adsr().mode.name().to_string(),
+            113 => self.mod_adsr().mode.name().to_string(),
+            115 => self.fm_adsr().mode.name().to_string(),
+            116 => self.glide_mode().name().to_string(),
+            117 => self.glide_curve().name().to_string(),
+            118 => self.pan_mode().name().to_string(),
+            119 => format!("{:.0}", self.pan_width.get() * 100.0),
+            120 => format!("{:.2}", self.random_rate.get()),
+            121 => format_note_division(&self.random_sync),
+            122 => format!("{:.0}", self.random_smooth.get() * 100.0),
+            123 => format!("{:.0}", self.wavetable_position.get() * 100.0),
+            132 => format!("{:+.1}", self.pitch_env_amount.get()),
+            133 => format_time(self.pitch_env_decay.get()).0,
+            MORPH_PARAM => format!("{:.0}", self.morph.get() * 100.0),
+            134 => format_note(&self.key_min),
+            135 => format_note(&self.key_max),
+            136 => format!("{}", self.velocity_min.get()),
+            137 => format!("{}", self.velocity_max.get()),
+            139 => self.stereo_mode().name().to_string(),
+            140 => format!("{:.0}", self.reverb_size.get() * 100.0),
+            141 => format!("{:.0}", self.reverb_damping.get() * 100.0),
+            142 => format!("{:.0}", self.reverb_mix.get() * 100.0),
+            143 => if self.dc_blocker() { "On" } else { "Off" }.to_string(),
+            144 => self.stop_mode().name().to_string(),
+            145 => if self.multitimbral() { "On" } else { "Off" }.to_string(),
+            146 => self.output_routing().name().to_string(),
+            _ => match (lfo_param(index), mod_slot_param(index)) {
+                (Some((lfo, 0)), _) => self.lfos[lfo].shape().name().to_string(),
+                (Some((lfo, _)), _) => format!("{:.2}", self.lfos[lfo].rate.get()),
+                (_, Some((slot, 0))) => self.mod_slots[slot].source().name().to_string(),
+                (_, Some((slot, 1))) => self.mod_slots[slot].destination().name().to_string(),
+                (_, Some((slot, _))) => format!("{:+.0}", self.mod_slots[slot].depth.get() * 100.0),
+                _ => "".to_string(),
+            },
+        }
+    }
+
+    /// The unit a parameter's value is shown in.
+    pub fn param_label(&self, index: i32) -> String {
+        match index {
+            0 => "cents",
+            2 => "x",
+            3 => "%",
+            4 => "voices",
+            5 => format_time(self.attack.get()).1,
+            6 => format_time(self.decay.get()).1,
+            7 => "%",
+            8 => format_time(self.release.get()).1,
+            11 => "semitones",
+            17 => match (self.glide_mode(), format_time(self.glide_time.get()).1) {
+                (GlideMode::ConstantTime, unit) => unit,
+                (GlideMode::ConstantRate, "ms") => "ms/oct",
+                (GlideMode::ConstantRate, _) => "s/oct",
+            },
+            19 => format_freq(self.cutoff.get()).1,
+            20 => "%",
+            21 => "oct",
+            22 => format_time(self.filter_attack.get()).1,
+            23 => format_time(self.filter_decay.get()).1,
+            24 => "%",
+            25 => format_time(self.filter_release.get()).1,
+            26 => "oct",
+            12 => "Hz",
+            13 => "cents",
+            27 => format_time(self.mod_attack.get()).1,
+            28 => format_time(self.mod_decay.get()).1,
+            29 => "%",
+            30 => format_time(self.mod_release.get()).1,
+            59 => "voices",
+            60 => "%",
+            64 => "dB",
+            66 => "%",
+            68 => "dB",
+            69 => "Hz",
+            70 | 71 | 73 | 74 => "%",
+            72 => format_time(self.delay_time.get()).1,
+            78 => "x",
+            80 => format_time(self.fm_attack.get()).1,
+            81 => format_time(self.fm_decay.get()).1,
+            83 => format_time(self.fm_release.get()).1,
+            82 | 91 | 92 | 95 => "%",
+            85 | 86 | 89 => "dB",
+            87 => "oct",
+            94 => "semitones",
+            96 => "Hz",
+            101 => "oct",
+            102 => "Hz",
+            104 | 105 | 119 | 122 | 123 | MORPH_PARAM => "%",
+            140..=142 => "%",
+            132 => "semitones",
+            133 => format_time(self.pitch_env_decay.get()).1,
+            108 => format_time(self.hold.get()).1,
+            110 => format_time(self.filter_hold.get()).1,
+            112 => format_time(self.mod_hold.get()).1,
+            114 => format_time(self.fm_hold.get()).1,
+            _ if harmonic_param(index).is_some() => "%",
+            _ => match (lfo_param(index), mod_slot_param(index)) {
+                (Some((_, 1)), _) => "Hz",
+                (_, Some((_, 2))) => "%",
+                _ => "",
+            },
+        }
+        .to_string()
+    }
+
+    /// A parameter's name.
+    pub fn param_name(&self, index: i32) -> String {
+        match index {
+            0 => "Unison Detune",
+            1 => "Osc Sync",
+            2 => "Sync Ratio",
+            3 => "Vel > Attack",
+            4 => "Polyphony",
+            5 => "Attack",
+            6 => "Decay",
+            7 => "Sustain",
+            8 => "Release",
+            9 => "Waveform",
+            10 => "Vel Curve",
+            11 => "Bend Range",
+            12 => "Vibrato Rate",
+            13 => "Vibrato Depth",
+            14 => "Voice Steal",
+            15 => "Note Priority",
+            16 => "Legato",
+            17 => "Glide",
+            18 => "Filter Type",
+            19 => "Cutoff",
+            20 => "Resonance",
+            21 => "Filter Env Amount",
+            22 => "Filter Attack",
+            23 => "Filter Decay",
+            24 => "Filter Sustain",
+            25 => "Filter Release",
+            26 => "Vel > Cutoff",
+            27 => "Mod Attack",
+            28 => "Mod Decay",
+            29 => "Mod Sustain",
+            30 => "Mod Release",
+            59 => "Unison",
+            60 => "Unison Spread",
+            61 => "Curve",
+            62 => "Filter Curve",
+            63 => "Mod Curve",
+            64 => "Master Gain",
+            65 => "Master Pan",
+            66 => "Stereo Width",
+            67 => "Saturation",
+            68 => "Drive",
+            69 => "Chorus Rate",
+            70 => "Chorus Depth",
+            71 => "Chorus Mix",
+            72 => "Delay Time",
+            73 => "Delay Feedback",
+            74 => "Delay Mix",
+            77 => "Delay Sync",
+            78 => "FM Ratio",
+            79 => "FM Index",
+            80 => "FM Attack",
+            81 => "FM Decay",
+            82 => "FM Sustain",
+            83 => "FM Release",
+            84 => "FM Curve",
+            85 => "Osc Level",
+            86 => "Sub Level",
+            87 => "Sub Octave",
+            88 => "Sub Wave",
+            89 => "Noise Level",
+            90 => "Noise Color",
+            91 => "Pulse Width",
+            92 => "Ring Mod",
+            93 => "MPE",
+            94 => "MPE Bend Range",
+            95 => "AT > Vibrato",
+            96 => "Reference Pitch",
+            97 => "MIDI Channel",
+            98 => "Program Change",
+            99 => "Arpeggiator",
+            100 => "Arp Mode",
+            101 => "Arp Octaves",
+            102 => "Arp Rate",
+            103 => "Arp Sync",
+            104 => "Arp Gate",
+            105 => "Drift",
+            106 => "Oversampling",
+            107 => "Retrigger",
+            108 => "Hold",
+            109 => "Env Mode",
+            110 => "Filter Hold",
+            111 => "Filter Env Mode",
+            112 => "Mod Hold",
+            113 => "Mod Env Mode",
+            114 => "FM Hold",
+            115 => "FM Env Mode",
+            116 => "Glide Mode",
+            117 => "Glide Curve",
+            118 => "Pan Mode",
+            119 => "Pan Width",
+            120 => "Random Rate",
+            121 => "Random Sync",
+            122 => "Random Smooth",
+            123 => "WT Position",
+            132 => "Pitch Env Amount",
+            133 => "Pitch Env Decay",
+            134 => "Key Min",
+            135 => "Key Max",
+            136 => "Velocity Min",
+            137 => "Velocity Max",
+            MORPH_PARAM => "A/B Morph",
+            139 => "Stereo Mode",
+            140 => "Reverb Size",
+            141 => "Reverb Damping",
+            142 => "Reverb Mix",
+            143 => "DC Blocker",
+            144 => "On Stop/Loop",
+            145 => "Multi-Timbral",
+            146 => "Voice Outputs",
+            _ => {
+                if let Some(lfo) = lfo_sync_param(index) {
+                    return format!("LFO {} Sync", lfo + 1);
+                }
+                if let Some(harmonic) = harmonic_param(index) {
+                    return format!("Harmonic {}", harmonic + 1);
+                }
+                return match (lfo_param(index), mod_slot_param(index)) {
+                    (Some((lfo, setting)), _) => format!("LFO {} {}", lfo + 1, ["Shape", "Rate"][setting]),
+                    (_, Some((slot, setting))) => {
+                        format!("Mod {} {}", slot + 1, ["Source", "Dest", "Depth"][setting])
+                    }
+                    _ => String::new(),
+                };
+            }
+        }
+        .to_string()
+    }
+}
+
+/// The host's view of the parameters and presets, which each plugin format passes its own calls on
+/// to. VST2 uses all of it, and the other formats only some.
+#[cfg_attr(not(feature = "vst2"), allow(dead_code))]
+impl ReplicantParameters {
+    // the `get_parameter` function reads the value of a parameter.
+    pub fn get_parameter(&self, index: i32) -> f32 {
+        self.normalized(index)
+    }
+
+    // the `set_parameter` function sets the value of a parameter.
+    pub fn set_parameter(&self, index: i32, val: f32) {
+        self.set_normalized(index, val);
+    }
+
+    // This is what will display underneath our control.  We can
+    // format it into a string that makes the most since.
+    pub fn get_parameter_text(&self, index: i32) -> String {
+        self.param_text(index)
+    }
+
+    pub fn get_parameter_label(&self, index: i32) -> String {
+        self.param_label(index)
+    }
+
+    pub fn string_to_parameter(&self, index: i32, text: String) -> bool {
+        self.set_param_text(index, &text)
+    }
+
+    // This shows the control's name.
+    pub fn get_parameter_name(&self, index: i32) -> String {
+        self.param_name(index)
+    }
+
+    pub fn change_preset(&self, preset: i32) {
+        let mut bank = self.bank();
+        let preset = preset as usize;
+        if preset == bank.current || preset >= bank.presets.len() {
+            return;
+        }
+        self.save_current_preset(&mut bank);
+        bank.current = preset;
+        self.history().clear();
+        if preset == RANDOM_PRESET {
+            // a new one every time it's picked, which stays until the next
+            bank.presets[preset] = Preset::random(self, random_seed());
+        }
+        bank.presets[preset].apply(self);
+    }
+
+    pub fn get_preset_num(&self) -> i32 {
+        self.bank().current as i32
+    }
+
+    pub fn set_preset_name(&self, name: String) {
+        let mut bank = self.bank();
+        let current = bank.current;
+        if let Some(preset) = bank.presets.get_mut(current) {
+            preset.name = name;
+        }
+    }
+
+    pub fn get_preset_name(&self, preset: i32) -> String {
+        self.bank().presets.get(preset as usize).map_or_else(String::new, |preset| preset.name.clone())
+    }
+
+    pub fn get_preset_data(&self) -> Vec<u8> {
+        self.current_preset().encode()
+    }
+
+    pub fn get_bank_data(&self) -> Vec<u8> {
+        let mut bank = self.bank();
+        self.save_current_preset(&mut bank);
+        bank.encode()
+    }
+
+    pub fn load_preset_data(&self, data: &[u8]) {
+        match Preset::decode(data) {
+            Some(preset) => self.load_preset(preset),
+            None => warn!("ignoring invalid preset data"),
+        }
+    }
+
+    pub fn load_bank_data(&self, data: &[u8]) {
+        match Bank::decode(data) {
+            Some(loaded) => {
+                let mut bank = self.bank();
+                *bank = loaded;
+                self.bank_loaded.store(true, Ordering::Release);
+                bank.presets[bank.current].apply(self);
+            }
+            None => warn!("ignoring invalid bank data"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lerp;
+    use crate::loader::wait_for;
+    use crate::presets::NUM_PRESETS;
+
+    #[test]
+    fn test_adsr_clamps_invalid_values() {
+        let params = ReplicantParameters::default();
+        params.attack.set(-1.0);
+        params.decay.set(f32::NAN);
+        params.sustain.set(2.0);
+
+        let adsr = params.adsr();
+        assert_eq!(adsr.attack, 0.0);
+        assert_eq!(adsr.decay, 0.0);
+        assert_eq!(adsr.sustain, 1.0);
+    }
+
+    #[test]
+    fn test_set_parameter_clamps_to_range() {
+        let params = ReplicantParameters::default();
+        params.set_parameter(0, 5.0);
+        assert_eq!(params.get_parameter(0), 1.0);
+        params.set_parameter(0, f32::NAN);
+        assert_eq!(params.get_parameter(0), 0.0);
+    }
+
+    #[test]
+    fn test_parameters_round_trip() {
+        let params = ReplicantParameters::default();
+        for index in 0..NUM_PARAMS as i32 {
+            params.set_parameter(index, 0.25);
+            let expected = params.get_parameter(index);
+            params.set_parameter(index, expected);
+            assert!((params.get_parameter(index) - expected).abs() < 1e-6, "param {}", index);
+            assert_ne!(params.get_parameter_name(index), "");
+        }
+    }
+
+    #[test]
+    fn test_parameter_blocks_end_before_later_parameters() {
+        let end = FIRST_MOD_SLOT_PARAM + (NUM_MOD_SLOTS * PARAMS_PER_MOD_SLOT) as i32;
+        assert_eq!(end, 59);
+        assert_eq!(mod_slot_param(end - 1), Some((NUM_MOD_SLOTS - 1, PARAMS_PER_MOD_SLOT - 1)));
+        assert_eq!(mod_slot_param(end), None);
+    }
+
+    #[test]
+    fn test_envelope_time_display() {
+        let params = ReplicantParameters::default();
+        params.set_parameter(5, 0.5);
+        assert_eq!(params.attack.get(), 1.25);
+        assert_eq!(params.get_parameter_text(5), "1.25");
+        assert_eq!(params.get_parameter_label(5), "s");
+
+        params.set_parameter(5, 0.1);
+        assert_eq!(params.get_parameter_text(5), "50.0");
+        assert_eq!(params.get_parameter_label(5), "ms");
+    }
+
+    #[test]
+    fn test_undo_editor_changes() {
+        let params = ReplicantParameters::default();
+        let cutoff = params.get_parameter(19);
+        params.edit_parameter(19, 0.2, false);
+        params.edit_parameter(19, 0.3, true);
+        params.edit_parameter(20, 0.9, false);
+        params.init_patch();
+        assert_eq!(params.get_parameter(20), ReplicantParameters::init().get_parameter(20));
+
+        params.undo();
+        assert_eq!(params.get_parameter(20), 0.9);
+        assert_eq!(params.undo(), vec![20]);
+        // the drag undoes in one go
+        assert_eq!(params.undo(), vec![19]);
+        assert_eq!(params.get_parameter(19), cutoff);
+        assert!(!params.can_undo());
+
+        assert_eq!(params.redo(), vec![19]);
+        assert!((params.get_parameter(19) - 0.3).abs() < 1e-6);
+
+        // another preset's parameters aren't restored
+        params.change_preset(1);
+        assert!(!params.can_undo() && !params.can_redo());
+    }
+
+    #[test]
+    fn test_ab_morph() {
+        let params = ReplicantParameters::default();
+        params.cutoff.set(100.0);
+        params.filter_type.set(0.0);
+        params.polyphony.set(4.0);
+        params.store_ab(Slot::A);
+        params.cutoff.set(10000.0);
+        params.filter_type.set(2.0);
+        params.polyphony.set(8.0);
+        params.store_ab(Slot::B);
+        let (a, b) = (params.ab.value(Slot::A, 19).unwrap(), params.ab.value(Slot::B, 19).unwrap());
+
+        params.set_parameter(MORPH_PARAM, 0.25);
+        assert!((params.get_parameter(19) - lerp(a.into(), b.into(), 0.25) as f32).abs() < 1e-6);
+        assert_eq!(params.filter_type.get(), 0.0);
+        // the setup isn't part of the patch
+        assert_eq!(params.polyphony.get(), 8.0);
+        params.set_parameter(MORPH_PARAM, 1.0);
+        assert_eq!(params.filter_type.get(), 2.0);
+
+        params.recall_ab(Slot::A);
+        assert!((params.cutoff.get() - 100.0).abs() < 0.01);
+        assert_eq!(params.get_parameter(MORPH_PARAM), 1.0);
+        params.undo();
+        assert!((params.cutoff.get() - 10000.0).abs() < 0.1);
+
+        // presets leave the morph be, rather than it moving them off their values
+        let preset = Preset::capture("Morphed", &ReplicantParameters::init());
+        preset.apply(&params);
+        assert_eq!(params.get_parameter(MORPH_PARAM), 1.0);
+        assert_eq!(params.cutoff.get(), ReplicantParameters::init().cutoff.get());
+    }
+
+    #[test]
+    fn test_typed_in_text() {
+        let params = ReplicantParameters::default();
+        assert!(params.string_to_parameter(5, "250 ms".to_string()));
+        assert!((params.attack.get() - 0.25).abs() < 1e-6);
+        assert!(params.set_param_text(5, "1.5s"));
+        assert_eq!(params.attack.get(), 1.5);
+        // without a unit, it's read in the one shown
+        assert!(params.set_param_text(5, "2"));
+        assert_eq!(params.attack.get(), 2.0);
+
+        assert!(params.set_param_text(19, "2.5 kHz"));
+        assert!((params.cutoff.get() - 2500.0).abs() < 0.1);
+        assert!(params.set_param_text(20, "50 %"));
+        assert_eq!(params.resonance.get(), 0.5);
+        assert!(params.set_param_text(85, "-6 dB"));
+        assert!((params.osc_level.get() - 0.501).abs() < 0.001);
+        assert!(params.set_param_text(86, "-inf"));
+        assert_eq!(params.sub_level.get(), 0.0);
+        assert!(params.set_param_text(65, "L 50"));
+        assert_eq!(params.master_pan.get(), -0.5);
+        assert!(params.set_param_text(61, "Log 25%"));
+        assert_eq!(params.curve.get(), -0.25);
+        assert!(params.set_param_text(9, "saw"));
+        assert_eq!(params.waveform(), Waveform::Saw);
+        assert!(params.set_param_text(4, "Mono"));
+        assert_eq!(params.polyphony(), 1);
+        assert!(params.set_param_text(134, "c#4"));
+        assert_eq!(params.key_min.get(), 61.0);
+        assert!(params.set_param_text(135, "72"));
+        assert_eq!(params.param_text(135), "C5");
+
+        // nonsense leaves the parameter alone
+        assert!(!params.set_param_text(19, "loud"));
+        assert!(!params.set_param_text(19, "3 dB"));
+        assert!(!params.set_param_text(9, "kazoo"));
+        assert!((params.cutoff.get() - 2500.0).abs() < 0.1);
+        assert!(!params.set_param_text(NUM_PARAMS as i32, "1"));
+    }
+
+    #[test]
+    fn test_every_parameter_reads_back_its_own_text() {
+        let shown = ReplicantParameters::init();
+        let typed = ReplicantParameters::init();
+        for index in 0..NUM_PARAMS as i32 {
+            for &normalized in &[0.0, 0.3, 0.7, 1.0] {
+                shown.set_normalized(index, normalized);
+                let text = format!("{} {}", shown.param_text(index), shown.param_label(index));
+                assert!(typed.set_param_text(index, &text), "param {} didn't read {:?}", index, text);
+                assert_eq!(typed.param_text(index), shown.param_text(index), "param {} from {:?}", index, text);
+            }
+        }
+    }
+
+    #[test]
+    fn test_bulk_values() {
+        let params = ReplicantParameters::init();
+        assert_eq!(params.normalized_values(), ReplicantParameters::default_values());
+        params.cutoff.set(500.0);
+        let values = params.normalized_values();
+        assert_eq!(values.len(), NUM_PARAMS);
+
+        let loaded = ReplicantParameters::init();
+        loaded.resonance.set(0.9);
+        // as if saved before the resonance was added
+        loaded.set_normalized_values(&values[..20]);
+        assert!((loaded.cutoff.get() - 500.0).abs() < 0.1);
+        assert!((loaded.resonance.get() - 0.9).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_switching_presets_keeps_edits() {
+        let params = ReplicantParameters::default();
+        params.cutoff.set(500.0);
+
+        params.change_preset(2);
+        assert_eq!(params.get_preset_num(), 2);
+        assert_eq!(params.get_preset_name(2), "Pluck");
+        assert!((params.cutoff.get() - 300.0).abs() < 0.1);
+
+        params.change_preset(0);
+        assert!((params.cutoff.get() - 500.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn test_bank_data_restores_state() {
+        let params = ReplicantParameters::default();
+        params.change_preset(4);
+        params.set_preset_name("My Bass".to_string());
+        params.resonance.set(0.9);
+        let data = params.get_bank_data();
+
+        let loaded = ReplicantParameters::default();
+        loaded.load_bank_data(&data);
+        assert_eq!(loaded.get_preset_num(), 4);
+        assert_eq!(loaded.get_preset_name(4), "My Bass");
+        assert!((loaded.resonance.get() - 0.9).abs() < 1e-6);
+
+        // garbage is ignored rather than clobbering the current patch
+        loaded.load_bank_data(b"nonsense");
+        assert!((loaded.resonance.get() - 0.9).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_background_loading() {
+        let params = Arc::new(ReplicantParameters::default());
+        ReplicantParameters::spawn_loader_thread(&params, Weak::new());
+
+        // the file's remembered straight away, before it's even been tried
+        let missing = std::env::temp_dir().join("replicant-no-such-table.wav");
+        params.request_wavetable_file(Some(missing.clone()));
+        assert_eq!(params.wavetable_file(), Some(missing));
+        wait_for(|| params.wavetable_status() != LoadStatus::Loading);
+        assert!(matches!(params.wavetable_status(), LoadStatus::Failed(_)));
+        assert!(Arc::ptr_eq(&params.try_wavetable().unwrap(), &Wavetable::basic()));
+
+        let dir = std::env::temp_dir().join(format!("replicant-scan-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let preset = Preset::capture("Scanned", &ReplicantParameters::init());
+        std::fs::write(dir.join("scanned.json"), preset.to_bytes()).unwrap();
+        let has_scanned = |params: &ReplicantParameters| {
+            (0..NUM_PRESETS as i32).any(|index| params.get_preset_name(index) == "Scanned")
+        };
+        params.request_preset_dir(dir.clone());
+        wait_for(|| params.take_preset_switched());
+        assert!(has_scanned(&params));
+
+        // a bank the host loaded first isn't overwritten
+        let loaded = ReplicantParameters::default();
+        loaded.load_bank_data(&ReplicantParameters::default().get_bank_data());
+        loaded.request_preset_dir(dir.clone());
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(!has_scanned(&loaded));
+    }
+
+}
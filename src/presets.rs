@@ -0,0 +1,803 @@
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::path::{Path, PathBuf};
+use std::{env, fs, io};
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::envelope::EnvelopeMode;
+use crate::lfo::{LfoShape, XorShift};
+use crate::modulation::{ModDestination, ModSource};
+use crate::oscillator::Waveform;
+use crate::params::{ReplicantParameters, NUM_PARAMS};
+use crate::tuning::TuningFiles;
+
+/// The number of preset slots the host sees. The factory presets come first, and the rest are
+/// left for the user.
+pub const NUM_PRESETS: usize = 32;
+
+/// How many of the preset slots the factory presets take up.
+const NUM_FACTORY_PRESETS: usize = 12;
+
+/// The factory preset slot that makes a new random patch each time it's picked.
+pub const RANDOM_PRESET: usize = NUM_FACTORY_PRESETS - 1;
+
+/// Parameters about how the synth is played and set up rather than how it sounds, which the init
+/// and random patches leave as they were: polyphony, velocity curve, bend range, voice stealing,
+/// note priority, legato, master gain, MPE, the reference pitch, the midi channel, program
+/// changes, oversampling, the key and velocity zone, the DC blocker, what the host's transport
+/// stopping does, multi-timbral mode and which outputs the voices play through.
+pub const SETUP_PARAMS: [i32; 21] =
+    [4, 10, 11, 14, 15, 16, 64, 93, 94, 96, 97, 98, 106, 134, 135, 136, 137, 143, 144, 145, 146];
+
+/// The parameters a random patch picks, each with the normalized range it's picked from. The
+/// ranges keep away from the extremes that make a patch silent, painfully loud or runaway, like
+/// very long envelopes, a closed filter, lots of drive or delay feedback. Everything else about the
+/// sound stays at the init patch, including the levels and the modulation matrix.
+const RANDOM_RANGES: [(i32, f32, f32); 56] = [
+    // oscillators
+    (9, 0.0, 1.0),
+    (0, 0.0, 0.4),
+    (1, 0.0, 1.0),
+    (2, 0.0, 0.5),
+    (59, 0.0, 0.5),
+    (60, 0.0, 1.0),
+    (91, 0.2, 0.8),
+    (92, 0.0, 0.5),
+    (105, 0.0, 0.5),
+    (123, 0.0, 1.0),
+    (124, 0.0, 1.0),
+    (125, 0.0, 1.0),
+    (126, 0.0, 1.0),
+    (127, 0.0, 1.0),
+    (128, 0.0, 1.0),
+    (129, 0.0, 1.0),
+    (130, 0.0, 1.0),
+    (131, 0.0, 1.0),
+    (86, 0.0, 0.5),
+    (87, 0.0, 1.0),
+    (88, 0.0, 1.0),
+    (89, 0.0, 0.2),
+    (90, 0.0, 1.0),
+    // FM
+    (78, 0.0, 1.0),
+    (79, 0.0, 0.4),
+    (80, 0.0, 0.3),
+    (81, 0.1, 0.6),
+    (82, 0.0, 1.0),
+    (83, 0.1, 0.5),
+    // amp envelope
+    (5, 0.0, 0.3),
+    (6, 0.1, 0.6),
+    (7, 0.0, 1.0),
+    (8, 0.1, 0.5),
+    (61, 0.2, 0.8),
+    // filter
+    (18, 0.0, 1.0),
+    (19, 0.5, 1.0),
+    (20, 0.0, 0.7),
+    (21, 0.4, 0.9),
+    (22, 0.0, 0.3),
+    (23, 0.1, 0.6),
+    (24, 0.0, 1.0),
+    (25, 0.1, 0.5),
+    (62, 0.2, 0.8),
+    // output and effects
+    (66, 0.3, 0.6),
+    (67, 0.0, 1.0),
+    (68, 0.0, 0.3),
+    (69, 0.0, 1.0),
+    (70, 0.0, 1.0),
+    (71, 0.0, 0.5),
+    (72, 0.2, 0.7),
+    (73, 0.0, 0.6),
+    (74, 0.0, 0.3),
+    (140, 0.0, 0.8),
+    (141, 0.0, 1.0),
+    (142, 0.0, 0.3),
+    (119, 0.0, 1.0),
+];
+
+/// Identifies our chunks, and the version of their layout. Only VST2 hosts store chunks; the
+/// other formats store the preset file layout.
+#[cfg_attr(not(feature = "vst2"), allow(dead_code))]
+const PRESET_MAGIC: &[u8; 4] = b"RPLP";
+#[cfg_attr(not(feature = "vst2"), allow(dead_code))]
+const BANK_MAGIC: &[u8; 4] = b"RPLB";
+///
+/// Version 2 added the tuning files, version 3 the midi CC mappings, and version 4 the wavetable
+/// file. Older chunks still load, in 12-TET, without any mappings and with the built in wavetable.
+#[cfg_attr(not(feature = "vst2"), allow(dead_code))]
+const FORMAT_VERSION: u32 = 4;
+
+/// The version of the preset file layout, written into every file.
+const FILE_VERSION: u32 = 1;
+
+/// The human-readable preset file format.
+///
+/// Every field has a default, so files from newer versions with fields we don't know about, or
+/// older ones missing parameters, still load.
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+struct PresetFile {
+    version: u32,
+    name: String,
+    /// Parameter values in their real units, by parameter name.
+    params: BTreeMap<String, f32>,
+    /// The Scala tuning, or `None` for 12-TET.
+    tuning: Option<TuningFiles>,
+    /// The parameter names the midi CCs are mapped to, by CC number.
+    midi_map: BTreeMap<u8, String>,
+    /// The wavetable file, or `None` for the built in table.
+    wavetable: Option<PathBuf>,
+}
+
+impl Default for PresetFile {
+    fn default() -> PresetFile {
+        PresetFile {
+            version: FILE_VERSION,
+            name: "Init".to_string(),
+            params: BTreeMap::new(),
+            tuning: None,
+            midi_map: BTreeMap::new(),
+            wavetable: None,
+        }
+    }
+}
+
+/// A named patch: every parameter's normalized value, by host index, the tuning, the midi CC
+/// mappings and the wavetable file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Preset {
+    pub name: String,
+    pub values: Vec<f32>,
+    pub tuning: Option<TuningFiles>,
+    /// Each mapped CC number, with the index of the parameter it controls.
+    pub midi_map: Vec<(u8, i32)>,
+    pub wavetable: Option<PathBuf>,
+}
+
+impl Preset {
+    /// Capture the current value of every parameter.
+    pub fn capture(name: &str, params: &ReplicantParameters) -> Preset {
+        Preset {
+            name: name.to_string(),
+            values: params.normalized_values(),
+            tuning: params.tuning_files(),
+            midi_map: params.midi_map.mappings(),
+            wavetable: params.wavetable_file(),
+        }
+    }
+
+    /// The init patch, keeping `current`'s setup: the parameters in `SETUP_PARAMS`, the tuning
+    /// and the midi mappings. The wavetable goes back to the built in one.
+    pub fn init(current: &ReplicantParameters) -> Preset {
+        let mut preset = Preset::capture("Init", &ReplicantParameters::init());
+        for &index in &SETUP_PARAMS {
+            preset.values[index as usize] = current.get_parameter(index);
+        }
+        preset.tuning = current.tuning_files();
+        preset.midi_map = current.midi_map.mappings();
+        preset
+    }
+
+    /// A random patch from `seed`, which is the init patch with the parameters in `RANDOM_RANGES`
+    /// picked at random, keeping `current`'s setup as `init()` does, and its wavetable.
+    pub fn random(current: &ReplicantParameters, seed: u32) -> Preset {
+        let mut preset = Preset { name: "Random".to_string(), ..Preset::init(current) };
+        preset.wavetable = current.wavetable_file();
+        // the generator gets stuck on 0
+        let mut rng = XorShift(seed.max(1));
+        for &(index, low, high) in &RANDOM_RANGES {
+            let amount = (rng.next_bipolar() as f32 + 1.0) / 2.0;
+            preset.values[index as usize] = low + (high - low) * amount;
+        }
+        preset
+    }
+
+    /// Set every parameter the preset has a value for, and load its tuning, midi mappings and
+    /// wavetable.
+    ///
+    /// Presets saved before a parameter was added leave it as it was, and so does the A/B morph.
+    /// A tuning that can't be loaded falls back to 12-TET, and a wavetable to the built in one.
+    /// The wavetable loads on the loader thread, if there is one. Presets without any midi
+    /// mappings keep the current ones, so a controller setup carries on working through the
+    /// factory presets.
+    pub fn apply(&self, params: &ReplicantParameters) {
+        params.set_normalized_values(&self.values);
+        if !self.midi_map.is_empty() {
+            params.midi_map.set_mappings(&self.midi_map);
+        }
+        if let Err(err) = params.set_tuning_files(self.tuning.clone()) {
+            warn!("couldn't load the tuning for preset {}: {}", self.name, err);
+        }
+        params.request_wavetable_file(self.wavetable.clone());
+    }
+
+    /// Serialize as a chunk for the host to store.
+    #[cfg_attr(not(feature = "vst2"), allow(dead_code))]
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(16 + self.name.len() + self.values.len() * 4);
+        data.extend_from_slice(PRESET_MAGIC);
+        data.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        write_u32(&mut data, self.name.len());
+        data.extend_from_slice(self.name.as_bytes());
+        write_u32(&mut data, self.values.len());
+        for value in &self.values {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        let (scale, mapping) = match &self.tuning {
+            Some(files) => (Some(&files.scale), files.mapping.as_ref()),
+            None => (None, None),
+        };
+        for path in [scale, mapping] {
+            // an empty path for none
+            let path = path.map_or_else(String::new, |path| path.to_string_lossy().into_owned());
+            write_u32(&mut data, path.len());
+            data.extend_from_slice(path.as_bytes());
+        }
+        write_u32(&mut data, self.midi_map.len());
+        for &(cc, param) in &self.midi_map {
+            write_u32(&mut data, usize::from(cc));
+            write_u32(&mut data, param as usize);
+        }
+        let wavetable = self.wavetable.as_ref().map_or_else(String::new, |path| path.to_string_lossy().into_owned());
+        write_u32(&mut data, wavetable.len());
+        data.extend_from_slice(wavetable.as_bytes());
+        data
+    }
+
+    /// Read a chunk written by `encode`, or `None` if it's not a valid preset.
+    #[cfg_attr(not(feature = "vst2"), allow(dead_code))]
+    pub fn decode(data: &[u8]) -> Option<Preset> {
+        let mut reader = Reader(data);
+        let preset = reader.preset()?;
+        reader.0.is_empty().then_some(preset)
+    }
+
+    /// Serialize as a preset file, which is JSON for sharing outside of the host.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let params = ReplicantParameters::init();
+        params.set_normalized_values(&self.values);
+        let file = PresetFile {
+            version: FILE_VERSION,
+            name: self.name.clone(),
+            params: (0..self.values.len().min(NUM_PARAMS) as i32)
+                .filter_map(|index| Some((params.get_parameter_name(index), params.value(index)?)))
+                .collect(),
+            tuning: self.tuning.clone(),
+            midi_map: self.midi_map.iter()
+                .map(|&(cc, index)| (cc, params.get_parameter_name(index)))
+                .collect(),
+            wavetable: self.wavetable.clone(),
+        };
+        // a map of strings to numbers always serializes
+        serde_json::to_vec_pretty(&file).unwrap_or_default()
+    }
+
+    /// Read a preset file written by `to_bytes`.
+    ///
+    /// Parameters missing from the file are left at their init patch values, and ones we don't
+    /// know about are ignored.
+    pub fn from_bytes(data: &[u8]) -> Result<Preset, serde_json::Error> {
+        let file: PresetFile = serde_json::from_slice(data)?;
+        let params = ReplicantParameters::init();
+        for index in 0..NUM_PARAMS as i32 {
+            if let Some(&value) = file.params.get(&params.get_parameter_name(index)) {
+                params.set_value(index, value);
+            }
+        }
+        let midi_map = file.midi_map.iter()
+            .filter_map(|(&cc, name)| {
+                let index = (0..NUM_PARAMS as i32).find(|&index| params.get_parameter_name(index) == *name);
+                Some((cc, index?))
+            })
+            .collect();
+        Ok(Preset {
+            tuning: file.tuning,
+            midi_map,
+            wavetable: file.wavetable,
+            ..Preset::capture(&file.name, &params)
+        })
+    }
+}
+
+/// Where the user's preset files are loaded from: `$REPLICANT_PRESET_DIR` if it's set, or else
+/// `.replicant/presets` in their home directory.
+pub fn user_preset_dir() -> Option<PathBuf> {
+    if let Some(dir) = env::var_os("REPLICANT_PRESET_DIR") {
+        return Some(PathBuf::from(dir));
+    }
+    let home = env::var_os("HOME").or_else(|| env::var_os("USERPROFILE"))?;
+    Some(Path::new(&home).join(".replicant").join("presets"))
+}
+
+/// The host's preset slots, and which one is selected.
+///
+/// The selected preset's values live in the parameters themselves while it's being edited; its
+/// entry here is only brought up to date when switching away or saving the bank.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Bank {
+    pub current: usize,
+    pub presets: Vec<Preset>,
+}
+
+impl Bank {
+    /// Serialize as a chunk for the host to store.
+    #[cfg_attr(not(feature = "vst2"), allow(dead_code))]
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(BANK_MAGIC);
+        data.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        write_u32(&mut data, self.current);
+        write_u32(&mut data, self.presets.len());
+        for preset in &self.presets {
+            let chunk = preset.encode();
+            write_u32(&mut data, chunk.len());
+            data.extend_from_slice(&chunk);
+        }
+        data
+    }
+
+    /// Read a chunk written by `encode`, or `None` if it's not a valid bank.
+    ///
+    /// The bank is padded or truncated to `NUM_PRESETS`, as the host can't change the number of
+    /// slots.
+    #[cfg_attr(not(feature = "vst2"), allow(dead_code))]
+    pub fn decode(data: &[u8]) -> Option<Bank> {
+        let mut reader = Reader(data);
+        reader.header(BANK_MAGIC)?;
+        let current = reader.u32()?;
+        let count = reader.u32()?;
+        let mut presets = Vec::with_capacity(NUM_PRESETS);
+        for _ in 0..count {
+            let len = reader.u32()?;
+            let preset = Preset::decode(reader.bytes(len)?)?;
+            if presets.len() < NUM_PRESETS {
+                presets.push(preset);
+            }
+        }
+        if !reader.0.is_empty() {
+            return None;
+        }
+
+        presets.resize_with(NUM_PRESETS, || Preset::capture("Init", &ReplicantParameters::init()));
+        Some(Bank { current: current.min(NUM_PRESETS - 1), presets })
+    }
+
+    /// Read the `.json` preset files in `dir`, in file name order, for `set_user_presets`. Files
+    /// that fail to load are skipped, and so are any past the slots there's room for.
+    pub fn read_dir(dir: &Path) -> io::Result<Vec<Preset>> {
+        let mut paths = fs::read_dir(dir)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+            .collect::<Vec<_>>();
+        paths.sort();
+
+        let mut presets = Vec::new();
+        for path in paths {
+            if presets.len() >= NUM_PRESETS - NUM_FACTORY_PRESETS {
+                warn!("no room for any more presets, skipping {}", path.display());
+                break;
+            }
+            match fs::read(&path).map_err(|err| err.to_string())
+                .and_then(|data| Preset::from_bytes(&data).map_err(|err| err.to_string()))
+            {
+                Ok(preset) => presets.push(preset),
+                Err(err) => warn!("skipping preset {}: {}", path.display(), err),
+            }
+        }
+        Ok(presets)
+    }
+
+    /// Fill the slots after the factory presets with `presets`, returning how many fit.
+    pub fn set_user_presets(&mut self, presets: Vec<Preset>) -> usize {
+        let slots = self.presets.iter_mut().skip(NUM_FACTORY_PRESETS);
+        slots.zip(presets).map(|(slot, preset)| *slot = preset).count()
+    }
+
+    /// The presets the plugin ships with.
+    pub fn factory() -> Bank {
+        let preset = |name: &str, setup: &dyn Fn(&ReplicantParameters)| {
+            let params = ReplicantParameters::init();
+            setup(&params);
+            Preset::capture(name, &params)
+        };
+        let route = |params: &ReplicantParameters, slot: usize, source: ModSource, destination: ModDestination,
+                     depth: f32| {
+            let slot = &params.mod_slots[slot];
+            slot.source.set(source as usize as f32);
+            slot.destination.set(destination as usize as f32);
+            slot.depth.set(depth);
+        };
+
+        let mut presets = vec![
+            preset("Init", &|_| {}),
+            preset("Soft Pad", &|params| {
+                params.waveform.set(Waveform::Saw as usize as f32);
+                params.attack.set(0.8);
+                params.decay.set(0.5);
+                params.sustain.set(0.8);
+                params.release.set(1.5);
+                params.cutoff.set(2000.0);
+                params.unison.set(3.0);
+                params.unison_detune.set(20.0);
+                params.lfos[1].rate.set(0.3);
+                route(params, 1, ModSource::Lfo2, ModDestination::Cutoff, 0.1);
+                params.reverb_size.set(0.8);
+                params.reverb_mix.set(0.35);
+            }),
+            preset("Pluck", &|params| {
+                params.waveform.set(Waveform::Saw as usize as f32);
+                params.decay.set(0.3);
+                params.sustain.set(0.0);
+                params.release.set(0.3);
+                params.curve.set(0.6);
+                params.cutoff.set(300.0);
+                params.filter_env_amount.set(5.0);
+                params.filter_decay.set(0.25);
+                params.filter_curve.set(0.6);
+                params.vel_to_cutoff.set(2.0);
+            }),
+            preset("Sync Lead", &|params| {
+                params.waveform.set(Waveform::Saw as usize as f32);
+                params.osc_sync.set(1.0);
+                params.sync_ratio.set(3.0);
+                params.polyphony.set(1.0);
+                params.legato.set(1.0);
+                params.glide_time.set(0.08);
+                params.release.set(0.1);
+                route(params, 2, ModSource::ModEnv, ModDestination::OscMix, -0.5);
+                route(params, 3, ModSource::ModEnv, ModDestination::SyncRatio, 0.3);
+            }),
+            preset("Bass", &|params| {
+                params.waveform.set(Waveform::Square as usize as f32);
+                params.polyphony.set(1.0);
+                params.unison.set(1.0);
+                params.sub_level.set(0.5);
+                params.cutoff.set(400.0);
+                params.resonance.set(0.3);
+                params.filter_env_amount.set(3.0);
+                params.filter_decay.set(0.15);
+                params.release.set(0.05);
+            }),
+            preset("Wobble", &|params| {
+                params.waveform.set(Waveform::Saw as usize as f32);
+                params.cutoff.set(500.0);
+                params.resonance.set(0.6);
+                params.lfos[1].shape.set(LfoShape::Sine as usize as f32);
+                params.lfos[1].rate.set(3.0);
+                route(params, 1, ModSource::Lfo2, ModDestination::Cutoff, 0.4);
+            }),
+            preset("Tremolo Keys", &|params| {
+                params.waveform.set(Waveform::Triangle as usize as f32);
+                params.decay.set(1.0);
+                params.sustain.set(0.5);
+                params.release.set(0.4);
+                params.reverb_mix.set(0.2);
+                params.lfos[0].rate.set(6.0);
+                route(params, 0, ModSource::Lfo1, ModDestination::Amplitude, 0.5);
+            }),
+            preset("Auto Pan", &|params| {
+                params.waveform.set(Waveform::Square as usize as f32);
+                params.cutoff.set(3000.0);
+                params.release.set(0.5);
+                params.lfos[0].rate.set(0.5);
+                route(params, 7, ModSource::Lfo1, ModDestination::Pan, 0.8);
+            }),
+            preset("FM Bell", &|params| {
+                params.waveform.set(Waveform::Sine as usize as f32);
+                params.decay.set(2.0);
+                params.sustain.set(0.0);
+                params.release.set(1.5);
+                params.curve.set(0.7);
+                params.fm_ratio.set(3.5);
+                params.fm_index.set(4.0);
+                params.fm_decay.set(1.2);
+                params.fm_sustain.set(0.1);
+                params.fm_curve.set(0.5);
+                params.reverb_size.set(0.6);
+                params.reverb_damping.set(0.3);
+                params.reverb_mix.set(0.25);
+            }),
+            preset("MPE Pad", &|params| {
+                params.waveform.set(Waveform::Saw as usize as f32);
+                params.mpe.set(1.0);
+                params.attack.set(0.05);
+                params.release.set(0.8);
+                params.cutoff.set(600.0);
+                params.resonance.set(0.3);
+                route(params, 0, ModSource::Aftertouch, ModDestination::Amplitude, 0.7);
+                route(params, 1, ModSource::Timbre, ModDestination::Cutoff, 0.5);
+                params.reverb_size.set(0.7);
+                params.reverb_mix.set(0.3);
+            }),
+            preset("Kick", &|params| {
+                params.waveform.set(Waveform::Sine as usize as f32);
+                params.unison.set(1.0);
+                params.env_mode.set(EnvelopeMode::OneShot as usize as f32);
+                params.decay.set(0.4);
+                params.curve.set(0.8);
+                params.pitch_env_amount.set(36.0);
+                params.pitch_env_decay.set(0.08);
+                // a burst of noise for the beater's click, closed off by the filter envelope
+                params.noise_level.set(0.3);
+                params.cutoff.set(200.0);
+                params.filter_env_amount.set(6.0);
+                params.filter_decay.set(0.03);
+            }),
+            // picking this one makes a new random patch, which this is only a placeholder for
+            preset("Random", &|_| {}),
+        ];
+        debug_assert_eq!(presets.len(), NUM_FACTORY_PRESETS);
+        presets.resize_with(NUM_PRESETS, || preset("Init", &|_| {}));
+
+        Bank { current: 0, presets }
+    }
+}
+
+#[cfg_attr(not(feature = "vst2"), allow(dead_code))]
+fn write_u32(data: &mut Vec<u8>, value: usize) {
+    data.extend_from_slice(&(value as u32).to_le_bytes());
+}
+
+/// Reads little endian values from the front of a chunk, failing rather than panicking if it runs
+/// out of data.
+#[cfg_attr(not(feature = "vst2"), allow(dead_code))]
+struct Reader<'a>(&'a [u8]);
+
+#[cfg_attr(not(feature = "vst2"), allow(dead_code))]
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        if len > self.0.len() {
+            return None;
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Some(bytes)
+    }
+
+    fn u32(&mut self) -> Option<usize> {
+        let bytes = self.bytes(4)?.try_into().ok()?;
+        Some(u32::from_le_bytes(bytes) as usize)
+    }
+
+    fn f32(&mut self) -> Option<f32> {
+        let bytes = self.bytes(4)?.try_into().ok()?;
+        Some(f32::from_le_bytes(bytes))
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let len = self.u32()?;
+        String::from_utf8(self.bytes(len)?.to_vec()).ok()
+    }
+
+    /// Check the magic number, and return the version if we can read it.
+    fn header(&mut self, magic: &[u8; 4]) -> Option<usize> {
+        if self.bytes(4)? != magic {
+            return None;
+        }
+        let version = self.u32()?;
+        (1..=FORMAT_VERSION as usize).contains(&version).then_some(version)
+    }
+
+    fn preset(&mut self) -> Option<Preset> {
+        let version = self.header(PRESET_MAGIC)?;
+        let name = self.string()?;
+        let count = self.u32()?;
+        let values = (0..count).map(|_| self.f32()).collect::<Option<Vec<f32>>>()?;
+        let mut tuning = None;
+        if version >= 2 {
+            let (scale, mapping) = (self.string()?, self.string()?);
+            if !scale.is_empty() {
+                let mapping = Some(mapping).filter(|mapping| !mapping.is_empty()).map(PathBuf::from);
+                tuning = Some(TuningFiles { scale: PathBuf::from(scale), mapping });
+            }
+        }
+        let mut midi_map = Vec::new();
+        if version >= 3 {
+            for _ in 0..self.u32()? {
+                let cc = self.u32()?.try_into().ok()?;
+                let param = self.u32()?.try_into().ok()?;
+                midi_map.push((cc, param));
+            }
+        }
+        let mut wavetable = None;
+        if version >= 4 {
+            wavetable = Some(self.string()?).filter(|path| !path.is_empty()).map(PathBuf::from);
+        }
+        Some(Preset { name, values, tuning, midi_map, wavetable })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_preset_round_trip() {
+        let params = ReplicantParameters::init();
+        params.cutoff.set(1234.0);
+        let preset = Preset::capture("Test", &params);
+        assert_eq!(Preset::decode(&preset.encode()), Some(preset.clone()));
+
+        let loaded = ReplicantParameters::init();
+        preset.apply(&loaded);
+        assert!((loaded.cutoff.get() - 1234.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn test_tuning_round_trip() {
+        let mut preset = Preset::capture("Tuned", &ReplicantParameters::init());
+        preset.tuning = Some(TuningFiles { scale: PathBuf::from("scales/19edo.scl"), mapping: None });
+        assert_eq!(Preset::decode(&preset.encode()), Some(preset.clone()));
+        assert_eq!(Preset::from_bytes(&preset.to_bytes()).unwrap().tuning, preset.tuning);
+
+        // version 1 chunks had no tuning, and play in 12-TET
+        preset.tuning = None;
+        let mut old = preset.encode();
+        old.truncate(old.len() - 16);
+        old[4] = 1;
+        assert_eq!(Preset::decode(&old), Some(preset));
+    }
+
+    #[test]
+    fn test_wavetable_round_trip() {
+        let mut preset = Preset::capture("Wavetable", &ReplicantParameters::init());
+        preset.wavetable = Some(PathBuf::from("tables/vowels.wav"));
+        assert_eq!(Preset::decode(&preset.encode()), Some(preset.clone()));
+        assert_eq!(Preset::from_bytes(&preset.to_bytes()).unwrap().wavetable, preset.wavetable);
+
+        // the table can't be found, so the built in one plays, but the preset keeps referring to it
+        let loaded = ReplicantParameters::init();
+        preset.apply(&loaded);
+        assert_eq!(loaded.wavetable_file(), preset.wavetable);
+
+        // version 3 chunks had no wavetable
+        preset.wavetable = None;
+        let mut old = preset.encode();
+        old.truncate(old.len() - 4);
+        old[4] = 3;
+        assert_eq!(Preset::decode(&old), Some(preset));
+    }
+
+    #[test]
+    fn test_midi_map_round_trip() {
+        let params = ReplicantParameters::init();
+        params.midi_map.set_mappings(&[(1, 19), (74, 20)]);
+        let preset = Preset::capture("Mapped", &params);
+        assert_eq!(Preset::decode(&preset.encode()), Some(preset.clone()));
+        let text = String::from_utf8(preset.to_bytes()).unwrap();
+        assert!(text.contains("\"74\": \"Resonance\""), "{}", text);
+        assert_eq!(Preset::from_bytes(text.as_bytes()).unwrap().midi_map, preset.midi_map);
+
+        // presets without mappings leave the current ones alone
+        let loaded = ReplicantParameters::init();
+        preset.apply(&loaded);
+        Preset::capture("Unmapped", &ReplicantParameters::init()).apply(&loaded);
+        assert_eq!(loaded.midi_map.mappings(), preset.midi_map);
+    }
+
+    #[test]
+    fn test_init_patch_keeps_setup() {
+        let params = ReplicantParameters::init();
+        params.cutoff.set(1234.0);
+        params.polyphony.set(3.0);
+        params.master_gain.set(0.25);
+        params.midi_map.set_mappings(&[(74, 19)]);
+        params.init_patch();
+        assert_eq!(params.cutoff.get(), ReplicantParameters::init().cutoff.get());
+        assert_eq!(params.polyphony.get(), 3.0);
+        assert_eq!(params.master_gain.get(), 0.25);
+        assert_eq!(params.midi_map.mappings(), vec![(74, 19)]);
+    }
+
+    #[test]
+    fn test_random_patch() {
+        let params = ReplicantParameters::default();
+        params.polyphony.set(3.0);
+        params.master_gain.set(0.25);
+        let first = Preset::random(&params, 1);
+        assert_eq!(first, Preset::random(&params, 1));
+        assert_ne!(first.values, Preset::random(&params, 2).values);
+
+        first.apply(&params);
+        assert_eq!(params.polyphony.get(), 3.0);
+        assert_eq!(params.master_gain.get(), 0.25);
+        for &(index, low, high) in &RANDOM_RANGES {
+            let value = params.get_parameter(index);
+            assert!(value >= low - 1e-3 && value <= high + 1e-3, "{} is {}", index, value);
+        }
+
+        // a new one each time the program is picked
+        params.change_preset(RANDOM_PRESET as i32);
+        let picked = Preset::capture("", &params).values;
+        params.change_preset(0);
+        params.change_preset(RANDOM_PRESET as i32);
+        assert_ne!(Preset::capture("", &params).values, picked);
+    }
+
+    #[test]
+    fn test_bank_round_trip() {
+        let mut bank = Bank::factory();
+        bank.current = 3;
+        assert_eq!(Bank::decode(&bank.encode()), Some(bank));
+    }
+
+    #[test]
+    fn test_invalid_chunks_are_rejected() {
+        let chunk = Bank::factory().encode();
+        assert_eq!(Bank::decode(&chunk[..chunk.len() - 1]), None);
+        assert_eq!(Bank::decode(b""), None);
+        assert_eq!(Preset::decode(&chunk), None);
+
+        let mut wrong_version = Preset::capture("Test", &ReplicantParameters::init()).encode();
+        wrong_version[4] = 99;
+        assert_eq!(Preset::decode(&wrong_version), None);
+    }
+
+    #[test]
+    fn test_factory_bank() {
+        let bank = Bank::factory();
+        assert_eq!(bank.presets.len(), NUM_PRESETS);
+        assert!(bank.presets.iter().all(|preset| preset.values.len() == NUM_PARAMS));
+        let factory = &bank.presets[..NUM_FACTORY_PRESETS];
+        for (i, preset) in factory.iter().enumerate() {
+            assert!(factory[..i].iter().all(|other| other.name != preset.name));
+        }
+    }
+
+    #[test]
+    fn test_preset_file_round_trip() {
+        for preset in &Bank::factory().presets[..NUM_FACTORY_PRESETS] {
+            let data = preset.to_bytes();
+            let loaded = Preset::from_bytes(&data).unwrap();
+            assert_eq!(loaded.name, preset.name);
+            for (a, b) in loaded.values.iter().zip(&preset.values) {
+                assert!((a - b).abs() < 1e-5, "{}", preset.name);
+            }
+        }
+    }
+
+    #[test]
+    fn test_preset_file_is_readable() {
+        let params = ReplicantParameters::init();
+        params.cutoff.set(440.0);
+        let text = String::from_utf8(Preset::capture("Readable", &params).to_bytes()).unwrap();
+        assert!(text.contains("\"name\": \"Readable\""), "{}", text);
+        assert!(text.contains("\"Cutoff\": 440.0"), "{}", text);
+    }
+
+    #[test]
+    fn test_preset_file_defaults() {
+        let data = br#"{ "name": "Sparse", "params": { "Resonance": 0.5, "Not A Param": 1.0 }, "future": [] }"#;
+        let preset = Preset::from_bytes(data).unwrap();
+        let params = ReplicantParameters::init();
+        preset.apply(&params);
+        assert_eq!(params.resonance.get(), 0.5);
+        assert_eq!(params.cutoff.get(), ReplicantParameters::init().cutoff.get());
+
+        assert!(Preset::from_bytes(b"{ not json").is_err());
+    }
+
+    #[test]
+    fn test_load_preset_dir() {
+        let dir = env::temp_dir().join(format!("replicant-presets-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let bank = Bank::factory();
+        fs::write(dir.join("b.json"), bank.presets[2].to_bytes()).unwrap();
+        fs::write(dir.join("a.json"), bank.presets[1].to_bytes()).unwrap();
+        fs::write(dir.join("broken.json"), b"{").unwrap();
+        fs::write(dir.join("notes.txt"), b"ignored").unwrap();
+
+        let mut loaded = Bank::factory();
+        let presets = Bank::read_dir(&dir);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(loaded.set_user_presets(presets.unwrap()), 2);
+        assert_eq!(loaded.presets[NUM_FACTORY_PRESETS].name, bank.presets[1].name);
+        assert_eq!(loaded.presets[NUM_FACTORY_PRESETS + 1].name, bank.presets[2].name);
+        assert_eq!(loaded.presets[NUM_FACTORY_PRESETS + 2].name, "Init");
+    }
+}
@@ -0,0 +1,247 @@
+use crate::denormal::flush_denormal;
+use crate::effects::Effect;
+use crate::params::ReplicantParameters;
+use crate::tempo::Transport;
+
+/// The comb and allpass filter lengths of Freeverb, in seconds: its sample counts at 44.1kHz.
+/// They're all mutually prime there, so their echoes don't pile up on the same samples.
+const COMB_TIMES: [f64; 8] = [
+    1116.0 / 44100.0,
+    1188.0 / 44100.0,
+    1277.0 / 44100.0,
+    1356.0 / 44100.0,
+    1422.0 / 44100.0,
+    1491.0 / 44100.0,
+    1557.0 / 44100.0,
+    1617.0 / 44100.0,
+];
+const ALLPASS_TIMES: [f64; 4] = [556.0 / 44100.0, 441.0 / 44100.0, 341.0 / 44100.0, 225.0 / 44100.0];
+
+/// How much longer the right channel's filters are than the left's, in seconds, so the two
+/// channels' tails differ.
+const STEREO_SPREAD: f64 = 23.0 / 44100.0;
+
+/// The comb feedback at the smallest and largest room sizes.
+const MIN_FEEDBACK: f64 = 0.7;
+const MAX_FEEDBACK: f64 = 0.98;
+
+/// The most the combs' feedback is low passed, at full damping.
+const MAX_DAMPING: f64 = 0.4;
+
+/// The allpass filters' feedback, which spreads each echo out without colouring it.
+const ALLPASS_FEEDBACK: f64 = 0.5;
+
+/// Scales the input down, as the eight combs in parallel add up to much louder than it.
+const INPUT_GAIN: f64 = 0.015;
+
+/// How quiet the reverb has to be before its tail counts as over: -80 dB.
+const TAIL_LEVEL: f64 = 1e-4;
+
+/// A fixed delay, fed back through a one pole low pass filter.
+#[derive(Debug)]
+struct Comb {
+    buffer: Vec<f64>,
+    pos: usize,
+    // the low pass filter's last output
+    filtered: f64,
+}
+
+impl Comb {
+    fn process(&mut self, input: f64, feedback: f64, damping: f64) -> f64 {
+        let output = self.buffer[self.pos];
+        self.filtered = flush_denormal(output + (self.filtered - output) * damping);
+        self.buffer[self.pos] = input + self.filtered * feedback;
+        self.pos = (self.pos + 1) % self.buffer.len();
+        output
+    }
+}
+
+/// A fixed delay that passes every frequency at the same level, but smears it out in time.
+#[derive(Debug)]
+struct Allpass {
+    buffer: Vec<f64>,
+    pos: usize,
+}
+
+impl Allpass {
+    fn process(&mut self, input: f64) -> f64 {
+        let delayed = self.buffer[self.pos];
+        self.buffer[self.pos] = flush_denormal(input + delayed * ALLPASS_FEEDBACK);
+        self.pos = (self.pos + 1) % self.buffer.len();
+        delayed - input
+    }
+}
+
+/// A buffer holding `time` seconds, and at least one sample.
+fn buffer(time: f64, time_per_sample: f64) -> Vec<f64> {
+    vec![0.0; ((time / time_per_sample).round() as usize).max(1)]
+}
+
+/// One channel's filters: the combs in parallel, then the allpasses in series.
+#[derive(Debug)]
+struct Tank {
+    combs: Vec<Comb>,
+    allpasses: Vec<Allpass>,
+}
+
+impl Tank {
+    fn new(spread: f64, time_per_sample: f64) -> Tank {
+        Tank {
+            combs: COMB_TIMES.iter()
+                .map(|&time| Comb { buffer: buffer(time + spread, time_per_sample), pos: 0, filtered: 0.0 })
+                .collect(),
+            allpasses: ALLPASS_TIMES.iter()
+                .map(|&time| Allpass { buffer: buffer(time + spread, time_per_sample), pos: 0 })
+                .collect(),
+        }
+    }
+
+    fn clear(&mut self) {
+        for comb in &mut self.combs {
+            comb.buffer.iter_mut().for_each(|sample| *sample = 0.0);
+            comb.filtered = 0.0;
+        }
+        for allpass in &mut self.allpasses {
+            allpass.buffer.iter_mut().for_each(|sample| *sample = 0.0);
+        }
+    }
+
+    fn process(&mut self, input: f64, feedback: f64, damping: f64) -> f64 {
+        let wet = self.combs.iter_mut().map(|comb| comb.process(input, feedback, damping)).sum();
+        self.allpasses.iter_mut().fold(wet, |wet, allpass| allpass.process(wet))
+    }
+}
+
+/// A Freeverb style room reverb: both channels summed into banks of damped combs, which ring like
+/// the room's walls, then allpasses to diffuse them.
+pub struct Reverb {
+    tanks: [Tank; 2],
+    /// How long the room rings, 0.0 to 1.0.
+    size: f64,
+    /// How quickly the high frequencies die away, 0.0 to 1.0.
+    damping: f64,
+    /// How loud the reverb is, 0.0 to 1.0, mixed on top of the dry signal.
+    mix: f64,
+}
+
+impl Reverb {
+    pub fn new(time_per_sample: f64) -> Reverb {
+        Reverb {
+            tanks: [Tank::new(0.0, time_per_sample), Tank::new(STEREO_SPREAD, time_per_sample)],
+            size: 0.0,
+            damping: 0.0,
+            mix: 0.0,
+        }
+    }
+}
+
+/// The comb feedback for a room size.
+fn feedback(size: f64) -> f64 {
+    MIN_FEEDBACK + (MAX_FEEDBACK - MIN_FEEDBACK) * size
+}
+
+impl Effect for Reverb {
+    fn update(&mut self, params: &ReplicantParameters, _transport: &Transport) {
+        self.size = f64::from(params.reverb_size.get()).clamp(0.0, 1.0);
+        self.damping = f64::from(params.reverb_damping.get()).clamp(0.0, 1.0);
+        self.mix = f64::from(params.reverb_mix.get()).clamp(0.0, 1.0);
+    }
+
+    fn set_time_per_sample(&mut self, time_per_sample: f64) {
+        *self = Reverb { size: self.size, damping: self.damping, mix: self.mix, ..Reverb::new(time_per_sample) };
+    }
+
+    fn reset(&mut self) {
+        self.tanks.iter_mut().for_each(Tank::clear);
+    }
+
+    fn tail(&self, params: &ReplicantParameters, _transport: &Transport) -> f64 {
+        let mix = f64::from(params.reverb_mix.get()).clamp(0.0, 1.0);
+        if mix <= TAIL_LEVEL {
+            return 0.0;
+        }
+        // the longest comb dies away slowest, by its feedback each time round. the damping only
+        // shortens that, so it's left out.
+        let feedback = feedback(f64::from(params.reverb_size.get()).clamp(0.0, 1.0));
+        let trips = (TAIL_LEVEL / mix).ln() / feedback.ln();
+        (COMB_TIMES[COMB_TIMES.len() - 1] + STEREO_SPREAD) * trips
+    }
+
+    fn process(&mut self, left: f64, right: f64) -> (f64, f64) {
+        let input = (left + right) * INPUT_GAIN;
+        let (feedback, damping) = (feedback(self.size), self.damping * MAX_DAMPING);
+        let [left_tank, right_tank] = &mut self.tanks;
+        (
+            left + left_tank.process(input, feedback, damping) * self.mix,
+            right + right_tank.process(input, feedback, damping) * self.mix,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIME_PER_SAMPLE: f64 = 1.0 / 44100.0;
+
+    fn reverb(size: f32, damping: f32) -> (Reverb, ReplicantParameters) {
+        let params = ReplicantParameters::init();
+        params.reverb_size.set(size);
+        params.reverb_damping.set(damping);
+        params.reverb_mix.set(1.0);
+        let mut reverb = Reverb::new(TIME_PER_SAMPLE);
+        reverb.update(&params, &Transport::default());
+        (reverb, params)
+    }
+
+    /// The reverb's left channel after an impulse, without the impulse itself.
+    fn impulse_response(reverb: &mut Reverb, len: usize) -> Vec<f64> {
+        (0..len).map(|i| if i == 0 { reverb.process(1.0, 1.0).0 - 1.0 } else { reverb.process(0.0, 0.0).0 }).collect()
+    }
+
+    fn energy(samples: &[f64]) -> f64 {
+        samples.iter().map(|sample| sample * sample).sum()
+    }
+
+    #[test]
+    fn test_reverb_rings_on_and_dies_away() {
+        let (mut reverb, params) = reverb(0.5, 0.5);
+        let tail = (reverb.tail(&params, &Transport::default()) / TIME_PER_SAMPLE) as usize;
+        let output = impulse_response(&mut reverb, tail + 4410);
+        // nothing until the shortest comb comes round
+        let first = (COMB_TIMES[0] / TIME_PER_SAMPLE).round() as usize;
+        assert!(output[..first].iter().all(|&sample| sample == 0.0));
+        assert!(energy(&output[first..first + 4410]) > 1e-3);
+        assert!(output[tail..].iter().all(|&sample| sample.abs() < TAIL_LEVEL));
+    }
+
+    #[test]
+    fn test_bigger_rooms_ring_longer() {
+        let late = |size| {
+            let (mut reverb, _) = reverb(size, 0.5);
+            energy(&impulse_response(&mut reverb, 44100)[22050..])
+        };
+        assert!(late(0.9) > late(0.3) * 10.0);
+    }
+
+    #[test]
+    fn test_damping_dulls_the_tail() {
+        // the difference between neighbouring samples is mostly high frequencies
+        let brightness = |damping| {
+            let (mut reverb, _) = reverb(0.7, damping);
+            let output = impulse_response(&mut reverb, 44100);
+            let highs: Vec<f64> = output[11025..].windows(2).map(|pair| pair[1] - pair[0]).collect();
+            energy(&highs) / energy(&output[11025..])
+        };
+        assert!(brightness(1.0) < brightness(0.0));
+    }
+
+    #[test]
+    fn test_dry_reverb_passes_through() {
+        let (mut reverb, params) = reverb(0.5, 0.5);
+        params.reverb_mix.set(0.0);
+        reverb.update(&params, &Transport::default());
+        assert_eq!(reverb.process(0.5, -0.25), (0.5, -0.25));
+        assert_eq!(reverb.tail(&params, &Transport::default()), 0.0);
+    }
+}
@@ -0,0 +1,115 @@
+use crate::denormal::flush_denormal;
+
+/// Below this level the soft clipper leaves the signal untouched.
+const SOFT_CLIP_KNEE: f64 = 0.7;
+
+/// How long the limiter takes to recover after a peak, in seconds.
+const LIMITER_RELEASE: f64 = 0.05;
+
+/// How the output is kept from going over 0 dBFS.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Saturation {
+    /// Round off peaks with a tanh curve, leaving quieter signals alone.
+    SoftClip,
+    /// Turn the whole output down while it peaks over full scale, without adding distortion.
+    Limiter,
+}
+
+impl Saturation {
+    pub const ALL: [Saturation; 2] = [Saturation::SoftClip, Saturation::Limiter];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Saturation::SoftClip => "Soft Clip",
+            Saturation::Limiter => "Limiter",
+        }
+    }
+}
+
+/// Soft clip a sample. Linear up to the knee, then bending smoothly towards, but never reaching,
+/// full scale.
+pub fn soft_clip(sample: f64) -> f64 {
+    let level = sample.abs();
+    if level <= SOFT_CLIP_KNEE {
+        sample
+    } else {
+        let headroom = 1.0 - SOFT_CLIP_KNEE;
+        (SOFT_CLIP_KNEE + headroom * ((level - SOFT_CLIP_KNEE) / headroom).tanh()).copysign(sample)
+    }
+}
+
+/// The final stage of the output, which keeps the stereo signal within full scale.
+#[derive(Debug)]
+pub struct Saturator {
+    // the limiter's peak level, jumping up instantly and falling back over the release
+    envelope: f64,
+    release_coeff: f64,
+}
+
+impl Saturator {
+    pub fn new(time_per_sample: f64) -> Saturator {
+        let mut saturator = Saturator { envelope: 0.0, release_coeff: 0.0 };
+        saturator.set_time_per_sample(time_per_sample);
+        saturator
+    }
+
+    pub fn set_time_per_sample(&mut self, time_per_sample: f64) {
+        self.release_coeff = (-time_per_sample / LIMITER_RELEASE).exp();
+    }
+
+    pub fn reset(&mut self) {
+        self.envelope = 0.0;
+    }
+
+    pub fn process(&mut self, mode: Saturation, left: f64, right: f64) -> (f64, f64) {
+        match mode {
+            Saturation::SoftClip => (soft_clip(left), soft_clip(right)),
+            Saturation::Limiter => {
+                // both channels share the gain, so the stereo image doesn't shift. with no
+                // lookahead the envelope has to catch each peak on the sample it arrives.
+                let peak = left.abs().max(right.abs());
+                self.envelope = peak.max(flush_denormal(self.envelope * self.release_coeff));
+                let gain = if self.envelope > 1.0 { 1.0 / self.envelope } else { 1.0 };
+                (left * gain, right * gain)
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_soft_clip_shape() {
+        // quiet signals pass straight through
+        for &sample in &[0.0, 0.3, -0.5, SOFT_CLIP_KNEE] {
+            assert_eq!(soft_clip(sample), sample);
+        }
+
+        // louder ones keep rising, but never reach full scale
+        let levels: Vec<f64> = (0..=200).map(|i| soft_clip(f64::from(i) / 20.0)).collect();
+        assert!(levels.windows(2).all(|w| w[1] >= w[0]));
+        assert!(levels.iter().all(|&level| level <= 1.0));
+        assert!(soft_clip(1.0) < 1.0);
+        assert_eq!(soft_clip(-2.0), -soft_clip(2.0));
+    }
+
+    #[test]
+    fn test_limiter_stays_under_full_scale() {
+        let mut saturator = Saturator::new(1.0 / 44100.0);
+        for i in 0..4410 {
+            let input = 4.0 * (f64::from(i) * 0.05).sin();
+            let (left, right) = saturator.process(Saturation::Limiter, input, -input / 2.0);
+            assert!(left.abs() <= 1.0 && right.abs() <= 1.0);
+            // the channels are turned down together
+            assert!((right + left / 2.0).abs() < 1e-12);
+        }
+
+        // once the peaks stop, it lets go again
+        for _ in 0..44100 {
+            saturator.process(Saturation::Limiter, 0.0, 0.0);
+        }
+        assert_eq!(saturator.process(Saturation::Limiter, 0.5, 0.5), (0.5, 0.5));
+    }
+}
@@ -0,0 +1,203 @@
+use std::ops::{Add, Mul, Sub};
+
+/// Two samples worked on together, as a voice's left and right channels are.
+///
+/// With the `simd` feature each operation is a single SSE2 instruction on x86_64, or NEON on ARM,
+/// for both samples at once. Otherwise, and on other CPUs, it's a pair of plain `f64`s. Either way
+/// the arithmetic is the same, so the results are too.
+#[derive(Clone, Copy, Debug)]
+pub struct F64x2(lanes::Lanes);
+
+impl F64x2 {
+    pub fn new(first: f64, second: f64) -> F64x2 {
+        F64x2(lanes::new(first, second))
+    }
+
+    pub fn splat(value: f64) -> F64x2 {
+        F64x2::new(value, value)
+    }
+
+    pub fn to_array(self) -> [f64; 2] {
+        lanes::to_array(self.0)
+    }
+}
+
+impl Add for F64x2 {
+    type Output = F64x2;
+
+    fn add(self, other: F64x2) -> F64x2 {
+        F64x2(lanes::add(self.0, other.0))
+    }
+}
+
+impl Sub for F64x2 {
+    type Output = F64x2;
+
+    fn sub(self, other: F64x2) -> F64x2 {
+        F64x2(lanes::sub(self.0, other.0))
+    }
+}
+
+impl Mul for F64x2 {
+    type Output = F64x2;
+
+    fn mul(self, other: F64x2) -> F64x2 {
+        F64x2(lanes::mul(self.0, other.0))
+    }
+}
+
+// SSE2 and NEON are always there on x86_64 and aarch64, so the intrinsics are safe to call
+#[cfg(all(feature = "simd", target_arch = "x86_64"))]
+#[allow(unused_unsafe)]
+mod lanes {
+    use std::arch::x86_64::{__m128d, _mm_add_pd, _mm_mul_pd, _mm_set_pd, _mm_storeu_pd, _mm_sub_pd};
+
+    pub type Lanes = __m128d;
+
+    pub fn new(first: f64, second: f64) -> Lanes {
+        unsafe { _mm_set_pd(second, first) }
+    }
+
+    pub fn to_array(lanes: Lanes) -> [f64; 2] {
+        let mut array = [0.0; 2];
+        unsafe { _mm_storeu_pd(array.as_mut_ptr(), lanes) };
+        array
+    }
+
+    pub fn add(a: Lanes, b: Lanes) -> Lanes {
+        unsafe { _mm_add_pd(a, b) }
+    }
+
+    pub fn sub(a: Lanes, b: Lanes) -> Lanes {
+        unsafe { _mm_sub_pd(a, b) }
+    }
+
+    pub fn mul(a: Lanes, b: Lanes) -> Lanes {
+        unsafe { _mm_mul_pd(a, b) }
+    }
+}
+
+#[cfg(all(feature = "simd", target_arch = "aarch64"))]
+#[allow(unused_unsafe)]
+mod lanes {
+    use std::arch::aarch64::{float64x2_t, vaddq_f64, vld1q_f64, vmulq_f64, vst1q_f64, vsubq_f64};
+
+    pub type Lanes = float64x2_t;
+
+    pub fn new(first: f64, second: f64) -> Lanes {
+        unsafe { vld1q_f64([first, second].as_ptr()) }
+    }
+
+    pub fn to_array(lanes: Lanes) -> [f64; 2] {
+        let mut array = [0.0; 2];
+        unsafe { vst1q_f64(array.as_mut_ptr(), lanes) };
+        array
+    }
+
+    pub fn add(a: Lanes, b: Lanes) -> Lanes {
+        unsafe { vaddq_f64(a, b) }
+    }
+
+    pub fn sub(a: Lanes, b: Lanes) -> Lanes {
+        unsafe { vsubq_f64(a, b) }
+    }
+
+    pub fn mul(a: Lanes, b: Lanes) -> Lanes {
+        unsafe { vmulq_f64(a, b) }
+    }
+}
+
+#[cfg(not(all(feature = "simd", any(target_arch = "x86_64", target_arch = "aarch64"))))]
+mod lanes {
+    pub type Lanes = [f64; 2];
+
+    pub fn new(first: f64, second: f64) -> Lanes {
+        [first, second]
+    }
+
+    pub fn to_array(lanes: Lanes) -> [f64; 2] {
+        lanes
+    }
+
+    pub fn add([a, b]: Lanes, [c, d]: Lanes) -> Lanes {
+        [a + c, b + d]
+    }
+
+    pub fn sub([a, b]: Lanes, [c, d]: Lanes) -> Lanes {
+        [a - c, b - d]
+    }
+
+    pub fn mul([a, b]: Lanes, [c, d]: Lanes) -> Lanes {
+        [a * c, b * d]
+    }
+}
+
+/// Add `from` into `into`, sample by sample, as when mixing a voice into the bus.
+///
+/// With the `simd` feature this works on two samples at a time with SSE2 on x86_64, or NEON on
+/// ARM. Otherwise, and on other CPUs, it's left to the compiler.
+pub fn mix(into: &mut [f64], from: &[f64]) {
+    let len = into.len().min(from.len());
+    let (into, from) = (&mut into[..len], &from[..len]);
+    let pairs = len / 2 * 2;
+    mix_pairs(&mut into[..pairs], &from[..pairs]);
+    for (into, from) in into[pairs..].iter_mut().zip(&from[pairs..]) {
+        *into += from;
+    }
+}
+
+#[cfg(all(feature = "simd", target_arch = "x86_64"))]
+fn mix_pairs(into: &mut [f64], from: &[f64]) {
+    use std::arch::x86_64::{_mm_add_pd, _mm_loadu_pd, _mm_storeu_pd};
+
+    for (into, from) in into.chunks_exact_mut(2).zip(from.chunks_exact(2)) {
+        // SSE2 is always there on x86_64, and each chunk is two samples long
+        unsafe {
+            let sum = _mm_add_pd(_mm_loadu_pd(into.as_ptr()), _mm_loadu_pd(from.as_ptr()));
+            _mm_storeu_pd(into.as_mut_ptr(), sum);
+        }
+    }
+}
+
+#[cfg(all(feature = "simd", target_arch = "aarch64"))]
+fn mix_pairs(into: &mut [f64], from: &[f64]) {
+    use std::arch::aarch64::{vaddq_f64, vld1q_f64, vst1q_f64};
+
+    for (into, from) in into.chunks_exact_mut(2).zip(from.chunks_exact(2)) {
+        // NEON is always there on aarch64, and each chunk is two samples long
+        unsafe {
+            let sum = vaddq_f64(vld1q_f64(into.as_ptr()), vld1q_f64(from.as_ptr()));
+            vst1q_f64(into.as_mut_ptr(), sum);
+        }
+    }
+}
+
+#[cfg(not(all(feature = "simd", any(target_arch = "x86_64", target_arch = "aarch64"))))]
+fn mix_pairs(into: &mut [f64], from: &[f64]) {
+    for (into, from) in into.iter_mut().zip(from) {
+        *into += from;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pairs() {
+        let a = F64x2::new(1.5, -2.0);
+        let b = F64x2::new(0.25, 4.0);
+        assert_eq!((a + b).to_array(), [1.75, 2.0]);
+        assert_eq!((a - b).to_array(), [1.25, -6.0]);
+        assert_eq!((a * b).to_array(), [0.375, -8.0]);
+        assert_eq!(F64x2::splat(3.0).to_array(), [3.0, 3.0]);
+    }
+
+    #[test]
+    fn test_mix() {
+        // an odd length, so the last sample is left over from the pairs
+        let mut bus = [1.0, 2.0, 3.0, 4.0, 5.0];
+        mix(&mut bus, &[0.5, 0.25, -3.0, 0.0, 1.0]);
+        assert_eq!(bus, [1.5, 2.25, 0.0, 4.0, 6.0]);
+    }
+}
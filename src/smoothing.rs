@@ -0,0 +1,108 @@
+/// How long parameter changes take to settle, in seconds.
+pub const PARAM_SMOOTHING: f64 = 0.02;
+
+/// A value that glides towards its target with a one-pole lowpass, so stepped changes from
+/// automation or the editor don't make zipper noise.
+#[derive(Clone, Copy, Debug)]
+pub struct SmoothedParam {
+    value: f64,
+    target: f64,
+    // time constant, in seconds
+    time: f64,
+    // how far towards the target each sample moves, derived from the time and sample rate
+    coeff: f64,
+}
+
+impl SmoothedParam {
+    /// Start settled at `value`, taking around `time` seconds to follow changes.
+    pub fn new(value: f64, time: f64, time_per_sample: f64) -> SmoothedParam {
+        let mut param = SmoothedParam { value, target: value, time, coeff: 1.0 };
+        param.set_time_per_sample(time_per_sample);
+        param
+    }
+
+    pub fn set_time_per_sample(&mut self, time_per_sample: f64) {
+        self.coeff = 1.0 - (-time_per_sample / self.time).exp();
+    }
+
+    pub fn set_target(&mut self, target: f64) {
+        self.target = target;
+    }
+
+    /// Jump straight to `value`, with no glide.
+    pub fn reset(&mut self, value: f64) {
+        self.value = value;
+        self.target = value;
+    }
+
+    /// Move one sample towards the target, and return the new value.
+    pub fn next(&mut self) -> f64 {
+        self.advance(1)
+    }
+
+    /// Move `samples` towards the target at once, landing where as many calls to `next()` would,
+    /// and return the new value.
+    pub fn advance(&mut self, samples: usize) -> f64 {
+        let coeff = if samples == 1 { self.coeff } else { 1.0 - (1.0 - self.coeff).powi(samples as i32) };
+        self.value += (self.target - self.value) * coeff;
+        // settle exactly once we're close, so is_smoothing() goes back to false
+        if (self.target - self.value).abs() <= 1e-6 * self.target.abs().max(1.0) {
+            self.value = self.target;
+        }
+        self.value
+    }
+
+    pub fn get(&self) -> f64 {
+        self.value
+    }
+
+    pub fn is_smoothing(&self) -> bool {
+        self.value != self.target
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_follows_target_with_time_constant() {
+        let mut param = SmoothedParam::new(0.0, 0.01, 0.001);
+        param.set_target(1.0);
+        assert!(param.is_smoothing());
+
+        // one time constant gets about 63% of the way there
+        for _ in 0..10 {
+            param.next();
+        }
+        assert!((param.get() - (1.0 - (-1.0f64).exp())).abs() < 0.01, "value was {}", param.get());
+
+        for _ in 0..1000 {
+            param.next();
+        }
+        assert_eq!(param.get(), 1.0);
+        assert!(!param.is_smoothing());
+    }
+
+    #[test]
+    fn test_advance_matches_stepping() {
+        let mut stepped = SmoothedParam::new(0.0, 0.01, 1.0 / 1000.0);
+        let mut advanced = stepped;
+        stepped.set_target(1.0);
+        advanced.set_target(1.0);
+        for _ in 0..32 {
+            stepped.next();
+        }
+        assert!((advanced.advance(32) - stepped.get()).abs() < 1e-12);
+        assert_eq!(advanced.advance(0), advanced.get());
+    }
+
+    #[test]
+    fn test_reset_jumps() {
+        let mut param = SmoothedParam::new(20.0, 0.01, 0.001);
+        param.set_target(100.0);
+        param.reset(50.0);
+        assert!(!param.is_smoothing());
+        assert_eq!(param.next(), 50.0);
+    }
+}
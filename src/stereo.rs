@@ -0,0 +1,218 @@
+use std::f64::consts::PI;
+
+use crate::cents_to_ratio;
+use crate::effects::DelayLine;
+
+/// How far the right channel is delayed behind the left in Haas mode at a width of 1.0, in
+/// seconds. It grows with the width.
+const HAAS_DELAY: f64 = 0.01;
+
+/// How far each channel is detuned in detune mode at a width of 1.0, in cents, the left up and
+/// the right down. It grows with the width.
+const DETUNE_CENTS: f64 = 8.0;
+
+/// The span of delays the pitch shifters sweep through, in seconds. Shorter smears transients
+/// less, longer makes the crossfades between the taps less audible.
+const SHIFT_WINDOW: f64 = 0.03;
+
+/// How the stereo section widens, or narrows, the output.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StereoMode {
+    /// Both channels mixed to the middle, for mixes that have to hold up in mono.
+    Mono,
+    /// Each channel mixed with a copy detuned away from the other's, for a chorus-like spread
+    /// that doesn't cancel out when summed to mono.
+    Detune,
+    /// The right channel delayed a few milliseconds, so the sound seems to come from the left
+    /// while filling both sides.
+    Haas,
+    /// The difference between the channels turned up or down, from mono at 0.0 to twice as wide
+    /// at 2.0.
+    MidSide,
+}
+
+impl StereoMode {
+    pub const ALL: [StereoMode; 4] = [StereoMode::Mono, StereoMode::Detune, StereoMode::Haas, StereoMode::MidSide];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            StereoMode::Mono => "Mono",
+            StereoMode::Detune => "Detune",
+            StereoMode::Haas => "Haas",
+            StereoMode::MidSide => "Mid/Side",
+        }
+    }
+}
+
+/// Shifts the pitch of its input by reading it back through two taps, whose delays sweep steadily
+/// across a window and jump back to its other end when they get there. The taps are half a
+/// window apart and crossfaded, so each jumps while it's silent.
+#[derive(Debug)]
+struct PitchShifter {
+    line: DelayLine,
+    // how far through the window the first tap is, 0.0 to 1.0
+    phase: f64,
+}
+
+impl PitchShifter {
+    fn new(time_per_sample: f64) -> PitchShifter {
+        PitchShifter { line: DelayLine::new(SHIFT_WINDOW, time_per_sample), phase: 0.0 }
+    }
+
+    /// Shift the next sample by `ratio`, with a window `window` samples long.
+    fn process(&mut self, input: f64, ratio: f64, window: f64) -> f64 {
+        // a delay shrinking by a sample each sample plays the input back twice as fast
+        self.phase = (self.phase + (1.0 - ratio) / window).rem_euclid(1.0);
+        let output = [self.phase, (self.phase + 0.5).fract()].iter()
+            .map(|&tap| {
+                // the two gains always add up to 1.0
+                let gain = (PI * tap).sin().powi(2);
+                gain * self.line.read(tap * window)
+            })
+            .sum();
+        self.line.write(input);
+        output
+    }
+}
+
+/// The output's stereo section, which widens or narrows the image by each of the `StereoMode`s.
+#[derive(Debug)]
+pub struct Widener {
+    haas: DelayLine,
+    // the left channel's shifter goes up, and the right's down
+    shifters: [PitchShifter; 2],
+    time_per_sample: f64,
+}
+
+impl Widener {
+    pub fn new(time_per_sample: f64) -> Widener {
+        Widener {
+            haas: DelayLine::new(HAAS_DELAY * 2.0, time_per_sample),
+            shifters: [PitchShifter::new(time_per_sample), PitchShifter::new(time_per_sample)],
+            time_per_sample,
+        }
+    }
+
+    /// Prepare for a new sample rate. This allocates, so it mustn't be called while processing.
+    pub fn set_time_per_sample(&mut self, time_per_sample: f64) {
+        *self = Widener::new(time_per_sample);
+    }
+
+    pub fn reset(&mut self) {
+        self.haas.clear();
+        for shifter in &mut self.shifters {
+            shifter.line.clear();
+            shifter.phase = 0.0;
+        }
+    }
+
+    /// Widen one stereo sample by `width`, from 0.0 up to 2.0, returning the left and right
+    /// outputs.
+    pub fn process(&mut self, mode: StereoMode, width: f64, left: f64, right: f64) -> (f64, f64) {
+        let mid = (left + right) / 2.0;
+        let side = (left - right) / 2.0;
+        match mode {
+            StereoMode::Mono => {
+                self.write(left, right);
+                (mid, mid)
+            }
+            StereoMode::Detune => {
+                let ratio = cents_to_ratio(width * DETUNE_CENTS);
+                let window = SHIFT_WINDOW / self.time_per_sample;
+                let [up, down] = &mut self.shifters;
+                let up = up.process(left, ratio, window);
+                let down = down.process(right, 1.0 / ratio, window);
+                self.haas.write(right);
+                ((left + up) / 2.0, (right + down) / 2.0)
+            }
+            StereoMode::Haas => {
+                let delayed = self.haas.read(width * HAAS_DELAY / self.time_per_sample);
+                self.write(left, right);
+                (left, delayed)
+            }
+            StereoMode::MidSide => {
+                self.write(left, right);
+                (mid + side * width, mid - side * width)
+            }
+        }
+    }
+
+    /// Keep the delay lines fed while their mode isn't in use, so switching to it doesn't play
+    /// back whatever was there from before.
+    fn write(&mut self, left: f64, right: f64) {
+        self.haas.write(right);
+        let [up, down] = &mut self.shifters;
+        up.line.write(left);
+        down.line.write(right);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TAU;
+
+    const TIME_PER_SAMPLE: f64 = 1.0 / 44100.0;
+
+    /// A stereo sine with the channels out of phase, the widest a signal can be.
+    fn wide(i: usize) -> (f64, f64) {
+        let sample = (i as f64 * 440.0 * TAU * TIME_PER_SAMPLE).sin();
+        (sample, -sample)
+    }
+
+    #[test]
+    fn test_mono_and_mid_side() {
+        let mut widener = Widener::new(TIME_PER_SAMPLE);
+        let (left, right) = widener.process(StereoMode::Mono, 2.0, 0.8, 0.2);
+        assert_eq!((left, right), (0.5, 0.5));
+        let (left, right) = widener.process(StereoMode::MidSide, 2.0, 0.8, 0.2);
+        assert!((left - 1.1).abs() < 1e-12 && (right + 0.1).abs() < 1e-12);
+        let (left, right) = widener.process(StereoMode::MidSide, 1.0, 0.8, 0.2);
+        assert!((left - 0.8).abs() < 1e-12 && (right - 0.2).abs() < 1e-12);
+
+        // out of phase channels vanish in mono
+        assert_eq!(widener.process(StereoMode::Mono, 1.0, 0.5, -0.5), (0.0, 0.0));
+    }
+
+    #[test]
+    fn test_haas_delays_the_right() {
+        let mut widener = Widener::new(TIME_PER_SAMPLE);
+        let delay = (HAAS_DELAY / TIME_PER_SAMPLE).round() as usize;
+        let impulse = |i| if i == 0 { 1.0 } else { 0.0 };
+        let outputs: Vec<_> = (0..delay * 2).map(|i| widener.process(StereoMode::Haas, 1.0, 1.0, impulse(i))).collect();
+        assert!(outputs.iter().all(|&(left, _)| left == 1.0));
+        let echo = outputs.iter().position(|&(_, right)| right > 0.5).unwrap();
+        assert!((echo as i64 - delay as i64).abs() <= 1, "the right came {} samples late", echo);
+    }
+
+    #[test]
+    fn test_detune_survives_mono() {
+        let mut widener = Widener::new(TIME_PER_SAMPLE);
+        let mut mono_level = 0.0;
+        for i in 0..44100 {
+            let (left, right) = wide(i);
+            let (left, right) = widener.process(StereoMode::Detune, 1.0, left, right);
+            assert!(left.abs() <= 1.0 && right.abs() <= 1.0);
+            if i >= 22050 {
+                mono_level += ((left + right) / 2.0).powi(2);
+            }
+        }
+        // out of phase channels no longer cancel out, as the copies are detuned apart
+        let rms = (mono_level / 22050.0).sqrt();
+        assert!(rms > 0.1, "mono level was {}", rms);
+    }
+
+    #[test]
+    fn test_pitch_shifter_shifts() {
+        let mut shifter = PitchShifter::new(TIME_PER_SAMPLE);
+        let window = SHIFT_WINDOW / TIME_PER_SAMPLE;
+        let ratio = cents_to_ratio(1200.0);
+        let output: Vec<_> = (0..44100)
+            .map(|i| shifter.process((i as f64 * 220.0 * TAU * TIME_PER_SAMPLE).sin(), ratio, window))
+            .collect();
+        // count the zero crossings after the taps have filled, which come twice a cycle
+        let crossings = output[4410..].windows(2).filter(|pair| (pair[0] < 0.0) != (pair[1] < 0.0)).count();
+        let freq = crossings as f64 / 2.0 / ((output.len() - 4410) as f64 * TIME_PER_SAMPLE);
+        assert!((freq - 440.0).abs() < 40.0, "shifted to {} Hz", freq);
+    }
+}
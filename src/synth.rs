@@ -0,0 +1,2865 @@
+use std::sync::Arc;
+
+use log::{error, warn};
+
+use crate::arpeggiator::{ArpEvents, Arpeggiator};
+use crate::denormal::FlushDenormals;
+use crate::effects::{Effect, EffectsChain};
+use crate::envelope::EnvelopeMode;
+use crate::filter::DcBlocker;
+use crate::lfo::{Lfo, LfoShape, RandomSource, NUM_LFOS};
+use crate::meter::Meter;
+use crate::midi_cc::{CcDecoder, Control};
+use crate::modulation::Modulation;
+use crate::mts::MtsClient;
+use crate::multi::PartBuilder;
+use crate::params::{self, ReplicantParameters};
+use crate::presets::Preset;
+use crate::saturation::Saturator;
+use crate::simd;
+use crate::smoothing::{SmoothedParam, PARAM_SMOOTHING};
+use crate::stereo::Widener;
+use crate::tempo::{StopMode, Transport};
+use crate::tuning::Tuning;
+use crate::voice::{
+    Expression, NoteExpression, OutputRouter, StealPolicy, Unison, Voice, VoiceParams, VoicePanner, NUM_OUTPUTS,
+};
+use crate::oscillator::Waveform;
+use crate::wavetable::{Wavetable, NUM_HARMONICS};
+use crate::{decode_pitch_bend, lerp, velocity_amount, PITCH_BEND_SMOOTHING};
+
+/// Midi CC numbers.
+const MOD_WHEEL: u8 = 1;
+const VOLUME: u8 = 7;
+const PAN: u8 = 10;
+const SUSTAIN_PEDAL: u8 = 64;
+const TIMBRE: u8 = 74;
+const ALL_SOUND_OFF: u8 = 120;
+const ALL_NOTES_OFF: u8 = 123;
+
+/// How many midi channels there are.
+const NUM_CHANNELS: usize = 16;
+
+/// Room for this many midi events per buffer. The queue never grows, so any more are dropped.
+const EVENT_QUEUE_CAPACITY: usize = 512;
+
+/// The voice pool's size, which leaves room for stolen voices while they fade out.
+const MAX_VOICES: usize = params::MAX_POLYPHONY * 2;
+
+/// The buffer size `Synth::render_midi` renders with.
+const RENDER_BUFFER_SIZE: usize = 512;
+
+/// The control rate: the most samples the voices are rendered at once. The modulation, envelopes
+/// and smoothed parameters move on once per block, and are interpolated across it. Blocks end
+/// early at each midi event.
+const BLOCK_SIZE: usize = 32;
+
+/// Room for rendering a block of voices, kept between buffers so process() doesn't allocate.
+#[derive(Default)]
+struct Block {
+    // the left and right of all the voices mixed together, and of each voice as it's rendered
+    mix: [[f64; BLOCK_SIZE]; 2],
+    voice: [[f64; BLOCK_SIZE]; 2],
+    // the voices playing through each of the extra outputs, mixed together
+    outputs: [[[f64; BLOCK_SIZE]; 2]; NUM_OUTPUTS - 1],
+}
+
+/// The synth engine: the voices and everything around them that turns midi into audio.
+///
+/// It knows nothing about plugin formats or hosts. Whatever drives it queues the midi, passes on
+/// the host's tempo and position with `set_transport`, and calls `process_buffer` for each buffer.
+pub struct Synth {
+    // the host's tempo and play position, as of the start of the buffer
+    transport: Transport,
+    sample_rate: f64,
+    // derived from sample_rate in set_sample_rate(), so process() never divides by it
+    time_per_sample: f64,
+    // ordered from oldest to most recently triggered
+    voices: Vec<Voice>,
+    // places each new poly voice in the stereo field
+    panner: VoicePanner,
+    // picks the output each new voice plays through
+    router: OutputRouter,
+    // notes whose keys are down, with their velocities, in the order they were pressed
+    held_notes: Vec<(u8, u8)>,
+    sustain_pedal: bool,
+    // set by the host's soft bypass, which silences the output and drops the midi
+    bypassed: bool,
+    // stands in for the held keys while it's on
+    arpeggiator: Arpeggiator,
+    // whether the arpeggiator was on for the last buffer. keys follow this rather than the
+    // parameter, so a key let go in the buffer the arpeggiator's switched in is still let go
+    arp: bool,
+    // 0.0 to 1.0, scales the vibrato depth
+    mod_wheel: f64,
+    // channel pressure and CC74, 0.0 to 1.0
+    aftertouch: f64,
+    timbre: f64,
+    // the latest expression on each MPE note channel, for notes yet to be played on it
+    channel_expression: [Expression; NUM_CHANNELS],
+    // the tuning table as of the start of the buffer
+    tuning: Tuning,
+    // an MTS-ESP master's tuning overrides our own while there is one
+    mts: Option<MtsClient>,
+    // the wavetable as of the start of the buffer
+    wavetable: Arc<Wavetable>,
+    // the additive waveform's table, and the harmonic levels it was last built from
+    additive: Wavetable,
+    harmonics: [f64; NUM_HARMONICS],
+    vibrato: Lfo,
+    lfos: [Lfo; NUM_LFOS],
+    random: RandomSource,
+    // -1.0 to 1.0, glided towards the latest value to avoid stepping
+    pitch_bend: SmoothedParam,
+    // the parameters that would zipper if they jumped, glided towards their latest values
+    cutoff: SmoothedParam,
+    unison_detune: SmoothedParam,
+    unison_spread: SmoothedParam,
+    // the output stage: amplitude, balance and stereo width
+    master_gain: SmoothedParam,
+    master_pan: SmoothedParam,
+    stereo_width: SmoothedParam,
+    drive: SmoothedParam,
+    stereo: Widener,
+    dc_blockers: [DcBlocker; 2],
+    saturator: Saturator,
+    effects: EffectsChain,
+    // the status of the last channel message, for messages that leave it out
+    running_status: Option<u8>,
+    // 14-bit controllers and NRPNs, as their halves arrive
+    cc_decoder: CcDecoder,
+    // midi events for the next buffer, with the sample index they occur at
+    event_queue: Vec<(usize, [u8; 3])>,
+    block: Block,
+    // parameters moved by mapped controllers since the host was last told about them
+    automated: Vec<i32>,
+    // in multi-timbral mode, the parts playing midi channels 2 to 16. they're empty until the
+    // mode's first switched on, and the part builder has built them.
+    parts: Vec<Synth>,
+    part_builder: Arc<PartBuilder>,
+    // whether multi-timbral mode was on for the last buffer
+    multitimbral: bool,
+    // a part mixes itself into the output it's given, rather than replacing it
+    is_part: bool,
+    // how long a part has had no voices, in seconds, so it can stop running once it falls silent
+    idle_time: f64,
+    params: Arc<ReplicantParameters>,
+    meter: Arc<Meter>,
+}
+
+impl Synth {
+    /// A synth for playing live, which follows an MTS-ESP master's tuning if there is one, and
+    /// switches presets on program changes. `Synth::default()` does neither, for rendering.
+    pub fn new() -> Synth {
+        let synth = Synth { mts: MtsClient::connect(), ..Synth::default() };
+        ReplicantParameters::spawn_loader_thread(&synth.params, Arc::downgrade(&synth.part_builder));
+        synth
+    }
+
+    /// One of multi-timbral mode's parts, playing `params`. It takes its volume and pan from
+    /// midi CCs 7 and 10, and is mixed into the output of the synth playing it.
+    pub(crate) fn part(params: Arc<ReplicantParameters>) -> Synth {
+        Synth { is_part: true, ..Synth::with_params(params) }
+    }
+
+    /// Process an incoming midi event.
+    ///
+    /// The midi data is split up like so:
+    ///
+    /// `data[0]`: Contains the status and the channel. Source: [source]
+    /// `data[1]`: Contains the supplemental data for the message - so, if this was a NoteOn then
+    ///            this would contain the note.
+    /// `data[2]`: Further supplemental data. Would be velocity in the case of a NoteOn message.
+    ///
+    /// [source]: http://www.midimountain.com/midi/midi_status.htm
+    ///
+    /// Only events on the selected midi channel are played, unless it's set to omni. In MPE mode
+    /// channel 1 carries the shared controls, and the rest of the channels are note channels. In
+    /// multi-timbral mode channel 1 plays this patch, and the rest are each passed on to their
+    /// part, queued at `frame`.
+    ///
+    /// A note on with velocity 0 is a note off, as many controllers send them that way.
+    fn process_midi_event(&mut self, frame: usize, data: [u8; 3]) {
+        let data = match data[0] {
+            // running status: the status byte was left out, as it's the same as the last one's
+            0x00..=0x7F => match self.running_status {
+                Some(status) => [status, data[0], data[1]],
+                None => return,
+            },
+            0x80..=0xEF => {
+                self.running_status = Some(data[0]);
+                data
+            }
+            // system common messages cancel running status. real time ones can come between the
+            // bytes of other messages, so they leave it alone.
+            0xF0..=0xF7 => {
+                self.running_status = None;
+                return;
+            }
+            _ => return,
+        };
+        // data bytes never have the top bit set, so anything that does is junk from a bad sender
+        let data = [data[0], data[1] & 0x7F, data[2] & 0x7F];
+
+        let channel = data[0] & 0x0F;
+        if self.multitimbral {
+            if channel != 0 {
+                // each part plays its channel as if it were the first
+                let index = usize::from(channel) - 1;
+                match self.parts.get_mut(index) {
+                    // only the loader thread can load the part's preset, as with the instance's own
+                    Some(part) if data[0] & 0xF0 == 0xC0 && part.params.program_change() => {
+                        self.params.request_part_preset(index, i32::from(data[1]));
+                    }
+                    Some(part) => part.queue_event(frame, [data[0] & 0xF0, data[1], data[2]]),
+                    None => (),
+                }
+                return;
+            }
+        } else if self.params.mpe() {
+            if channel != 0 {
+                return self.process_mpe_event(data);
+            }
+        } else if self.params.midi_channel().is_some_and(|selected| selected != channel) {
+            return;
+        }
+
+        match data[0] & 0xF0 {
+            0x80 => self.key_off(data[1]),
+            0x90 if data[2] == 0 => self.key_off(data[1]),
+            0x90 => self.key_on(data[1], data[2]),
+            0xB0 => match self.cc_decoder.decode(data[1], data[2]) {
+                Some(Control::Cc(controller, value)) => self.control_change(controller, value),
+                Some(Control::Nrpn(number, value)) => self.nrpn(number, value),
+                None => (),
+            },
+            0xC0 => self.program_change(data[1]),
+            0xA0 => self.poly_aftertouch(data[1], data[2]),
+            0xD0 => self.aftertouch = velocity_amount(data[1]),
+            0xE0 => self.pitch_bend.set_target(decode_pitch_bend(data[1], data[2])),
+            _ => (),
+        }
+    }
+
+    /// Switch to the preset in the slot numbered by a program change, if they're switched on.
+    ///
+    /// Loading a preset takes locks and allocates, so the loader thread makes the switch. It
+    /// lands a little after the program change, usually within a buffer or two.
+    fn program_change(&mut self, program: u8) {
+        if self.params.program_change() {
+            self.params.request_preset(i32::from(program));
+        }
+    }
+
+    /// A key went down, which plays a note unless the arpeggiator is on to play it instead. Keys
+    /// outside the zone are ignored.
+    fn key_on(&mut self, note: u8, velocity: u8) {
+        if !self.params.in_zone(note, velocity) {
+            return;
+        }
+        if self.arp {
+            self.arpeggiator.note_on(note, velocity);
+        } else {
+            self.note_on(note, velocity);
+        }
+    }
+
+    fn play_arp_events(&mut self, events: ArpEvents) {
+        if let Some(note) = events.note_off {
+            self.note_off(note);
+        }
+        if let Some((note, velocity)) = events.note_on {
+            self.note_on(note, velocity);
+        }
+    }
+
+    fn key_off(&mut self, note: u8) {
+        if self.arp {
+            self.arpeggiator.note_off(note);
+        } else {
+            self.note_off(note);
+        }
+    }
+
+    fn poly_aftertouch(&mut self, note: u8, pressure: u8) {
+        for voice in self.voices.iter_mut().filter(|voice| voice.note == note && voice.channel == 0) {
+            voice.pressure = velocity_amount(pressure);
+        }
+    }
+
+    /// Process an event on one of the MPE note channels, 2 to 16, which each carry the expression
+    /// of the notes played on them. Channel 1 is left for everything shared by all notes.
+    fn process_mpe_event(&mut self, data: [u8; 3]) {
+        let channel = data[0] & 0x0F;
+        let index = usize::from(channel);
+        match data[0] & 0xF0 {
+            0x80 => self.note_off_on_channel(channel, data[1]),
+            0x90 if data[2] == 0 => self.note_off_on_channel(channel, data[1]),
+            0x90 if !self.params.in_zone(data[1], data[2]) => (),
+            0x90 => {
+                self.note_on(data[1], data[2]);
+                let expression = self.channel_expression[index];
+                // note_on() leaves the voice playing the new note last, unless mono note priority
+                // kept the old one
+                if let Some(voice) = self.voices.last_mut().filter(|voice| voice.note == data[1]) {
+                    voice.channel = channel;
+                    voice.expression = Some(expression);
+                }
+            }
+            0xB0 if data[1] == TIMBRE => {
+                let timbre = velocity_amount(data[2]);
+                self.channel_expression[index].timbre = timbre;
+                self.held_expressions(channel).for_each(|expression| expression.timbre = timbre);
+            }
+            0xD0 => {
+                let pressure = velocity_amount(data[1]);
+                self.channel_expression[index].pressure = pressure;
+                self.held_expressions(channel).for_each(|expression| expression.pressure = pressure);
+            }
+            0xE0 => {
+                let bend = decode_pitch_bend(data[1], data[2]);
+                self.channel_expression[index].bend.reset(bend);
+                self.held_expressions(channel).for_each(|expression| expression.bend.set_target(bend));
+            }
+            _ => (),
+        }
+    }
+
+    /// The expression of the MPE notes still held on `channel`. Released notes keep theirs, so
+    /// the channel can be reused for a new note without bending their tails.
+    fn held_expressions(&mut self, channel: u8) -> impl Iterator<Item = &mut Expression> {
+        self.voices.iter_mut()
+            .filter(move |voice| voice.channel == channel && voice.is_held())
+            .filter_map(|voice| voice.expression.as_mut())
+    }
+
+    /// Change the expression of the held voices playing `note`, for plugin formats that send it
+    /// per note rather than per MPE channel.
+    pub fn note_expression(&mut self, note: u8, expression: NoteExpression) {
+        let time_per_sample = self.time_per_sample;
+        for voice in self.voices.iter_mut().filter(|voice| voice.note == note && voice.is_held()) {
+            voice.expression.get_or_insert_with(|| Expression::new(time_per_sample)).apply(expression);
+        }
+    }
+
+    /// Silence `note` straight away, rather than letting it ring out through its release, for
+    /// plugin formats that can choke a single note, as a hi-hat closing cuts off an open one.
+    pub fn choke(&mut self, note: u8) {
+        self.held_notes.retain(|&(held, _)| held != note);
+        if self.arp {
+            self.arpeggiator.note_off(note);
+        }
+        self.voices.retain(|voice| voice.note != note);
+    }
+
+    fn note_on(&mut self, note: u8, velocity: u8) {
+        // keyboard mappings can leave keys silent
+        if !self.tuning.is_mapped(note) {
+            return;
+        }
+        let params = self.voice_params();
+        let polyphony = self.params.polyphony();
+
+        self.held_notes.retain(|&(held, _)| held != note);
+        self.held_notes.push((note, velocity));
+
+        if polyphony == 1 {
+            // mono: play whichever held note has priority, which may not be the new one
+            let priority = self.params.note_priority().choose(&self.held_notes);
+            if let Some((note, velocity)) = priority {
+                let sounding = self.mono_voice().map(|voice| voice.note);
+                if sounding != Some(note) || self.mono_voice().is_none_or(|voice| !voice.is_held()) {
+                    self.play_mono_note(note, velocity, &params);
+                }
+            }
+        } else {
+            let mut voice = Voice::new(note, velocity, &params);
+            voice.pan = self.panner.next(self.params.pan_mode(), note);
+            self.push_voice(voice);
+        }
+
+        // fade out voices until we're back within the polyphony, which also covers it having been
+        // turned down since the last note
+        let policy = self.params.steal_policy();
+        while self.voices.iter().filter(|voice| !voice.is_stolen()).count() > polyphony {
+            let idx = self.voice_to_steal(policy, note, &params);
+            self.voices[idx].steal();
+        }
+    }
+
+    /// The voice the mono path plays on, if there is one.
+    fn mono_voice(&self) -> Option<&Voice> {
+        self.voices.iter().rev().find(|voice| !voice.is_stolen())
+    }
+
+    /// Move the mono voice to a new note, gliding to its pitch.
+    ///
+    /// The envelopes are retriggered as the retrigger mode says, unless legato is on and the
+    /// previous note is still held.
+    fn play_mono_note(&mut self, note: u8, velocity: u8, params: &VoiceParams) {
+        match self.voices.iter().rposition(|voice| !voice.is_stolen()) {
+            Some(idx) => {
+                let mut voice = self.voices.remove(idx);
+                let legato = self.params.legato() && voice.is_held();
+                voice.glide_to(note, params);
+                if !legato {
+                    voice.note_on(velocity, params);
+                }
+                voice.sustained = false;
+                self.voices.push(voice);
+            }
+            None => self.push_voice(Voice::new(note, velocity, params)),
+        }
+    }
+
+    /// Add a voice to the pool, routed to its output. If it's full, the oldest stolen voice is
+    /// dropped to make room, so the pool never has to grow.
+    fn push_voice(&mut self, mut voice: Voice) {
+        voice.output = self.router.next(self.params.output_routing(), voice.note, self.params.key_zone());
+        if self.voices.len() == MAX_VOICES {
+            // no more voices than the polyphony are left unstolen, so the rest are fading out
+            if let Some(idx) = self.voices.iter().position(Voice::is_stolen) {
+                self.voices.remove(idx);
+            }
+        }
+        self.voices.push(voice);
+    }
+
+    /// Pick a voice to make room for a new note. The new note's voice is never picked.
+    fn voice_to_steal(&self, policy: StealPolicy, note: u8, params: &VoiceParams) -> usize {
+        // the new voice is the last one
+        let candidates = || self.voices[..self.voices.len() - 1].iter()
+            .enumerate()
+            .filter(|(_, voice)| !voice.is_stolen());
+        let oldest = candidates().next().map_or(0, |(idx, _)| idx);
+
+        match policy {
+            StealPolicy::Oldest => oldest,
+            StealPolicy::Quietest => candidates()
+                .min_by(|(_, a), (_, b)| a.alpha(params).total_cmp(&b.alpha(params)))
+                .map_or(oldest, |(idx, _)| idx),
+            StealPolicy::SameNoteFirst => candidates()
+                .find(|(_, voice)| voice.note == note)
+                .map_or(oldest, |(idx, _)| idx),
+        }
+    }
+
+    fn note_off(&mut self, note: u8) {
+        self.note_off_on_channel(0, note);
+    }
+
+    /// Release `note`, if it was played on `channel`.
+    fn note_off_on_channel(&mut self, channel: u8, note: u8) {
+        let params = self.voice_params();
+        self.held_notes.retain(|&(held, _)| held != note);
+
+        // mono: releasing the sounding note falls back to the next held note with priority
+        if self.params.polyphony() == 1 && self.mono_voice().map(|voice| voice.note) == Some(note) {
+            if let Some((next, velocity)) = self.params.note_priority().choose(&self.held_notes) {
+                self.play_mono_note(next, velocity, &params);
+                return;
+            }
+        }
+
+        let pedal_down = self.sustain_pedal;
+        let voices = self.voices.iter_mut()
+            .filter(|voice| voice.note == note && voice.channel == channel && voice.is_held());
+        for voice in voices {
+            // with the pedal down, the note keeps sounding until the pedal is released
+            if pedal_down {
+                voice.sustained = true;
+            } else {
+                voice.note_off(&params);
+            }
+        }
+    }
+
+    /// A controller moved, to a value from 0.0 to 1.0.
+    fn control_change(&mut self, controller: u8, value: f64) {
+        // controllers the user has mapped to a parameter do only that
+        if let Some(index) = self.params.midi_map.control(controller) {
+            self.automate(index, value);
+            return;
+        }
+        match controller {
+            MOD_WHEEL => self.mod_wheel = value,
+            TIMBRE => self.timbre = value,
+            // midi's recommended volume curve, which falls to silence at 0
+            VOLUME if self.is_part => {
+                let db = (40.0 * value.log10()).max(f64::from(params::MIN_MASTER_GAIN));
+                self.params.master_gain.set(db as f32);
+            }
+            PAN if self.is_part => {
+                // 64 is the middle
+                let pan = (value * 127.0 - 64.0) / 63.0;
+                self.params.master_pan.set(pan.clamp(-1.0, 1.0) as f32);
+            }
+            SUSTAIN_PEDAL => {
+                self.sustain_pedal = value >= 0.5;
+                if !self.sustain_pedal {
+                    self.release_sustained_notes();
+                }
+            },
+            ALL_SOUND_OFF => self.voices.clear(),
+            ALL_NOTES_OFF => self.release_all_notes(),
+            _ => (),
+        }
+    }
+
+    /// An NRPN's data entry changed. Each parameter number sets the parameter at the same host
+    /// index, at up to 14 bits.
+    fn nrpn(&mut self, number: u16, value: f64) {
+        if usize::from(number) < params::NUM_PARAMS {
+            self.automate(i32::from(number), value);
+        }
+    }
+
+    /// Set a parameter from midi, to a value from 0.0 to 1.0 across its range.
+    fn automate(&mut self, index: i32, value: f64) {
+        self.params.set_normalized(index, value as f32);
+        // so the host can be told, and record the change as automation
+        if !self.automated.contains(&index) {
+            self.automated.push(index);
+        }
+    }
+
+    /// Release every voice, including those held by the sustain pedal.
+    fn release_all_notes(&mut self) {
+        let params = self.voice_params();
+        self.arpeggiator.reset();
+        self.held_notes.clear();
+        for voice in self.voices.iter_mut().filter(|voice| voice.is_held()) {
+            voice.sustained = false;
+            voice.note_off(&params);
+        }
+    }
+
+    /// Release the keys played straight to the voices, which the arpeggiator never saw go down, so
+    /// they aren't left stuck once it's switched on and takes their key offs.
+    fn release_held_keys(&mut self) {
+        let params = self.voice_params();
+        let pedal_down = self.sustain_pedal;
+        self.held_notes.clear();
+        for voice in self.voices.iter_mut().filter(|voice| voice.channel == 0 && voice.is_held()) {
+            if pedal_down {
+                voice.sustained = true;
+            } else {
+                voice.note_off(&params);
+            }
+        }
+    }
+
+    /// Turn the host's soft bypass on or off. Bypassing resets the synth, so nothing is left
+    /// ringing or stuck once it's turned back on, and it stays silent and ignores midi until then.
+    pub fn set_bypass(&mut self, bypass: bool) {
+        if bypass && !self.bypassed {
+            self.reset();
+        }
+        self.bypassed = bypass;
+    }
+
+    /// Silence everything and forget all midi state, as if the plugin had just been loaded.
+    pub fn reset(&mut self) {
+        self.voices.clear();
+        self.held_notes.clear();
+        self.event_queue.clear();
+        self.running_status = None;
+        self.cc_decoder = CcDecoder::default();
+        self.sustain_pedal = false;
+        self.mod_wheel = 0.0;
+        self.aftertouch = 0.0;
+        self.timbre = 0.0;
+        self.channel_expression = [Expression::new(self.time_per_sample); NUM_CHANNELS];
+        self.pitch_bend.reset(0.0);
+        self.flush();
+        self.update_smoothed_params();
+        self.vibrato.reset();
+        self.arpeggiator.reset();
+        for lfo in &mut self.lfos {
+            lfo.reset();
+        }
+        self.random.reset();
+        self.parts.iter_mut().for_each(Synth::reset);
+    }
+
+    /// Silence every voice and clear the effects and output stage, without touching the midi
+    /// state.
+    fn flush(&mut self) {
+        self.voices.clear();
+        self.stereo.reset();
+        self.dc_blockers.iter_mut().for_each(DcBlocker::reset);
+        self.saturator.reset();
+        self.effects.reset();
+        self.parts.iter_mut().for_each(Synth::flush);
+    }
+
+    /// Release the notes whose note-offs were held back by the sustain pedal.
+    fn release_sustained_notes(&mut self) {
+        let params = self.voice_params();
+        for voice in self.voices.iter_mut().filter(|voice| voice.sustained) {
+            voice.sustained = false;
+            voice.note_off(&params);
+        }
+    }
+
+    /// Point the smoothed parameters at their latest values. While nothing is playing there's
+    /// nothing to zipper, so they jump straight there.
+    fn update_smoothed_params(&mut self) {
+        let silent = self.voices.is_empty();
+        let params = [
+            (&mut self.cutoff, f64::from(self.params.cutoff.get())),
+            (&mut self.unison_detune, f64::from(self.params.unison_detune.get())),
+            (&mut self.unison_spread, f64::from(self.params.unison_spread.get())),
+            (&mut self.master_gain, self.params.master_gain()),
+            (&mut self.master_pan, f64::from(self.params.master_pan.get()).clamp(-1.0, 1.0)),
+            (&mut self.stereo_width, f64::from(self.params.stereo_width.get())),
+            (&mut self.drive, self.params.drive()),
+        ];
+        for (param, value) in params {
+            if silent {
+                param.reset(value);
+            } else {
+                param.set_target(value);
+            }
+        }
+    }
+
+    /// The host's tempo and play position, as last set.
+    pub fn transport(&self) -> &Transport {
+        &self.transport
+    }
+
+    /// Set the host's tempo and play position for the next buffer. Without a host, it's left at
+    /// 120 bpm and stopped.
+    ///
+    /// When playback stops or loops back, the notes playing are released or killed, as the stop
+    /// mode says, as their note offs may never come. While the host plays, synced LFOs jump to its
+    /// position, so they line up with the music.
+    pub fn set_transport(&mut self, transport: Transport) {
+        if self.transport.is_interrupted_by(&transport) {
+            match self.params.stop_mode() {
+                StopMode::Keep => (),
+                StopMode::Release => self.release_all_notes(),
+                StopMode::Kill => {
+                    self.release_all_notes();
+                    self.voices.clear();
+                }
+            }
+        }
+        // the position's where the host's buffer starts, which may be rendered in several
+        // stretches, so the LFOs are only moved here and run on freely from it
+        if let Some(position) = transport.position {
+            for (lfo, settings) in self.lfos.iter_mut().zip(&self.params.lfos) {
+                if let Some(division) = settings.snapshot().division {
+                    lfo.set_phase(position / division.beats());
+                }
+            }
+            if let Some(division) = self.params.random().division {
+                self.random.set_phase(position / division.beats());
+            }
+        }
+        for part in &mut self.parts {
+            part.set_transport(transport);
+        }
+        self.transport = transport;
+    }
+
+    /// The parameters that mapped midi controllers have moved since this was last called, with
+    /// their new values, for telling the host.
+    pub fn take_automated(&mut self) -> impl Iterator<Item = (i32, f32)> + '_ {
+        let params = &self.params;
+        self.automated.drain(..).map(move |index| (index, params.normalized(index)))
+    }
+
+    /// The shared parameters, which the host and the editor change.
+    pub(crate) fn params(&self) -> &Arc<ReplicantParameters> {
+        &self.params
+    }
+
+    pub fn set_sample_rate(&mut self, rate: f32) {
+        // some hosts report a rate of 0 before the real one arrives. keep the previous rate
+        // rather than producing an infinite time step.
+        let rate = f64::from(rate);
+        if !(rate.is_finite() && rate > 0.0) {
+            return;
+        }
+        // hosts set it again whenever they set up processing, and starting the effects over would
+        // cut their tails off
+        if rate == self.sample_rate {
+            return;
+        }
+
+        // the envelopes, oscillators and the voices' control values keep their state in seconds
+        // and Hz, so they carry over to the new rate unchanged - only the per-sample increments
+        // need to be recomputed.
+        self.sample_rate = rate;
+        self.time_per_sample = 1.0 / rate;
+        let params = [
+            &mut self.pitch_bend,
+            &mut self.cutoff,
+            &mut self.unison_detune,
+            &mut self.unison_spread,
+            &mut self.master_gain,
+            &mut self.master_pan,
+            &mut self.stereo_width,
+            &mut self.drive,
+        ];
+        for param in params {
+            param.set_time_per_sample(self.time_per_sample);
+        }
+        let expressions = self.voices.iter_mut()
+            .filter_map(|voice| voice.expression.as_mut())
+            .chain(&mut self.channel_expression);
+        for expression in expressions {
+            expression.bend.set_time_per_sample(self.time_per_sample);
+        }
+        self.stereo.set_time_per_sample(self.time_per_sample);
+        for blocker in &mut self.dc_blockers {
+            blocker.set_time_per_sample(self.time_per_sample);
+        }
+        self.saturator.set_time_per_sample(self.time_per_sample);
+        self.effects.set_time_per_sample(self.time_per_sample);
+        for part in &mut self.parts {
+            part.set_sample_rate(rate as f32);
+        }
+    }
+
+    /// How long the synth rings on after the last note off, in seconds: the notes playing out
+    /// their amp envelopes, then the effects' tails. In multi-timbral mode, it's the longest of
+    /// all the parts'.
+    pub fn tail_time(&self) -> f64 {
+        let adsr = self.params.adsr();
+        let release = match adsr.mode {
+            EnvelopeMode::Sustain => adsr.release,
+            // one shot notes play their whole envelope whenever they're let go
+            EnvelopeMode::OneShot => adsr.attack + adsr.hold + adsr.decay,
+        };
+        let tail = release + self.effects.tail(&self.params, &self.transport);
+        let parts = self.parts.iter().filter(|_| self.params.multitimbral());
+        parts.map(Synth::tail_time).fold(tail, f64::max)
+    }
+
+    /// The tail time in samples, for hosts.
+    pub fn tail_samples(&self) -> u32 {
+        (self.tail_time() * self.sample_rate).ceil().min(f64::from(u32::MAX)) as u32
+    }
+
+    fn voice_params(&self) -> VoiceParams {
+        VoiceParams {
+            adsr: self.params.adsr(),
+            waveform: self.params.waveform(),
+            pulse_width: f64::from(self.params.pulse_width.get()),
+            wavetable_position: self.params.wavetable_position(),
+            unison: Unison::new(self.params.unison(), self.unison_detune.get(), self.unison_spread.get()),
+            sync: self.params.osc_sync(),
+            sync_ratio: f64::from(self.params.sync_ratio.get()),
+            velocity_curve: self.params.velocity_curve(),
+            vel_to_attack: f64::from(self.params.vel_to_attack.get()),
+            retrigger: self.params.retrigger(),
+            glide_time: f64::from(self.params.glide_time.get()).max(0.0),
+            glide_mode: self.params.glide_mode(),
+            glide_curve: self.params.glide_curve(),
+
+            filter_type: self.params.filter_type(),
+            cutoff: self.cutoff.get(),
+            resonance: f64::from(self.params.resonance.get()),
+            filter_adsr: self.params.filter_adsr(),
+            filter_env_amount: f64::from(self.params.filter_env_amount.get()),
+            vel_to_cutoff: f64::from(self.params.vel_to_cutoff.get()),
+
+            mod_adsr: self.params.mod_adsr(),
+            mod_matrix: self.params.mod_matrix(),
+
+            fm_ratio: f64::from(self.params.fm_ratio.get()),
+            fm_index: f64::from(self.params.fm_index.get()),
+            fm_adsr: self.params.fm_adsr(),
+            pitch_env_amount: f64::from(self.params.pitch_env_amount.get()),
+            pitch_env_decay: f64::from(self.params.pitch_env_decay.get()).max(0.0),
+
+            ring_mod: f64::from(self.params.ring_mod.get()),
+            osc_level: f64::from(self.params.osc_level.get()),
+            sub_level: f64::from(self.params.sub_level.get()),
+            noise_level: f64::from(self.params.noise_level.get()),
+            sub_octave: self.params.sub_octave(),
+            sub_waveform: self.params.sub_waveform(),
+            noise_color: self.params.noise_color(),
+
+            mpe_bend_range: f64::from(self.params.mpe_bend_range.get()),
+            drift: self.params.drift_cents(),
+            oversampling: self.params.oversampling(),
+            pan_width: f64::from(self.params.pan_width.get()),
+
+            tuning: self.tuning,
+            reference_pitch: self.params.reference_pitch(),
+        }
+    }
+
+    /// The output levels and waveform, which can be read from any thread while audio is running.
+    pub fn meter(&self) -> Arc<Meter> {
+        Arc::clone(&self.meter)
+    }
+
+    /// Set a parameter by its host index, to a value from 0.0 to 1.0 across its range, as the host
+    /// would.
+    pub fn set_parameter(&self, index: i32, value: f32) {
+        self.params.set_normalized(index, value);
+    }
+
+    /// The current patch as a preset file, for sharing outside of the host.
+    pub fn export_preset(&self) -> Vec<u8> {
+        self.params.current_preset().to_bytes()
+    }
+
+    /// Load a preset file written by `export_preset` into the selected preset slot.
+    pub fn import_preset(&mut self, data: &[u8]) -> Result<(), serde_json::Error> {
+        self.params.load_preset(Preset::from_bytes(data)?);
+        Ok(())
+    }
+
+    /// Queue a midi event to be applied at sample `frame` of the next buffer, keeping the queue in
+    /// order. Events on the same sample keep the order they arrived in.
+    pub fn queue_event(&mut self, frame: usize, data: [u8; 3]) {
+        if self.event_queue.len() == EVENT_QUEUE_CAPACITY {
+            warn!("dropped a midi event, as there were more than {} in one buffer", EVENT_QUEUE_CAPACITY);
+            return;
+        }
+        // hosts send events in order, so this is almost always the end of the queue
+        let idx = self.event_queue.partition_point(|&(queued, _)| queued <= frame);
+        self.event_queue.insert(idx, (frame, data));
+    }
+
+    /// Render audio without a host, returning the left and right output buffers.
+    ///
+    /// `events` are raw midi messages, each paired with the sample index at which it should be
+    /// applied. Events at or beyond `num_samples` are ignored.
+    pub fn render(&mut self, events: &[(u32, [u8; 3])], num_samples: usize) -> (Vec<f32>, Vec<f32>) {
+        let mut left = vec![0.0; num_samples];
+        let mut right = vec![0.0; num_samples];
+
+        for &(frame, data) in events.iter().filter(|&&(frame, _)| (frame as usize) < num_samples) {
+            self.queue_event(frame as usize, data);
+        }
+
+        self.process_buffer(&mut left, &mut right);
+        (left, right)
+    }
+
+    /// Render midi without a host, a buffer at a time as a host would, returning `len` stereo
+    /// samples interleaved left then right.
+    ///
+    /// `events` are raw midi messages, each paired with the sample index at which it should be
+    /// applied. Unlike `render`, they can be spread over any length. Events at or beyond `len`
+    /// are ignored.
+    pub fn render_midi(&mut self, events: &[(u32, [u8; 3])], len: usize) -> Vec<f32> {
+        let mut events = events.to_vec();
+        // the sort is stable, so events on the same sample keep their order
+        events.sort_by_key(|&(frame, _)| frame);
+        let mut events = events.into_iter().peekable();
+
+        let mut output = Vec::with_capacity(len * 2);
+        let (mut left, mut right) = ([0.0; RENDER_BUFFER_SIZE], [0.0; RENDER_BUFFER_SIZE]);
+        for start in (0..len).step_by(RENDER_BUFFER_SIZE) {
+            let samples = RENDER_BUFFER_SIZE.min(len - start);
+            while let Some((frame, data)) = events.next_if(|&(frame, _)| (frame as usize) < start + samples) {
+                self.queue_event(frame as usize - start, data);
+            }
+            let (left, right) = (&mut left[..samples], &mut right[..samples]);
+            self.process_buffer(left, right);
+            output.extend(left.iter().zip(right.iter()).flat_map(|(&left, &right)| [left, right]));
+        }
+        output
+    }
+
+    /// Render the next buffer into `left` and `right`, applying the midi events queued for it.
+    /// Every voice plays through them, whatever output it's routed to.
+    pub fn process_buffer(&mut self, left: &mut [f32], right: &mut [f32]) {
+        self.process_outputs(left, right, &mut []);
+    }
+
+    /// Render the next buffer into the main `left` and `right`, and the extra stereo `outputs`
+    /// the host has room for, applying the midi events queued for it.
+    ///
+    /// The extra outputs carry the voices routed to them dry, after only the master gain and pan,
+    /// for processing in the host. Voices routed to an output beyond them play through the main
+    /// one.
+    ///
+    /// Nothing here allocates or waits on a lock, so it can't hold up the audio thread. The tests'
+    /// allocator checks the first part.
+    pub fn process_outputs(&mut self, left: &mut [f32], right: &mut [f32], outputs: &mut [(&mut [f32], &mut [f32])]) {
+        let len = outputs.len().min(NUM_OUTPUTS - 1);
+        let outputs = &mut outputs[..len];
+        // a part adds itself to the outputs it's given, which the synth playing it has cleared
+        if !self.is_part {
+            for (left, right) in outputs.iter_mut() {
+                left.fill(0.0);
+                right.fill(0.0);
+            }
+        }
+        if self.bypassed {
+            left.fill(0.0);
+            right.fill(0.0);
+            self.event_queue.clear();
+            return;
+        }
+        // decaying tails would otherwise become denormal, which is slow on x86
+        let _flush_denormals = FlushDenormals::new();
+        let samples = outputs.iter()
+            .fold(left.len().min(right.len()), |samples, (left, right)| samples.min(left.len()).min(right.len()));
+        let time_per_sample = self.time_per_sample;
+        let mut next_event = 0;
+
+        let multitimbral = self.params.multitimbral();
+        if multitimbral && self.parts.is_empty() {
+            self.parts = self.part_builder.take(self.sample_rate, &self.params).unwrap_or_default();
+        }
+        // switching multi-timbral mode off leaves nothing ringing or stuck in the parts
+        if !multitimbral && self.multitimbral {
+            self.parts.iter_mut().for_each(Synth::reset);
+        }
+        self.multitimbral = multitimbral;
+
+        self.update_smoothed_params();
+        if let Some(tuning) = self.mts.as_ref().and_then(MtsClient::tuning).or_else(|| self.params.try_tuning()) {
+            self.tuning = tuning;
+        }
+        match self.params.try_wavetable() {
+            Some(wavetable) if !Arc::ptr_eq(&wavetable, &self.wavetable) => self.wavetable = wavetable,
+            _ => {}
+        }
+        let mut params = self.voice_params();
+        let harmonics = self.params.harmonics();
+        if params.waveform == Waveform::Additive && harmonics != self.harmonics {
+            self.additive.set_harmonics(&harmonics);
+            self.harmonics = harmonics;
+        }
+        let bend_range = f64::from(self.params.bend_range.get());
+        let stereo_mode = self.params.stereo_mode();
+        let dc_blocker = self.params.dc_blocker();
+        let saturation = self.params.saturation();
+        self.effects.update(&self.params, &self.transport);
+        let vibrato_rate = f64::from(self.params.vibrato_rate.get());
+        let max_vibrato_depth = f64::from(self.params.vibrato_depth.get());
+        let aftertouch_vibrato = f64::from(self.params.aftertouch_vibrato.get());
+        let arp = self.params.arp();
+        let arp_params = self.params.arp_params();
+        let arp_step_time = arp_params.step_time(self.transport.tempo);
+        // switching the arpeggiator off releases whatever it was playing, and switching it on
+        // releases the keys that were already down
+        if !arp && self.arpeggiator.is_active() {
+            if let Some(note) = self.arpeggiator.reset() {
+                self.note_off(note);
+            }
+        }
+        if arp && !self.arp {
+            self.release_held_keys();
+        }
+        self.arp = arp;
+        let lfo_params = self.params.lfos.each_ref().map(|lfo| lfo.snapshot());
+        let lfo_rates = lfo_params.map(|settings| settings.rate_at(self.transport.tempo));
+        let random_params = self.params.random();
+        let random_rate = random_params.rate_at(self.transport.tempo);
+
+        // voices are rendered a block at a time, so each block ends where the next event lands
+        let mut block_start = 0;
+        // the arpeggiator's notes for the start of the next block, if they ended this one
+        let mut arp_events = None;
+        while block_start < samples {
+            while let Some(&(frame, data)) = self.event_queue.get(next_event) {
+                if frame > block_start {
+                    break;
+                }
+                self.process_midi_event(frame, data);
+                next_event += 1;
+            }
+            let mut block_end = (block_start + BLOCK_SIZE).min(samples);
+            if let Some(&(frame, _)) = self.event_queue.get(next_event) {
+                block_end = block_end.min(frame);
+            }
+            if arp {
+                let events = arp_events.take()
+                    .unwrap_or_else(|| self.arpeggiator.next(&arp_params, arp_step_time, time_per_sample));
+                self.play_arp_events(events);
+                // look ahead through the block, ending it early at the arpeggiator's next notes
+                for sample_idx in block_start + 1..block_end {
+                    let events = self.arpeggiator.next(&arp_params, arp_step_time, time_per_sample);
+                    if events != ArpEvents::default() {
+                        arp_events = Some(events);
+                        block_end = sample_idx;
+                        break;
+                    }
+                }
+            }
+            let len = block_end - block_start;
+            let block_time = len as f64 * time_per_sample;
+
+            // the modulation shared by every voice, as of the end of the block
+            let vibrato_amount = (self.mod_wheel + self.aftertouch * aftertouch_vibrato).min(1.0);
+            let vibrato = self.vibrato.advance(LfoShape::Sine, vibrato_rate, block_time)
+                * max_vibrato_depth * vibrato_amount;
+            let semitones = self.pitch_bend.advance(len) * bend_range + vibrato / 100.0;
+            let mut modulation = Modulation {
+                pitch_ratio: (semitones / 12.0).exp2(),
+                lfos: [0.0; NUM_LFOS],
+                random: self.random.advance(random_rate, random_params.smooth, block_time),
+                mod_wheel: self.mod_wheel,
+                aftertouch: self.aftertouch,
+                timbre: self.timbre,
+            };
+            let lfos = self.lfos.iter_mut().zip(&lfo_params).zip(&lfo_rates);
+            for (((lfo, settings), &rate), output) in lfos.zip(&mut modulation.lfos) {
+                *output = lfo.advance(settings.shape, rate, block_time);
+            }
+            params.cutoff = self.cutoff.advance(len);
+            // the unison layout is only worth recalculating while it's moving
+            if self.unison_detune.is_smoothing() || self.unison_spread.is_smoothing() {
+                let (detune, spread) = (self.unison_detune.advance(len), self.unison_spread.advance(len));
+                params.unison = Unison::new(params.unison.count, detune, spread);
+            }
+
+            let block = &mut self.block;
+            let [voice_left, voice_right] = &mut block.voice;
+            for [mix_left, mix_right] in std::iter::once(&mut block.mix).chain(&mut block.outputs[..outputs.len()]) {
+                mix_left[..len].fill(0.0);
+                mix_right[..len].fill(0.0);
+            }
+            let wavetable = if params.waveform == Waveform::Additive { &self.additive } else { &self.wavetable };
+            for voice in &mut self.voices {
+                let (left, right) = (&mut voice_left[..len], &mut voice_right[..len]);
+                voice.render(&params, &modulation, wavetable, time_per_sample, left, right);
+                let [mix_left, mix_right] = match voice.output {
+                    output @ 1.. if output <= outputs.len() => &mut block.outputs[output - 1],
+                    _ => &mut block.mix,
+                };
+                simd::mix(&mut mix_left[..len], left);
+                simd::mix(&mut mix_right[..len], right);
+            }
+
+            // the output stage's settings, from the start of the block to the end
+            let output_stage = [&mut self.stereo_width, &mut self.master_pan, &mut self.master_gain, &mut self.drive]
+                .map(|param| (param.get(), param.advance(len)));
+            for idx in 0..len {
+                let (output_sample_left, output_sample_right) =
+                    self.effects.process(block.mix[0][idx], block.mix[1][idx]);
+
+                // the output stage: widen or narrow the stereo image, then balance, then the level,
+                // then take out any DC offset before it eats into the saturation's headroom
+                let t = (idx + 1) as f64 / len as f64;
+                let [width, pan, gain, drive] = output_stage.map(|(from, to)| lerp(from, to, t));
+                let (left_gain, right_gain) = ((1.0 - pan).min(1.0) * gain, (1.0 + pan).min(1.0) * gain);
+                for ((output_left, output_right), [mix_left, mix_right]) in outputs.iter_mut().zip(&block.outputs) {
+                    output_left[block_start + idx] += (mix_left[idx] * left_gain) as f32;
+                    output_right[block_start + idx] += (mix_right[idx] * right_gain) as f32;
+                }
+                let (output_sample_left, output_sample_right) =
+                    self.stereo.process(stereo_mode, width, output_sample_left, output_sample_right);
+                let mut output = [output_sample_left * left_gain * drive, output_sample_right * right_gain * drive];
+                if dc_blocker {
+                    for (blocker, sample) in self.dc_blockers.iter_mut().zip(&mut output) {
+                        *sample = blocker.process(*sample);
+                    }
+                }
+                let (output_sample_left, output_sample_right) =
+                    self.saturator.process(saturation, output[0], output[1]);
+
+                if self.is_part {
+                    left[block_start + idx] += output_sample_left as f32;
+                    right[block_start + idx] += output_sample_right as f32;
+                } else {
+                    left[block_start + idx] = output_sample_left as f32;
+                    right[block_start + idx] = output_sample_right as f32;
+                }
+            }
+            block_start = block_end;
+        }
+
+        if multitimbral {
+            for part in &mut self.parts {
+                part.process_part(&mut left[..samples], &mut right[..samples], outputs);
+            }
+        }
+
+        // a bug in the DSP mustn't send NaNs or infinities on to the host, and its speakers. the
+        // check is a pass over the buffer, so only debug builds make it. the parts leave it, and
+        // the meter, to the synth playing them.
+        if !self.is_part {
+            let finite = |left: &[f32], right: &[f32]| left.iter().chain(right).all(|sample| sample.is_finite());
+            let all_finite = || finite(left, right) && outputs.iter().all(|(left, right)| finite(left, right));
+            if cfg!(debug_assertions) && !all_finite() {
+                error!("the output went non-finite, so it was silenced and the voices and effects flushed");
+                left.fill(0.0);
+                right.fill(0.0);
+                for (left, right) in outputs.iter_mut() {
+                    left.fill(0.0);
+                    right.fill(0.0);
+                }
+                self.flush();
+            }
+
+            self.meter.update(left, right);
+        }
+
+        // any events that claimed to be beyond the end of the buffer take effect now, and the
+        // parts' at the start of the next
+        for idx in next_event..self.event_queue.len() {
+            let (_, data) = self.event_queue[idx];
+            self.process_midi_event(0, data);
+        }
+        self.event_queue.clear();
+
+        self.voices.retain(|voice| !voice.is_finished());
+    }
+
+    /// Mix a part into the next buffer. Once it's been silent for longer than its tail, it stops
+    /// running until there's midi for it, so the parts that aren't in use cost nothing.
+    fn process_part(&mut self, left: &mut [f32], right: &mut [f32], outputs: &mut [(&mut [f32], &mut [f32])]) {
+        if self.voices.is_empty() && self.event_queue.is_empty() && self.idle_time > self.tail_time() {
+            return;
+        }
+        self.process_outputs(left, right, outputs);
+        self.idle_time = if self.voices.is_empty() {
+            self.idle_time + left.len() as f64 * self.time_per_sample
+        } else {
+            0.0
+        };
+    }
+}
+
+impl Default for Synth {
+    fn default() -> Synth {
+        Synth::with_params(Arc::new(ReplicantParameters::default()))
+    }
+}
+
+impl Synth {
+    /// A synth playing `params`, which like `Synth::default()` neither follows MTS-ESP nor
+    /// switches presets.
+    fn with_params(params: Arc<ReplicantParameters>) -> Synth {
+        let time_per_sample = 1.0 / 44100.0;
+        let smoothed = |value: f64| SmoothedParam::new(value, PARAM_SMOOTHING, time_per_sample);
+
+        Synth {
+            transport: Transport::default(),
+            sample_rate: 44100.0,
+            time_per_sample,
+            voices: Vec::with_capacity(MAX_VOICES),
+            panner: VoicePanner::new(),
+            router: OutputRouter::default(),
+            held_notes: Vec::with_capacity(128),
+            sustain_pedal: false,
+            bypassed: false,
+            arpeggiator: Arpeggiator::default(),
+            arp: false,
+            mod_wheel: 0.0,
+            aftertouch: 0.0,
+            timbre: 0.0,
+            channel_expression: [Expression::new(time_per_sample); NUM_CHANNELS],
+            tuning: params.try_tuning().unwrap_or_default(),
+            mts: None,
+            wavetable: params.try_wavetable().unwrap_or_else(Wavetable::basic),
+            additive: Wavetable::additive(&params.harmonics()),
+            harmonics: params.harmonics(),
+            vibrato: Lfo::new(1),
+            // different seeds, so the two sample and holds don't move together
+            lfos: [Lfo::new(0x1234_5678), Lfo::new(0x8765_4321)],
+            random: RandomSource::new(0x2468_ACE1),
+            pitch_bend: SmoothedParam::new(0.0, PITCH_BEND_SMOOTHING, time_per_sample),
+            cutoff: smoothed(f64::from(params.cutoff.get())),
+            unison_detune: smoothed(f64::from(params.unison_detune.get())),
+            unison_spread: smoothed(f64::from(params.unison_spread.get())),
+            master_gain: smoothed(params.master_gain()),
+            master_pan: smoothed(f64::from(params.master_pan.get())),
+            stereo_width: smoothed(f64::from(params.stereo_width.get())),
+            drive: smoothed(params.drive()),
+            stereo: Widener::new(time_per_sample),
+            dc_blockers: [DcBlocker::new(time_per_sample), DcBlocker::new(time_per_sample)],
+            saturator: Saturator::new(time_per_sample),
+            effects: EffectsChain::new(time_per_sample),
+            running_status: None,
+            cc_decoder: CcDecoder::default(),
+            event_queue: Vec::with_capacity(EVENT_QUEUE_CAPACITY),
+            block: Block::default(),
+            automated: Vec::with_capacity(params::NUM_PARAMS),
+            parts: Vec::new(),
+            part_builder: Arc::default(),
+            multitimbral: false,
+            is_part: false,
+            idle_time: 0.0,
+            params,
+            meter: Arc::new(Meter::new()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::alloc_guard;
+    use crate::envelope::ADSRPhase;
+    use crate::loader::{wait_for, LoadStatus};
+    use crate::modulation::{ModDestination, ModSource};
+    use crate::oversampling::Oversampling;
+    use crate::saturation::Saturation;
+    use crate::stereo::StereoMode;
+    use crate::tuning::TuningFiles;
+    use crate::voice::{GlideCurve, GlideMode, NotePriority, OutputRouting, PanMode, Retrigger};
+    use crate::{db_to_gain, meter, midi_pitch_to_freq, oscillator, render_midi_to_buffer, tempo, TAU};
+
+    const NOTE_ON: u8 = 144;
+    const NOTE_OFF: u8 = 128;
+    const CONTROL_CHANGE: u8 = 176;
+    const POLY_PRESSURE: u8 = 160;
+    const CHANNEL_PRESSURE: u8 = 208;
+
+    /// A synth without the DC blocker, for tests of exactly what the voices and effects put out,
+    /// which its slowly settling output would blur.
+    fn raw_synth() -> Synth {
+        let synth = Synth::default();
+        synth.params.dc_blocker.set(0.0);
+        synth
+    }
+
+    #[test]
+    fn test_sample_rate_change_keeps_envelope_timing() {
+        let mut synth = Synth::default();
+        synth.params.attack.set(0.1);
+        synth.set_sample_rate(44100.0);
+
+        // 50ms at 44.1k, then switch rates mid-note
+        synth.render(&[(0, [NOTE_ON, 60, 100])], 2205);
+        synth.set_sample_rate(48000.0);
+
+        // the remaining 50ms of attack should take 2400 samples at 48k
+        synth.render(&[], 2390);
+        assert_eq!(synth.voices[0].envelope.current_phase, ADSRPhase::Attack);
+        synth.render(&[], 20);
+        assert_eq!(synth.voices[0].envelope.current_phase, ADSRPhase::Decay);
+    }
+
+    #[test]
+    fn test_pitch_carries_over_a_sample_rate_change() {
+        let mut synth = raw_synth();
+        synth.params.waveform.set(oscillator::Waveform::Sine as usize as f32);
+        synth.params.unison.set(1.0);
+        synth.params.cutoff.set(5000.0);
+        synth.set_sample_rate(22050.0);
+        synth.render(&[(0, [NOTE_ON, 81, 127])], 2205);
+
+        // a sine moves no further between samples straight after the change than it does later,
+        // as it would if the phase stepped on at the old rate for a block
+        synth.set_sample_rate(44100.0);
+        let (left, _) = synth.render(&[], 4410);
+        let steepest = |samples: &[f32]| samples.windows(2).map(|w| (w[1] - w[0]).abs()).fold(0.0, f32::max);
+        assert!(steepest(&left[..BLOCK_SIZE]) <= steepest(&left[2205..]) * 1.05);
+    }
+
+    #[test]
+    fn test_setting_the_same_sample_rate_keeps_the_effects() {
+        let mut synth = Synth::default();
+        synth.params.delay_mix.set(1.0);
+        synth.params.delay_time.set(0.1);
+        synth.render(&[(0, [NOTE_ON, 60, 127]), (100, [NOTE_OFF, 60, 0])], 2205);
+
+        // hosts can set the rate again whenever they set up processing, which mustn't cut the
+        // echoes off
+        synth.set_sample_rate(44100.0);
+        let (left, _) = synth.render(&[], 8820);
+        assert!(peak(&left[4410..]) > 0.01);
+    }
+
+    #[test]
+    fn test_zero_sample_rate_is_ignored() {
+        let mut synth = Synth::default();
+        synth.set_sample_rate(0.0);
+        assert_eq!(synth.sample_rate, 44100.0);
+
+        let (left, right) = synth.render(&[(0, [NOTE_ON, 60, 100])], 64);
+        assert!(left.iter().chain(right.iter()).all(|s| s.is_finite()));
+    }
+
+    #[test]
+    fn test_velocity_shortens_attack() {
+        let attack_level = |velocity: u8| {
+            let mut synth = Synth::default();
+            synth.params.attack.set(0.1);
+            synth.params.vel_to_attack.set(1.0);
+            synth.params.unison.set(1.0);
+
+            // envelope level 50ms in
+            synth.render(&[(0, [NOTE_ON, 60, velocity])], 2205);
+            synth.voices[0].alpha(&synth.voice_params())
+        };
+
+        let (soft, hard) = (attack_level(20), attack_level(127));
+        assert!(soft < 0.9);
+        assert!(hard > soft);
+    }
+
+    fn zero_crossing_freq(samples: &[f32], sample_rate: f64) -> f64 {
+        let crossings = samples.windows(2).filter(|w| w[0] < 0.0 && w[1] >= 0.0).count();
+        crossings as f64 * sample_rate / samples.len() as f64
+    }
+
+    fn peak(samples: &[f32]) -> f32 {
+        samples.iter().fold(0.0, |peak, s| peak.max(s.abs()))
+    }
+
+    #[test]
+    fn test_render_middle_c() {
+        let mut synth = raw_synth();
+        synth.params.attack.set(0.1);
+        synth.params.decay.set(0.1);
+        synth.params.sustain.set(0.5);
+        synth.params.release.set(0.2);
+        synth.params.unison.set(1.0);
+
+        // one second, releasing at 0.6s
+        let (left, right) = synth.render(&[(0, [NOTE_ON, 60, 127]), (26460, [NOTE_OFF, 60, 0])], 44100);
+        assert_eq!(left, right);
+
+        let freq = zero_crossing_freq(&left[13230..26460], 44100.0);
+        assert!((freq - midi_pitch_to_freq(60.0)).abs() < 5.0, "fundamental was {}", freq);
+
+        // 10ms windows of the amplitude contour
+        let level_at = |seconds: f64| {
+            let start = (seconds * 44100.0) as usize;
+            peak(&left[start..start + 441])
+        };
+        assert!(level_at(0.02) < level_at(0.05));
+        assert!(level_at(0.05) < level_at(0.09));
+        assert!(level_at(0.09) > 0.9);
+        assert!((level_at(0.4) - 0.5).abs() < 0.01);
+        assert!(level_at(0.7) < level_at(0.62));
+        assert_eq!(level_at(0.9), 0.0);
+    }
+
+    #[test]
+    fn test_chord_plays_all_notes() {
+        let mut synth = Synth::default();
+        let chord = [(0, [NOTE_ON, 60, 100]), (0, [NOTE_ON, 64, 100]), (0, [NOTE_ON, 67, 100])];
+        synth.render(&chord, 64);
+        let notes: Vec<u8> = synth.voices.iter().map(|voice| voice.note).collect();
+        assert_eq!(notes, vec![60, 64, 67]);
+    }
+
+    #[test]
+    fn test_voice_count_is_limited() {
+        let mut synth = Synth::default();
+        synth.params.polyphony.set(2.0);
+        let notes = [(0, [NOTE_ON, 60, 100]), (1, [NOTE_ON, 64, 100]), (2, [NOTE_ON, 67, 100])];
+        synth.render(&notes, 64);
+
+        // the oldest note was stolen, and is fading out
+        assert!(synth.voices[0].is_stolen());
+        let notes: Vec<u8> = synth.voices.iter().map(|voice| voice.note).collect();
+        assert_eq!(notes, vec![60, 64, 67]);
+
+        // 10ms later it's gone
+        synth.render(&[], 441);
+        let notes: Vec<u8> = synth.voices.iter().map(|voice| voice.note).collect();
+        assert_eq!(notes, vec![64, 67]);
+    }
+
+    fn stolen_note(policy: StealPolicy, notes: &[(u32, [u8; 3])]) -> u8 {
+        let mut synth = Synth::default();
+        synth.params.polyphony.set(3.0);
+        synth.params.attack.set(1.0);
+        synth.params.steal_policy.set(StealPolicy::ALL.iter().position(|&p| p == policy).unwrap() as f32);
+        synth.render(notes, 1000);
+        synth.voices.iter().find(|voice| voice.is_stolen()).unwrap().note
+    }
+
+    #[test]
+    fn test_steal_policies() {
+        // the fourth note needs a voice. note 64 is played twice, so the first 64 is the oldest
+        // voice, and the quietest is the latest note, still early in its attack.
+        let notes = [
+            (0, [NOTE_ON, 64, 100]),
+            (100, [NOTE_ON, 60, 100]),
+            (200, [NOTE_ON, 67, 100]),
+            (900, [NOTE_ON, 64, 100]),
+        ];
+        assert_eq!(stolen_note(StealPolicy::Oldest, &notes), 64);
+        assert_eq!(stolen_note(StealPolicy::Quietest, &notes), 67);
+
+        let notes = [
+            (0, [NOTE_ON, 60, 100]),
+            (100, [NOTE_ON, 64, 100]),
+            (200, [NOTE_ON, 67, 100]),
+            (900, [NOTE_ON, 64, 100]),
+        ];
+        assert_eq!(stolen_note(StealPolicy::Oldest, &notes), 60);
+        assert_eq!(stolen_note(StealPolicy::SameNoteFirst, &notes), 64);
+    }
+
+    #[test]
+    fn test_stolen_voice_fades_out() {
+        let mut synth = Synth::default();
+        synth.params.polyphony.set(2.0);
+        synth.params.unison.set(1.0);
+        let (left, _) = synth.render(&[
+            (0, [NOTE_ON, 69, 127]),
+            (0, [NOTE_ON, 57, 127]),
+            (1000, [NOTE_ON, 81, 127]),
+        ], 1500);
+
+        // no sample-to-sample jump bigger than the sines' own slopes allow
+        let max_step = left.windows(2).map(|w| (w[1] - w[0]).abs()).fold(0.0, f32::max);
+        assert!(max_step < 0.2, "{}", max_step);
+    }
+
+    #[test]
+    fn test_released_voices_are_dropped() {
+        let mut synth = Synth::default();
+        synth.params.release.set(0.01);
+        synth.render(&[(0, [NOTE_ON, 60, 100]), (0, [NOTE_ON, 64, 100]), (10, [NOTE_OFF, 60, 0])], 64);
+        assert_eq!(synth.voices.len(), 2);
+
+        // well past the 10ms release
+        synth.render(&[], 882);
+        let notes: Vec<u8> = synth.voices.iter().map(|voice| voice.note).collect();
+        assert_eq!(notes, vec![64]);
+    }
+
+    #[test]
+    fn test_events_are_sample_accurate() {
+        let mut synth = raw_synth();
+        let (left, _) = synth.render(&[(100, [NOTE_ON, 60, 100]), (200, [NOTE_OFF, 60, 0])], 300);
+        assert!(left[..100].iter().all(|&s| s == 0.0));
+        assert!(left[100..110].iter().any(|&s| s != 0.0));
+
+        // the default release is half a millisecond, ~22 samples, and the amp envelope's worked out
+        // for every sample, so it's silent from the one it finishes on
+        assert!(left[200..210].iter().any(|&s| s != 0.0));
+        assert!(left[223..].iter().all(|&s| s == 0.0));
+    }
+
+    #[test]
+    fn test_velocity_scales_level() {
+        let level = |velocity: u8| {
+            let mut synth = Synth::default();
+            // the limiter leaves anything under full scale alone, where soft clipping would round
+            // off the loudest peaks
+            synth.params.saturation.set(Saturation::Limiter as usize as f32);
+            let (left, _) = synth.render(&[(0, [NOTE_ON, 69, velocity])], 441);
+            peak(&left[200..])
+        };
+
+        assert!((level(127) - 1.0).abs() < 0.01);
+        assert!((level(64) - 0.5).abs() < 0.01);
+    }
+
+
+    #[test]
+    fn test_pitch_bend_range() {
+        let mut synth = Synth::default();
+        synth.params.unison.set(1.0);
+        synth.params.bend_range.set(7.0);
+
+        // bend fully up, a fifth above A4
+        let (left, _) = synth.render(&[(0, [224, 127, 127]), (0, [NOTE_ON, 69, 127])], 44100);
+        let freq = zero_crossing_freq(&left[4410..], 44100.0);
+        assert!((freq - 440.0 * 1.5).abs() < 3.0, "bent frequency was {}", freq);
+    }
+
+    #[test]
+    fn test_reference_pitch() {
+        let mut synth = Synth::default();
+        synth.params.unison.set(1.0);
+        synth.params.reference_pitch.set(432.0);
+
+        let (left, _) = synth.render(&[(0, [NOTE_ON, 69, 127])], 44100);
+        let freq = zero_crossing_freq(&left[4410..], 44100.0);
+        assert!((freq - 432.0).abs() < 3.0, "A4 was {}", freq);
+    }
+
+    #[test]
+    fn test_drift_stays_close_to_pitch() {
+        let render = |drift: f32| {
+            let mut synth = Synth::default();
+            synth.params.unison.set(1.0);
+            synth.params.drift.set(drift);
+            synth.render(&[(0, [NOTE_ON, 69, 127])], 88200).0
+        };
+        let (steady, drifting) = (render(0.0), render(1.0));
+        assert_ne!(steady, drifting);
+        // at most 10 cents, which is about 2.5Hz at A4
+        let freq = zero_crossing_freq(&drifting[4410..], 44100.0);
+        assert!((freq - 440.0).abs() < 3.5, "drifting A4 was {}", freq);
+    }
+
+    #[test]
+    fn test_scala_tuning() {
+        let dir = std::env::temp_dir().join(format!("replicant-tuning-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // 19 equal steps to the octave, on the white keys only
+        let scale = dir.join("19edo.scl");
+        let steps = (1..=19).map(|step| format!("{:.5}\n", step as f64 * 1200.0 / 19.0)).collect::<String>();
+        std::fs::write(&scale, format!("19-EDO\n19\n{}", steps)).unwrap();
+        let mapping = dir.join("white.kbm");
+        std::fs::write(&mapping, "12\n0\n127\n60\n60\n261.625565\n7\n0\nx\n1\nx\n2\n3\nx\n4\nx\n5\nx\n6\n").unwrap();
+
+        let mut synth = Synth::default();
+        synth.params.unison.set(1.0);
+        let loaded = synth.params.set_tuning_files(Some(TuningFiles { scale, mapping: Some(mapping) }));
+        std::fs::remove_dir_all(&dir).unwrap();
+        loaded.unwrap();
+
+        // D is one step above C
+        let (left, _) = synth.render(&[(0, [NOTE_ON, 62, 127])], 44100);
+        let freq = zero_crossing_freq(&left[4410..], 44100.0);
+        let expected = 261.625565 * (1.0f64 / 19.0).exp2();
+        assert!((freq - expected).abs() < 3.0, "D was {}", freq);
+
+        // and the black keys don't play at all
+        synth.render(&[(0, [NOTE_OFF, 62, 0]), (0, [NOTE_ON, 61, 127])], 4410);
+        assert!(synth.voices.iter().all(|voice| voice.note != 61));
+    }
+
+    #[test]
+    fn test_mpe_pitch_bend_is_per_note() {
+        let render = |mpe: bool, events: &[(u32, [u8; 3])]| {
+            let mut synth = Synth::default();
+            synth.params.unison.set(1.0);
+            synth.params.mpe.set(if mpe { 1.0 } else { 0.0 });
+            synth.render(events, 44100).0
+        };
+        // channel 2 bent a quarter of the way up the default 48 semitones, before its note
+        let bent = [(0, [0xE1, 0, 80]), (0, [0x91, 69, 127])];
+        let left = render(true, &bent);
+        let freq = zero_crossing_freq(&left[4410..], 44100.0);
+        assert!((freq - 880.0).abs() < 5.0, "bent frequency was {}", freq);
+
+        // other channels aren't bent
+        let left = render(true, &[(0, [0xE1, 0, 80]), (0, [0x92, 69, 127])]);
+        let freq = zero_crossing_freq(&left[4410..], 44100.0);
+        assert!((freq - 440.0).abs() < 3.0, "unbent frequency was {}", freq);
+
+        // note offs only release the note on their own channel
+        let left = render(true, &[(0, [0x91, 69, 127]), (0, [0x92, 69, 127]), (0, [0x81, 69, 0])]);
+        assert!(peak(&left[22050..]) > 0.3);
+
+        // without MPE, channel 2 is bent by the shared bend range like any other
+        let left = render(false, &bent);
+        let freq = zero_crossing_freq(&left[4410..], 44100.0);
+        let expected = 440.0 * (0.5f64 / 12.0).exp2();
+        assert!((freq - expected).abs() < 3.0, "frequency without MPE was {}", freq);
+    }
+
+    #[test]
+    fn test_note_expression_is_per_note() {
+        let mut synth = Synth::default();
+        synth.params.unison.set(1.0);
+        synth.render(&[(0, [NOTE_ON, 69, 127]), (0, [NOTE_ON, 57, 127])], 1);
+        synth.note_expression(69, NoteExpression::Tuning(12.0));
+        synth.note_expression(69, NoteExpression::Pressure(2.0));
+        let expression = synth.voices[0].expression.unwrap();
+        assert_eq!((expression.tuning, expression.pressure), (12.0, 1.0));
+        assert!(synth.voices[1].expression.is_none());
+
+        // released notes keep what they had
+        synth.render(&[(0, [NOTE_OFF, 69, 0])], 1);
+        synth.note_expression(69, NoteExpression::Tuning(0.0));
+        assert_eq!(synth.voices[0].expression.unwrap().tuning, 12.0);
+    }
+
+    #[test]
+    fn test_midi_learn() {
+        let mut synth = Synth::default();
+        synth.params.midi_map.learn(Some(20));
+        synth.render(&[(0, [CONTROL_CHANGE, MOD_WHEEL, 127])], 1);
+        assert_eq!(synth.params.resonance.get(), 1.0);
+        // the mapped controller no longer moves the mod wheel
+        assert_eq!(synth.mod_wheel, 0.0);
+
+        // the host hears about each moved parameter once, with its latest value
+        synth.render(&[(0, [CONTROL_CHANGE, MOD_WHEEL, 64]), (0, [CONTROL_CHANGE, MOD_WHEEL, 0])], 1);
+        assert_eq!(synth.params.resonance.get(), 0.0);
+        assert_eq!(synth.take_automated().collect::<Vec<_>>(), [(20, 0.0)]);
+        assert_eq!(synth.take_automated().count(), 0);
+    }
+
+    #[test]
+    fn test_high_resolution_controllers() {
+        let mut synth = Synth::default();
+        synth.params.midi_map.learn(Some(20));
+        // a 14-bit mod wheel, with its least significant half on CC 33
+        synth.render(&[(0, [CONTROL_CHANGE, MOD_WHEEL, 64]), (0, [CONTROL_CHANGE, MOD_WHEEL + 32, 1])], 1);
+        assert!((synth.params.resonance.get() - 8193.0 / 16383.0).abs() < 1e-6);
+        assert_eq!(synth.params.midi_map.mappings(), [(MOD_WHEEL, 20)]);
+
+        // NRPN 19 is the cutoff, at the host index of the same number
+        synth.take_automated().for_each(drop);
+        let cc = |controller, value| (0, [CONTROL_CHANGE, controller, value]);
+        synth.render(&[cc(99, 0), cc(98, 19), cc(6, 127), cc(38, 127)], 1);
+        assert_eq!(synth.params.get_parameter(19), 1.0);
+        assert_eq!(synth.take_automated().map(|(index, _)| index).collect::<Vec<_>>(), [19]);
+        synth.render(&[cc(6, 64), cc(38, 0)], 1);
+        assert!((synth.params.get_parameter(19) - 8192.0 / 16383.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_program_change_switches_preset() {
+        let mut synth = Synth::default();
+        // without a loader thread, as when rendering, there's nothing to make the switch
+        synth.render(&[(0, [0xC0, 2, 0])], 1);
+        assert_eq!(synth.params.get_preset_num(), 0);
+
+        ReplicantParameters::spawn_loader_thread(&synth.params, Arc::downgrade(&synth.part_builder));
+        synth.render(&[(0, [0xC0, 2, 0])], 1);
+        wait_for(|| synth.params.take_preset_switched());
+        assert_eq!(synth.params.get_preset_num(), 2);
+        assert!((synth.params.cutoff.get() - 300.0).abs() < 0.1);
+
+        // slots past the end of the bank are ignored
+        assert!(!synth.params.switch_preset(100));
+        assert_eq!(synth.params.get_preset_num(), 2);
+
+        // the loader does its loads in order, so had the first been sent it'd be done by the second
+        synth.params.program_change.set(0.0);
+        synth.render(&[(0, [0xC0, 0, 0])], 1);
+        synth.params.program_change.set(1.0);
+        synth.render(&[(0, [0xC0, 3, 0])], 1);
+        wait_for(|| synth.params.take_preset_switched());
+        assert_eq!(synth.params.get_preset_num(), 3);
+    }
+
+    #[test]
+    fn test_arpeggiator_plays_held_notes_in_turn() {
+        let mut synth = Synth::default();
+        synth.params.arp.set(1.0);
+        synth.params.arp_sync.set(0.0);
+        synth.params.arp_rate.set(10.0);
+        let chord = [(0, [NOTE_ON, 60, 100]), (0, [NOTE_ON, 64, 100]), (0, [NOTE_ON, 67, 100])];
+        let mut played = Vec::new();
+        synth.render(&chord, 1);
+        for _ in 0..6 {
+            played.extend(synth.voices.iter().filter(|voice| voice.is_held()).map(|voice| voice.note));
+            synth.render(&[], 4410);
+        }
+        assert_eq!(played, [60, 64, 67, 60, 64, 67]);
+
+        // letting go of the keys stops it, and so does switching it off
+        synth.render(&[(0, [NOTE_OFF, 60, 0]), (0, [NOTE_OFF, 64, 0])], 4410);
+        assert!(synth.voices.iter().filter(|voice| voice.is_held()).all(|voice| voice.note == 67));
+        synth.params.arp.set(0.0);
+        synth.render(&[], 1);
+        assert!(synth.voices.iter().all(|voice| !voice.is_held()));
+    }
+
+    #[test]
+    fn test_switching_arpeggiator_on_releases_held_keys() {
+        let mut synth = Synth::default();
+        synth.render(&[(0, [NOTE_ON, 60, 100])], 1);
+        synth.params.set_parameter(99, 1.0);
+        let (left, _) = synth.render(&[(0, [NOTE_OFF, 60, 0])], 44100);
+        assert!(synth.voices.iter().all(|voice| !voice.is_held()));
+        assert!(left[22050..].iter().all(|sample| sample.abs() < 1e-3));
+    }
+
+    #[test]
+    fn test_note_on_with_zero_velocity_is_note_off() {
+        let mut synth = Synth::default();
+        synth.render(&[(0, [0x93, 60, 127]), (10, [0x93, 60, 0])], 100);
+        assert!(synth.voices.iter().all(|voice| !voice.is_held()));
+
+        synth.params.mpe.set(1.0);
+        synth.render(&[(0, [0x91, 62, 127]), (10, [0x91, 62, 0])], 100);
+        assert!(synth.voices.iter().all(|voice| !voice.is_held()));
+    }
+
+    #[test]
+    fn test_running_status() {
+        let mut synth = Synth::default();
+        // two notes on and one off, sent with a single status byte and a clock tick between them
+        let events = [(0, [NOTE_ON, 60, 127]), (0, [0xF8, 0, 0]), (0, [64, 127, 0]), (10, [60, 0, 0])];
+        synth.render(&events, 100);
+        let held: Vec<_> = synth.voices.iter().filter(|voice| voice.is_held()).map(|voice| voice.note).collect();
+        assert_eq!(held, [64]);
+
+        // a system common message ends it
+        synth.render(&[(0, [0xF2, 0, 0]), (0, [67, 127, 0])], 1);
+        assert!(synth.voices.iter().all(|voice| voice.note != 67));
+    }
+
+    #[test]
+    fn test_key_and_velocity_zone() {
+        let mut synth = Synth::default();
+        synth.params.key_min.set(48.0);
+        synth.params.key_max.set(59.0);
+        synth.params.velocity_min.set(64.0);
+        let notes = [
+            (0, [NOTE_ON, 47, 100]),
+            (0, [NOTE_ON, 48, 100]),
+            (0, [NOTE_ON, 59, 64]),
+            (0, [NOTE_ON, 60, 100]),
+            (0, [NOTE_ON, 52, 63]),
+        ];
+        synth.render(&notes, 441);
+        assert_eq!(synth.voices.iter().map(|voice| voice.note).collect::<Vec<_>>(), [48, 59]);
+
+        // a note still held when the zone moves away from it can still be released
+        synth.params.key_min.set(60.0);
+        synth.params.key_max.set(72.0);
+        synth.render(&[(0, [NOTE_OFF, 48, 0])], 1);
+        assert!(!synth.voices.iter().find(|voice| voice.note == 48).unwrap().is_held());
+
+        // MPE notes are zoned too
+        synth.params.mpe.set(1.0);
+        synth.render(&[(0, [0x91, 40, 100]), (0, [0x92, 62, 100])], 1);
+        assert!(synth.voices.iter().all(|voice| voice.note != 40));
+        assert!(synth.voices.iter().any(|voice| voice.note == 62));
+    }
+
+    #[test]
+    fn test_midi_channel_filter() {
+        let render = |channel: f32| {
+            let mut synth = Synth::default();
+            synth.params.midi_channel.set(channel);
+            let notes = [(0, [0x90, 60, 127]), (0, [0x92, 64, 127]), (0, [0x9F, 67, 127])];
+            synth.render(&notes, 441);
+            synth.voices.iter().map(|voice| voice.note).collect::<Vec<_>>()
+        };
+        // omni plays every channel
+        assert_eq!(render(0.0), [60, 64, 67]);
+        assert_eq!(render(3.0), [64]);
+        assert_eq!(render(16.0), [67]);
+
+        // and the other channels' controllers are ignored too
+        let mut synth = Synth::default();
+        synth.params.midi_channel.set(1.0);
+        synth.render(&[(0, [0xB1, MOD_WHEEL, 127]), (0, [0xD1, 127, 0])], 1);
+        assert_eq!((synth.mod_wheel, synth.aftertouch), (0.0, 0.0));
+        synth.render(&[(0, [0xB0, MOD_WHEEL, 127])], 1);
+        assert_eq!(synth.mod_wheel, 1.0);
+    }
+
+    #[test]
+    fn test_mpe_pressure_is_per_note() {
+        let render = |events: &[(u32, [u8; 3])]| {
+            let mut synth = mod_synth(ModSource::Aftertouch, ModDestination::Amplitude);
+            synth.params.unison.set(1.0);
+            synth.params.mpe.set(1.0);
+            synth.render(events, 8820).0
+        };
+        // the note's own pressure sets its level, and channel pressure on channel 1 doesn't
+        let pressed = render(&[(0, [0xD0, 0, 0]), (0, [0xD1, 127, 0]), (0, [0x91, 69, 127])]);
+        assert!(peak(&pressed[4410..]) > 0.9);
+        let released = render(&[(0, [0xD0, 127, 0]), (0, [0x91, 69, 127]), (4410, [0xD1, 0, 0])]);
+        assert!(peak(&released[..4410]) == 0.0 && peak(&released[4410..]) == 0.0);
+        // notes played on channel 1 still follow channel pressure
+        let channel = render(&[(0, [0xD0, 127, 0]), (0, [0xD1, 0, 0]), (0, [NOTE_ON, 69, 127])]);
+        assert!(peak(&channel[4410..]) > 0.9);
+    }
+
+    #[test]
+    fn test_sustain_pedal_defers_note_off() {
+        let mut synth = Synth::default();
+        synth.render(&[
+            (0, [CONTROL_CHANGE, SUSTAIN_PEDAL, 127]),
+            (0, [NOTE_ON, 60, 100]),
+            (10, [NOTE_OFF, 60, 0]),
+        ], 4410);
+        assert!(synth.voices[0].is_held());
+
+        synth.render(&[(0, [CONTROL_CHANGE, SUSTAIN_PEDAL, 0])], 10);
+        assert!(!synth.voices[0].is_held());
+    }
+
+    #[test]
+    fn test_replayed_note_is_not_released_by_pedal() {
+        let mut synth = Synth::default();
+        synth.render(&[
+            (0, [CONTROL_CHANGE, SUSTAIN_PEDAL, 127]),
+            (0, [NOTE_ON, 60, 100]),
+            (10, [NOTE_OFF, 60, 0]),
+            (20, [NOTE_ON, 60, 100]),
+            (30, [CONTROL_CHANGE, SUSTAIN_PEDAL, 0]),
+        ], 40);
+
+        // the replayed note gets its own voice, and only the sustained one is released
+        assert_eq!(synth.voices.len(), 2);
+        assert!(!synth.voices[0].is_held());
+        assert!(synth.voices[1].is_held());
+    }
+
+    #[test]
+    fn test_mod_wheel_vibrato() {
+        let half_cycle_freqs = |mod_wheel: u8| {
+            let mut synth = Synth::default();
+            synth.params.unison.set(1.0);
+            synth.params.vibrato_rate.set(5.0);
+            synth.params.vibrato_depth.set(100.0);
+
+            let (left, _) = synth.render(&[(0, [CONTROL_CHANGE, MOD_WHEEL, mod_wheel]), (0, [NOTE_ON, 69, 127])], 8820);
+            (zero_crossing_freq(&left[..4410], 44100.0), zero_crossing_freq(&left[4410..], 44100.0))
+        };
+
+        // no vibrato without the mod wheel
+        let (first, second) = half_cycle_freqs(0);
+        assert!((first - second).abs() <= 10.0, "{} {}", first, second);
+
+        // the first half of the vibrato cycle is sharp, the second flat
+        let (first, second) = half_cycle_freqs(127);
+        assert!(first > second + 10.0, "{} {}", first, second);
+    }
+
+    #[test]
+    fn test_aftertouch_vibrato() {
+        let half_cycle_freqs = |aftertouch_vibrato: f32| {
+            let mut synth = Synth::default();
+            synth.params.unison.set(1.0);
+            synth.params.vibrato_rate.set(5.0);
+            synth.params.vibrato_depth.set(100.0);
+            synth.params.aftertouch_vibrato.set(aftertouch_vibrato);
+
+            let (left, _) = synth.render(&[(0, [CHANNEL_PRESSURE, 127, 0]), (0, [NOTE_ON, 69, 127])], 8820);
+            (zero_crossing_freq(&left[..4410], 44100.0), zero_crossing_freq(&left[4410..], 44100.0))
+        };
+
+        let (first, second) = half_cycle_freqs(0.0);
+        assert!((first - second).abs() <= 10.0, "{} {}", first, second);
+        let (first, second) = half_cycle_freqs(1.0);
+        assert!(first > second + 10.0, "{} {}", first, second);
+    }
+
+    #[test]
+    fn test_poly_aftertouch_is_per_note() {
+        let mut synth = mod_synth(ModSource::PolyAftertouch, ModDestination::Amplitude);
+        synth.params.unison.set(1.0);
+        let events = [(0, [NOTE_ON, 69, 127]), (0, [NOTE_ON, 76, 127]), (0, [POLY_PRESSURE, 69, 127])];
+        let (left, _) = synth.render(&events, 8820);
+
+        // only the pressed note is heard
+        let freq = zero_crossing_freq(&left[4410..], 44100.0);
+        assert!((freq - 440.0).abs() < 3.0, "frequency was {}", freq);
+    }
+
+    /// A synth with one modulation matrix slot routed at full depth.
+    fn mod_synth(source: ModSource, destination: ModDestination) -> Synth {
+        let synth = raw_synth();
+        let slot = &synth.params.mod_slots[0];
+        slot.source.set(source as usize as f32);
+        slot.destination.set(destination as usize as f32);
+        slot.depth.set(1.0);
+        synth
+    }
+
+    fn lfo_synth(shape: LfoShape, destination: ModDestination) -> Synth {
+        let synth = mod_synth(ModSource::Lfo1, destination);
+        synth.params.lfos[0].shape.set(shape as usize as f32);
+        synth.params.lfos[0].rate.set(5.0);
+        synth
+    }
+
+    #[test]
+    fn test_lfo_tremolo() {
+        let mut synth = lfo_synth(LfoShape::Square, ModDestination::Amplitude);
+        let (left, _) = synth.render(&[(0, [NOTE_ON, 69, 127])], 8820);
+
+        // full level for the first half of the cycle, silent for the second
+        assert!(peak(&left[..4000]) > 0.9);
+        assert_eq!(peak(&left[4500..8500]), 0.0);
+    }
+
+    #[test]
+    fn test_random_source() {
+        let mut synth = mod_synth(ModSource::Random, ModDestination::Amplitude);
+        synth.params.random_rate.set(10.0);
+        let (left, _) = synth.render(&[(0, [NOTE_ON, 69, 127])], 4410 * 4);
+
+        // stepped, the level holds for each step and jumps between them
+        let level = |step: usize, quarter: usize| {
+            let start = step * 4410 + quarter * 1100;
+            peak(&left[start + 100..start + 1000])
+        };
+        for step in 1..4 {
+            assert!((level(step, 0) - level(step, 3)).abs() < 1e-3, "step {}", step);
+        }
+        assert!((1..4).any(|step| (level(step, 0) - level(step - 1, 0)).abs() > 0.05));
+
+        // smoothed, it glides from one to the next instead
+        synth.reset();
+        synth.params.random_smooth.set(1.0);
+        let (left, _) = synth.render(&[(0, [NOTE_ON, 69, 127])], 4410 * 4);
+        let level = |step: usize, quarter: usize| {
+            let start = step * 4410 + quarter * 1100;
+            peak(&left[start + 100..start + 1000])
+        };
+        assert!((1..4).any(|step| (level(step, 0) - level(step, 3)).abs() > 0.05));
+    }
+
+    #[test]
+    fn test_synced_lfo_follows_host_position() {
+        let mut synth = lfo_synth(LfoShape::Square, ModDestination::Amplitude);
+        let quarter = tempo::NoteDivision::ALL.iter().position(|&d| d == tempo::NoteDivision::Quarter).unwrap();
+        synth.params.lfos[0].sync.set((quarter + 1) as f32);
+
+        // a cycle per beat, lasting half a second at 120bpm. half way through a beat, the square
+        // is in its silent half.
+        synth.set_transport(Transport { tempo: 120.0, position: Some(10.5) });
+        let (left, _) = synth.render(&[(0, [NOTE_ON, 69, 127])], 4410);
+        assert_eq!(peak(&left[100..]), 0.0);
+
+        synth.set_transport(Transport { tempo: 120.0, position: Some(11.0) });
+        let (left, _) = synth.render(&[], 4410);
+        assert!(peak(&left) > 0.9);
+    }
+
+    #[test]
+    fn test_synced_lfo_runs_on_through_a_split_buffer() {
+        // hosts split their buffers at automation, and each piece carries on from the last rather
+        // than starting over from the buffer's position
+        let render = |pieces: usize| {
+            let mut synth = lfo_synth(LfoShape::Square, ModDestination::Amplitude);
+            let quarter = tempo::NoteDivision::ALL.iter().position(|&d| d == tempo::NoteDivision::Quarter).unwrap();
+            synth.params.lfos[0].sync.set((quarter + 1) as f32);
+            synth.set_transport(Transport { tempo: 120.0, position: Some(10.0) });
+            synth.queue_event(0, [NOTE_ON, 69, 127]);
+            let len = 32 * 1024;
+            let (mut left, mut right) = (vec![0.0; len], vec![0.0; len]);
+            for (left, right) in left.chunks_mut(len / pieces).zip(right.chunks_mut(len / pieces)) {
+                synth.process_buffer(left, right);
+            }
+            left
+        };
+        let whole = render(1);
+        // the second half of the beat is silent
+        assert_eq!(peak(&whole[11100..22000]), 0.0);
+        assert_eq!(whole, render(8));
+    }
+
+    #[test]
+    fn test_fm_envelope_shapes_modulation() {
+        let render = |fm_index: f32| {
+            let mut synth = raw_synth();
+            synth.params.waveform.set(oscillator::Waveform::Sine as usize as f32);
+            synth.params.unison.set(1.0);
+            synth.params.fm_ratio.set(2.0);
+            synth.params.fm_index.set(fm_index);
+            synth.params.fm_decay.set(0.05);
+            synth.params.fm_sustain.set(0.0);
+            synth.render(&[(0, [NOTE_ON, 69, 127])], 8820).0
+        };
+        let (plain, fm) = (render(0.0), render(5.0));
+
+        // brighter while the FM envelope is open, then back to a plain sine once it's closed
+        let differs = |range: std::ops::Range<usize>| plain[range.clone()].iter().zip(&fm[range]).any(|(a, b)| a != b);
+        assert!(differs(10..2000));
+        assert!(!differs(4410..8820));
+    }
+
+    #[test]
+    fn test_mixer_sub_and_noise() {
+        let render = |setup: &dyn Fn(&ReplicantParameters)| {
+            let mut synth = raw_synth();
+            synth.params.unison.set(1.0);
+            synth.params.osc_level.set(0.0);
+            setup(&synth.params);
+            synth.render(&[(0, [NOTE_ON, 69, 127])], 44100).0
+        };
+
+        // with the main oscillators muted, the sub plays alone an octave or two down
+        for &octave in &[1, 2] {
+            let sub = render(&|params| {
+                params.sub_level.set(1.0);
+                params.sub_octave.set(octave as f32);
+            });
+            let freq = zero_crossing_freq(&sub[22050..], 44100.0);
+            let expected = 440.0 / f64::from(octave).exp2();
+            assert!((freq - expected).abs() < 2.0, "sub was {}Hz", freq);
+        }
+
+        assert!(render(&|_| ()).iter().all(|&sample| sample == 0.0));
+        let noise = render(&|params| params.noise_level.set(0.5));
+        assert!(peak(&noise[22050..]) > 0.1);
+    }
+
+    #[test]
+    fn test_oversampling_reduces_aliasing() {
+        // the fraction of the power that isn't at one of the note's harmonics, which from a steady
+        // FM tone with a whole number ratio can only have folded back from above Nyquist
+        let inharmonic = |oversampling: Oversampling| {
+            let mut synth = Synth::default();
+            synth.params.waveform.set(oscillator::Waveform::Sine as usize as f32);
+            synth.params.unison.set(1.0);
+            synth.params.fm_ratio.set(3.0);
+            synth.params.fm_index.set(8.0);
+            synth.params.oversampling.set(oversampling as usize as f32);
+            let (left, _) = synth.render(&[(0, [NOTE_ON, 93, 64])], 44100);
+            let samples: Vec<f64> = left[4410..].iter().map(|&sample| f64::from(sample)).collect();
+            let harmonics: f64 = (1..=12)
+                .map(|harmonic| {
+                    let omega = TAU * 1760.0 * f64::from(harmonic) / 44100.0;
+                    let (cos, sin) = samples.iter().enumerate().fold((0.0, 0.0), |(cos, sin), (i, &sample)| {
+                        (cos + sample * (omega * i as f64).cos(), sin + sample * (omega * i as f64).sin())
+                    });
+                    let scale = 2.0 / samples.len() as f64;
+                    ((cos * scale).powi(2) + (sin * scale).powi(2)) / 2.0
+                })
+                .sum();
+            let power = samples.iter().map(|sample| sample * sample).sum::<f64>() / samples.len() as f64;
+            1.0 - harmonics / power
+        };
+        let (off, x2, x4) = (inharmonic(Oversampling::Off), inharmonic(Oversampling::X2), inharmonic(Oversampling::X4));
+        assert!(off > 0.01, "without oversampling, {} of the power is aliasing", off);
+        assert!(x2 < off / 100.0 && x4 < off / 100.0, "{} with 2x, {} with 4x, {} without", x2, x4, off);
+    }
+
+    #[test]
+    fn test_ring_mod() {
+        let render = |ring_mod: f32| {
+            let mut synth = raw_synth();
+            synth.params.waveform.set(oscillator::Waveform::Sine as usize as f32);
+            synth.params.unison.set(1.0);
+            synth.params.sync_ratio.set(1.0);
+            synth.params.ring_mod.set(ring_mod);
+            synth.render(&[(0, [NOTE_ON, 57, 127])], 22050).0
+        };
+
+        // a sine ring modulated by itself is squared, so it never goes negative, and swings at
+        // twice the frequency
+        let ring = render(1.0);
+        assert!(ring[11025..].iter().all(|&sample| sample > -0.05));
+        assert!(peak(&ring[11025..]) > 0.9);
+        let centred: Vec<f32> = ring[11025..].iter().map(|sample| sample - 0.5).collect();
+        let freq = zero_crossing_freq(&centred, 44100.0);
+        assert!((freq - 440.0).abs() < 5.0, "ring mod was {}Hz", freq);
+
+        assert!(render(0.0)[11025..].iter().any(|&sample| sample < -0.9));
+    }
+
+    #[test]
+    fn test_lfo_pan() {
+        let mut synth = lfo_synth(LfoShape::Square, ModDestination::Pan);
+        let (left, right) = synth.render(&[(0, [NOTE_ON, 69, 127])], 8820);
+
+        // panned hard right, then hard left
+        assert_eq!(peak(&left[..4000]), 0.0);
+        assert!(peak(&right[..4000]) > 0.9);
+        assert!(peak(&left[4500..8500]) > 0.9);
+        assert_eq!(peak(&right[4500..8500]), 0.0);
+    }
+
+    #[test]
+    fn test_alternate_voice_pan() {
+        let render = |width: f32| {
+            let mut synth = Synth::default();
+            synth.params.unison.set(1.0);
+            synth.params.pan_mode.set(PanMode::Alternate as usize as f32);
+            synth.params.pan_width.set(width);
+            synth.render(&[(0, [NOTE_ON, 57, 127]), (2205, [NOTE_ON, 69, 127])], 4410)
+        };
+
+        // the first voice goes hard left and the second hard right, where both can be heard
+        let (left, right) = render(1.0);
+        assert!(peak(&left[..2000]) > 0.9);
+        assert_eq!(peak(&right[..2000]), 0.0);
+        assert!(peak(&right[2300..]) > 0.9);
+
+        // with no width they're both centred
+        let (left, right) = render(0.0);
+        assert_eq!(left, right);
+    }
+
+    #[test]
+    fn test_lfo_pitch() {
+        let mut synth = lfo_synth(LfoShape::Square, ModDestination::Pitch);
+        synth.params.unison.set(1.0);
+        synth.params.mod_slots[0].depth.set(1.0 / 12.0);
+        let (left, _) = synth.render(&[(0, [NOTE_ON, 69, 127])], 8820);
+
+        // a semitone either side of A4
+        let up = zero_crossing_freq(&left[..4410], 44100.0);
+        let down = zero_crossing_freq(&left[4410..], 44100.0);
+        assert!((up - 466.2).abs() < 10.0, "{}", up);
+        assert!((down - 415.3).abs() < 10.0, "{}", down);
+    }
+
+    #[test]
+    fn test_velocity_to_amplitude() {
+        let mut synth = mod_synth(ModSource::Velocity, ModDestination::Amplitude);
+        let (left, _) = synth.render(&[(0, [NOTE_ON, 69, 127])], 4410);
+        let loud = peak(&left);
+
+        let mut synth = mod_synth(ModSource::Velocity, ModDestination::Amplitude);
+        let (left, _) = synth.render(&[(0, [NOTE_ON, 69, 32])], 4410);
+        assert!(peak(&left) < loud * 0.5, "{} {}", peak(&left), loud);
+    }
+
+    #[test]
+    fn test_aftertouch_to_pan() {
+        let mut synth = mod_synth(ModSource::Aftertouch, ModDestination::Pan);
+        let (left, right) = synth.render(&[(0, [NOTE_ON, 69, 127]), (2205, [CHANNEL_PRESSURE, 127, 0])], 4410);
+
+        assert!(peak(&left[..2000]) > 0.9);
+        assert!(peak(&right[..2000]) > 0.9);
+        // pressing harder pans hard right
+        assert_eq!(peak(&left[2300..]), 0.0);
+        assert!(peak(&right[2300..]) > 0.9);
+    }
+
+    #[test]
+    fn test_mod_wheel_to_pulse_width() {
+        // how much of the time the wave is high
+        let duty_cycle = |pulse_width: f32, mod_wheel: u8| {
+            let mut synth = mod_synth(ModSource::ModWheel, ModDestination::PulseWidth);
+            synth.params.waveform.set(oscillator::Waveform::Square as usize as f32);
+            synth.params.unison.set(1.0);
+            synth.params.pulse_width.set(pulse_width);
+            let events = [(0, [CONTROL_CHANGE, MOD_WHEEL, mod_wheel]), (0, [NOTE_ON, 57, 127])];
+            let (left, _) = synth.render(&events, 44100);
+            left[22050..].iter().filter(|&&sample| sample > 0.0).count() as f64 / 22050.0
+        };
+        assert!((duty_cycle(0.5, 0) - 0.5).abs() < 0.02);
+        assert!((duty_cycle(0.25, 0) - 0.25).abs() < 0.02);
+        // the mod wheel widens it by up to 45% of the cycle, until it reaches the widest allowed
+        assert!((duty_cycle(0.25, 127) - 0.7).abs() < 0.02);
+        assert!((duty_cycle(0.75, 127) - 0.95).abs() < 0.02);
+    }
+
+    #[test]
+    fn test_wavetable_position() {
+        // how square the wave is, from around a sine's 0.71 up to 1.0
+        let squareness = |waveform: oscillator::Waveform, position: f32, mod_wheel: u8| {
+            let mut synth = mod_synth(ModSource::ModWheel, ModDestination::WavetablePosition);
+            synth.params.waveform.set(waveform as usize as f32);
+            synth.params.unison.set(1.0);
+            synth.params.cutoff.set(20000.0);
+            synth.params.wavetable_position.set(position);
+            let events = [(0, [CONTROL_CHANGE, MOD_WHEEL, mod_wheel]), (0, [NOTE_ON, 57, 127])];
+            let (left, _) = synth.render(&events, 8820);
+            let left = &left[4410..];
+            let rms = (left.iter().map(|sample| sample * sample).sum::<f32>() / left.len() as f32).sqrt();
+            rms / peak(left)
+        };
+        let wavetable = |position, mod_wheel| squareness(oscillator::Waveform::Wavetable, position, mod_wheel);
+        // the built in table sweeps from a sine to a square
+        assert!((wavetable(0.0, 0) - squareness(oscillator::Waveform::Sine, 0.0, 0)).abs() < 0.01);
+        assert!(wavetable(1.0, 0) > 0.9);
+        // and the mod wheel moves it along
+        assert!(wavetable(0.0, 127) > 0.9);
+
+        // a table that can't be loaded falls back to the built in one, but is remembered
+        let synth = Synth::default();
+        let missing = std::env::temp_dir().join("replicant-no-such-table.wav");
+        synth.params.request_wavetable_file(Some(missing.clone()));
+        assert!(matches!(synth.params.wavetable_status(), LoadStatus::Failed(_)));
+        assert_eq!(synth.params.wavetable_file(), Some(missing));
+        assert!(Arc::ptr_eq(&synth.params.try_wavetable().unwrap(), &Wavetable::basic()));
+    }
+
+    #[test]
+    fn test_additive_harmonics() {
+        let mut synth = raw_synth();
+        synth.params.waveform.set(oscillator::Waveform::Additive as usize as f32);
+        synth.params.unison.set(1.0);
+        // the default is the fundamental alone
+        let (left, _) = synth.render(&[(0, [NOTE_ON, 57, 127])], 8820);
+        assert!((zero_crossing_freq(&left[4410..], 44100.0) - 220.0).abs() < 2.0);
+
+        // the second harmonic alone plays an octave up, as soon as it's drawn out
+        synth.params.harmonics[0].set(0.0);
+        synth.params.harmonics[1].set(1.0);
+        let (left, _) = synth.render(&[], 4410);
+        assert!((zero_crossing_freq(&left, 44100.0) - 440.0).abs() < 4.0);
+    }
+
+    #[test]
+    fn test_pitch_envelope() {
+        let mut synth = Synth::default();
+        synth.params.waveform.set(oscillator::Waveform::Sine as usize as f32);
+        synth.params.unison.set(1.0);
+        synth.params.pitch_env_amount.set(12.0);
+        synth.params.pitch_env_decay.set(1.0);
+        let (left, _) = synth.render(&[(0, [NOTE_ON, 57, 127])], 66150);
+        // it starts an octave up, and sweeps down onto the note
+        assert!(zero_crossing_freq(&left[..882], 44100.0) > 380.0);
+        let middle = zero_crossing_freq(&left[2205..4410], 44100.0);
+        assert!(middle > 250.0 && middle < 380.0, "{}", middle);
+        assert!((zero_crossing_freq(&left[48510..], 44100.0) - 220.0).abs() < 2.0);
+
+        // and each new note starts it again
+        synth.render(&[(0, [NOTE_OFF, 57, 0])], 88200);
+        assert!(synth.voices.is_empty());
+        let (left, _) = synth.render(&[(0, [NOTE_ON, 69, 127])], 441);
+        assert!(zero_crossing_freq(&left, 44100.0) > 760.0);
+    }
+
+    #[test]
+    fn test_mod_env_to_cutoff() {
+        let mut synth = mod_synth(ModSource::ModEnv, ModDestination::Cutoff);
+        synth.params.cutoff.set(100.0);
+        synth.params.mod_decay.set(0.05);
+        let (left, _) = synth.render(&[(0, [NOTE_ON, 69, 127])], 8820);
+
+        // the envelope opens the filter, then it closes down onto the note
+        assert!(peak(&left[100..1000]) > 0.5);
+        assert!(peak(&left[6000..]) < 0.3);
+    }
+
+    #[test]
+    fn test_mod_env_sync_sweep() {
+        let mut synth = mod_synth(ModSource::ModEnv, ModDestination::SyncRatio);
+        synth.params.waveform.set(oscillator::Waveform::Sine as usize as f32);
+        synth.params.unison.set(1.0);
+        synth.params.osc_sync.set(1.0);
+        synth.params.sync_ratio.set(1.0);
+        synth.params.mod_sustain.set(0.0);
+        synth.params.mod_decay.set(0.2);
+        let (left, _) = synth.render(&[(0, [NOTE_ON, 57, 127])], 44100);
+
+        // the slave starts three octaves up, and sweeps down onto the note
+        let early = zero_crossing_freq(&left[..441], 44100.0);
+        let late = zero_crossing_freq(&left[22050..], 44100.0);
+        assert!(early > 1200.0, "early was {}Hz", early);
+        assert!((late - 220.0).abs() < 2.0, "late was {}Hz", late);
+    }
+
+    #[test]
+    fn test_meter_follows_output() {
+        let mut synth = raw_synth();
+        let meter = synth.meter();
+        let (left, right) = synth.render(&[(0, [NOTE_ON, 69, 127])], 4410);
+
+        let levels = meter.levels();
+        assert_eq!(levels.peak.0, peak(&left));
+        assert!(levels.rms.0 > 0.1 && levels.rms.0 < levels.peak.0);
+
+        let mut scope = [0.0; 64];
+        meter.scope(&mut scope);
+        // the scope shows the mono mix
+        assert_eq!(scope[63], (left[4409] + right[4409]) / 2.0);
+
+        synth.render(&[(0, [NOTE_OFF, 69, 0])], 4410);
+        synth.render(&[], 64);
+        assert_eq!(meter.levels(), meter::Levels::default());
+    }
+
+    #[test]
+    fn test_all_notes_off_releases_everything() {
+        let mut synth = Synth::default();
+        synth.render(&[
+            (0, [CONTROL_CHANGE, SUSTAIN_PEDAL, 127]),
+            (0, [NOTE_ON, 60, 100]),
+            (0, [NOTE_ON, 64, 100]),
+            (1, [NOTE_OFF, 64, 0]),
+            (2, [CONTROL_CHANGE, ALL_NOTES_OFF, 0]),
+        ], 10);
+        assert!(synth.voices.iter().all(|voice| !voice.is_held()));
+
+        // the default release is half a millisecond
+        synth.render(&[], 441);
+        assert!(synth.voices.is_empty());
+    }
+
+    #[test]
+    fn test_transport_stops_and_loops() {
+        let playing = |position| Transport { tempo: 120.0, position };
+        let held = |mode: StopMode, next: Option<f64>| {
+            let mut synth = Synth::default();
+            synth.params.stop_mode.set(mode as usize as f32);
+            synth.params.release.set(1.0);
+            synth.set_transport(playing(Some(4.0)));
+            synth.render(&[(0, [NOTE_ON, 60, 100])], 10);
+            synth.set_transport(playing(next));
+            synth.render(&[], 10);
+            synth.voices.iter().filter(|voice| voice.is_held()).count()
+        };
+        assert_eq!(held(StopMode::Release, Some(4.1)), 1);
+        assert_eq!(held(StopMode::Keep, None), 1);
+        // the loop comes round, and the note off was past its end
+        assert_eq!(held(StopMode::Release, Some(0.0)), 0);
+        assert_eq!(held(StopMode::Release, None), 0);
+
+        let mut synth = Synth::default();
+        synth.params.stop_mode.set(StopMode::Kill as usize as f32);
+        synth.set_transport(playing(Some(4.0)));
+        synth.render(&[(0, [NOTE_ON, 60, 100])], 10);
+        synth.set_transport(playing(None));
+        assert!(synth.voices.is_empty());
+    }
+
+    /// A synth in multi-timbral mode, with its parts built.
+    fn multitimbral_synth() -> Synth {
+        let mut synth = Synth::default();
+        synth.params.multitimbral.set(1.0);
+        synth.part_builder.build(synth.sample_rate as f32);
+        synth.render(&[], 1);
+        assert_eq!(synth.parts.len(), crate::multi::NUM_PARTS);
+        synth
+    }
+
+    #[test]
+    fn test_multitimbral_parts_are_mixed_in() {
+        let note = |channel| (0, [NOTE_ON | channel, 60, 100]);
+        let (main, _) = multitimbral_synth().render(&[note(0)], 1000);
+        let (part, _) = multitimbral_synth().render(&[note(1)], 1000);
+        let mut synth = multitimbral_synth();
+        let (both, _) = synth.render(&[note(0), note(1)], 1000);
+        assert_eq!(synth.voices.len(), 1);
+        assert_eq!(synth.parts[0].voices.len(), 1);
+        assert!(synth.parts[1..].iter().all(|part| part.voices.is_empty()));
+        // both play the init patch
+        assert!(main.iter().zip(&part).all(|(main, part)| (main - part).abs() < 1e-6));
+        assert!(part.iter().any(|&sample| sample.abs() > 0.01));
+        for ((both, main), part) in both.iter().zip(&main).zip(&part) {
+            assert!((both - (main + part)).abs() < 1e-6);
+        }
+
+        // with the mode off, every channel plays the instance's own patch
+        synth.params.multitimbral.set(0.0);
+        synth.render(&[note(2)], 10);
+        assert_eq!(synth.voices.len(), 2);
+        assert!(synth.parts.iter().all(|part| part.voices.is_empty()));
+    }
+
+    #[test]
+    fn test_multitimbral_part_volume_and_pan() {
+        let mut synth = multitimbral_synth();
+        synth.render(&[(0, [CONTROL_CHANGE | 1, VOLUME, 0]), (0, [CONTROL_CHANGE | 2, PAN, 127])], 10);
+        let (left, right) = synth.render(&[(0, [NOTE_ON | 1, 60, 100]), (0, [NOTE_ON | 2, 60, 100])], 1000);
+        assert_eq!(synth.parts[0].params.master_gain(), 0.0);
+        assert_eq!(synth.parts[1].params.master_pan.get(), 1.0);
+        // the first part is silent, and the second only in the right
+        assert!(left.iter().all(|&sample| sample == 0.0));
+        assert!(right.iter().any(|&sample| sample.abs() > 0.01));
+        // the instance's own patch doesn't take them
+        synth.render(&[(0, [CONTROL_CHANGE, VOLUME, 0])], 10);
+        assert!(synth.params.master_gain() > 0.0);
+    }
+
+    #[test]
+    fn test_multitimbral_program_change() {
+        let mut synth = multitimbral_synth();
+        ReplicantParameters::spawn_loader_thread(&synth.params, Arc::downgrade(&synth.part_builder));
+        let expected = ReplicantParameters::init();
+        crate::multi::load_part_preset(&expected, &synth.params.preset(5).unwrap());
+        synth.render(&[(0, [0xC0 | 3, 5, 0])], 10);
+        wait_for(|| synth.parts[2].params.normalized_values() == expected.normalized_values());
+        // the instance's own patch stays put
+        assert_eq!(synth.params.get_preset_num(), 0);
+    }
+
+    /// Render a buffer into six channels: the main output, then two extra ones.
+    fn render_outputs(synth: &mut Synth, events: &[[u8; 3]], len: usize) -> Vec<Vec<f32>> {
+        for &data in events {
+            synth.queue_event(0, data);
+        }
+        let mut channels = vec![vec![0.0; len]; 6];
+        let (main, extra) = channels.split_at_mut(2);
+        let [left, right] = main else { unreachable!() };
+        let mut outputs: Vec<_> = extra.chunks_mut(2)
+            .map(|pair| {
+                let (left, right) = pair.split_at_mut(1);
+                (&mut left[0][..], &mut right[0][..])
+            })
+            .collect();
+        synth.process_outputs(left, right, &mut outputs);
+        channels
+    }
+
+    #[test]
+    fn test_voice_outputs() {
+        let level = |channel: &[f32]| channel.iter().map(|sample| sample.abs()).fold(0.0, f32::max);
+        let mut synth = raw_synth();
+        synth.params.output_routing.set(OutputRouting::RoundRobin as usize as f32);
+        let notes = [48, 60, 72, 84].map(|note| [NOTE_ON, note, 100]);
+        let channels = render_outputs(&mut synth, &notes, 1000);
+        assert_eq!(synth.voices.iter().map(|voice| voice.output).collect::<Vec<_>>(), [0, 1, 2, 3]);
+        assert!(level(&channels[2]) > 0.01 && level(&channels[4]) > 0.01);
+        // the fourth output isn't there, so its voice plays through the main one
+        let (main, _) = raw_synth().render(&[(0, [NOTE_ON, 48, 100]), (0, [NOTE_ON, 84, 100])], 1000);
+        assert!(channels[0].iter().zip(&main).all(|(left, main)| (left - main).abs() < 1e-6));
+
+        // the extra outputs are dry, so the main one's effects don't hear them
+        let mut synth = raw_synth();
+        synth.params.output_routing.set(OutputRouting::ByKey as usize as f32);
+        synth.params.reverb_mix.set(1.0);
+        let channels = render_outputs(&mut synth, &[[NOTE_ON, 80, 100]], 1000);
+        assert!(level(&channels[4]) > 0.01);
+        assert!(channels[..4].iter().flatten().all(|&sample| sample == 0.0));
+    }
+
+    #[test]
+    fn test_all_sound_off_is_immediate() {
+        let mut synth = raw_synth();
+        synth.params.release.set(5.0);
+        let (left, _) = synth.render(&[
+            (0, [NOTE_ON, 60, 100]),
+            (100, [CONTROL_CHANGE, ALL_SOUND_OFF, 0]),
+        ], 200);
+        assert!(synth.voices.is_empty());
+        assert!(left[100..].iter().all(|&s| s == 0.0));
+    }
+
+
+    #[test]
+    fn test_master_output_stage() {
+        let render = |gain: f32, pan: f32, width: f32| {
+            let mut synth = Synth::default();
+            synth.params.saturation.set(Saturation::Limiter as usize as f32);
+            synth.params.master_gain.set(gain);
+            synth.params.master_pan.set(pan);
+            synth.params.stereo_width.set(width);
+            synth.render(&[(0, [NOTE_ON, 60, 127])], 4410)
+        };
+        let (left, right) = render(0.0, 0.0, 1.0);
+        let (quiet_left, quiet_right) = render(-6.0, 0.0, 1.0);
+        assert!((peak(&quiet_left) / peak(&left) - db_to_gain(-6.0) as f32).abs() < 0.01);
+        assert!((peak(&quiet_right) / peak(&right) - db_to_gain(-6.0) as f32).abs() < 0.01);
+
+        // the bottom of the gain range is silent
+        let (silent_left, _) = render(-60.0, 0.0, 1.0);
+        assert_eq!(peak(&silent_left), 0.0);
+
+        let (_, panned_right) = render(0.0, -1.0, 1.0);
+        assert_eq!(peak(&panned_right), 0.0);
+
+        // the detuned unison pair differs between the channels, until it's collapsed to mono
+        assert_ne!(left, right);
+        let (mono_left, mono_right) = render(0.0, 0.0, 0.0);
+        assert_eq!(mono_left, mono_right);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn test_non_finite_output_is_silenced() {
+        let mut synth = Synth::default();
+        // hosts can't set a NaN, but a bug could produce one
+        synth.params.stereo_width.set(f32::NAN);
+        let (left, right) = synth.render(&[(0, [NOTE_ON, 60, 127])], 512);
+        assert!(left.iter().chain(&right).all(|&sample| sample == 0.0));
+        assert!(synth.voices.is_empty());
+
+        synth.params.stereo_width.set(1.0);
+        let (left, _) = synth.render(&[(0, [NOTE_ON, 60, 127])], 512);
+        assert!(peak(&left) > 0.0 && left.iter().all(|sample| sample.is_finite()));
+    }
+
+    #[test]
+    fn test_dc_offset_is_removed() {
+        let mut synth = Synth::default();
+        synth.params.saturation.set(Saturation::Limiter as usize as f32);
+        synth.params.pulse_width.set(0.1);
+        synth.params.waveform.set(Waveform::Square as usize as f32);
+        let (left, _) = synth.render(&[(0, [NOTE_ON, 48, 127])], 88200);
+        let mean = left[44100..].iter().sum::<f32>() / 44100.0;
+        assert!(mean.abs() < 0.005, "the offset was {}", mean);
+    }
+
+    #[test]
+    fn test_stereo_modes() {
+        let render = |mode: StereoMode| {
+            let mut synth = Synth::default();
+            synth.params.stereo_mode.set(mode as usize as f32);
+            synth.render(&[(0, [NOTE_ON, 60, 127])], 4410)
+        };
+        let (left, right) = render(StereoMode::Mono);
+        assert_eq!(left, right);
+
+        // the right comes in after the left
+        let (left, right) = render(StereoMode::Haas);
+        let start = |channel: &[f32]| channel.iter().position(|sample| sample.abs() > 0.001).unwrap();
+        assert!(start(&right) > start(&left) + 200);
+
+        let (left, right) = render(StereoMode::Detune);
+        assert_ne!(left, right);
+        assert!(peak(&left) > 0.1 && peak(&right) > 0.1);
+    }
+
+    #[test]
+    fn test_output_never_exceeds_full_scale() {
+        for &saturation in &Saturation::ALL {
+            let mut synth = Synth::default();
+            synth.params.saturation.set(saturation as usize as f32);
+            synth.params.drive.set(24.0);
+            synth.params.master_gain.set(6.0);
+            synth.params.resonance.set(1.0);
+            synth.params.cutoff.set(500.0);
+            let chord: Vec<_> = [48, 52, 55, 60, 64, 67].iter()
+                .map(|&note| (0, [NOTE_ON, note, 127]))
+                .collect();
+            let (left, right) = synth.render(&chord, 4410);
+            assert!(peak(&left) <= 1.0 && peak(&right) <= 1.0, "{:?} clipped", saturation);
+            assert!(peak(&left) > 0.5);
+        }
+    }
+
+    #[test]
+    fn test_delay_echoes_after_the_note_ends() {
+        let mut synth = raw_synth();
+        synth.params.delay_time.set(0.5);
+        synth.params.delay_mix.set(0.5);
+        synth.params.decay.set(0.0);
+        synth.params.sustain.set(1.0);
+        let (left, _) = synth.render(&[(0, [NOTE_ON, 60, 127]), (4410, [NOTE_OFF, 60, 0])], 44100);
+
+        // the note is over by 0.2s, and echoes back at 0.5s
+        assert!(synth.voices.is_empty());
+        assert_eq!(peak(&left[8820..22050]), 0.0);
+        assert!(peak(&left[22050..26460]) > 0.2);
+    }
+
+    #[test]
+    fn test_cutoff_changes_are_smoothed() {
+        let mut synth = Synth::default();
+        synth.params.cutoff.set(20000.0);
+        synth.render(&[(0, [NOTE_ON, 60, 100])], 10);
+
+        // the first sample after the change barely moves, and it settles well within half a second
+        synth.params.cutoff.set(100.0);
+        synth.render(&[], 1);
+        assert!(synth.cutoff.get() > 19000.0, "cutoff jumped to {}", synth.cutoff.get());
+        synth.render(&[], 22050);
+        assert_eq!(synth.cutoff.get(), 100.0);
+
+        // with nothing playing there's nothing to smooth
+        synth.render(&[(0, [NOTE_OFF, 60, 0])], 44100);
+        synth.params.cutoff.set(5000.0);
+        synth.render(&[], 1);
+        assert_eq!(synth.cutoff.get(), 5000.0);
+    }
+
+    #[test]
+    fn test_reset_clears_state() {
+        let mut synth = Synth::default();
+        synth.render(&[(0, [CONTROL_CHANGE, SUSTAIN_PEDAL, 127]), (0, [NOTE_ON, 60, 100]), (0, [224, 0, 0])], 10);
+        synth.reset();
+        assert!(synth.voices.is_empty());
+        assert!(!synth.sustain_pedal);
+        assert_eq!(synth.pitch_bend.get(), 0.0);
+    }
+
+    #[test]
+    fn test_bypass_silences_and_resets() {
+        let mut synth = Synth::default();
+        synth.params.delay_mix.set(1.0);
+        synth.params.delay_time.set(0.1);
+        synth.render(&[(0, [NOTE_ON, 60, 100])], 2205);
+
+        // notes played while bypassed are dropped too, rather than sounding once it's turned off
+        synth.set_bypass(true);
+        let (left, right) = synth.render(&[(0, [NOTE_ON, 64, 100])], 2205);
+        assert!(left.iter().chain(right.iter()).all(|&sample| sample == 0.0));
+        assert!(synth.voices.is_empty());
+
+        // and nothing's left ringing in the delay afterwards
+        synth.set_bypass(false);
+        let (left, right) = synth.render(&[], 8820);
+        assert!(left.iter().chain(right.iter()).all(|&sample| sample == 0.0));
+        let (left, _) = synth.render(&[(0, [NOTE_ON, 60, 100])], 2205);
+        assert!(peak(&left) > 0.1);
+    }
+
+    fn mono_synth(priority: NotePriority) -> Synth {
+        let synth = Synth::default();
+        synth.params.polyphony.set(1.0);
+        synth.params.note_priority.set(NotePriority::ALL.iter().position(|&p| p == priority).unwrap() as f32);
+        synth
+    }
+
+    #[test]
+    fn test_mono_note_priority() {
+        let mut synth = mono_synth(NotePriority::Last);
+        synth.render(&[(0, [NOTE_ON, 60, 100]), (10, [NOTE_ON, 64, 100]), (20, [NOTE_ON, 55, 100])], 30);
+        assert_eq!(synth.mono_voice().unwrap().note, 55);
+        synth.render(&[(0, [NOTE_OFF, 55, 0])], 10);
+        assert_eq!(synth.mono_voice().unwrap().note, 64);
+
+        let mut synth = mono_synth(NotePriority::Low);
+        synth.render(&[(0, [NOTE_ON, 60, 100]), (10, [NOTE_ON, 64, 100]), (20, [NOTE_ON, 55, 100])], 30);
+        assert_eq!(synth.mono_voice().unwrap().note, 55);
+        synth.render(&[(0, [NOTE_OFF, 55, 0])], 10);
+        assert_eq!(synth.mono_voice().unwrap().note, 60);
+
+        let mut synth = mono_synth(NotePriority::High);
+        synth.render(&[(0, [NOTE_ON, 60, 100]), (10, [NOTE_ON, 64, 100]), (20, [NOTE_ON, 55, 100])], 30);
+        assert_eq!(synth.mono_voice().unwrap().note, 64);
+
+        // releasing the last held note releases the voice
+        synth.render(&[(0, [NOTE_OFF, 55, 0]), (0, [NOTE_OFF, 60, 0]), (0, [NOTE_OFF, 64, 0])], 10);
+        assert!(!synth.mono_voice().unwrap().is_held());
+        assert_eq!(synth.voices.len(), 1);
+    }
+
+    #[test]
+    fn test_mono_note_change_keeps_oscillator_phase() {
+        let mut synth = mono_synth(NotePriority::Last);
+        synth.params.waveform.set(oscillator::Waveform::Sine as usize as f32);
+        synth.params.unison.set(1.0);
+        synth.params.attack.set(0.0);
+        synth.params.sustain.set(1.0);
+        let (left, _) = synth.render(&[(0, [NOTE_ON, 60, 127]), (1000, [NOTE_ON, 64, 127])], 2000);
+
+        // the oscillator carries on from where it was, so the step across the change is no bigger
+        // than a sine at the new pitch ever moves in one sample
+        let max_step = (TAU * midi_pitch_to_freq(64.0) / 44100.0) as f32 * peak(&left[1000..]);
+        for i in 990..1010 {
+            assert!((left[i + 1] - left[i]).abs() <= max_step * 1.1, "jump at sample {}", i);
+        }
+    }
+
+    #[test]
+    fn test_retrigger_is_declicked() {
+        let mut synth = mono_synth(NotePriority::Last);
+        synth.params.waveform.set(oscillator::Waveform::Sine as usize as f32);
+        synth.params.unison.set(1.0);
+        synth.params.attack.set(0.0);
+        synth.params.sustain.set(1.0);
+        // the level drops straight to the new velocity's
+        let (left, _) = synth.render(&[(0, [NOTE_ON, 60, 127]), (1000, [NOTE_ON, 64, 10])], 2000);
+
+        // but it takes a few milliseconds to get there
+        let max_step = (TAU * midi_pitch_to_freq(64.0) / 44100.0) as f32 + 1.0 / 132.0;
+        for i in 990..1200 {
+            assert!((left[i + 1] - left[i]).abs() <= max_step * 1.1, "jump at sample {}", i);
+        }
+        assert!(peak(&left[1500..]) < 0.2);
+    }
+
+    #[test]
+    fn test_mono_legato() {
+        let retriggered = |legato: bool| {
+            let mut synth = mono_synth(NotePriority::Last);
+            synth.params.attack.set(0.1);
+            synth.params.legato.set(if legato { 1.0 } else { 0.0 });
+            synth.render(&[(0, [NOTE_ON, 60, 100]), (2000, [NOTE_ON, 64, 100])], 2100);
+            synth.mono_voice().unwrap().envelope.phase_elapsed < 0.01
+        };
+        assert!(retriggered(false));
+        assert!(!retriggered(true));
+    }
+
+    #[test]
+    fn test_retrigger_modes() {
+        // the amp envelope's level just after the note change, and whether it restarted
+        let retrigger = |retrigger: Retrigger| {
+            let mut synth = mono_synth(NotePriority::Last);
+            synth.params.attack.set(0.01);
+            synth.params.sustain.set(0.5);
+            synth.params.retrigger.set(retrigger as usize as f32);
+            synth.render(&[(0, [NOTE_ON, 60, 100]), (4410, [NOTE_ON, 64, 100])], 4411);
+            let voice = synth.mono_voice().unwrap();
+            (voice.alpha(&synth.voice_params()), voice.envelope.current_phase == ADSRPhase::Attack)
+        };
+        let (level, restarted) = retrigger(Retrigger::FromCurrent);
+        assert!(restarted && (level - 0.5).abs() < 0.01, "from current: {}", level);
+        let (level, restarted) = retrigger(Retrigger::FromZero);
+        assert!(restarted && level < 0.01, "from zero: {}", level);
+        let (level, restarted) = retrigger(Retrigger::Legato);
+        assert!(!restarted && level == 0.5, "legato: {}", level);
+
+        // once released, even legato attacks again
+        let mut synth = mono_synth(NotePriority::Last);
+        synth.params.retrigger.set(Retrigger::Legato as usize as f32);
+        synth.params.release.set(1.0);
+        synth.render(&[(0, [NOTE_ON, 60, 100]), (100, [NOTE_OFF, 60, 0]), (200, [NOTE_ON, 64, 100])], 201);
+        assert_eq!(synth.mono_voice().unwrap().envelope.current_phase, ADSRPhase::Attack);
+    }
+
+    #[test]
+    fn test_one_shot_envelope_plays_out_after_note_off() {
+        let mut synth = Synth::default();
+        synth.params.env_mode.set(EnvelopeMode::OneShot as usize as f32);
+        synth.params.hold.set(0.05);
+        synth.params.decay.set(0.1);
+        synth.params.release.set(0.0);
+
+        // released straight away, but still sounding through the hold and half the decay
+        synth.render(&[(0, [NOTE_ON, 60, 100]), (10, [NOTE_OFF, 60, 0])], 2205 + 2205);
+        assert_eq!(synth.voices.len(), 1);
+        assert!(!synth.voices[0].is_held());
+        assert_eq!(synth.voices[0].envelope.current_phase, ADSRPhase::Decay);
+        assert!(synth.voices[0].alpha(&synth.voice_params()) > 0.1);
+
+        // then finished once the decay reaches silence
+        synth.render(&[], 2205 + 100);
+        assert!(synth.voices.is_empty());
+    }
+
+    #[test]
+    fn test_tail_covers_the_release_and_the_echoes() {
+        let mut synth = Synth::default();
+        synth.set_sample_rate(44100.0);
+        synth.params.release.set(0.5);
+        assert_eq!(synth.tail_samples(), 22050);
+
+        synth.params.delay_time.set(0.1);
+        synth.params.delay_feedback.set(0.5);
+        synth.params.delay_mix.set(0.5);
+        let tail = synth.tail_samples() as usize;
+        assert!(tail > 22050 + 4410 * 10, "{}", tail);
+
+        // the last echo is heard within the tail, and nothing after it
+        let (left, _) = synth.render(&[(0, [NOTE_ON, 60, 100]), (4410, [NOTE_OFF, 60, 0])], 4410 + tail + 4410);
+        let level = |samples: &[f32]| samples.iter().fold(0.0f32, |max, sample| max.max(sample.abs()));
+        let peak = level(&left[..4410]);
+        assert!(level(&left[tail..4410 + tail]) > peak * 1e-5);
+        assert!(level(&left[4410 + tail..]) < peak * 1e-4);
+
+        synth.params.env_mode.set(EnvelopeMode::OneShot as usize as f32);
+        synth.params.delay_mix.set(0.0);
+        let adsr = synth.params.adsr();
+        assert_eq!(synth.tail_time(), adsr.attack + adsr.hold + adsr.decay);
+    }
+
+    #[test]
+    fn test_mono_glide() {
+        let mut synth = mono_synth(NotePriority::Last);
+        synth.params.glide_time.set(0.1);
+        synth.render(&[(0, [NOTE_ON, 57, 100]), (100, [NOTE_ON, 69, 100])], 100 + 2205);
+
+        // halfway through the glide
+        let pitch = synth.mono_voice().unwrap().pitch;
+        assert!((pitch - 63.0).abs() < 0.01, "{}", pitch);
+
+        synth.render(&[], 2300);
+        assert_eq!(synth.mono_voice().unwrap().pitch, 69.0);
+    }
+
+    #[test]
+    fn test_glide_modes_and_curves() {
+        // the pitch 50ms into a glide up from 57
+        let glide = |mode: GlideMode, curve: GlideCurve, to: u8| {
+            let mut synth = mono_synth(NotePriority::Last);
+            synth.params.glide_time.set(0.1);
+            synth.params.glide_mode.set(mode as usize as f32);
+            synth.params.glide_curve.set(curve as usize as f32);
+            synth.render(&[(0, [NOTE_ON, 57, 100]), (100, [NOTE_ON, to, 100])], 100 + 2205);
+            synth.mono_voice().unwrap().pitch
+        };
+
+        // constant time covers half of any interval in half the glide time
+        assert!((glide(GlideMode::ConstantTime, GlideCurve::Linear, 81) - 69.0).abs() < 0.01);
+
+        // constant rate covers half an octave in half the time per octave, whatever the interval
+        let pitch = glide(GlideMode::ConstantRate, GlideCurve::Linear, 81);
+        assert!((pitch - 63.0).abs() < 0.01, "{}", pitch);
+        let pitch = glide(GlideMode::ConstantRate, GlideCurve::Linear, 69);
+        assert!((pitch - 63.0).abs() < 0.01, "{}", pitch);
+
+        // the exponential curve gets most of the way there early on, and still arrives on time
+        let pitch = glide(GlideMode::ConstantTime, GlideCurve::Exponential, 69);
+        assert!(pitch > 67.0 && pitch < 69.0, "{}", pitch);
+        let mut synth = mono_synth(NotePriority::Last);
+        synth.params.glide_time.set(0.1);
+        synth.params.glide_curve.set(GlideCurve::Exponential as usize as f32);
+        synth.render(&[(0, [NOTE_ON, 57, 100]), (100, [NOTE_ON, 69, 100])], 100 + 4410 + 32);
+        assert_eq!(synth.mono_voice().unwrap().pitch, 69.0);
+    }
+
+    #[test]
+    fn test_velocity_brightens_cutoff() {
+        let cutoff = |velocity: u8| {
+            let mut synth = Synth::default();
+            synth.params.cutoff.set(500.0);
+            synth.params.vel_to_cutoff.set(2.0);
+            synth.render(&[(0, [NOTE_ON, 60, velocity])], 10);
+            synth.voices[0].cutoff(&synth.voice_params())
+        };
+        assert!(cutoff(127) > cutoff(20));
+        assert!((cutoff(127) - 2000.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_filter_envelope_sweeps_cutoff() {
+        let mut synth = Synth::default();
+        synth.params.cutoff.set(500.0);
+        synth.params.filter_env_amount.set(3.0);
+        synth.params.filter_attack.set(0.01);
+        synth.params.filter_decay.set(0.1);
+        synth.params.filter_sustain.set(0.0);
+
+        // peak at the end of the filter envelope's attack, then back down to the base cutoff
+        synth.render(&[(0, [NOTE_ON, 60, 100])], 441);
+        let params = synth.voice_params();
+        assert!((synth.voices[0].cutoff(&params) - 4000.0).abs() < 10.0);
+        synth.render(&[], 8820);
+        assert!((synth.voices[0].cutoff(&params) - 500.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_process_does_not_allocate() {
+        /// Queue and play one buffer's events, as the host would, checking neither allocates.
+        fn play(synth: &mut Synth, events: &[(usize, [u8; 3])]) {
+            let (mut left, mut right) = ([0.0; 256], [0.0; 256]);
+            alloc_guard::assert_no_alloc(|| {
+                for &(frame, data) in events {
+                    synth.queue_event(frame, data);
+                }
+                synth.process_buffer(&mut left, &mut right);
+            });
+        }
+
+        let mut synth = Synth::default();
+        synth.params.polyphony.set(params::MAX_POLYPHONY as f32);
+
+        // enough notes to steal voices over and over, played back to front
+        let notes: Vec<_> = (0..100u8).rev().map(|note| (usize::from(note) * 2, [NOTE_ON, note, 100])).collect();
+        play(&mut synth, &notes);
+        assert!(synth.voices.len() <= MAX_VOICES);
+        assert_eq!(synth.voices.capacity(), MAX_VOICES);
+
+        // more events than the queue holds, a program change, and the pedal
+        let mut events: Vec<_> = (0..EVENT_QUEUE_CAPACITY + 10).map(|i| (i % 256, [CONTROL_CHANGE, 1, 64])).collect();
+        events.push((10, [0xC0, 2, 0]));
+        events.push((20, [CONTROL_CHANGE, SUSTAIN_PEDAL, 127]));
+        play(&mut synth, &events);
+        assert_eq!(synth.event_queue.capacity(), EVENT_QUEUE_CAPACITY);
+
+        // the arpeggiator, then everything off
+        synth.params.arp.set(1.0);
+        play(&mut synth, &[(0, [NOTE_ON, 60, 100]), (1, [NOTE_ON, 64, 100])]);
+        play(&mut synth, &[(0, [CONTROL_CHANGE, ALL_NOTES_OFF, 0])]);
+    }
+
+    #[test]
+    fn test_render_midi_to_buffer() {
+        // a note starting part way through the third buffer, and released in the fifth
+        let events = [(1100, [NOTE_ON, 69, 100]), (2100, [NOTE_OFF, 69, 0])];
+        let output = render_midi_to_buffer(&events, 48000.0, 48000);
+        assert_eq!(output.len(), 96000);
+        assert!(output[..2200].iter().all(|&sample| sample == 0.0));
+        assert!(output[2200..2400].iter().any(|&sample| sample != 0.0));
+        // both channels are there, interleaved
+        assert!(output[2200..].chunks(2).any(|frame| frame[0] != 0.0 && frame[1] != 0.0));
+        assert!(output[90000..].iter().all(|&sample| sample.abs() < 1e-3));
+    }
+
+    mod properties {
+        use super::*;
+        use proptest::prelude::*;
+
+        /// Raw messages, which needn't be valid midi, at samples up to past the end of the buffer.
+        fn buffers() -> impl Strategy<Value = Vec<Vec<(usize, [u8; 3])>>> {
+            let event = (0..300usize, any::<[u8; 3]>());
+            prop::collection::vec(prop::collection::vec(event, 0..40), 1..8)
+        }
+
+        proptest! {
+            #![proptest_config(ProptestConfig::with_cases(32))]
+
+            #[test]
+            fn arbitrary_midi_is_handled(buffers in buffers()) {
+                let mut synth = Synth::default();
+                let (mut left, mut right) = ([0.0; 256], [0.0; 256]);
+                for events in buffers {
+                    for (frame, data) in events {
+                        synth.queue_event(frame, data);
+                    }
+                    synth.process_buffer(&mut left, &mut right);
+                    prop_assert!(left.iter().chain(&right).all(|sample| sample.abs() <= 1.0));
+                }
+
+                // letting go of everything on every channel leaves nothing playing
+                for channel in 0..16 {
+                    synth.queue_event(0, [CONTROL_CHANGE | channel, SUSTAIN_PEDAL, 0]);
+                    synth.queue_event(0, [CONTROL_CHANGE | channel, ALL_NOTES_OFF, 0]);
+                }
+                synth.render(&[], 44100);
+                prop_assert!(synth.voices.is_empty(), "{} voices still playing", synth.voices.len());
+            }
+        }
+    }
+}
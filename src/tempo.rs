@@ -0,0 +1,169 @@
+/// The tempo assumed until the host reports one, in beats per minute.
+pub const DEFAULT_TEMPO: f64 = 120.0;
+
+/// A note length that delay times and LFO rates can be synced to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NoteDivision {
+    Whole,
+    Half,
+    HalfDotted,
+    HalfTriplet,
+    Quarter,
+    QuarterDotted,
+    QuarterTriplet,
+    Eighth,
+    EighthDotted,
+    EighthTriplet,
+    Sixteenth,
+    SixteenthDotted,
+    SixteenthTriplet,
+    ThirtySecond,
+}
+
+impl NoteDivision {
+    pub const ALL: [NoteDivision; 14] = [
+        NoteDivision::Whole,
+        NoteDivision::Half,
+        NoteDivision::HalfDotted,
+        NoteDivision::HalfTriplet,
+        NoteDivision::Quarter,
+        NoteDivision::QuarterDotted,
+        NoteDivision::QuarterTriplet,
+        NoteDivision::Eighth,
+        NoteDivision::EighthDotted,
+        NoteDivision::EighthTriplet,
+        NoteDivision::Sixteenth,
+        NoteDivision::SixteenthDotted,
+        NoteDivision::SixteenthTriplet,
+        NoteDivision::ThirtySecond,
+    ];
+
+    /// The length in beats, ie. quarter notes.
+    pub fn beats(self) -> f64 {
+        match self {
+            NoteDivision::Whole => 4.0,
+            NoteDivision::Half => 2.0,
+            NoteDivision::HalfDotted => 3.0,
+            NoteDivision::HalfTriplet => 4.0 / 3.0,
+            NoteDivision::Quarter => 1.0,
+            NoteDivision::QuarterDotted => 1.5,
+            NoteDivision::QuarterTriplet => 2.0 / 3.0,
+            NoteDivision::Eighth => 0.5,
+            NoteDivision::EighthDotted => 0.75,
+            NoteDivision::EighthTriplet => 1.0 / 3.0,
+            NoteDivision::Sixteenth => 0.25,
+            NoteDivision::SixteenthDotted => 0.375,
+            NoteDivision::SixteenthTriplet => 1.0 / 6.0,
+            NoteDivision::ThirtySecond => 0.125,
+        }
+    }
+
+    /// The length in seconds at `tempo` beats per minute.
+    pub fn seconds(self, tempo: f64) -> f64 {
+        self.beats() * 60.0 / tempo
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            NoteDivision::Whole => "1/1",
+            NoteDivision::Half => "1/2",
+            NoteDivision::HalfDotted => "1/2D",
+            NoteDivision::HalfTriplet => "1/2T",
+            NoteDivision::Quarter => "1/4",
+            NoteDivision::QuarterDotted => "1/4D",
+            NoteDivision::QuarterTriplet => "1/4T",
+            NoteDivision::Eighth => "1/8",
+            NoteDivision::EighthDotted => "1/8D",
+            NoteDivision::EighthTriplet => "1/8T",
+            NoteDivision::Sixteenth => "1/16",
+            NoteDivision::SixteenthDotted => "1/16D",
+            NoteDivision::SixteenthTriplet => "1/16T",
+            NoteDivision::ThirtySecond => "1/32",
+        }
+    }
+}
+
+/// What happens to the notes playing when the host's transport stops, or loops back. Either way,
+/// note offs the host would have sent later never arrive.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StopMode {
+    /// Leave them playing, for hosts that send their own note offs.
+    Keep,
+    /// Release them, so they ring out through their release.
+    Release,
+    /// Silence them straight away.
+    Kill,
+}
+
+impl StopMode {
+    pub const ALL: [StopMode; 3] = [StopMode::Keep, StopMode::Release, StopMode::Kill];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            StopMode::Keep => "Keep",
+            StopMode::Release => "Release",
+            StopMode::Kill => "Kill",
+        }
+    }
+}
+
+/// The host's tempo and transport, read once per buffer.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Transport {
+    /// In beats per minute.
+    pub tempo: f64,
+    /// Where playback is, in beats from the start of the song, while the host is playing.
+    pub position: Option<f64>,
+}
+
+impl Transport {
+    /// Whether playback stopped, or jumped back as a loop comes round, between this and `next`.
+    pub fn is_interrupted_by(&self, next: &Transport) -> bool {
+        match (self.position, next.position) {
+            (Some(_), None) => true,
+            (Some(position), Some(next)) => next < position,
+            (None, _) => false,
+        }
+    }
+}
+
+impl Default for Transport {
+    fn default() -> Transport {
+        Transport { tempo: DEFAULT_TEMPO, position: None }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_division_lengths() {
+        assert_eq!(NoteDivision::Quarter.seconds(120.0), 0.5);
+        assert_eq!(NoteDivision::QuarterDotted.seconds(120.0), 0.75);
+        assert!((NoteDivision::EighthTriplet.seconds(120.0) - 1.0 / 6.0).abs() < 1e-12);
+        assert_eq!(NoteDivision::Whole.seconds(60.0), 4.0);
+
+        // three triplets fill the same time as two straight notes
+        for (straight, triplet) in [
+            (NoteDivision::Half, NoteDivision::HalfTriplet),
+            (NoteDivision::Quarter, NoteDivision::QuarterTriplet),
+            (NoteDivision::Eighth, NoteDivision::EighthTriplet),
+            (NoteDivision::Sixteenth, NoteDivision::SixteenthTriplet),
+        ] {
+            assert!((triplet.beats() * 3.0 - straight.beats() * 2.0).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_stops_and_loops_interrupt() {
+        let at = |position| Transport { tempo: 120.0, position };
+        assert!(at(Some(4.0)).is_interrupted_by(&at(None)));
+        assert!(at(Some(8.0)).is_interrupted_by(&at(Some(4.0))));
+        assert!(!at(Some(4.0)).is_interrupted_by(&at(Some(4.1))));
+        assert!(!at(Some(4.0)).is_interrupted_by(&at(Some(4.0))));
+        // starting playback, wherever from, isn't
+        assert!(!at(None).is_interrupted_by(&at(Some(0.0))));
+        assert!(!at(None).is_interrupted_by(&at(None)));
+    }
+}
@@ -0,0 +1,362 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::midi_pitch_to_freq;
+
+/// The frequency of A4 that tunings are built around, in Hz. The reference pitch parameter
+/// retunes relative to it.
+pub const STANDARD_PITCH: f64 = 440.0;
+
+/// Where a Scala tuning was loaded from, saved with presets so it can be loaded again.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TuningFiles {
+    /// The `.scl` scale file.
+    pub scale: PathBuf,
+    /// The `.kbm` keyboard mapping file, if the scale isn't laid out the default way.
+    #[serde(default)]
+    pub mapping: Option<PathBuf>,
+}
+
+/// Why a tuning couldn't be loaded.
+#[derive(Debug)]
+pub enum TuningError {
+    Io(io::Error),
+    /// A line of a Scala file, counting from 1, and what's wrong with it.
+    Parse(usize, String),
+}
+
+impl fmt::Display for TuningError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TuningError::Io(err) => write!(f, "{}", err),
+            TuningError::Parse(line, message) => write!(f, "line {}: {}", line, message),
+        }
+    }
+}
+
+impl From<io::Error> for TuningError {
+    fn from(err: io::Error) -> TuningError {
+        TuningError::Io(err)
+    }
+}
+
+/// The lines of a Scala file that aren't comments, with their line numbers.
+fn scala_lines(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    text.lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| !line.starts_with('!'))
+}
+
+/// Read the first whitespace separated word of a line, ignoring anything after it.
+fn parse_first<T: std::str::FromStr>((number, line): (usize, &str), what: &str) -> Result<T, TuningError> {
+    line.split_whitespace()
+        .next()
+        .and_then(|word| word.parse().ok())
+        .ok_or_else(|| TuningError::Parse(number, format!("expected {}, found {:?}", what, line)))
+}
+
+/// A scale from a Scala `.scl` file: the pitch of each degree above the first, in cents. The
+/// last degree is the period the scale repeats at, usually an octave.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Scale {
+    pub cents: Vec<f64>,
+}
+
+impl Scale {
+    pub fn parse(text: &str) -> Result<Scale, TuningError> {
+        let mut lines = scala_lines(text);
+        let end = |number| TuningError::Parse(number, "the file ended early".to_string());
+        // the first line is a description, which may be blank
+        lines.next().ok_or_else(|| end(1))?;
+        let count_line = lines.next().ok_or_else(|| end(2))?;
+        let count: usize = parse_first(count_line, "the number of notes")?;
+
+        let mut cents = Vec::with_capacity(count);
+        for _ in 0..count {
+            let (number, line) = lines.next().ok_or_else(|| end(count_line.0))?;
+            let pitch = line.split_whitespace().next().unwrap_or("");
+            let invalid = || TuningError::Parse(number, format!("invalid pitch {:?}", pitch));
+            // cents always have a decimal point, and anything else is a ratio or a whole number
+            let value = if pitch.contains('.') {
+                pitch.parse().map_err(|_| invalid())?
+            } else {
+                let (numerator, denominator) = pitch.split_once('/').unwrap_or((pitch, "1"));
+                let numerator: f64 = numerator.parse().map_err(|_| invalid())?;
+                let denominator: f64 = denominator.parse().map_err(|_| invalid())?;
+                if !(numerator > 0.0 && denominator > 0.0) {
+                    return Err(invalid());
+                }
+                1200.0 * (numerator / denominator).log2()
+            };
+            cents.push(value);
+        }
+        if cents.is_empty() {
+            return Err(TuningError::Parse(count_line.0, "the scale has no notes".to_string()));
+        }
+        Ok(Scale { cents })
+    }
+
+    /// The pitch of a scale degree in cents, counting from 0 at the first degree and carrying on
+    /// into the periods above and below.
+    fn degree_cents(&self, degree: i32) -> f64 {
+        let len = self.cents.len() as i32;
+        let period = self.cents[self.cents.len() - 1];
+        let step = degree.rem_euclid(len);
+        let within = if step == 0 { 0.0 } else { self.cents[step as usize - 1] };
+        f64::from(degree.div_euclid(len)) * period + within
+    }
+}
+
+/// How a scale is laid out across the keys, from a Scala `.kbm` file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeyboardMapping {
+    /// The lowest and highest keys that play, inclusive.
+    pub first_note: u8,
+    pub last_note: u8,
+    /// The key that plays the scale's first degree.
+    pub middle_note: u8,
+    /// The key that's tuned to `reference_freq`, in Hz.
+    pub reference_note: u8,
+    pub reference_freq: f64,
+    /// The scale degree each key plays, going up from the middle note and repeating, with `None`
+    /// for keys that don't play. Empty maps each key to the next degree.
+    pub degrees: Vec<Option<i32>>,
+    /// How many degrees up the pattern of `degrees` moves each time it repeats.
+    pub octave_degree: i32,
+}
+
+impl KeyboardMapping {
+    /// The layout used without a `.kbm` file: every key plays the next degree, with the scale
+    /// starting at middle C tuned as it is in 12-TET.
+    pub fn standard() -> KeyboardMapping {
+        KeyboardMapping {
+            first_note: 0,
+            last_note: 127,
+            middle_note: 60,
+            reference_note: 60,
+            reference_freq: midi_pitch_to_freq(60.0),
+            degrees: Vec::new(),
+            octave_degree: 0,
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<KeyboardMapping, TuningError> {
+        let lines: Vec<_> = scala_lines(text).filter(|(_, line)| !line.is_empty()).collect();
+        let header = |index: usize, what: &str| {
+            lines.get(index).copied().ok_or_else(|| {
+                TuningError::Parse(text.lines().count(), format!("the file ended before {}", what))
+            })
+        };
+        let note = |index, what| -> Result<u8, TuningError> {
+            let line = header(index, what)?;
+            let value: u8 = parse_first(line, what)?;
+            if value > 127 {
+                return Err(TuningError::Parse(line.0, format!("{} is not a midi note", value)));
+            }
+            Ok(value)
+        };
+
+        let size: usize = parse_first(header(0, "the map size")?, "the map size")?;
+        let first_note = note(1, "the first note")?;
+        let last_note = note(2, "the last note")?;
+        let middle_note = note(3, "the middle note")?;
+        let reference_note = note(4, "the reference note")?;
+        let reference_freq = parse_first(header(5, "the reference frequency")?, "the reference frequency")?;
+        let octave_degree = parse_first(header(6, "the octave degree")?, "the octave degree")?;
+        // a file may stop short, leaving the rest of the keys unmapped
+        let mut degrees = Vec::with_capacity(size);
+        for &line in lines.iter().skip(7).take(size) {
+            degrees.push(if line.1.starts_with('x') { None } else { Some(parse_first(line, "a scale degree")?) });
+        }
+        degrees.resize(size, None);
+        Ok(KeyboardMapping {
+            first_note,
+            last_note,
+            middle_note,
+            reference_note,
+            reference_freq,
+            degrees,
+            octave_degree,
+        })
+    }
+
+    /// The scale degree a key plays, whether or not it's mapped.
+    ///
+    /// Unmapped keys are given the degree they'd have if the mapping were linear, so glides and
+    /// bends through them still have a pitch to pass through.
+    fn degree(&self, note: u8) -> (i32, bool) {
+        let offset = i32::from(note) - i32::from(self.middle_note);
+        if self.degrees.is_empty() {
+            return (offset, true);
+        }
+        let size = self.degrees.len() as i32;
+        let repeats = offset.div_euclid(size);
+        match self.degrees[offset.rem_euclid(size) as usize] {
+            Some(degree) => (degree + repeats * self.octave_degree, true),
+            None => (offset, false),
+        }
+    }
+}
+
+/// The frequency of every midi note, and which ones play.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tuning {
+    freqs: [f64; 128],
+    mapped: [bool; 128],
+}
+
+impl Default for Tuning {
+    /// 12-TET, with A4 at the standard pitch.
+    fn default() -> Tuning {
+        Tuning {
+            freqs: std::array::from_fn(|note| midi_pitch_to_freq(note as f64)),
+            mapped: [true; 128],
+        }
+    }
+}
+
+impl Tuning {
+    /// Lay a scale out across the keys.
+    pub fn new(scale: &Scale, mapping: &KeyboardMapping) -> Tuning {
+        let (reference_degree, _) = mapping.degree(mapping.reference_note);
+        let reference_cents = scale.degree_cents(reference_degree);
+        let mut tuning = Tuning { freqs: [0.0; 128], mapped: [false; 128] };
+        for note in 0..=127u8 {
+            let (degree, mapped) = mapping.degree(note);
+            let cents = scale.degree_cents(degree) - reference_cents;
+            tuning.freqs[usize::from(note)] = mapping.reference_freq * (cents / 1200.0).exp2();
+            tuning.mapped[usize::from(note)] =
+                mapped && (mapping.first_note..=mapping.last_note).contains(&note);
+        }
+        tuning
+    }
+
+    /// A tuning from a table of frequencies in Hz, eg. one shared by another plugin. Frequencies
+    /// that aren't positive are replaced with 12-TET.
+    pub fn from_table(freqs: [f64; 128], mapped: [bool; 128]) -> Tuning {
+        let mut tuning = Tuning { freqs, mapped };
+        for (note, freq) in tuning.freqs.iter_mut().enumerate() {
+            if !(freq.is_finite() && *freq > 0.0) {
+                *freq = midi_pitch_to_freq(note as f64);
+            }
+        }
+        tuning
+    }
+
+    /// Read a scale, and its keyboard mapping if there is one, from Scala files.
+    pub fn load(files: &TuningFiles) -> Result<Tuning, TuningError> {
+        let scale = Scale::parse(&fs::read_to_string(&files.scale)?)?;
+        let mapping = match &files.mapping {
+            Some(path) => KeyboardMapping::parse(&fs::read_to_string(path)?)?,
+            None => KeyboardMapping::standard(),
+        };
+        Ok(Tuning::new(&scale, &mapping))
+    }
+
+    /// The frequency of a midi pitch, in Hz. Fractional pitches, eg. during a glide, fall
+    /// between the neighbouring notes in proportion to their interval.
+    pub fn freq(&self, pitch: f64) -> f64 {
+        let pitch = pitch.clamp(0.0, 127.0);
+        let below = (pitch.floor() as usize).min(126);
+        let (low, high) = (self.freqs[below], self.freqs[below + 1]);
+        low * (high / low).powf(pitch - below as f64)
+    }
+
+    /// Whether a key plays at all. Keyboard mappings can leave keys out.
+    pub fn is_mapped(&self, note: u8) -> bool {
+        self.mapped.get(usize::from(note)).copied().unwrap_or(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MEANTONE: &str = "! meanquar.scl
+!
+1/4-comma meantone scale. Pietro Aaron's temperament (1523)
+ 12
+!
+ 76.04900
+ 193.15686
+ 310.26471
+ 5/4
+ 503.42157
+ 579.47057
+ 696.57843
+ 25/16
+ 889.73529
+ 1006.84314
+ 1082.89214
+ 2/1
+";
+
+    #[test]
+    fn test_parse_scale() {
+        let scale = Scale::parse(MEANTONE).unwrap();
+        assert_eq!(scale.cents.len(), 12);
+        assert_eq!(scale.cents[0], 76.049);
+        assert!((scale.cents[3] - 386.3137).abs() < 1e-4);
+        assert!((scale.cents[11] - 1200.0).abs() < 1e-9);
+
+        // degrees carry on into the octaves either side
+        assert!((scale.degree_cents(12) - 1200.0).abs() < 1e-9);
+        assert!((scale.degree_cents(-11) - (76.049 - 1200.0)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_invalid_scales_are_rejected() {
+        assert!(matches!(Scale::parse("no notes\n 0\n"), Err(TuningError::Parse(2, _))));
+        assert!(matches!(Scale::parse("short\n 3\n 100.0\n"), Err(TuningError::Parse(..))));
+        assert!(matches!(Scale::parse("bad\n 1\n 3/-2\n"), Err(TuningError::Parse(3, _))));
+        assert!(matches!(Scale::parse("bad\n 1\n up\n"), Err(TuningError::Parse(3, _))));
+    }
+
+    #[test]
+    fn test_standard_mapping_of_12_tet_matches_default() {
+        let scale = Scale::parse("12-TET\n12\n100.\n200.\n300.\n400.\n500.\n600.\n700.\n800.\n900.\n1000.\n1100.\n2/1\n")
+            .unwrap();
+        let tuning = Tuning::new(&scale, &KeyboardMapping::standard());
+        let default = Tuning::default();
+        for note in 0..128 {
+            assert!((tuning.freqs[note] / default.freqs[note] - 1.0).abs() < 1e-9);
+        }
+        assert!((default.freq(69.0) - STANDARD_PITCH).abs() < 1e-9);
+        // halfway between A and B flat is a quarter tone up
+        assert!((default.freq(69.5) - STANDARD_PITCH * (1.0f64 / 24.0).exp2()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_keyboard_mapping() {
+        // a pentatonic scale on the white keys from C, with the black keys and everything below
+        // C2 left out. A4 is 432Hz.
+        let scale = Scale::parse("pentatonic\n5\n200.\n400.\n700.\n900.\n2/1\n").unwrap();
+        let mapping = KeyboardMapping::parse("! white keys\n12\n36\n127\n60\n69\n432.0\n5\n\
+            0\nx\n1\nx\n2\nx\nx\n3\nx\n4\nx\nx\n").unwrap();
+        assert_eq!(mapping.degrees.len(), 12);
+        let tuning = Tuning::new(&scale, &mapping);
+
+        assert!((tuning.freq(69.0) - 432.0).abs() < 1e-9);
+        // G is a fifth above C, and C5 an octave above C4
+        assert!((tuning.freq(67.0) / tuning.freq(60.0) - 1.5).abs() < 0.01);
+        assert!((tuning.freq(72.0) / tuning.freq(60.0) - 2.0).abs() < 1e-9);
+        assert!(tuning.is_mapped(60) && !tuning.is_mapped(61) && !tuning.is_mapped(24));
+    }
+
+    #[test]
+    fn test_tuning_table_is_sanitized() {
+        let mut freqs = [100.0; 128];
+        freqs[69] = f64::NAN;
+        freqs[70] = 0.0;
+        let tuning = Tuning::from_table(freqs, [true; 128]);
+        assert_eq!(tuning.freq(68.0), 100.0);
+        assert_eq!(tuning.freq(69.0), STANDARD_PITCH);
+        assert!(tuning.freq(70.0) > STANDARD_PITCH);
+        assert!(tuning.freq(69.5).is_finite());
+    }
+}
@@ -0,0 +1,945 @@
+use std::ops::RangeInclusive;
+
+use crate::envelope::{shape, ADSREnvelope, ADSRParams, ADSRPhase, IsDone};
+use crate::filter::{FilterType, StateVariableFilter};
+use crate::lfo::{Drift, XorShift};
+use crate::modulation::{ModSlot, ModSource, Modulation, Targets, NUM_MOD_SLOTS};
+use crate::smoothing::SmoothedParam;
+use crate::tuning::{Tuning, STANDARD_PITCH};
+use crate::oversampling::{Decimator, Oversampling, MAX_OVERSAMPLING};
+use crate::simd::F64x2;
+use crate::wavetable::Wavetable;
+use crate::oscillator::{
+    Modulator, Noise, NoiseColor, SubOscillator, SubWaveform, SyncedOscillator, Waveform, MAX_PULSE_WIDTH,
+    MIN_PULSE_WIDTH,
+};
+use crate::{cents_to_ratio, lerp, velocity_amount, PITCH_BEND_SMOOTHING, TAU};
+
+/// How note velocity maps onto a voice's level.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VelocityCurve {
+    Linear,
+    /// Quieter notes are louder than linear, so less effort is needed to play loudly.
+    Soft,
+    /// Quieter notes are quieter than linear, so it takes more effort to play loudly.
+    Hard,
+}
+
+impl VelocityCurve {
+    pub const ALL: [VelocityCurve; 3] = [VelocityCurve::Linear, VelocityCurve::Soft, VelocityCurve::Hard];
+
+    /// The level of a note played at `velocity`, from 0.0 to 1.0.
+    pub fn gain(self, velocity: u8) -> f64 {
+        let amount = velocity_amount(velocity);
+        match self {
+            VelocityCurve::Linear => amount,
+            VelocityCurve::Soft => amount.sqrt(),
+            VelocityCurve::Hard => amount * amount,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            VelocityCurve::Linear => "Linear",
+            VelocityCurve::Soft => "Soft",
+            VelocityCurve::Hard => "Hard",
+        }
+    }
+}
+
+/// The most oscillators that can play each note.
+pub const MAX_UNISON: usize = 7;
+
+/// How many stereo outputs the voices can be routed between, the main one included.
+pub const NUM_OUTPUTS: usize = 4;
+
+/// How the unison oscillators are detuned and panned.
+#[derive(Clone, Copy, Debug)]
+pub struct Unison {
+    /// How many oscillators play, up to `MAX_UNISON`.
+    pub count: usize,
+    /// Each oscillator's frequency multiplier.
+    pub ratios: [f64; MAX_UNISON],
+    /// Each oscillator's left and right gains. Each channel's gains add up to 1.0, so stacking
+    /// oscillators doesn't get louder.
+    pub gains: [(f64, f64); MAX_UNISON],
+}
+
+impl Unison {
+    /// Spread `count` oscillators evenly across `detune` cents, and pan them evenly across the
+    /// stereo field, where `spread` is 0.0 for all centred up to 1.0 for hard left and right.
+    pub fn new(count: usize, detune: f64, spread: f64) -> Unison {
+        let count = count.clamp(1, MAX_UNISON);
+        let mut unison = Unison { count, ratios: [1.0; MAX_UNISON], gains: [(1.0, 1.0); MAX_UNISON] };
+        if count == 1 {
+            return unison;
+        }
+
+        for i in 0..count {
+            // -1.0 for the lowest, leftmost oscillator to 1.0 for the highest, rightmost
+            let position = 2.0 * i as f64 / (count - 1) as f64 - 1.0;
+            unison.ratios[i] = cents_to_ratio(position * detune / 2.0);
+            let pan = position * spread;
+            unison.gains[i] = ((1.0 - pan).min(1.0), (1.0 + pan).min(1.0));
+        }
+        let (total_left, total_right) = unison.gains[..count].iter()
+            .fold((0.0, 0.0), |(left, right), gains| (left + gains.0, right + gains.1));
+        for gains in &mut unison.gains[..count] {
+            *gains = (gains.0 / total_left, gains.1 / total_right);
+        }
+        unison
+    }
+}
+
+/// How long a stolen voice takes to fade out, in seconds.
+const STEAL_FADE_TIME: f64 = 0.005;
+
+/// How long a retriggered voice takes to crossfade from where it was to the new note, in seconds.
+const DECLICK_TIME: f64 = 0.003;
+
+/// Which voice to take over when a note is played and all voices are busy.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StealPolicy {
+    Oldest,
+    /// The voice with the lowest envelope level.
+    Quietest,
+    /// A voice already playing the same note, or else the oldest voice.
+    SameNoteFirst,
+}
+
+impl StealPolicy {
+    pub const ALL: [StealPolicy; 3] = [StealPolicy::Oldest, StealPolicy::Quietest, StealPolicy::SameNoteFirst];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            StealPolicy::Oldest => "Oldest",
+            StealPolicy::Quietest => "Quietest",
+            StealPolicy::SameNoteFirst => "Same Note",
+        }
+    }
+}
+
+/// How a voice's envelopes restart when it's played again while it's still sounding, as the mono
+/// voice is on every note change.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Retrigger {
+    /// Attack again from the current level, so the level never jumps.
+    FromCurrent,
+    /// Attack again from silence, for a fresh start to every note. The drop is crossfaded over a
+    /// few milliseconds, so it doesn't click.
+    FromZero,
+    /// Carry on with the envelopes where they are while the voice is held, only changing the note.
+    /// A released voice attacks again from its current level.
+    Legato,
+}
+
+impl Retrigger {
+    pub const ALL: [Retrigger; 3] = [Retrigger::FromCurrent, Retrigger::FromZero, Retrigger::Legato];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Retrigger::FromCurrent => "From Current",
+            Retrigger::FromZero => "From Zero",
+            Retrigger::Legato => "Legato",
+        }
+    }
+}
+
+/// What the mono glide time measures.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GlideMode {
+    /// Every glide takes the glide time, however far apart the notes are.
+    ConstantTime,
+    /// The glide time is how long it takes to slide an octave, so wider intervals take longer.
+    ConstantRate,
+}
+
+impl GlideMode {
+    pub const ALL: [GlideMode; 2] = [GlideMode::ConstantTime, GlideMode::ConstantRate];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            GlideMode::ConstantTime => "Constant Time",
+            GlideMode::ConstantRate => "Constant Rate",
+        }
+    }
+}
+
+/// How the pitch moves over the course of a glide. Either way it moves in semitones, not Hz, so
+/// a glide sounds the same anywhere on the keyboard.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GlideCurve {
+    /// A steady slide from one note to the next.
+    Linear,
+    /// Quickly at first, easing into the new note, like an analog portamento.
+    Exponential,
+}
+
+impl GlideCurve {
+    pub const ALL: [GlideCurve; 2] = [GlideCurve::Linear, GlideCurve::Exponential];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            GlideCurve::Linear => "Linear",
+            GlideCurve::Exponential => "Exponential",
+        }
+    }
+
+    /// How far through the interval the pitch is, 0.0 to 1.0, given how far through the glide's
+    /// time.
+    fn shape(self, progress: f64) -> f64 {
+        match self {
+            GlideCurve::Linear => progress,
+            GlideCurve::Exponential => shape(progress, 1.0),
+        }
+    }
+}
+
+/// Where each new voice of the poly engine sits in the stereo field, before the pan width scales
+/// it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PanMode {
+    Centre,
+    /// Every other voice to the left, and the rest to the right.
+    Alternate,
+    /// Low notes to the left and high notes to the right, like a piano from the player's seat.
+    Keyboard,
+    /// Anywhere, picked afresh for each note.
+    Random,
+}
+
+impl PanMode {
+    pub const ALL: [PanMode; 4] = [PanMode::Centre, PanMode::Alternate, PanMode::Keyboard, PanMode::Random];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            PanMode::Centre => "Centre",
+            PanMode::Alternate => "Alternate",
+            PanMode::Keyboard => "Keyboard",
+            PanMode::Random => "Random",
+        }
+    }
+}
+
+/// Picks where new voices sit, remembering which side the last alternate voice went to.
+#[derive(Debug)]
+pub struct VoicePanner {
+    right: bool,
+    rng: XorShift,
+}
+
+impl VoicePanner {
+    pub fn new() -> VoicePanner {
+        VoicePanner { right: true, rng: XorShift(0x2545_f491) }
+    }
+
+    /// The position of a new voice playing `note`, from -1.0 (left) to 1.0 (right).
+    pub fn next(&mut self, mode: PanMode, note: u8) -> f64 {
+        match mode {
+            PanMode::Centre => 0.0,
+            PanMode::Alternate => {
+                self.right = !self.right;
+                if self.right { 1.0 } else { -1.0 }
+            }
+            // centred between E4 and F4, in the middle of the midi range
+            PanMode::Keyboard => (f64::from(note) - 63.5) / 63.5,
+            PanMode::Random => self.rng.next_bipolar(),
+        }
+    }
+}
+
+/// Which of the stereo outputs each new voice plays through, so the host can process them apart.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputRouting {
+    /// Every voice through the main output.
+    Main,
+    /// Each new voice through the next output along, and back to the main one after the last.
+    RoundRobin,
+    /// The key zone split evenly between the outputs, the lowest keys through the main one.
+    ByKey,
+}
+
+impl OutputRouting {
+    pub const ALL: [OutputRouting; 3] = [OutputRouting::Main, OutputRouting::RoundRobin, OutputRouting::ByKey];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            OutputRouting::Main => "Main",
+            OutputRouting::RoundRobin => "Round Robin",
+            OutputRouting::ByKey => "By Key",
+        }
+    }
+}
+
+/// Picks the output each new voice plays through, remembering where round robin got to.
+#[derive(Debug, Default)]
+pub struct OutputRouter {
+    next: usize,
+}
+
+impl OutputRouter {
+    /// The output for a new voice playing `note`, from 0 (the main one) to `NUM_OUTPUTS - 1`.
+    /// `keys` is the key zone.
+    pub fn next(&mut self, routing: OutputRouting, note: u8, keys: RangeInclusive<u8>) -> usize {
+        match routing {
+            OutputRouting::Main => 0,
+            OutputRouting::RoundRobin => {
+                let output = self.next;
+                self.next = (self.next + 1) % NUM_OUTPUTS;
+                output
+            }
+            OutputRouting::ByKey => {
+                let (low, high) = (*keys.start(), *keys.end());
+                if high <= low {
+                    return 0;
+                }
+                let key = usize::from(note.clamp(low, high) - low);
+                key * NUM_OUTPUTS / (usize::from(high - low) + 1)
+            }
+        }
+    }
+}
+
+/// Which of the held notes the mono path plays.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NotePriority {
+    Last,
+    Low,
+    High,
+}
+
+impl NotePriority {
+    pub const ALL: [NotePriority; 3] = [NotePriority::Last, NotePriority::Low, NotePriority::High];
+
+    /// Pick a note from `held_notes`, which are `(note, velocity)` pairs in the order they were
+    /// pressed.
+    pub fn choose(self, held_notes: &[(u8, u8)]) -> Option<(u8, u8)> {
+        let mut notes = held_notes.iter().cloned();
+        match self {
+            NotePriority::Last => notes.next_back(),
+            NotePriority::Low => notes.min_by_key(|&(note, _)| note),
+            NotePriority::High => notes.max_by_key(|&(note, _)| note),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            NotePriority::Last => "Last",
+            NotePriority::Low => "Low",
+            NotePriority::High => "High",
+        }
+    }
+}
+
+/// A note's own expression, from an MPE controller or a host that sends it per note.
+#[derive(Clone, Copy, Debug)]
+pub struct Expression {
+    /// Pitch bend, -1.0 to 1.0, glided towards the latest value to avoid stepping.
+    pub bend: SmoothedParam,
+    /// A pitch offset in semitones, on top of the bend.
+    pub tuning: f64,
+    /// Pressure, 0.0 to 1.0, which takes the place of channel pressure.
+    pub pressure: f64,
+    /// CC74, 0.0 to 1.0, which takes the place of the channel's.
+    pub timbre: f64,
+}
+
+impl Expression {
+    /// Expression at rest, as on a channel that hasn't been sent anything yet.
+    pub fn new(time_per_sample: f64) -> Expression {
+        Expression {
+            bend: SmoothedParam::new(0.0, PITCH_BEND_SMOOTHING, time_per_sample),
+            tuning: 0.0,
+            pressure: 0.0,
+            timbre: 0.0,
+        }
+    }
+
+    /// Take on a new value for one of the expressions.
+    pub fn apply(&mut self, expression: NoteExpression) {
+        match expression {
+            NoteExpression::Tuning(semitones) => self.tuning = semitones,
+            NoteExpression::Pressure(pressure) => self.pressure = pressure.clamp(0.0, 1.0),
+            NoteExpression::Brightness(brightness) => self.timbre = brightness.clamp(0.0, 1.0),
+        }
+    }
+}
+
+/// A change to one of a note's expressions, as plugin formats with per note expression send
+/// them.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NoteExpression {
+    /// An offset from the note's pitch, in semitones.
+    Tuning(f64),
+    /// 0.0 to 1.0.
+    Pressure(f64),
+    /// 0.0 to 1.0, in place of CC74.
+    Brightness(f64),
+}
+
+/// Settings shared by every voice, read from the plugin parameters once per buffer.
+#[derive(Clone, Copy, Debug)]
+pub struct VoiceParams {
+    pub adsr: ADSRParams,
+    pub waveform: Waveform,
+    /// The square wave's pulse width before modulation, as a fraction of the cycle.
+    pub pulse_width: f64,
+    /// How far through the wavetable the oscillators read before modulation, 0.0 to 1.0.
+    pub wavetable_position: f64,
+    pub unison: Unison,
+    pub sync: bool,
+    pub sync_ratio: f64,
+    pub velocity_curve: VelocityCurve,
+    /// How much note velocity shortens the attack, 0.0 to 1.0.
+    pub vel_to_attack: f64,
+    pub retrigger: Retrigger,
+    /// The mono glide time, in seconds, per glide or per octave as `glide_mode` says.
+    pub glide_time: f64,
+    pub glide_mode: GlideMode,
+    pub glide_curve: GlideCurve,
+
+    pub filter_type: FilterType,
+    /// Filter cutoff before modulation, in Hz.
+    pub cutoff: f64,
+    pub resonance: f64,
+    pub filter_adsr: ADSRParams,
+    /// How far the filter envelope moves the cutoff at its peak, in octaves.
+    pub filter_env_amount: f64,
+    /// How far a full velocity note raises the cutoff, in octaves.
+    pub vel_to_cutoff: f64,
+
+    pub mod_adsr: ADSRParams,
+    pub mod_matrix: [ModSlot; NUM_MOD_SLOTS],
+
+    /// The FM modulator's frequency, as a multiple of the note's.
+    pub fm_ratio: f64,
+    /// The peak FM modulation index, in radians of the carrier's phase.
+    pub fm_index: f64,
+    pub fm_adsr: ADSRParams,
+
+    /// How far the pitch envelope bends each note at its start, in semitones, and how long it
+    /// takes to settle back onto the note, in seconds.
+    pub pitch_env_amount: f64,
+    pub pitch_env_decay: f64,
+
+    /// How much of the sync pair's product is blended in, 0.0 to 1.0.
+    pub ring_mod: f64,
+    /// The levels of the main oscillators, the sub-oscillator and the noise, 0.0 to 1.0.
+    pub osc_level: f64,
+    pub sub_level: f64,
+    pub noise_level: f64,
+    /// How many octaves below the note the sub-oscillator plays.
+    pub sub_octave: i32,
+    pub sub_waveform: SubWaveform,
+    pub noise_color: NoiseColor,
+
+    /// How far MPE notes' own pitch bend reaches, in semitones.
+    pub mpe_bend_range: f64,
+    /// The most each voice's pitch wanders, in cents.
+    pub drift: f64,
+    pub oversampling: Oversampling,
+    /// How far the voices' own pans reach, 0.0 to 1.0.
+    pub pan_width: f64,
+
+    /// The frequency of each note, with A4 at `STANDARD_PITCH`.
+    pub tuning: Tuning,
+    /// The frequency A4 is actually tuned to, in Hz.
+    pub reference_pitch: f64,
+}
+
+/// Everything about a voice that's worked out at control rate, once per block, and interpolated
+/// across the samples in between. Nothing in it depends on the sample rate, so it carries over a
+/// change of rate.
+#[derive(Clone, Copy, Debug)]
+struct Control {
+    // the oscillators' frequency in Hz, before unison detune
+    freq: f64,
+    fm_index: f64,
+    pulse_width: f64,
+    wavetable_position: f64,
+    sync_ratio: f64,
+    osc_mix: f64,
+    // in Hz, with the envelope and modulation applied
+    cutoff: f64,
+    // velocity, modulation and pan together. the envelope's applied a sample at a time on top
+    level_left: f64,
+    level_right: f64,
+}
+
+impl Control {
+    fn lerp(&self, to: &Control, t: f64) -> Control {
+        Control {
+            freq: lerp(self.freq, to.freq, t),
+            fm_index: lerp(self.fm_index, to.fm_index, t),
+            pulse_width: lerp(self.pulse_width, to.pulse_width, t),
+            wavetable_position: lerp(self.wavetable_position, to.wavetable_position, t),
+            sync_ratio: lerp(self.sync_ratio, to.sync_ratio, t),
+            osc_mix: lerp(self.osc_mix, to.osc_mix, t),
+            cutoff: lerp(self.cutoff, to.cutoff, t),
+            level_left: lerp(self.level_left, to.level_left, t),
+            level_right: lerp(self.level_right, to.level_right, t),
+        }
+    }
+}
+
+/// A single sounding note.
+#[derive(Debug)]
+pub struct Voice {
+    pub note: u8,
+    /// The pitch we're playing, in midi notes, which lags behind `note` while gliding.
+    pub pitch: f64,
+    // the pitch the current glide started from, how long it's been going and how long it takes,
+    // in seconds
+    glide_from: f64,
+    glide_elapsed: f64,
+    glide_duration: f64,
+    // how long it's been since the pitch envelope started, in seconds
+    pitch_env_elapsed: f64,
+    pub velocity: u8,
+    /// The midi channel the note was played on, 0 to 15.
+    pub channel: u8,
+    /// Set for MPE notes, which are bent and shaped on their own.
+    pub expression: Option<Expression>,
+    /// Polyphonic key pressure, 0.0 to 1.0.
+    pub pressure: f64,
+    /// Set when the note has been released while the sustain pedal is down.
+    pub sustained: bool,
+    /// Where the voice sits in the stereo field, from -1.0 (left) to 1.0 (right), before the pan
+    /// width scales it.
+    pub pan: f64,
+    /// The stereo output the voice plays through, 0 for the main one.
+    pub output: usize,
+    // set by the note off, which a one shot amp envelope doesn't show
+    released: bool,
+    // once stolen, the level the voice is fading out from, 1.0 down to 0.0
+    steal_fade: Option<f64>,
+    // the last output, and how much of it is still being crossfaded from after a retrigger
+    last_output: (f64, f64),
+    declick: f64,
+    // velocity modulation of the level and attack time, fixed for the duration of each note
+    gain: f64,
+    attack_scale: f64,
+    // velocity modulation of the cutoff in octaves, fixed for the duration of each note
+    cutoff_offset: f64,
+    // one oscillator pair and FM modulator per unison voice, and a filter for both output channels
+    oscillators: [SyncedOscillator; MAX_UNISON],
+    modulators: [Modulator; MAX_UNISON],
+    sub: SubOscillator,
+    noise: Noise,
+    drift: Drift,
+    filter: StateVariableFilter,
+    // bring each output channel back down to the sample rate when oversampling
+    decimators: [Decimator; 2],
+    pub envelope: ADSREnvelope,
+    // whether the amp envelope has finished its release, as of the last sample
+    envelope_done: IsDone,
+    // as of the end of the last block, or None before the first
+    control: Option<Control>,
+    pub filter_envelope: ADSREnvelope,
+    pub mod_envelope: ADSREnvelope,
+    pub fm_envelope: ADSREnvelope,
+}
+
+impl Voice {
+    pub fn new(note: u8, velocity: u8, params: &VoiceParams) -> Voice {
+        let mut voice = Voice {
+            note,
+            pitch: f64::from(note),
+            glide_from: f64::from(note),
+            glide_elapsed: 0.0,
+            glide_duration: 0.0,
+            pitch_env_elapsed: 0.0,
+            velocity,
+            channel: 0,
+            expression: None,
+            pressure: 0.0,
+            sustained: false,
+            pan: 0.0,
+            output: 0,
+            released: false,
+            steal_fade: None,
+            last_output: (0.0, 0.0),
+            declick: 0.0,
+            gain: 1.0,
+            attack_scale: 1.0,
+            cutoff_offset: 0.0,
+            // staggered by the golden ratio, so stacked oscillators don't all start in phase
+            oscillators: std::array::from_fn(|i| {
+                SyncedOscillator::with_phase((i as f64 * 0.618_034).fract())
+            }),
+            modulators: Default::default(),
+            sub: SubOscillator::default(),
+            // a different sequence for each note, so chords don't stack up identical noise
+            noise: Noise::new(u32::from(note).wrapping_mul(0x9e37_79b9)),
+            // never 0, which the generator would get stuck on
+            drift: Drift::new((u32::from(note) + 1).wrapping_mul(0x85eb_ca6b)),
+            filter: StateVariableFilter::new(),
+            decimators: [Decimator::new(), Decimator::new()],
+            envelope: ADSREnvelope::new(),
+            envelope_done: IsDone::Continue,
+            control: None,
+            filter_envelope: ADSREnvelope::new(),
+            mod_envelope: ADSREnvelope::new(),
+            fm_envelope: ADSREnvelope::new(),
+        };
+        voice.note_on(velocity, params);
+        voice.declick = 0.0;
+        voice
+    }
+
+    /// Start (or restart) the note. How a sounding voice's envelopes restart is up to
+    /// `params.retrigger`.
+    ///
+    /// Restarting can still jump the level with the new velocity, and the filter with its
+    /// envelope, so a restarted voice also crossfades from where it was over `DECLICK_TIME`. A new
+    /// voice has nothing to crossfade from, so it starts as sharply as the envelope says.
+    pub fn note_on(&mut self, velocity: u8, params: &VoiceParams) {
+        if params.retrigger == Retrigger::Legato && self.is_held() {
+            self.sustained = false;
+            return;
+        }
+        let from_zero = params.retrigger == Retrigger::FromZero;
+        let start = |alpha: f64| if from_zero { 0.0 } else { alpha };
+        self.declick = 1.0;
+        self.envelope.note_on(start(self.alpha(params)));
+        self.envelope_done = IsDone::Continue;
+        self.filter_envelope.note_on(start(self.filter_envelope.alpha(&params.filter_adsr)));
+        self.mod_envelope.note_on(start(self.mod_envelope.alpha(&params.mod_adsr)));
+        self.fm_envelope.note_on(start(self.fm_envelope.alpha(&params.fm_adsr)));
+        self.pitch_env_elapsed = 0.0;
+        self.velocity = velocity;
+        self.sustained = false;
+        self.released = false;
+        self.gain = params.velocity_curve.gain(velocity);
+
+        // at full depth, the hardest notes get an attack 16 times shorter than the softest
+        self.attack_scale = (-4.0 * params.vel_to_attack * velocity_amount(velocity)).exp2();
+        self.cutoff_offset = params.vel_to_cutoff * velocity_amount(velocity);
+    }
+
+    pub fn note_off(&mut self, params: &VoiceParams) {
+        self.released = true;
+        self.envelope.note_off(&self.adsr(params));
+        self.filter_envelope.note_off(&params.filter_adsr);
+        self.mod_envelope.note_off(&params.mod_adsr);
+        self.fm_envelope.note_off(&params.fm_adsr);
+    }
+
+    /// Change the note, sliding from the current pitch to the new one as the glide settings say.
+    pub fn glide_to(&mut self, note: u8, params: &VoiceParams) {
+        let distance = (f64::from(note) - self.pitch).abs();
+        self.note = note;
+        self.glide_from = self.pitch;
+        self.glide_elapsed = 0.0;
+        self.glide_duration = match params.glide_mode {
+            GlideMode::ConstantTime => params.glide_time,
+            GlideMode::ConstantRate => params.glide_time * distance / 12.0,
+        };
+    }
+
+    /// Quickly fade the voice out, so it can be replaced by a new note without clicking.
+    pub fn steal(&mut self) {
+        self.steal_fade = Some(1.0);
+    }
+
+    pub fn is_stolen(&self) -> bool {
+        self.steal_fade.is_some()
+    }
+
+    /// Whether the note is still held, ie. hasn't been released or stolen yet.
+    pub fn is_held(&self) -> bool {
+        !self.is_stolen() && !self.released && self.envelope.current_phase != ADSRPhase::Release
+    }
+
+    /// Whether the voice is silent, after its release or steal fade, and can be dropped.
+    pub fn is_finished(&self) -> bool {
+        match self.steal_fade {
+            Some(fade) => fade <= 0.0,
+            None => self.envelope_done == IsDone::Done,
+        }
+    }
+
+    /// The current envelope level.
+    pub fn alpha(&self, params: &VoiceParams) -> f64 {
+        self.envelope.alpha(&self.adsr(params))
+    }
+
+    /// How far the pitch envelope bends the note right now, in semitones. It drops quickly at
+    /// first and eases onto the note, like an envelope with a curve of 1.0, for the pitch sweep
+    /// of a kick or tom.
+    fn pitch_env(&self, params: &VoiceParams) -> f64 {
+        if params.pitch_env_amount == 0.0 {
+            return 0.0;
+        }
+        let progress = if params.pitch_env_decay > 0.0 { self.pitch_env_elapsed / params.pitch_env_decay } else { 1.0 };
+        params.pitch_env_amount * (1.0 - shape(progress, 1.0))
+    }
+
+    /// The filter cutoff in Hz, with the filter envelope and velocity modulation applied.
+    pub fn cutoff(&self, params: &VoiceParams) -> f64 {
+        let env = self.filter_envelope.alpha(&params.filter_adsr);
+        let octaves = params.filter_env_amount * env + self.cutoff_offset;
+        params.cutoff * octaves.exp2()
+    }
+
+    /// The envelope settings for this note, with velocity modulation applied.
+    fn adsr(&self, params: &VoiceParams) -> ADSRParams {
+        let mut adsr = params.adsr;
+        adsr.attack *= self.attack_scale;
+        adsr
+    }
+
+    /// Render a block of samples into `left` and `right`. `modulation` is the shared modulation
+    /// as of the end of the block, and `params.cutoff` the cutoff before modulation. `wavetable`
+    /// is the table the `Wavetable` or `Additive` waveform plays.
+    ///
+    /// The glide, modulation and all but the amp envelope move on once for the whole block, and
+    /// the voice glides linearly from where they were at the end of the last one to where they are
+    /// now. The amp envelope is worked out for every sample, so short attacks stay sharp. With
+    /// oversampling on, the oscillators and filter run that many times per sample, and the result
+    /// is filtered back down to the sample rate.
+    pub fn render(&mut self, params: &VoiceParams, modulation: &Modulation, wavetable: &Wavetable,
+                  time_per_sample: f64, left: &mut [f64], right: &mut [f64])
+    {
+        let len = left.len().min(right.len());
+        if len == 0 {
+            return;
+        }
+        // a new voice starts from where its envelopes and modulation start
+        let from = match self.control {
+            Some(control) => control,
+            None => self.control(params, modulation),
+        };
+        // the amp envelope's levels go in the output until each sample's rendered over its own
+        self.envelope_done = self.envelope.process_block(time_per_sample, &self.adsr(params), &mut left[..len]);
+        self.advance(params, len, time_per_sample);
+        let to = self.control(params, modulation);
+        self.control = Some(to);
+
+        let factor = params.oversampling.factor();
+        let steps = (len * factor) as f64;
+        let time_per_step = time_per_sample / factor as f64;
+        let mut oversampled = [[0.0; MAX_OVERSAMPLING]; 2];
+        let outputs = left.iter_mut().zip(right.iter_mut());
+        for (idx, (left, right)) in outputs.enumerate() {
+            let [oversampled_left, oversampled_right] = &mut oversampled;
+            let oversampled_outputs = oversampled_left.iter_mut().zip(oversampled_right.iter_mut());
+            let alpha = *left;
+            for (step, (left, right)) in oversampled_outputs.take(factor).enumerate() {
+                let mut control = from.lerp(&to, (idx * factor + step + 1) as f64 / steps);
+                control.level_left *= alpha;
+                control.level_right *= alpha;
+                (*left, *right) = self.next_sample(params, &control, wavetable, time_per_step);
+            }
+            let [decimator_left, decimator_right] = &mut self.decimators;
+            *left = decimator_left.process(&oversampled[0][..factor]);
+            *right = decimator_right.process(&oversampled[1][..factor]);
+        }
+    }
+
+    /// Move the glide, expression and all but the amp envelope on by `samples`.
+    fn advance(&mut self, params: &VoiceParams, samples: usize, time_per_sample: f64) {
+        let time = samples as f64 * time_per_sample;
+        let target = f64::from(self.note);
+        self.glide_elapsed += time;
+        self.pitch = if self.glide_elapsed >= self.glide_duration {
+            target
+        } else {
+            let progress = params.glide_curve.shape(self.glide_elapsed / self.glide_duration);
+            lerp(self.glide_from, target, progress)
+        };
+
+        if let Some(expression) = &mut self.expression {
+            expression.bend.advance(samples);
+        }
+        self.drift.advance(time);
+        self.pitch_env_elapsed += time;
+        self.filter_envelope.inc_timer(time, &params.filter_adsr);
+        self.mod_envelope.inc_timer(time, &params.mod_adsr);
+        self.fm_envelope.inc_timer(time, &params.fm_adsr);
+    }
+
+    /// Work out the control rate values, as of now.
+    fn control(&self, params: &VoiceParams, modulation: &Modulation) -> Control {
+        let targets = self.mod_targets(params, modulation);
+
+        let bend = self.expression
+            .map_or(0.0, |expression| expression.bend.get() * params.mpe_bend_range + expression.tuning);
+        let drift = self.drift.get() * params.drift / 100.0;
+        let semitones = targets.semitones + bend + drift + self.pitch_env(params);
+        let pitch_ratio = modulation.pitch_ratio * (semitones / 12.0).exp2();
+        let freq = params.tuning.freq(self.pitch) * params.reference_pitch / STANDARD_PITCH;
+
+        let level = self.gain * targets.gain;
+        let pan = (targets.pan + self.pan * params.pan_width).clamp(-1.0, 1.0);
+        Control {
+            freq: freq * pitch_ratio,
+            // the FM envelope shapes the modulation index
+            fm_index: params.fm_index * self.fm_envelope.alpha(&params.fm_adsr),
+            pulse_width: (params.pulse_width + targets.pulse_width).clamp(MIN_PULSE_WIDTH, MAX_PULSE_WIDTH),
+            wavetable_position: (params.wavetable_position + targets.wavetable_position).clamp(0.0, 1.0),
+            sync_ratio: params.sync_ratio * targets.sync_octaves.exp2(),
+            osc_mix: targets.osc_mix,
+            cutoff: self.cutoff(params) * targets.cutoff_octaves.exp2(),
+            // balance rather than constant power panning, so the centre is untouched
+            level_left: level * (1.0 - pan).min(1.0),
+            level_right: level * (1.0 + pan).min(1.0),
+        }
+    }
+
+    /// Render one sample, returning the left and right outputs.
+    fn next_sample(&mut self, params: &VoiceParams, control: &Control, wavetable: &Wavetable,
+                   time_per_sample: f64) -> (f64, f64)
+    {
+        let Control { freq, fm_index, pulse_width, sync_ratio, .. } = *control;
+        let phase_inc = freq * time_per_sample;
+
+        // with sync on we hear the slave oscillator, otherwise the master. the osc mix
+        // crossfades towards the other one.
+        let (mut signal_left, mut signal_right) = (0.0, 0.0);
+        let frame = wavetable.at(control.wavetable_position);
+        let unison = &params.unison;
+        let oscillators = self.oscillators.iter_mut()
+            .zip(&mut self.modulators)
+            .zip(&unison.ratios)
+            .zip(&unison.gains);
+        for (((osc, modulator), &ratio), &(gain_left, gain_right)) in oscillators.take(unison.count) {
+            let phase_inc = phase_inc * ratio;
+            let phase_offset = if fm_index > 0.0 {
+                modulator.next_sample(phase_inc * params.fm_ratio) * fm_index / TAU
+            } else {
+                0.0
+            };
+            let (master, slave) = if matches!(params.waveform, Waveform::Wavetable | Waveform::Additive) {
+                osc.next_shape_sample(&frame, phase_inc, sync_ratio, params.sync, phase_offset)
+            } else {
+                osc.next_sample(params.waveform, pulse_width, phase_inc, sync_ratio, params.sync, phase_offset)
+            };
+            let (main, other) = if params.sync { (slave, master) } else { (master, slave) };
+            let signal = main + (other - main) * control.osc_mix;
+            let signal = signal + (master * slave - signal) * params.ring_mod;
+            signal_left += signal * gain_left;
+            signal_right += signal * gain_right;
+        }
+
+        // the sub-oscillator and noise are mono, so they're mixed equally into both channels
+        let mut mono = 0.0;
+        if params.sub_level > 0.0 {
+            let sub_phase_inc = phase_inc * (-params.sub_octave as f64).exp2();
+            mono += self.sub.next_sample(params.sub_waveform, sub_phase_inc) * params.sub_level;
+        }
+        if params.noise_level > 0.0 {
+            mono += self.noise.next_sample(params.noise_color) * params.noise_level;
+        }
+        let signal_left = signal_left * params.osc_level + mono;
+        let signal_right = signal_right * params.osc_level + mono;
+
+        let [signal_left, signal_right] = self.filter
+            .process(F64x2::new(signal_left, signal_right), params.filter_type, control.cutoff, params.resonance,
+                     time_per_sample)
+            .to_array();
+
+        let mut fade = 1.0;
+        if let Some(steal_fade) = &mut self.steal_fade {
+            fade = *steal_fade;
+            *steal_fade = (*steal_fade - time_per_sample / STEAL_FADE_TIME).max(0.0);
+        }
+        let mut output = (signal_left * control.level_left * fade, signal_right * control.level_right * fade);
+
+        if self.declick > 0.0 {
+            let (last_left, last_right) = self.last_output;
+            output.0 += (last_left - output.0) * self.declick;
+            output.1 += (last_right - output.1) * self.declick;
+            self.declick = (self.declick - time_per_sample / DECLICK_TIME).max(0.0);
+        }
+        self.last_output = output;
+        output
+    }
+
+    /// Evaluate the modulation matrix for this voice.
+    fn mod_targets(&self, params: &VoiceParams, modulation: &Modulation) -> Targets {
+        Targets::evaluate(&params.mod_matrix, |source| match source {
+            ModSource::Lfo1 => modulation.lfos[0],
+            ModSource::Lfo2 => modulation.lfos[1],
+            ModSource::ModEnv => self.mod_envelope.alpha(&params.mod_adsr),
+            ModSource::Velocity => velocity_amount(self.velocity),
+            ModSource::KeyTrack => (self.pitch - 60.0) / 96.0,
+            ModSource::ModWheel => modulation.mod_wheel,
+            ModSource::Aftertouch => self.expression.map_or(modulation.aftertouch, |expression| expression.pressure),
+            ModSource::Timbre => self.expression.map_or(modulation.timbre, |expression| expression.timbre),
+            ModSource::PolyAftertouch => self.pressure,
+            ModSource::Random => modulation.random,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unison_layout() {
+        let single = Unison::new(1, 50.0, 1.0);
+        assert_eq!((single.ratios[0], single.gains[0]), (1.0, (1.0, 1.0)));
+
+        // the old stereo detune: one oscillator each side
+        let pair = Unison::new(2, 34.0, 1.0);
+        assert_eq!(pair.gains[..2], [(1.0, 0.0), (0.0, 1.0)]);
+        assert!((pair.ratios[0] * pair.ratios[1] - 1.0).abs() < 1e-12);
+        assert!((pair.ratios[1] / pair.ratios[0] - cents_to_ratio(34.0)).abs() < 1e-12);
+
+        for count in 1..=MAX_UNISON {
+            for &spread in &[0.0, 0.5, 1.0] {
+                let unison = Unison::new(count, 20.0, spread);
+                let left: f64 = unison.gains[..count].iter().map(|gains| gains.0).sum();
+                let right: f64 = unison.gains[..count].iter().map(|gains| gains.1).sum();
+                assert!((left - 1.0).abs() < 1e-12 && (right - 1.0).abs() < 1e-12);
+            }
+        }
+    }
+
+    #[test]
+    fn test_pan_modes() {
+        let mut panner = VoicePanner::new();
+        assert_eq!(panner.next(PanMode::Centre, 20), 0.0);
+
+        let alternate: Vec<_> = (0..4).map(|_| panner.next(PanMode::Alternate, 60)).collect();
+        assert_eq!(alternate, [-1.0, 1.0, -1.0, 1.0]);
+
+        assert_eq!(panner.next(PanMode::Keyboard, 0), -1.0);
+        assert_eq!(panner.next(PanMode::Keyboard, 127), 1.0);
+        assert!(panner.next(PanMode::Keyboard, 40) < panner.next(PanMode::Keyboard, 80));
+
+        let random: Vec<_> = (0..100).map(|_| panner.next(PanMode::Random, 60)).collect();
+        assert!(random.iter().all(|pan| (-1.0..=1.0).contains(pan)));
+        assert!(random.iter().any(|&pan| pan < -0.5) && random.iter().any(|&pan| pan > 0.5));
+    }
+
+    #[test]
+    fn test_output_routing() {
+        let mut router = OutputRouter::default();
+        assert_eq!(router.next(OutputRouting::Main, 100, 0..=127), 0);
+
+        let round_robin: Vec<_> = (0..5).map(|_| router.next(OutputRouting::RoundRobin, 60, 0..=127)).collect();
+        assert_eq!(round_robin, [0, 1, 2, 3, 0]);
+
+        let mut by_key = |note, keys| router.next(OutputRouting::ByKey, note, keys);
+        assert_eq!([0, 31, 32, 127].map(|note| by_key(note, 0..=127)), [0, 0, 1, 3]);
+        // the zone is split, rather than the whole keyboard
+        assert_eq!([48, 51, 52, 59, 70].map(|note| by_key(note, 48..=63)), [0, 0, 1, 2, 3]);
+        assert_eq!(by_key(60, 60..=60), 0);
+    }
+
+    #[test]
+    fn test_velocity_curves() {
+        for &curve in &VelocityCurve::ALL {
+            assert_eq!(curve.gain(0), 0.0);
+            assert_eq!(curve.gain(127), 1.0);
+        }
+
+        let linear = VelocityCurve::Linear.gain(64);
+        assert!((linear - 0.5).abs() < 0.01);
+        assert!(VelocityCurve::Soft.gain(64) > linear);
+        assert!(VelocityCurve::Hard.gain(64) < linear);
+    }
+}
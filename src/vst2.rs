@@ -0,0 +1,363 @@
+// Adapted from the sine-synth example from vst-rs
+// author: Rob Saunders <hello@robsaunders.io>
+
+use std::sync::Arc;
+
+use vst::api::{Events, Supported, TimeInfo, TimeInfoFlags};
+use vst::buffer::AudioBuffer;
+use vst::channels::{ChannelInfo, SpeakerArrangementType, StereoChannel, StereoConfig};
+#[cfg(feature = "gui")]
+use vst::editor::Editor;
+use vst::event::Event;
+use vst::host::Host;
+use vst::plugin::{CanDo, Category, HostCallback, Info, Plugin, PluginParameters};
+
+#[cfg(feature = "gui")]
+use crate::editor;
+use crate::params::{ReplicantParameters, NUM_PARAMS};
+use crate::presets::NUM_PRESETS;
+use crate::synth::Synth;
+use crate::tempo::Transport;
+use crate::voice::NUM_OUTPUTS;
+
+/// The VST plugin, which passes the host's midi, audio and time info through to the synth.
+#[derive(Default)]
+pub struct Replicant {
+    host: HostCallback,
+    synth: Synth,
+    // the right channel, when the host only gives one output to mix it into
+    scratch: Vec<f32>,
+}
+
+impl Replicant {
+    /// Ask the host for its tempo and play position.
+    fn update_transport(&mut self) {
+        let mask = TimeInfoFlags::TEMPO_VALID | TimeInfoFlags::PPQ_POS_VALID;
+        if let Some(info) = self.host.get_time_info(mask.bits()) {
+            let transport = transport_from_time_info(&info, self.synth.transport());
+            self.synth.set_transport(transport);
+        }
+    }
+}
+
+/// Render into however many channels the host gave. The first two are the main output, and each
+/// pair after them one of the extra outputs voices can be routed to. A single channel gets the
+/// main output mixed to mono, and any past the last output are left silent. With no channels at
+/// all, the `samples` are rendered into `scratch` and thrown away, so the synth keeps time.
+fn render_channels<'a>(
+    synth: &mut Synth,
+    mut channels: impl Iterator<Item = &'a mut [f32]>,
+    samples: usize,
+    scratch: &mut Vec<f32>,
+) {
+    // the host's block size is known ahead of time, so this only allocates if it sends more
+    let scratch_len = if samples > scratch.len() / 2 { samples * 2 } else { scratch.len() };
+    scratch.resize(scratch_len, 0.0);
+    match (channels.next(), channels.next()) {
+        (Some(left), Some(right)) => {
+            // empty slices for the outputs the host didn't give, as this can't allocate
+            let mut outputs: [(&mut [f32], &mut [f32]); NUM_OUTPUTS - 1] = Default::default();
+            let mut count = 0;
+            for output in &mut outputs {
+                match (channels.next(), channels.next()) {
+                    (Some(left), Some(right)) => {
+                        *output = (left, right);
+                        count += 1;
+                    }
+                    (Some(odd), None) => odd.fill(0.0),
+                    (None, _) => break,
+                }
+            }
+            synth.process_outputs(left, right, &mut outputs[..count]);
+            channels.for_each(|channel| channel.fill(0.0));
+        }
+        (Some(mono), None) => {
+            let right = &mut scratch[..mono.len()];
+            synth.process_buffer(mono, right);
+            for (mono, &right) in mono.iter_mut().zip(right.iter()) {
+                *mono = (*mono + right) * 0.5;
+            }
+        }
+        (None, _) => {
+            let (left, right) = scratch.split_at_mut(scratch_len / 2);
+            synth.process_buffer(&mut left[..samples], &mut right[..samples]);
+        }
+    }
+}
+
+/// Read the host's time info, keeping `previous`'s tempo if the host doesn't give one.
+fn transport_from_time_info(info: &TimeInfo, previous: &Transport) -> Transport {
+    let flags = TimeInfoFlags::from_bits_truncate(info.flags);
+    let tempo_valid = flags.contains(TimeInfoFlags::TEMPO_VALID) && info.tempo > 0.0;
+    let playing = flags.contains(TimeInfoFlags::TRANSPORT_PLAYING | TimeInfoFlags::PPQ_POS_VALID);
+    Transport {
+        tempo: if tempo_valid { info.tempo } else { previous.tempo },
+        position: if playing { Some(info.ppq_pos) } else { None },
+    }
+}
+
+impl Plugin for Replicant {
+    fn new(host: HostCallback) -> Replicant {
+        Replicant { host, synth: Synth::new(), scratch: Vec::new() }
+    }
+
+    fn get_info(&self) -> Info {
+        Info {
+            name: "Replicant".to_string(),
+            vendor: "James Sully".to_string(),
+            unique_id: 144_153_144,
+            category: Category::Synth,
+            inputs: 0,
+            outputs: (NUM_OUTPUTS * 2) as i32,
+            parameters: NUM_PARAMS as i32,
+            presets: NUM_PRESETS as i32,
+            preset_chunks: true,
+            initial_delay: 0,
+            ..Info::default()
+        }
+    }
+
+    fn init(&mut self) {
+        crate::init_plugin(self.synth.params());
+    }
+
+    #[allow(unused_variables)]
+    #[allow(clippy::single_match)]
+    fn process_events(&mut self, events: &Events) {
+        for event in events.events() {
+            match event {
+                Event::Midi(ev) => {
+                    // applied at the right sample in process()
+                    let frame = ev.delta_frames.max(0) as usize;
+                    self.synth.queue_event(frame, ev.data);
+                },
+                // More events can be handled here.
+                _ => (),
+            }
+        }
+    }
+
+    fn resume(&mut self) {
+        self.synth.reset();
+    }
+
+    fn suspend(&mut self) {
+        self.synth.reset();
+    }
+
+    fn set_sample_rate(&mut self, rate: f32) {
+        self.synth.set_sample_rate(rate);
+    }
+
+    fn get_tail_size(&self) -> isize {
+        self.synth.tail_samples() as isize
+    }
+
+    fn set_block_size(&mut self, size: i64) {
+        // room for a stereo block, should the host give fewer than two outputs
+        self.scratch = vec![0.0; size.max(0) as usize * 2];
+    }
+
+    fn process(&mut self, buffer: &mut AudioBuffer<f32>) {
+        // the host shows the preset's name, and the parameters have all moved
+        if self.synth.params().take_preset_switched() {
+            self.host.update_display();
+        }
+        self.update_transport();
+
+        let samples = buffer.samples();
+        let (_, mut outputs) = buffer.split();
+        render_channels(&mut self.synth, (&mut outputs).into_iter(), samples, &mut self.scratch);
+
+        // tell the host about parameters moved by midi controllers, so it can record them
+        for (index, value) in self.synth.take_automated() {
+            self.host.automate(index, value);
+        }
+    }
+
+    fn can_do(&self, can_do: CanDo) -> Supported {
+        match can_do {
+            CanDo::ReceiveMidiEvent => Supported::Yes,
+            // the vst crate doesn't pass the soft bypass on, so hosts bypass by suspending us,
+            // which resets the synth
+            CanDo::Bypass => Supported::No,
+            _ => Supported::Maybe,
+        }
+    }
+
+    fn get_output_info(&self, output: i32) -> ChannelInfo {
+        let pair = output / 2;
+        if output < 0 || pair as usize >= NUM_OUTPUTS {
+            return ChannelInfo::new(format!("Output {}", output + 1), None, false, None);
+        }
+        let (name, short_name, channel) = match output % 2 {
+            0 => ("Left", "L", StereoChannel::Left),
+            _ => ("Right", "R", StereoChannel::Right),
+        };
+        // the main output, then the extra ones numbered from 2
+        let (name, short_name) = match pair {
+            0 => (name.to_string(), short_name.to_string()),
+            _ => (format!("{} {}", name, pair + 1), format!("{}{}", short_name, pair + 1)),
+        };
+        let arrangement = SpeakerArrangementType::Stereo(StereoConfig::L_R, channel);
+        ChannelInfo::new(name, Some(short_name), true, Some(arrangement))
+    }
+
+    fn get_parameter_object(&mut self) -> Arc<dyn PluginParameters> {
+        Arc::clone(self.synth.params()) as Arc<dyn PluginParameters>
+    }
+
+    #[cfg(feature = "gui")]
+    fn get_editor(&mut self) -> Option<Box<dyn Editor>> {
+        Some(Box::new(editor::ReplicantEditor::new(Arc::clone(self.synth.params()), self.synth.meter(), self.host)))
+    }
+}
+
+/// The host's parameter and preset calls, passed on to the parameters' own methods of the same
+/// names, which the other plugin formats share.
+impl PluginParameters for ReplicantParameters {
+    fn get_parameter(&self, index: i32) -> f32 {
+        ReplicantParameters::get_parameter(self, index)
+    }
+
+    fn set_parameter(&self, index: i32, value: f32) {
+        ReplicantParameters::set_parameter(self, index, value)
+    }
+
+    fn get_parameter_text(&self, index: i32) -> String {
+        ReplicantParameters::get_parameter_text(self, index)
+    }
+
+    fn get_parameter_label(&self, index: i32) -> String {
+        ReplicantParameters::get_parameter_label(self, index)
+    }
+
+    fn string_to_parameter(&self, index: i32, text: String) -> bool {
+        ReplicantParameters::string_to_parameter(self, index, text)
+    }
+
+    fn get_parameter_name(&self, index: i32) -> String {
+        ReplicantParameters::get_parameter_name(self, index)
+    }
+
+    fn change_preset(&self, preset: i32) {
+        ReplicantParameters::change_preset(self, preset)
+    }
+
+    fn get_preset_num(&self) -> i32 {
+        ReplicantParameters::get_preset_num(self)
+    }
+
+    fn set_preset_name(&self, name: String) {
+        ReplicantParameters::set_preset_name(self, name)
+    }
+
+    fn get_preset_name(&self, preset: i32) -> String {
+        ReplicantParameters::get_preset_name(self, preset)
+    }
+
+    fn get_preset_data(&self) -> Vec<u8> {
+        ReplicantParameters::get_preset_data(self)
+    }
+
+    fn get_bank_data(&self) -> Vec<u8> {
+        ReplicantParameters::get_bank_data(self)
+    }
+
+    fn load_preset_data(&self, data: &[u8]) {
+        ReplicantParameters::load_preset_data(self, data)
+    }
+
+    fn load_bank_data(&self, data: &[u8]) {
+        ReplicantParameters::load_bank_data(self, data)
+    }
+}
+
+vst::plugin_main!(Replicant);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vst::api::SmpteFrameRate;
+
+    use crate::voice;
+
+    fn playing_synth() -> Synth {
+        let mut synth = Synth::new();
+        synth.set_sample_rate(44100.0);
+        synth.queue_event(0, [0x90, 60, 100]);
+        synth
+    }
+
+    #[test]
+    fn test_render_channels_mono() {
+        let (mut left, mut right) = (vec![0.0; 64], vec![0.0; 64]);
+        playing_synth().process_buffer(&mut left, &mut right);
+
+        let mut mono = vec![0.0; 64];
+        let mut scratch = Vec::new();
+        render_channels(&mut playing_synth(), std::iter::once(&mut mono[..]), 64, &mut scratch);
+        assert!(mono.iter().any(|&s| s != 0.0));
+        for i in 0..64 {
+            assert!((mono[i] - (left[i] + right[i]) * 0.5).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn test_render_channels_extra_and_none() {
+        let mut channels = vec![vec![1.0; 64]; 4];
+        let mut scratch = Vec::new();
+        let outputs = channels.iter_mut().map(|c| &mut c[..]);
+        render_channels(&mut playing_synth(), outputs, 64, &mut scratch);
+        assert!(channels[0].iter().any(|&s| s != 0.0));
+        assert!(channels[2].iter().chain(&channels[3]).all(|&s| s == 0.0));
+
+        // no outputs at all: still renders, without panicking
+        render_channels(&mut playing_synth(), std::iter::empty(), 64, &mut scratch);
+        assert!(scratch.len() >= 128);
+    }
+
+    #[test]
+    fn test_render_channels_voice_outputs() {
+        let mut synth = playing_synth();
+        synth.params().output_routing.set(voice::OutputRouting::RoundRobin as usize as f32);
+        synth.queue_event(0, [0x90, 64, 100]);
+        // the second pair, and an odd channel left over after it
+        let mut channels = vec![vec![1.0; 64]; 5];
+        let outputs = channels.iter_mut().map(|c| &mut c[..]);
+        render_channels(&mut synth, outputs, 64, &mut Vec::new());
+        assert!(channels[..4].iter().all(|channel| channel.iter().any(|&s| s != 0.0)));
+        assert!(channels[4].iter().all(|&s| s == 0.0));
+    }
+
+    fn time_info(tempo: f64, ppq_pos: f64, flags: TimeInfoFlags) -> TimeInfo {
+        TimeInfo {
+            sample_pos: 0.0,
+            sample_rate: 44100.0,
+            nanoseconds: 0.0,
+            ppq_pos,
+            tempo,
+            bar_start_pos: 0.0,
+            cycle_start_pos: 0.0,
+            cycle_end_pos: 0.0,
+            time_sig_numerator: 4,
+            time_sig_denominator: 4,
+            smpte_offset: 0,
+            smpte_frame_rate: SmpteFrameRate::Smpte24fps,
+            samples_to_next_clock: 0,
+            flags: flags.bits(),
+        }
+    }
+
+    #[test]
+    fn test_transport_from_time_info() {
+        let previous = Transport::default();
+        let playing = TimeInfoFlags::TEMPO_VALID | TimeInfoFlags::PPQ_POS_VALID | TimeInfoFlags::TRANSPORT_PLAYING;
+        let transport = transport_from_time_info(&time_info(90.0, 8.5, playing), &previous);
+        assert_eq!(transport, Transport { tempo: 90.0, position: Some(8.5) });
+
+        // stopped, and without a tempo the last one carries on
+        let stopped = transport_from_time_info(&time_info(0.0, 8.5, TimeInfoFlags::PPQ_POS_VALID), &transport);
+        assert_eq!(stopped, Transport { tempo: 90.0, position: None });
+    }
+}
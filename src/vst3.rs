@@ -0,0 +1,1444 @@
+// the bindings' enums are signed on Windows and unsigned elsewhere, so the casts between them are
+// only needed on some platforms
+#![allow(clippy::unnecessary_cast)]
+
+use std::cell::UnsafeCell;
+use std::convert::TryFrom;
+use std::ffi::{c_char, c_void};
+use std::mem;
+use std::slice;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Arc;
+
+use vst3::Steinberg::Vst::*;
+use vst3::Steinberg::*;
+use vst3::{uid, Class, ComRef, ComWrapper};
+
+use crate::param_groups::ParamGroup;
+use crate::params::{ReplicantParameters, NUM_PARAMS};
+use crate::presets::Preset;
+use crate::tempo::Transport;
+use crate::voice::NoteExpression;
+use crate::{init_plugin, Synth};
+
+const NOTE_ON: u8 = 0x90;
+const NOTE_OFF: u8 = 0x80;
+const CONTROL_CHANGE: u8 = 0xB0;
+const CHANNEL_PRESSURE: u8 = 0xD0;
+const PITCH_BEND: u8 = 0xE0;
+
+const NAME: &str = "Replicant";
+const VENDOR: &str = "James Sully";
+const VERSION: &str = "0.1.0";
+
+/// VST3 has no midi controllers, pitch bend or channel pressure. Hosts send them as changes to
+/// the parameters `IMidiMapping` assigns them to, which are these hidden ones: a block of 130
+/// per channel, the 128 controllers then channel pressure and pitch bend.
+const MIDI_PARAM_BASE: ParamID = 0x1000_0000;
+const MIDI_CONTROLLERS: u32 = 130;
+const MIDI_CHANNELS: u32 = 16;
+const AFTERTOUCH: u32 = ControllerNumbers_::kAfterTouch as u32;
+const PITCH_BEND_CONTROLLER: u32 = ControllerNumbers_::kPitchBend as u32;
+
+/// The host's bypass switch, listed after the synth's own parameters. While it's on the synth is
+/// reset and outputs silence.
+const BYPASS_PARAM: ParamID = 0x0FFF_0000;
+
+/// The unit a parameter group's shown in. They're numbered from 1 in `ParamGroup::ALL`'s order, as
+/// the root unit is 0.
+fn group_unit(group: ParamGroup) -> UnitID {
+    ParamGroup::ALL.iter().position(|&other| other == group).map_or(kRootUnitId, |index| index as UnitID + 1)
+}
+
+/// The range of the tuning note expression, in semitones either side of the note.
+const TUNING_RANGE: f64 = 120.0;
+
+/// The parameter standing in for `controller` on `channel`.
+fn midi_param(channel: u32, controller: u32) -> ParamID {
+    MIDI_PARAM_BASE + channel * MIDI_CONTROLLERS + controller
+}
+
+/// The channel and controller a midi parameter stands in for.
+fn midi_controller(id: ParamID) -> Option<(u8, u32)> {
+    let offset = id.checked_sub(MIDI_PARAM_BASE)?;
+    let channel = u8::try_from(offset / MIDI_CONTROLLERS).ok().filter(|&channel| u32::from(channel) < MIDI_CHANNELS)?;
+    Some((channel, offset % MIDI_CONTROLLERS))
+}
+
+/// The midi message for a midi parameter's value.
+fn controller_message(channel: u8, controller: u32, value: f64) -> [u8; 3] {
+    let seven_bit = (value.clamp(0.0, 1.0) * 127.0).round() as u8;
+    match controller {
+        AFTERTOUCH => [CHANNEL_PRESSURE | channel, seven_bit, 0],
+        PITCH_BEND_CONTROLLER => {
+            let bend = (value.clamp(0.0, 1.0) * 16383.0).round() as u16;
+            [PITCH_BEND | channel, (bend & 0x7F) as u8, (bend >> 7) as u8]
+        }
+        controller => [CONTROL_CHANGE | channel, controller as u8, seven_bit],
+    }
+}
+
+/// The synth as a VST3 plugin. It's its own edit controller, as hosts allow for plugins without
+/// a separate one, so the controller shares the processor's parameters. There's no editor, so
+/// hosts show their generic one.
+struct Vst3Replicant {
+    // only touched on the audio thread, or on the main thread while processing is off, as VST3's
+    // threading rules promise
+    processor: UnsafeCell<Processor>,
+    params: Arc<ReplicantParameters>,
+    // the host visible parameters' indices, with their defaults
+    param_info: Vec<(ParamID, f64)>,
+    // a spare set of parameters, for showing values other than the current ones
+    display: ReplicantParameters,
+    // the bypass parameter, as last passed to process()
+    bypass: AtomicBool,
+    // the synth's tail in samples, as of the last buffer, for the main thread to read
+    tail: AtomicU32,
+}
+
+struct Processor {
+    synth: Synth,
+    // the id of the last note played on each key, as note expressions name notes by id
+    note_ids: [i32; 128],
+}
+
+impl Class for Vst3Replicant {
+    type Interfaces = (
+        IComponent,
+        IAudioProcessor,
+        IProcessContextRequirements,
+        IEditController,
+        IMidiMapping,
+        INoteExpressionController,
+        IUnitInfo,
+    );
+}
+
+impl Vst3Replicant {
+    const CID: TUID = uid(0xA164_E9A2, 0xCD76_4939, 0x8558_3394, 0x2478_E170);
+
+    fn new() -> Vst3Replicant {
+        let synth = Synth::new();
+        let params = Arc::clone(synth.params());
+        let defaults = ReplicantParameters::default();
+        let param_info = (0..NUM_PARAMS as i32).zip(ReplicantParameters::default_values())
+            .filter(|&(index, _)| defaults.value(index).is_some())
+            .map(|(index, default)| (index as ParamID, f64::from(default)))
+            .collect();
+        let tail = AtomicU32::new(synth.tail_samples());
+        Vst3Replicant {
+            processor: UnsafeCell::new(Processor { synth, note_ids: [-1; 128] }),
+            params,
+            param_info,
+            display: defaults,
+            bypass: AtomicBool::new(false),
+            tail,
+        }
+    }
+
+    /// The synth and its note ids, for the threads VST3 allows to process audio.
+    #[allow(clippy::mut_from_ref)]
+    unsafe fn processor(&self) -> &mut Processor {
+        &mut *self.processor.get()
+    }
+
+    fn has_param(&self, id: ParamID) -> bool {
+        self.params.value(id as i32).is_some() || id == BYPASS_PARAM || midi_controller(id).is_some()
+    }
+
+    /// Tell the host about parameters that moved on the audio thread: all of them when a program
+    /// change switched presets, and those that mapped midi controllers moved.
+    unsafe fn send_changes(&self, synth: &mut Synth, preset_switched: bool, changes: &ComRef<'_, IParameterChanges>) {
+        if preset_switched {
+            for &(id, _) in &self.param_info {
+                add_change(changes, id, f64::from(self.params.normalized(id as i32)));
+            }
+        }
+        for (index, value) in synth.take_automated() {
+            add_change(changes, index as ParamID, f64::from(value));
+        }
+    }
+}
+
+unsafe fn add_change(changes: &ComRef<'_, IParameterChanges>, id: ParamID, value: f64) {
+    let mut index = 0;
+    if let Some(queue) = ComRef::from_raw(changes.addParameterData(&id, &mut index)) {
+        queue.addPoint(0, value, &mut index);
+    }
+}
+
+/// Apply the host's parameter changes. The synth reads its parameters once per buffer, so only
+/// the last value of each counts. Midi parameters are left for `queue_controllers`.
+unsafe fn apply_param_changes(synth: &mut Synth, bypass: &AtomicBool, changes: &ComRef<'_, IParameterChanges>) {
+    for index in 0..changes.getParameterCount() {
+        let Some(queue) = ComRef::from_raw(changes.getParameterData(index)) else { continue };
+        let id = queue.getParameterId();
+        let (mut offset, mut value) = (0, 0.0);
+        let last = queue.getPointCount() - 1;
+        if midi_controller(id).is_some() || queue.getPoint(last, &mut offset, &mut value) != kResultOk {
+            continue;
+        }
+        if id == BYPASS_PARAM {
+            bypass.store(value >= 0.5, Ordering::Relaxed);
+            synth.set_bypass(value >= 0.5);
+        } else {
+            synth.set_parameter(id as i32, value as f32);
+        }
+    }
+}
+
+/// Queue the midi messages for the midi parameters' changes between `start` and `end`. Changes
+/// from before the buffer or after its last sample land on its first or last.
+unsafe fn queue_controllers(
+    synth: &mut Synth,
+    changes: &ComRef<'_, IParameterChanges>,
+    frames: usize,
+    start: usize,
+    end: usize,
+) {
+    for index in 0..changes.getParameterCount() {
+        let Some(queue) = ComRef::from_raw(changes.getParameterData(index)) else { continue };
+        let Some((channel, controller)) = midi_controller(queue.getParameterId()) else { continue };
+        for point in 0..queue.getPointCount() {
+            let (mut offset, mut value) = (0, 0.0);
+            if queue.getPoint(point, &mut offset, &mut value) != kResultOk {
+                continue;
+            }
+            let time = (offset.max(0) as usize).min(frames.saturating_sub(1));
+            if (start..end).contains(&time) || (start == end && time == start) {
+                synth.queue_event(time - start, controller_message(channel, controller, value));
+            }
+        }
+    }
+}
+
+/// Apply the host's events and midi controllers up to the end of `left` and `right`, rendering
+/// in between so each lands on its own sample.
+unsafe fn process_events(
+    processor: &mut Processor,
+    events: Option<&ComRef<'_, IEventList>>,
+    changes: Option<&ComRef<'_, IParameterChanges>>,
+    left: &mut [f32],
+    right: &mut [f32],
+) {
+    let frames = left.len().min(right.len());
+    let mut start = 0;
+    let count = events.map_or(0, |events| events.getEventCount());
+    for index in 0..count {
+        let mut event: Event = mem::zeroed();
+        if events.map_or(kResultFalse, |events| events.getEvent(index, &mut event)) != kResultOk {
+            continue;
+        }
+        let time = (event.sampleOffset.max(0) as usize).clamp(start, frames);
+        match event.r#type as Event_::EventTypes {
+            Event_::EventTypes_::kNoteOnEvent => {
+                let note = event.__field0.noteOn;
+                let Some((channel, key)) = note_key(note.channel, note.pitch) else { continue };
+                processor.note_ids[usize::from(key)] = note.noteId;
+                // a velocity of 0 would be a note off
+                let velocity = (note.velocity * 127.0).round().clamp(1.0, 127.0) as u8;
+                processor.synth.queue_event(time - start, [NOTE_ON | channel, key, velocity]);
+            }
+            Event_::EventTypes_::kNoteOffEvent => {
+                let note = event.__field0.noteOff;
+                let Some((channel, key)) = note_key(note.channel, note.pitch) else { continue };
+                let velocity = (note.velocity * 127.0).round().clamp(0.0, 127.0) as u8;
+                processor.synth.queue_event(time - start, [NOTE_OFF | channel, key, velocity]);
+            }
+            // these take effect straight away, so the audio before them is rendered first
+            Event_::EventTypes_::kPolyPressureEvent | Event_::EventTypes_::kNoteExpressionValueEvent => {
+                if time > start {
+                    let (left, right) = (&mut left[start..time], &mut right[start..time]);
+                    render(&mut processor.synth, changes, frames, left, right, start);
+                    start = time;
+                }
+                apply_expression(processor, &event);
+            }
+            _ => (),
+        }
+    }
+    render(&mut processor.synth, changes, frames, &mut left[start..frames], &mut right[start..frames], start);
+}
+
+/// Render the part of the buffer starting at `start`, after queueing its midi controllers.
+unsafe fn render(
+    synth: &mut Synth,
+    changes: Option<&ComRef<'_, IParameterChanges>>,
+    frames: usize,
+    left: &mut [f32],
+    right: &mut [f32],
+    start: usize,
+) {
+    if let Some(changes) = changes {
+        queue_controllers(synth, changes, frames, start, start + left.len());
+    }
+    synth.process_buffer(left, right);
+}
+
+/// The midi channel and key of a note event, unless they're out of range.
+fn note_key(channel: i16, pitch: i16) -> Option<(u8, u8)> {
+    let channel = u8::try_from(channel).unwrap_or(0) & 0x0F;
+    let key = u8::try_from(pitch).ok().filter(|&key| key < 128)?;
+    Some((channel, key))
+}
+
+/// Apply a poly pressure or note expression event.
+unsafe fn apply_expression(processor: &mut Processor, event: &Event) {
+    if event.r#type as Event_::EventTypes == Event_::EventTypes_::kPolyPressureEvent {
+        let pressure = event.__field0.polyPressure;
+        if let Some((_, key)) = note_key(pressure.channel, pressure.pitch) {
+            processor.synth.note_expression(key, NoteExpression::Pressure(f64::from(pressure.pressure)));
+        }
+        return;
+    }
+    let expression = event.__field0.noteExpressionValue;
+    let value = expression.value.clamp(0.0, 1.0);
+    let expression_value = match expression.typeId {
+        NoteExpressionTypeIDs_::kTuningTypeID => NoteExpression::Tuning((value - 0.5) * 2.0 * TUNING_RANGE),
+        NoteExpressionTypeIDs_::kBrightnessTypeID => NoteExpression::Brightness(value),
+        _ => return,
+    };
+    if expression.noteId == -1 {
+        return;
+    }
+    if let Some(key) = processor.note_ids.iter().position(|&id| id == expression.noteId) {
+        processor.synth.note_expression(key as u8, expression_value);
+    }
+}
+
+/// The host's transport, keeping `previous`'s tempo if it doesn't give one.
+fn transport(context: &ProcessContext, previous: &Transport) -> Transport {
+    use ProcessContext_::StatesAndFlags_::*;
+    let has_tempo = context.state & kTempoValid as u32 != 0 && context.tempo > 0.0;
+    let playing = context.state & kPlaying as u32 != 0 && context.state & kProjectTimeMusicValid as u32 != 0;
+    Transport {
+        tempo: if has_tempo { context.tempo } else { previous.tempo },
+        position: if playing { Some(context.projectTimeMusic) } else { None },
+    }
+}
+
+/// Read a null terminated UTF-16 string, replacing anything that isn't valid.
+unsafe fn read_wide(string: *const TChar) -> String {
+    let len = (0..).take_while(|&i| *string.add(i) != 0).count();
+    let units = std::slice::from_raw_parts(string as *const u16, len);
+    String::from_utf16_lossy(units)
+}
+
+/// Copy `text` into a UTF-16 string buffer, cutting it short if it doesn't fit.
+fn write_wide(text: &str, buffer: &mut [TChar]) {
+    let Some(capacity) = buffer.len().checked_sub(1) else { return };
+    let mut len = 0;
+    for (unit, slot) in text.encode_utf16().zip(&mut buffer[..capacity]) {
+        *slot = unit as TChar;
+        len += 1;
+    }
+    // a surrogate pair cut in half is no character at all
+    if len == capacity && len > 0 && (0xD800..0xDC00).contains(&buffer[len - 1]) {
+        len -= 1;
+    }
+    buffer[len] = 0;
+}
+
+/// Copy `text` into a C string buffer, cutting it short at a character boundary if it doesn't fit.
+fn write_c_chars(text: &str, buffer: &mut [c_char]) {
+    let Some(capacity) = buffer.len().checked_sub(1) else { return };
+    let mut len = text.len().min(capacity);
+    while !text.is_char_boundary(len) {
+        len -= 1;
+    }
+    for (byte, slot) in text.bytes().zip(&mut buffer[..len]) {
+        *slot = byte as c_char;
+    }
+    buffer[len] = 0;
+}
+
+impl IPluginBaseTrait for Vst3Replicant {
+    unsafe fn initialize(&self, _context: *mut FUnknown) -> tresult {
+        init_plugin(&self.params);
+        kResultOk
+    }
+
+    unsafe fn terminate(&self) -> tresult {
+        kResultOk
+    }
+}
+
+impl IComponentTrait for Vst3Replicant {
+    unsafe fn getControllerClassId(&self, _class_id: *mut TUID) -> tresult {
+        // the host finds the controller on this object instead
+        kNotImplemented
+    }
+
+    unsafe fn setIoMode(&self, _mode: IoMode) -> tresult {
+        kResultOk
+    }
+
+    unsafe fn getBusCount(&self, media_type: MediaType, dir: BusDirection) -> int32 {
+        match (media_type as MediaTypes, dir as BusDirections) {
+            (MediaTypes_::kAudio, BusDirections_::kOutput) | (MediaTypes_::kEvent, BusDirections_::kInput) => 1,
+            _ => 0,
+        }
+    }
+
+    unsafe fn getBusInfo(&self, media_type: MediaType, dir: BusDirection, index: int32, bus: *mut BusInfo) -> tresult {
+        if index != 0 || bus.is_null() {
+            return kInvalidArgument;
+        }
+        let bus = &mut *bus;
+        match (media_type as MediaTypes, dir as BusDirections) {
+            (MediaTypes_::kAudio, BusDirections_::kOutput) => {
+                bus.channelCount = 2;
+                write_wide("Output", &mut bus.name);
+            }
+            (MediaTypes_::kEvent, BusDirections_::kInput) => {
+                bus.channelCount = MIDI_CHANNELS as int32;
+                write_wide("Notes", &mut bus.name);
+            }
+            _ => return kInvalidArgument,
+        }
+        bus.mediaType = media_type;
+        bus.direction = dir;
+        bus.busType = BusTypes_::kMain as BusType;
+        bus.flags = BusInfo_::BusFlags_::kDefaultActive as uint32;
+        kResultOk
+    }
+
+    unsafe fn getRoutingInfo(&self, _in_info: *mut RoutingInfo, _out_info: *mut RoutingInfo) -> tresult {
+        kNotImplemented
+    }
+
+    unsafe fn activateBus(&self, _media_type: MediaType, _dir: BusDirection, _index: int32, _state: TBool) -> tresult {
+        kResultOk
+    }
+
+    unsafe fn setActive(&self, _state: TBool) -> tresult {
+        self.processor().synth.reset();
+        kResultOk
+    }
+
+    /// The host loads a patch saved by `getState`.
+    unsafe fn setState(&self, state: *mut IBStream) -> tresult {
+        let Some(stream) = ComRef::from_raw(state) else { return kInvalidArgument };
+        let mut data = Vec::new();
+        let mut chunk = [0u8; 4096];
+        loop {
+            let mut read = 0;
+            let result = stream.read(chunk.as_mut_ptr() as *mut c_void, chunk.len() as int32, &mut read);
+            if result != kResultOk {
+                return kResultFalse;
+            }
+            if read <= 0 {
+                break;
+            }
+            data.extend_from_slice(&chunk[..read as usize]);
+        }
+        match Preset::from_bytes(&data) {
+            Ok(preset) => {
+                self.params.load_preset(preset);
+                kResultOk
+            }
+            Err(err) => {
+                log::warn!("ignoring invalid state: {}", err);
+                kResultFalse
+            }
+        }
+    }
+
+    /// The host saves the current patch, in the same format as the editor's preset export.
+    unsafe fn getState(&self, state: *mut IBStream) -> tresult {
+        let Some(stream) = ComRef::from_raw(state) else { return kInvalidArgument };
+        let data = self.params.current_preset().to_bytes();
+        let mut written = 0;
+        while written < data.len() {
+            let remaining = &data[written..];
+            let mut len = 0;
+            let result = stream.write(remaining.as_ptr() as *mut c_void, remaining.len() as int32, &mut len);
+            if result != kResultOk || len <= 0 {
+                return kResultFalse;
+            }
+            written += len as usize;
+        }
+        kResultOk
+    }
+}
+
+impl IAudioProcessorTrait for Vst3Replicant {
+    unsafe fn setBusArrangements(
+        &self,
+        _inputs: *mut SpeakerArrangement,
+        num_ins: int32,
+        outputs: *mut SpeakerArrangement,
+        num_outs: int32,
+    ) -> tresult {
+        // the one output bus is stereo
+        if num_ins == 0 && num_outs == 1 && *outputs == SpeakerArr::kStereo {
+            kResultTrue
+        } else {
+            kResultFalse
+        }
+    }
+
+    unsafe fn getBusArrangement(&self, dir: BusDirection, index: int32, arr: *mut SpeakerArrangement) -> tresult {
+        if dir as BusDirections != BusDirections_::kOutput || index != 0 || arr.is_null() {
+            return kInvalidArgument;
+        }
+        *arr = SpeakerArr::kStereo;
+        kResultOk
+    }
+
+    unsafe fn canProcessSampleSize(&self, symbolic_sample_size: int32) -> tresult {
+        if symbolic_sample_size as SymbolicSampleSizes == SymbolicSampleSizes_::kSample32 {
+            kResultTrue
+        } else {
+            kResultFalse
+        }
+    }
+
+    unsafe fn getLatencySamples(&self) -> uint32 {
+        0
+    }
+
+    unsafe fn setupProcessing(&self, setup: *mut ProcessSetup) -> tresult {
+        let Some(setup) = setup.as_ref() else { return kInvalidArgument };
+        if setup.symbolicSampleSize as SymbolicSampleSizes != SymbolicSampleSizes_::kSample32 {
+            return kResultFalse;
+        }
+        let synth = &mut self.processor().synth;
+        synth.set_sample_rate(setup.sampleRate as f32);
+        self.tail.store(synth.tail_samples(), Ordering::Relaxed);
+        kResultOk
+    }
+
+    unsafe fn setProcessing(&self, _state: TBool) -> tresult {
+        kResultOk
+    }
+
+    unsafe fn process(&self, data: *mut ProcessData) -> tresult {
+        let Some(data) = data.as_mut() else { return kInvalidArgument };
+        let processor = self.processor();
+        let synth = &mut processor.synth;
+
+        if let Some(context) = data.processContext.as_ref() {
+            synth.set_transport(transport(context, synth.transport()));
+        }
+        let preset_switched = self.params.take_preset_switched();
+        let changes = ComRef::from_raw(data.inputParameterChanges);
+        if let Some(changes) = &changes {
+            apply_param_changes(synth, &self.bypass, changes);
+        }
+
+        // hosts can process without any audio, just to pass on parameter changes
+        let frames = usize::try_from(data.numSamples).unwrap_or(0);
+        let (left, right): (&mut [f32], &mut [f32]) = if frames == 0 || data.numOutputs == 0 {
+            (&mut [], &mut [])
+        } else {
+            // the one output bus is stereo
+            let output = match data.outputs.as_mut() {
+                Some(output) if data.numOutputs == 1 && output.numChannels == 2 => output,
+                _ => return kResultFalse,
+            };
+            let channels = output.__field0.channelBuffers32;
+            if data.symbolicSampleSize as SymbolicSampleSizes != SymbolicSampleSizes_::kSample32 || channels.is_null() {
+                return kResultFalse;
+            }
+            let (left, right) = (*channels, *channels.add(1));
+            if left.is_null() || right.is_null() {
+                return kResultFalse;
+            }
+            output.silenceFlags = 0;
+            (slice::from_raw_parts_mut(left, frames), slice::from_raw_parts_mut(right, frames))
+        };
+
+        let events = ComRef::from_raw(data.inputEvents);
+        process_events(processor, events.as_ref(), changes.as_ref(), left, right);
+        match ComRef::from_raw(data.outputParameterChanges) {
+            Some(out) => self.send_changes(&mut processor.synth, preset_switched, &out),
+            None => processor.synth.take_automated().for_each(drop),
+        }
+        self.tail.store(processor.synth.tail_samples(), Ordering::Relaxed);
+        kResultOk
+    }
+
+    unsafe fn getTailSamples(&self) -> uint32 {
+        // released notes ring on for as long as their envelopes and the effects say
+        self.tail.load(Ordering::Relaxed)
+    }
+}
+
+impl IProcessContextRequirementsTrait for Vst3Replicant {
+    unsafe fn getProcessContextRequirements(&self) -> uint32 {
+        use IProcessContextRequirements_::Flags_::*;
+        (kNeedTempo | kNeedProjectTimeMusic | kNeedTransportState) as uint32
+    }
+}
+
+// the parameters are the same as the VST ones, with the same ids and the same 0.0 to 1.0 values,
+// so automation carries over between the two
+impl IEditControllerTrait for Vst3Replicant {
+    unsafe fn setComponentState(&self, _state: *mut IBStream) -> tresult {
+        // the component's state is already the controller's, as they share the parameters
+        kResultOk
+    }
+
+    unsafe fn setState(&self, _state: *mut IBStream) -> tresult {
+        kResultOk
+    }
+
+    unsafe fn getState(&self, _state: *mut IBStream) -> tresult {
+        kResultOk
+    }
+
+    unsafe fn getParameterCount(&self) -> int32 {
+        (self.param_info.len() as u32 + 1 + MIDI_CHANNELS * MIDI_CONTROLLERS) as int32
+    }
+
+    unsafe fn getParameterInfo(&self, param_index: int32, info: *mut ParameterInfo) -> tresult {
+        let Ok(index) = usize::try_from(param_index) else { return kInvalidArgument };
+        let Some(info) = info.as_mut() else { return kInvalidArgument };
+        use ParameterInfo_::ParameterFlags_::*;
+        if let Some(&(id, default)) = self.param_info.get(index) {
+            info.id = id;
+            write_wide(&self.params.param_name(id as i32), &mut info.title);
+            write_wide(&self.params.param_name(id as i32), &mut info.shortTitle);
+            write_wide(&self.params.param_label(id as i32), &mut info.units);
+            info.defaultNormalizedValue = default;
+            info.flags = kCanAutomate as int32;
+        } else if index == self.param_info.len() {
+            info.id = BYPASS_PARAM;
+            write_wide("Bypass", &mut info.title);
+            write_wide("Bypass", &mut info.shortTitle);
+            write_wide("", &mut info.units);
+            info.defaultNormalizedValue = 0.0;
+            info.flags = (kCanAutomate | kIsBypass) as int32;
+            info.stepCount = 1;
+            info.unitId = kRootUnitId;
+            return kResultOk;
+        } else {
+            let midi_index = (index - self.param_info.len() - 1) as u32;
+            if midi_index >= MIDI_CHANNELS * MIDI_CONTROLLERS {
+                return kInvalidArgument;
+            }
+            let (channel, controller) = (midi_index / MIDI_CONTROLLERS, midi_index % MIDI_CONTROLLERS);
+            let name = match controller {
+                AFTERTOUCH => format!("Channel Pressure ({})", channel + 1),
+                PITCH_BEND_CONTROLLER => format!("Pitch Bend ({})", channel + 1),
+                controller => format!("CC {} ({})", controller, channel + 1),
+            };
+            info.id = midi_param(channel, controller);
+            write_wide(&name, &mut info.title);
+            write_wide(&name, &mut info.shortTitle);
+            write_wide("", &mut info.units);
+            info.defaultNormalizedValue = if controller == PITCH_BEND_CONTROLLER { 0.5 } else { 0.0 };
+            info.flags = (kCanAutomate | kIsHidden) as int32;
+        }
+        info.stepCount = 0;
+        // the midi parameters aren't in a group, so they're in the root unit
+        info.unitId = ParamGroup::of(info.id as i32).map_or(kRootUnitId, group_unit);
+        kResultOk
+    }
+
+    unsafe fn getParamStringByValue(
+        &self,
+        id: ParamID,
+        value_normalized: ParamValue,
+        string: *mut String128,
+    ) -> tresult {
+        let Some(string) = string.as_mut() else { return kInvalidArgument };
+        let index = id as i32;
+        if self.display.value(index).is_some() {
+            // the text comes from the parameter's current value, so it's shown through the spare set
+            self.display.set_normalized(index, value_normalized as f32);
+            let text = format!("{} {}", self.display.param_text(index), self.display.param_label(index));
+            write_wide(text.trim_end(), string);
+        } else if id == BYPASS_PARAM {
+            write_wide(if value_normalized >= 0.5 { "On" } else { "Off" }, string);
+        } else if let Some((channel, controller)) = midi_controller(id) {
+            let message = controller_message(channel, controller, value_normalized);
+            let value = match controller {
+                PITCH_BEND_CONTROLLER => (i32::from(message[2]) << 7 | i32::from(message[1])) - 8192,
+                AFTERTOUCH => i32::from(message[1]),
+                _ => i32::from(message[2]),
+            };
+            write_wide(&value.to_string(), string);
+        } else {
+            return kInvalidArgument;
+        }
+        kResultOk
+    }
+
+    unsafe fn getParamValueByString(
+        &self,
+        id: ParamID,
+        string: *mut TChar,
+        value_normalized: *mut ParamValue,
+    ) -> tresult {
+        if string.is_null() || value_normalized.is_null() {
+            return kInvalidArgument;
+        }
+        let index = id as i32;
+        if id == BYPASS_PARAM {
+            *value_normalized = match read_wide(string).trim() {
+                "On" => 1.0,
+                "Off" => 0.0,
+                _ => return kResultFalse,
+            };
+            return kResultOk;
+        }
+        // read into the spare set, so the live value is left alone
+        if !self.display.set_param_text(index, &read_wide(string)) {
+            return kResultFalse;
+        }
+        *value_normalized = f64::from(self.display.normalized(index));
+        kResultOk
+    }
+
+    unsafe fn normalizedParamToPlain(&self, _id: ParamID, value_normalized: ParamValue) -> ParamValue {
+        value_normalized
+    }
+
+    unsafe fn plainParamToNormalized(&self, _id: ParamID, plain_value: ParamValue) -> ParamValue {
+        plain_value
+    }
+
+    unsafe fn getParamNormalized(&self, id: ParamID) -> ParamValue {
+        match self.params.value(id as i32) {
+            Some(_) => f64::from(self.params.normalized(id as i32)),
+            None if id == BYPASS_PARAM && self.bypass.load(Ordering::Relaxed) => 1.0,
+            None => 0.0,
+        }
+    }
+
+    unsafe fn setParamNormalized(&self, id: ParamID, _value: ParamValue) -> tresult {
+        // the host passes the same change to process(), which is where it's applied
+        if self.has_param(id) { kResultOk } else { kInvalidArgument }
+    }
+
+    unsafe fn setComponentHandler(&self, _handler: *mut IComponentHandler) -> tresult {
+        kResultOk
+    }
+
+    unsafe fn createView(&self, _name: FIDString) -> *mut IPlugView {
+        std::ptr::null_mut()
+    }
+}
+
+impl IMidiMappingTrait for Vst3Replicant {
+    unsafe fn getMidiControllerAssignment(
+        &self,
+        bus_index: int32,
+        channel: int16,
+        midi_controller_number: CtrlNumber,
+        id: *mut ParamID,
+    ) -> tresult {
+        let (Ok(channel), Ok(controller)) = (u32::try_from(channel), u32::try_from(midi_controller_number)) else {
+            return kResultFalse;
+        };
+        if bus_index != 0 || channel >= MIDI_CHANNELS || controller >= MIDI_CONTROLLERS || id.is_null() {
+            return kResultFalse;
+        }
+        *id = midi_param(channel, controller);
+        kResultTrue
+    }
+}
+
+// a unit for each parameter group, so hosts show them organized. there are no program lists, as
+// the presets are handled through the host's own preset browser
+impl IUnitInfoTrait for Vst3Replicant {
+    unsafe fn getUnitCount(&self) -> int32 {
+        ParamGroup::ALL.len() as int32 + 1
+    }
+
+    unsafe fn getUnitInfo(&self, unit_index: int32, info: *mut UnitInfo) -> tresult {
+        let Some(info) = info.as_mut() else { return kInvalidArgument };
+        let (id, parent, name) = match unit_index {
+            0 => (kRootUnitId, kNoParentUnitId, "Root"),
+            _ => match usize::try_from(unit_index - 1).ok().and_then(|index| ParamGroup::ALL.get(index)) {
+                Some(&group) => (group_unit(group), kRootUnitId, group.name()),
+                None => return kInvalidArgument,
+            },
+        };
+        info.id = id;
+        info.parentUnitId = parent;
+        write_wide(name, &mut info.name);
+        info.programListId = kNoProgramListId;
+        kResultOk
+    }
+
+    unsafe fn getProgramListCount(&self) -> int32 {
+        0
+    }
+
+    unsafe fn getProgramListInfo(&self, _list_index: int32, _info: *mut ProgramListInfo) -> tresult {
+        kInvalidArgument
+    }
+
+    unsafe fn getProgramName(&self, _list_id: ProgramListID, _program_index: int32, _name: *mut String128) -> tresult {
+        kInvalidArgument
+    }
+
+    unsafe fn getProgramInfo(
+        &self,
+        _list_id: ProgramListID,
+        _program_index: int32,
+        _attribute_id: FIDString,
+        _attribute_value: *mut String128,
+    ) -> tresult {
+        kInvalidArgument
+    }
+
+    unsafe fn hasProgramPitchNames(&self, _list_id: ProgramListID, _program_index: int32) -> tresult {
+        kResultFalse
+    }
+
+    unsafe fn getProgramPitchName(
+        &self,
+        _list_id: ProgramListID,
+        _program_index: int32,
+        _midi_pitch: int16,
+        _name: *mut String128,
+    ) -> tresult {
+        kResultFalse
+    }
+
+    unsafe fn getSelectedUnit(&self) -> UnitID {
+        kRootUnitId
+    }
+
+    unsafe fn selectUnit(&self, _unit_id: UnitID) -> tresult {
+        kResultOk
+    }
+
+    unsafe fn getUnitByBus(
+        &self,
+        _type: MediaType,
+        _dir: BusDirection,
+        _bus_index: int32,
+        _channel: int32,
+        unit_id: *mut UnitID,
+    ) -> tresult {
+        let Some(unit_id) = unit_id.as_mut() else { return kInvalidArgument };
+        *unit_id = kRootUnitId;
+        kResultOk
+    }
+
+    unsafe fn setUnitProgramData(
+        &self,
+        _list_or_unit_id: int32,
+        _program_index: int32,
+        _data: *mut IBStream,
+    ) -> tresult {
+        kNotImplemented
+    }
+}
+
+impl INoteExpressionControllerTrait for Vst3Replicant {
+    unsafe fn getNoteExpressionCount(&self, bus_index: int32, _channel: int16) -> int32 {
+        if bus_index == 0 { 2 } else { 0 }
+    }
+
+    unsafe fn getNoteExpressionInfo(
+        &self,
+        bus_index: int32,
+        _channel: int16,
+        note_expression_index: int32,
+        info: *mut NoteExpressionTypeInfo,
+    ) -> tresult {
+        let Some(info) = info.as_mut().filter(|_| bus_index == 0) else { return kInvalidArgument };
+        use NoteExpressionTypeInfo_::NoteExpressionTypeFlags_::*;
+        let (type_id, title, units, flags) = match note_expression_index {
+            0 => (NoteExpressionTypeIDs_::kTuningTypeID, "Tuning", "semitones", kIsBipolar),
+            1 => (NoteExpressionTypeIDs_::kBrightnessTypeID, "Brightness", "", 0),
+            _ => return kInvalidArgument,
+        };
+        info.typeId = type_id;
+        write_wide(title, &mut info.title);
+        write_wide(title, &mut info.shortTitle);
+        write_wide(units, &mut info.units);
+        info.unitId = kRootUnitId;
+        info.valueDesc = NoteExpressionValueDescription { defaultValue: 0.5, minimum: 0.0, maximum: 1.0, stepCount: 0 };
+        info.associatedParameterId = kNoParamId;
+        info.flags = flags as int32;
+        kResultOk
+    }
+
+    unsafe fn getNoteExpressionStringByValue(
+        &self,
+        _bus_index: int32,
+        _channel: int16,
+        id: NoteExpressionTypeID,
+        value_normalized: NoteExpressionValue,
+        string: *mut String128,
+    ) -> tresult {
+        let Some(string) = string.as_mut() else { return kInvalidArgument };
+        let text = match id {
+            NoteExpressionTypeIDs_::kTuningTypeID => format!("{:+.2}", (value_normalized - 0.5) * 2.0 * TUNING_RANGE),
+            NoteExpressionTypeIDs_::kBrightnessTypeID => format!("{:.2}", value_normalized),
+            _ => return kInvalidArgument,
+        };
+        write_wide(&text, string);
+        kResultOk
+    }
+
+    unsafe fn getNoteExpressionValueByString(
+        &self,
+        _bus_index: int32,
+        _channel: int16,
+        _id: NoteExpressionTypeID,
+        _string: *const TChar,
+        _value_normalized: *mut NoteExpressionValue,
+    ) -> tresult {
+        kResultFalse
+    }
+}
+
+struct Factory;
+
+impl Class for Factory {
+    type Interfaces = (IPluginFactory2,);
+}
+
+impl IPluginFactoryTrait for Factory {
+    unsafe fn getFactoryInfo(&self, info: *mut PFactoryInfo) -> tresult {
+        let Some(info) = info.as_mut() else { return kInvalidArgument };
+        write_c_chars(VENDOR, &mut info.vendor);
+        write_c_chars("https://github.com/sullyj3/replicant", &mut info.url);
+        write_c_chars("sullyj3@gmail.com", &mut info.email);
+        info.flags = PFactoryInfo_::FactoryFlags_::kUnicode as int32;
+        kResultOk
+    }
+
+    unsafe fn countClasses(&self) -> int32 {
+        1
+    }
+
+    unsafe fn getClassInfo(&self, index: int32, info: *mut PClassInfo) -> tresult {
+        let Some(info) = info.as_mut().filter(|_| index == 0) else { return kInvalidArgument };
+        info.cid = Vst3Replicant::CID;
+        info.cardinality = PClassInfo_::ClassCardinality_::kManyInstances as int32;
+        write_c_chars("Audio Module Class", &mut info.category);
+        write_c_chars(NAME, &mut info.name);
+        kResultOk
+    }
+
+    unsafe fn createInstance(&self, cid: FIDString, iid: FIDString, obj: *mut *mut c_void) -> tresult {
+        if cid.is_null() || obj.is_null() || *(cid as *const TUID) != Vst3Replicant::CID {
+            return kInvalidArgument;
+        }
+        let Some(instance) = ComWrapper::new(Vst3Replicant::new()).to_com_ptr::<FUnknown>() else {
+            return kNoInterface;
+        };
+        let ptr = instance.as_ptr();
+        ((*(*ptr).vtbl).queryInterface)(ptr, iid as *const TUID, obj)
+    }
+}
+
+impl IPluginFactory2Trait for Factory {
+    unsafe fn getClassInfo2(&self, index: int32, info: *mut PClassInfo2) -> tresult {
+        let Some(info) = info.as_mut().filter(|_| index == 0) else { return kInvalidArgument };
+        info.cid = Vst3Replicant::CID;
+        info.cardinality = PClassInfo_::ClassCardinality_::kManyInstances as int32;
+        write_c_chars("Audio Module Class", &mut info.category);
+        write_c_chars(NAME, &mut info.name);
+        info.classFlags = 0;
+        write_c_chars("Instrument|Synth", &mut info.subCategories);
+        write_c_chars(VENDOR, &mut info.vendor);
+        write_c_chars(VERSION, &mut info.version);
+        write_c_chars("VST 3.7.9", &mut info.sdkVersion);
+        kResultOk
+    }
+}
+
+/// The VST3 entry point, which hosts look for alongside the VST one.
+#[no_mangle]
+#[allow(non_snake_case)]
+extern "system" fn GetPluginFactory() -> *mut IPluginFactory {
+    match ComWrapper::new(Factory).to_com_ptr::<IPluginFactory>() {
+        Some(factory) => factory.into_raw(),
+        None => std::ptr::null_mut(),
+    }
+}
+
+#[cfg(target_os = "windows")]
+#[no_mangle]
+#[allow(non_snake_case)]
+extern "system" fn InitDll() -> bool {
+    true
+}
+
+#[cfg(target_os = "windows")]
+#[no_mangle]
+#[allow(non_snake_case)]
+extern "system" fn ExitDll() -> bool {
+    true
+}
+
+#[cfg(target_os = "macos")]
+#[no_mangle]
+extern "system" fn bundleEntry(_bundle: *mut c_void) -> bool {
+    true
+}
+
+#[cfg(target_os = "macos")]
+#[no_mangle]
+extern "system" fn bundleExit() -> bool {
+    true
+}
+
+#[cfg(target_os = "linux")]
+#[no_mangle]
+#[allow(non_snake_case)]
+extern "system" fn ModuleEntry(_library: *mut c_void) -> bool {
+    true
+}
+
+#[cfg(target_os = "linux")]
+#[no_mangle]
+#[allow(non_snake_case)]
+extern "system" fn ModuleExit() -> bool {
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::{Cell, RefCell};
+
+    use crate::modulation::{ModDestination, ModSource};
+    use crate::tempo::NoteDivision;
+    use vst3::{ComPtr, Interface};
+
+    /// An event list over events the test owns.
+    struct Events(Vec<Event>);
+
+    impl Class for Events {
+        type Interfaces = (IEventList,);
+    }
+
+    impl IEventListTrait for Events {
+        unsafe fn getEventCount(&self) -> int32 {
+            self.0.len() as int32
+        }
+
+        unsafe fn getEvent(&self, index: int32, e: *mut Event) -> tresult {
+            match self.0.get(index as usize) {
+                Some(event) => {
+                    *e = *event;
+                    kResultOk
+                }
+                None => kInvalidArgument,
+            }
+        }
+
+        unsafe fn addEvent(&self, _e: *mut Event) -> tresult {
+            kNotImplemented
+        }
+    }
+
+    struct Queue {
+        id: ParamID,
+        points: RefCell<Vec<(int32, ParamValue)>>,
+    }
+
+    impl Class for Queue {
+        type Interfaces = (IParamValueQueue,);
+    }
+
+    impl IParamValueQueueTrait for Queue {
+        unsafe fn getParameterId(&self) -> ParamID {
+            self.id
+        }
+
+        unsafe fn getPointCount(&self) -> int32 {
+            self.points.borrow().len() as int32
+        }
+
+        unsafe fn getPoint(&self, index: int32, sample_offset: *mut int32, value: *mut ParamValue) -> tresult {
+            match self.points.borrow().get(index as usize) {
+                Some(&(offset, point)) => {
+                    *sample_offset = offset;
+                    *value = point;
+                    kResultOk
+                }
+                None => kInvalidArgument,
+            }
+        }
+
+        unsafe fn addPoint(&self, sample_offset: int32, value: ParamValue, index: *mut int32) -> tresult {
+            let mut points = self.points.borrow_mut();
+            *index = points.len() as int32;
+            points.push((sample_offset, value));
+            kResultOk
+        }
+    }
+
+    /// Parameter changes, either the host's or for the plugin to add to.
+    #[derive(Default)]
+    struct Changes(RefCell<Vec<ComWrapper<Queue>>>);
+
+    impl Changes {
+        fn new(changes: &[(ParamID, &[(int32, ParamValue)])]) -> Changes {
+            let queues = changes.iter()
+                .map(|&(id, points)| ComWrapper::new(Queue { id, points: RefCell::new(points.to_vec()) }))
+                .collect();
+            Changes(RefCell::new(queues))
+        }
+    }
+
+    impl Class for Changes {
+        type Interfaces = (IParameterChanges,);
+    }
+
+    impl IParameterChangesTrait for Changes {
+        unsafe fn getParameterCount(&self) -> int32 {
+            self.0.borrow().len() as int32
+        }
+
+        unsafe fn getParameterData(&self, index: int32) -> *mut IParamValueQueue {
+            let queues = self.0.borrow();
+            queues.get(index as usize)
+                .and_then(|queue| queue.as_com_ref::<IParamValueQueue>())
+                .map_or(std::ptr::null_mut(), |queue| queue.as_ptr())
+        }
+
+        unsafe fn addParameterData(&self, id: *const ParamID, index: *mut int32) -> *mut IParamValueQueue {
+            let mut queues = self.0.borrow_mut();
+            *index = queues.len() as int32;
+            queues.push(ComWrapper::new(Queue { id: *id, points: RefCell::new(Vec::new()) }));
+            queues[*index as usize].as_com_ref::<IParamValueQueue>().unwrap().as_ptr()
+        }
+    }
+
+    #[derive(Default)]
+    struct Stream {
+        data: RefCell<Vec<u8>>,
+        position: Cell<usize>,
+    }
+
+    impl Class for Stream {
+        type Interfaces = (IBStream,);
+    }
+
+    impl IBStreamTrait for Stream {
+        unsafe fn read(&self, buffer: *mut c_void, num_bytes: int32, num_bytes_read: *mut int32) -> tresult {
+            let data = self.data.borrow();
+            let remaining = &data[self.position.get()..];
+            let len = remaining.len().min(num_bytes as usize);
+            std::ptr::copy_nonoverlapping(remaining.as_ptr(), buffer as *mut u8, len);
+            self.position.set(self.position.get() + len);
+            *num_bytes_read = len as int32;
+            kResultOk
+        }
+
+        unsafe fn write(&self, buffer: *mut c_void, num_bytes: int32, num_bytes_written: *mut int32) -> tresult {
+            // a few bytes at a time, as streams are allowed to take
+            let len = (num_bytes as usize).min(7);
+            self.data.borrow_mut().extend_from_slice(slice::from_raw_parts(buffer as *const u8, len));
+            *num_bytes_written = len as int32;
+            kResultOk
+        }
+
+        unsafe fn seek(&self, _pos: int64, _mode: int32, _result: *mut int64) -> tresult {
+            kNotImplemented
+        }
+
+        unsafe fn tell(&self, _pos: *mut int64) -> tresult {
+            kNotImplemented
+        }
+    }
+
+    fn create() -> ComPtr<IComponent> {
+        unsafe {
+            let factory = ComPtr::from_raw(GetPluginFactory()).unwrap();
+            let mut info: PClassInfo = mem::zeroed();
+            assert_eq!(factory.getClassInfo(0, &mut info), kResultOk);
+            let mut obj = std::ptr::null_mut();
+            let iid = IComponent::IID.as_ptr() as FIDString;
+            assert_eq!(factory.createInstance(info.cid.as_ptr(), iid, &mut obj), kResultOk);
+            let component = ComPtr::from_raw(obj as *mut IComponent).unwrap();
+            assert_eq!(component.initialize(std::ptr::null_mut()), kResultOk);
+
+            let processor = component.cast::<IAudioProcessor>().unwrap();
+            let mut setup = ProcessSetup {
+                processMode: ProcessModes_::kRealtime as int32,
+                symbolicSampleSize: SymbolicSampleSizes_::kSample32 as int32,
+                maxSamplesPerBlock: 512,
+                sampleRate: 44100.0,
+            };
+            assert_eq!(processor.setupProcessing(&mut setup), kResultOk);
+            assert_eq!(component.setActive(1), kResultOk);
+            component
+        }
+    }
+
+    fn note_on(time: int32, pitch: i16, note_id: i32) -> Event {
+        let mut event: Event = unsafe { mem::zeroed() };
+        event.sampleOffset = time;
+        event.r#type = Event_::EventTypes_::kNoteOnEvent as u16;
+        let note = NoteOnEvent { channel: 0, pitch, tuning: 0.0, velocity: 1.0, length: 0, noteId: note_id };
+        event.__field0.noteOn = note;
+        event
+    }
+
+    /// Render a buffer of `len` samples with `events` and `changes`, returning the left channel.
+    fn process(component: &ComPtr<IComponent>, events: Vec<Event>, changes: &Changes, len: usize) -> Vec<f32> {
+        process_in(component, events, changes, len, std::ptr::null_mut())
+    }
+
+    /// The same, with the host's transport as `context` says.
+    fn process_in(
+        component: &ComPtr<IComponent>,
+        events: Vec<Event>,
+        changes: &Changes,
+        len: usize,
+        context: *mut ProcessContext,
+    ) -> Vec<f32> {
+        let (mut left, mut right) = (vec![0.0f32; len], vec![0.0f32; len]);
+        let mut channels = [left.as_mut_ptr(), right.as_mut_ptr()];
+        let mut output = AudioBusBuffers {
+            numChannels: 2,
+            silenceFlags: 0,
+            __field0: AudioBusBuffers__type0 { channelBuffers32: channels.as_mut_ptr() },
+        };
+        let events = ComWrapper::new(Events(events));
+        let changes = ComWrapper::new(Changes(RefCell::new(changes.0.borrow().clone())));
+        let out_changes = ComWrapper::new(Changes(RefCell::new(Vec::new())));
+        let mut data = ProcessData {
+            processMode: ProcessModes_::kRealtime as int32,
+            symbolicSampleSize: SymbolicSampleSizes_::kSample32 as int32,
+            numSamples: len as int32,
+            numInputs: 0,
+            numOutputs: 1,
+            inputs: std::ptr::null_mut(),
+            outputs: &mut output,
+            inputParameterChanges: changes.as_com_ref::<IParameterChanges>().unwrap().as_ptr(),
+            outputParameterChanges: out_changes.as_com_ref::<IParameterChanges>().unwrap().as_ptr(),
+            inputEvents: events.as_com_ref::<IEventList>().unwrap().as_ptr(),
+            outputEvents: std::ptr::null_mut(),
+            processContext: context,
+        };
+        unsafe {
+            let processor = component.cast::<IAudioProcessor>().unwrap();
+            assert_eq!(processor.process(&mut data), kResultOk);
+        }
+        left
+    }
+
+    fn wide(text: &[TChar]) -> String {
+        let len = text.iter().position(|&unit| unit == 0).unwrap_or(text.len());
+        String::from_utf16(&text[..len]).unwrap()
+    }
+
+    #[test]
+    fn test_notes_are_sample_accurate() {
+        let component = create();
+        let left = process(&component, vec![note_on(100, 60, 1)], &Changes::default(), 256);
+        assert!(left[..100].iter().all(|&sample| sample == 0.0));
+        assert!(left[100..].iter().any(|&sample| sample != 0.0));
+    }
+
+    #[test]
+    fn test_note_expressions_follow_note_ids() {
+        let render = |note_id: i32| {
+            let component = create();
+            let mut expression: Event = unsafe { mem::zeroed() };
+            expression.sampleOffset = 1024;
+            expression.r#type = Event_::EventTypes_::kNoteExpressionValueEvent as u16;
+            expression.__field0.noteExpressionValue = NoteExpressionValueEvent {
+                typeId: NoteExpressionTypeIDs_::kTuningTypeID,
+                noteId: note_id,
+                value: 0.6,
+            };
+            process(&component, vec![note_on(0, 60, 7), expression], &Changes::default(), 2048)
+        };
+        let (tuned, untouched) = (render(7), render(8));
+        assert_eq!(tuned[..1024], untouched[..1024]);
+        assert_ne!(tuned[1024..], untouched[1024..]);
+    }
+
+    #[test]
+    fn test_split_renders_keep_synced_lfos_running() {
+        // a sine LFO synced to a quarter note, on the amplitude
+        let settings = ReplicantParameters::default();
+        settings.lfos[0].shape.set(crate::lfo::LfoShape::Sine as usize as f32);
+        let quarter = NoteDivision::ALL.iter().position(|&division| division == NoteDivision::Quarter).unwrap();
+        settings.lfos[0].sync.set((quarter + 1) as f32);
+        settings.mod_slots[0].source.set(ModSource::Lfo1 as usize as f32);
+        settings.mod_slots[0].destination.set(ModDestination::Amplitude as usize as f32);
+        settings.mod_slots[0].depth.set(1.0);
+        let names = ["LFO 1 Shape", "LFO 1 Sync", "Mod 1 Source", "Mod 1 Dest", "Mod 1 Depth"];
+        let indices: Vec<i32> = names.iter()
+            .map(|&name| (0..NUM_PARAMS as i32).find(|&index| settings.get_parameter_name(index) == name).unwrap())
+            .collect();
+        let points: Vec<[(int32, ParamValue); 1]> = indices.iter()
+            .map(|&index| [(0, f64::from(settings.normalized(index)))])
+            .collect();
+        let changes: Vec<_> = indices.iter()
+            .zip(&points)
+            .map(|(&index, points)| (index as ParamID, &points[..]))
+            .collect();
+
+        // poly pressure on a key that isn't playing changes nothing, but splits the render
+        let render = |split: bool| {
+            let component = create();
+            let mut context: ProcessContext = unsafe { mem::zeroed() };
+            context.state = (ProcessContext_::StatesAndFlags_::kPlaying
+                | ProcessContext_::StatesAndFlags_::kProjectTimeMusicValid
+                | ProcessContext_::StatesAndFlags_::kTempoValid) as u32;
+            context.tempo = 120.0;
+            context.projectTimeMusic = 10.0;
+            let mut events = vec![note_on(0, 60, 1)];
+            if split {
+                for time in [512, 1024, 1536] {
+                    let mut pressure: Event = unsafe { mem::zeroed() };
+                    pressure.sampleOffset = time;
+                    pressure.r#type = Event_::EventTypes_::kPolyPressureEvent as u16;
+                    let event = PolyPressureEvent { channel: 0, pitch: 100, pressure: 1.0, noteId: -1 };
+                    pressure.__field0.polyPressure = event;
+                    events.push(pressure);
+                }
+            }
+            process_in(&component, events, &Changes::new(&changes), 2048, &mut context)
+        };
+        assert_eq!(render(false), render(true));
+    }
+
+    #[test]
+    fn test_params() {
+        let component = create();
+        unsafe {
+            let controller = component.cast::<IEditController>().unwrap();
+            let count = controller.getParameterCount();
+            assert!(count as u32 > MIDI_CHANNELS * MIDI_CONTROLLERS);
+
+            let mut info: ParameterInfo = mem::zeroed();
+            let found = (0..count).any(|index| {
+                controller.getParameterInfo(index, &mut info);
+                wide(&info.title) == "Cutoff"
+            });
+            assert!(found);
+            assert_eq!(info.id, 19);
+            assert_eq!(info.flags, ParameterInfo_::ParameterFlags_::kCanAutomate as int32);
+            assert_ne!(controller.getParameterInfo(count, &mut info), kResultOk);
+
+            // each parameter is in its group's unit, under the root
+            let units = component.cast::<IUnitInfo>().unwrap();
+            let mut unit: UnitInfo = mem::zeroed();
+            let filter = (1..units.getUnitCount()).find(|&index| {
+                units.getUnitInfo(index, &mut unit);
+                wide(&unit.name) == "Filter"
+            });
+            assert!(filter.is_some());
+            assert_eq!(unit.parentUnitId, kRootUnitId);
+            controller.getParameterInfo(19, &mut info);
+            assert_eq!(info.unitId, unit.id);
+            assert_ne!(units.getUnitInfo(units.getUnitCount(), &mut unit), kResultOk);
+
+            // values from the host are applied before the buffer, and the text follows any value
+            let changes = Changes::new(&[(19, &[(10, 0.5), (20, 1.0)])]);
+            process(&component, Vec::new(), &changes, 64);
+            assert_eq!(controller.getParamNormalized(19), 1.0);
+            let mut text = [0; 128];
+            assert_eq!(controller.getParamStringByValue(19, 1.0, &mut text), kResultOk);
+            assert_eq!(wide(&text), "20.00 kHz");
+            let mut value = 0.0;
+            assert_eq!(controller.getParamValueByString(19, text.as_mut_ptr(), &mut value), kResultOk);
+            assert_eq!(value, 1.0);
+
+            // midi controllers come in through the hidden parameters they're mapped to
+            let mapping = component.cast::<IMidiMapping>().unwrap();
+            let mut id = 0;
+            let bend = ControllerNumbers_::kPitchBend as CtrlNumber;
+            assert_eq!(mapping.getMidiControllerAssignment(0, 1, bend, &mut id), kResultTrue);
+            assert_eq!(midi_controller(id), Some((1, PITCH_BEND_CONTROLLER)));
+            assert_eq!(controller.getParamStringByValue(id, 1.0, &mut text), kResultOk);
+            assert_eq!(wide(&text), "8191");
+            assert_ne!(mapping.getMidiControllerAssignment(0, 16, bend, &mut id), kResultTrue);
+        }
+    }
+
+    #[test]
+    fn test_bypass() {
+        let component = create();
+        unsafe {
+            let controller = component.cast::<IEditController>().unwrap();
+            let mut info: ParameterInfo = mem::zeroed();
+            let index = (0..controller.getParameterCount())
+                .find(|&index| {
+                    controller.getParameterInfo(index, &mut info);
+                    info.id == BYPASS_PARAM
+                })
+                .unwrap();
+            assert_ne!(info.flags & ParameterInfo_::ParameterFlags_::kIsBypass as int32, 0);
+            assert_eq!(info.stepCount, 1);
+            let mut text = [0; 128];
+            assert_eq!(controller.getParamStringByValue(BYPASS_PARAM, 1.0, &mut text), kResultOk);
+            assert_eq!(wide(&text), "On");
+            let mut value = 0.0;
+            assert_eq!(controller.getParamValueByString(BYPASS_PARAM, text.as_mut_ptr(), &mut value), kResultOk);
+            assert_eq!(value, 1.0);
+            // the midi parameters still follow it
+            controller.getParameterInfo(index + 1, &mut info);
+            assert_eq!(info.id, midi_param(0, 0));
+
+            // a bypassed buffer is silent, and cuts off the notes playing before it
+            let playing = process(&component, vec![note_on(0, 60, 1)], &Changes::default(), 256);
+            assert!(playing.iter().any(|&sample| sample != 0.0));
+            let bypassed = process(&component, Vec::new(), &Changes::new(&[(BYPASS_PARAM, &[(0, 1.0)])]), 256);
+            assert!(bypassed.iter().all(|&sample| sample == 0.0));
+            assert_eq!(controller.getParamNormalized(BYPASS_PARAM), 1.0);
+            let after = process(&component, Vec::new(), &Changes::new(&[(BYPASS_PARAM, &[(0, 0.0)])]), 256);
+            assert!(after.iter().all(|&sample| sample == 0.0));
+        }
+    }
+
+    #[test]
+    fn test_tail_follows_the_release() {
+        let component = create();
+        unsafe {
+            let processor = component.cast::<IAudioProcessor>().unwrap();
+            let short = processor.getTailSamples();
+            // the amp release, at its longest of 5 seconds
+            process(&component, Vec::new(), &Changes::new(&[(8, &[(0, 1.0)])]), 64);
+            let long = processor.getTailSamples();
+            assert!(long > short);
+            assert!(long >= 5 * 44100);
+        }
+    }
+
+    #[test]
+    fn test_midi_controllers_reach_the_synth() {
+        assert_eq!(controller_message(2, 1, 1.0), [CONTROL_CHANGE | 2, 1, 127]);
+        assert_eq!(controller_message(0, AFTERTOUCH, 0.5), [CHANNEL_PRESSURE, 64, 0]);
+        assert_eq!(controller_message(0, PITCH_BEND_CONTROLLER, 0.5), [PITCH_BEND, 0, 64]);
+
+        // a bend on a later sample only bends what comes after it
+        let render = |bend: f64| {
+            let component = create();
+            let changes = Changes::new(&[(midi_param(0, PITCH_BEND_CONTROLLER), &[(1024, bend)])]);
+            process(&component, vec![note_on(0, 60, 1)], &changes, 2048)
+        };
+        let (centred, bent) = (render(0.5), render(1.0));
+        assert_eq!(centred[..1024], bent[..1024]);
+        assert_ne!(centred[1024..], bent[1024..]);
+    }
+
+    #[test]
+    fn test_state_round_trip() {
+        let component = create();
+        let stream = ComWrapper::new(Stream::default());
+        let stream_ptr = stream.as_com_ref::<IBStream>().unwrap().as_ptr();
+        unsafe {
+            let changes = Changes::new(&[(20, &[(0, 0.75)])]);
+            process(&component, Vec::new(), &changes, 0);
+            assert_eq!(component.getState(stream_ptr), kResultOk);
+
+            let loaded = create();
+            assert_eq!(loaded.setState(stream_ptr), kResultOk);
+            let controller = loaded.cast::<IEditController>().unwrap();
+            assert_eq!(controller.getParamNormalized(20), f64::from(0.75f32));
+
+            let invalid = ComWrapper::new(Stream { data: RefCell::new(b"not a preset".to_vec()), ..Stream::default() });
+            assert_eq!(loaded.setState(invalid.as_com_ref::<IBStream>().unwrap().as_ptr()), kResultFalse);
+        }
+    }
+}
@@ -0,0 +1,500 @@
+use std::convert::TryInto;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, OnceLock};
+
+use crate::oscillator::{Shape, Waveform};
+use crate::TAU;
+
+/// The length every frame is resampled to. A frame holds at most half as many harmonics.
+pub const TABLE_SIZE: usize = 2048;
+const HARMONICS: usize = TABLE_SIZE / 2;
+
+/// How many copies of each frame are kept, each with half the harmonics of the last, so higher
+/// notes can read one with nothing above the Nyquist frequency to alias.
+const LEVELS: usize = 11;
+
+/// The most frames a table can have, as many as Serum's.
+pub const MAX_FRAMES: usize = 256;
+
+/// How many harmonics the additive waveform has a level for, like an organ's drawbars.
+pub const NUM_HARMONICS: usize = 8;
+
+/// The frame size Serum writes, for files that don't say.
+const SERUM_FRAME_SIZE: usize = 2048;
+
+// each copy is one sample longer than the cycle, repeating its first sample, so reading between
+// samples never has to wrap
+const STRIDE: usize = TABLE_SIZE + 1;
+
+/// Why a wavetable couldn't be loaded.
+#[derive(Debug)]
+pub enum WavetableError {
+    Io(io::Error),
+    /// The file isn't a wav file we can read, and why.
+    Format(String),
+}
+
+impl fmt::Display for WavetableError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WavetableError::Io(err) => write!(f, "{}", err),
+            WavetableError::Format(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl From<io::Error> for WavetableError {
+    fn from(err: io::Error) -> WavetableError {
+        WavetableError::Io(err)
+    }
+}
+
+/// Single cycle frames for an oscillator to sweep through, each kept band limited at every
+/// octave.
+pub struct Wavetable {
+    // each frame's copies, from the full bandwidth down, one after another
+    samples: Vec<f32>,
+    frames: usize,
+}
+
+impl Wavetable {
+    /// Build a table from single cycle frames of any length, which are resampled to
+    /// `TABLE_SIZE`. The DC offset is taken out, and the table is scaled to a peak of 1.0.
+    pub fn from_frames<F: AsRef<[f64]>>(frames: &[F]) -> Wavetable {
+        let mut samples = Vec::with_capacity(frames.len() * LEVELS * STRIDE);
+        let (mut re, mut im) = (vec![0.0; TABLE_SIZE], vec![0.0; TABLE_SIZE]);
+        let (mut level_re, mut level_im) = (vec![0.0; TABLE_SIZE], vec![0.0; TABLE_SIZE]);
+        for frame in frames {
+            resample(frame.as_ref(), &mut re);
+            im.iter_mut().for_each(|sample| *sample = 0.0);
+            fft(&mut re, &mut im, false);
+            for level in 0..LEVELS {
+                // the Nyquist harmonic's phase is lost, so even the full bandwidth copy leaves it out
+                let top = (HARMONICS >> level).min(HARMONICS - 1);
+                level_re.iter_mut().chain(&mut level_im).for_each(|bin| *bin = 0.0);
+                for harmonic in (1..=top).flat_map(|harmonic| [harmonic, TABLE_SIZE - harmonic]) {
+                    level_re[harmonic] = re[harmonic];
+                    level_im[harmonic] = im[harmonic];
+                }
+                fft(&mut level_re, &mut level_im, true);
+                samples.extend(level_re.iter().map(|&sample| (sample / TABLE_SIZE as f64) as f32));
+                samples.push((level_re[0] / TABLE_SIZE as f64) as f32);
+            }
+        }
+
+        let frames = frames.len();
+        let peak = (0..frames)
+            .flat_map(|frame| &samples[frame * LEVELS * STRIDE..][..STRIDE])
+            .fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+        if peak > 0.0 {
+            samples.iter_mut().for_each(|sample| *sample /= peak);
+        }
+        if frames == 0 {
+            // a silent frame, so there's always something to read
+            samples.resize(LEVELS * STRIDE, 0.0);
+        }
+        Wavetable { samples, frames: frames.max(1) }
+    }
+
+    /// The table played until one is loaded: a sine, triangle, saw and square to sweep through.
+    pub fn basic() -> Arc<Wavetable> {
+        static BASIC: OnceLock<Arc<Wavetable>> = OnceLock::new();
+        let table = BASIC.get_or_init(|| {
+            let frames: Vec<Vec<f64>> = [Waveform::Sine, Waveform::Triangle, Waveform::Saw, Waveform::Square]
+                .iter()
+                .map(|waveform| {
+                    (0..TABLE_SIZE).map(|i| waveform.sample(i as f64 / TABLE_SIZE as f64, 0.5)).collect()
+                })
+                .collect();
+            Arc::new(Wavetable::from_frames(&frames))
+        });
+        Arc::clone(table)
+    }
+
+    /// A single frame of sine harmonics, with `levels` giving each one's level from the
+    /// fundamental up.
+    pub fn additive(levels: &[f64]) -> Wavetable {
+        let mut table = Wavetable { samples: vec![0.0; LEVELS * STRIDE], frames: 1 };
+        table.set_harmonics(levels);
+        table
+    }
+
+    /// Rebuild a table made by `additive()` with new harmonic levels, without allocating, so the
+    /// audio thread can follow the levels as they change.
+    ///
+    /// The levels add up as they would on an organ, so more harmonics play louder, until they
+    /// would peak over 1.0 and the table is scaled down to fit.
+    pub fn set_harmonics(&mut self, levels: &[f64]) {
+        let mut last_top = None;
+        for level in 0..LEVELS {
+            let top = (HARMONICS >> level).min(HARMONICS - 1).min(levels.len());
+            let start = level * STRIDE;
+            if last_top == Some(top) {
+                // the same harmonics as the copy before, which most are with so few of them
+                self.samples.copy_within(start - STRIDE..start, start);
+                continue;
+            }
+            last_top = Some(top);
+            let copy = &mut self.samples[start..start + STRIDE];
+            for (i, sample) in copy.iter_mut().enumerate() {
+                let sum: f64 = levels[..top].iter().enumerate()
+                    .filter(|(_, &level)| level != 0.0)
+                    .map(|(harmonic, level)| {
+                        let cycles = ((harmonic + 1) * i % TABLE_SIZE) as f64 / TABLE_SIZE as f64;
+                        level * (cycles * TAU).sin()
+                    })
+                    .sum();
+                *sample = sum as f32;
+            }
+        }
+
+        let peak = self.samples[..STRIDE].iter().fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+        if peak > 1.0 {
+            self.samples.iter_mut().for_each(|sample| *sample /= peak);
+        }
+    }
+
+    /// Load a wav file of single cycle frames one after another, as Serum and most other
+    /// wavetable synths save them. Only its first channel is used.
+    ///
+    /// The frame size comes from the `clm ` chunk Serum writes, if the file has one. Otherwise a
+    /// file a whole number of 2048 sample frames long is read as those, and anything else as a
+    /// single cycle.
+    pub fn load(path: &Path) -> Result<Wavetable, WavetableError> {
+        let (samples, frame_size) = read_wav(&fs::read(path)?)?;
+        let frame_size = match frame_size {
+            Some(size) if size > 0 => size,
+            _ if samples.len() % SERUM_FRAME_SIZE == 0 => SERUM_FRAME_SIZE,
+            _ => samples.len(),
+        };
+        let frames: Vec<&[f64]> = samples.chunks_exact(frame_size.max(1)).take(MAX_FRAMES).collect();
+        if frames.is_empty() {
+            return Err(WavetableError::Format("the file is shorter than one frame".to_string()));
+        }
+        Ok(Wavetable::from_frames(&frames))
+    }
+
+    /// The point `position` of the way through the table, from 0.0 at the first frame to 1.0 at
+    /// the last, crossfading between the frames either side.
+    pub fn at(&self, position: f64) -> WavetableFrame<'_> {
+        let position = position.clamp(0.0, 1.0) * (self.frames - 1) as f64;
+        let frame = (position as usize).min(self.frames.saturating_sub(2));
+        WavetableFrame { table: self, frame, mix: position - frame as f64 }
+    }
+
+    /// One copy of a frame at `phase`, in cycles from 0.0 to 1.0, interpolated linearly.
+    fn read(&self, frame: usize, level: usize, phase: f64) -> f64 {
+        let copy = &self.samples[(frame * LEVELS + level) * STRIDE..][..STRIDE];
+        let position = phase.clamp(0.0, 1.0) * TABLE_SIZE as f64;
+        let index = (position as usize).min(TABLE_SIZE - 1);
+        let (a, b) = (f64::from(copy[index]), f64::from(copy[index + 1]));
+        a + (b - a) * (position - index as f64)
+    }
+}
+
+/// The copy with the most harmonics that all stay below the Nyquist frequency, for an
+/// oscillator moving on by `phase_inc` cycles per sample.
+fn level(phase_inc: f64) -> usize {
+    let level = (phase_inc.abs() * TABLE_SIZE as f64).log2().ceil();
+    if level > 0.0 { (level as usize).min(LEVELS - 1) } else { 0 }
+}
+
+/// A point in a wavetable, for an oscillator to play.
+pub struct WavetableFrame<'a> {
+    table: &'a Wavetable,
+    // the frame before the point, and how far it is towards the next one
+    frame: usize,
+    mix: f64,
+}
+
+impl WavetableFrame<'_> {
+    fn read(&self, level: usize, phase: f64) -> f64 {
+        let first = self.table.read(self.frame, level, phase);
+        if self.mix > 0.0 {
+            first + (self.table.read(self.frame + 1, level, phase) - first) * self.mix
+        } else {
+            first
+        }
+    }
+}
+
+impl Shape for WavetableFrame<'_> {
+    fn sample(&self, phase: f64) -> f64 {
+        self.read(0, phase)
+    }
+
+    fn sample_bandlimited(&self, phase: f64, phase_inc: f64) -> f64 {
+        self.read(level(phase_inc), phase)
+    }
+}
+
+/// Resample a single cycle to fill `out`, interpolating linearly round the cycle.
+fn resample(cycle: &[f64], out: &mut [f64]) {
+    if cycle.is_empty() {
+        out.iter_mut().for_each(|sample| *sample = 0.0);
+        return;
+    }
+    let step = cycle.len() as f64 / out.len() as f64;
+    for (i, sample) in out.iter_mut().enumerate() {
+        let position = i as f64 * step;
+        let index = position as usize;
+        let (a, b) = (cycle[index], cycle[(index + 1) % cycle.len()]);
+        *sample = a + (b - a) * (position - index as f64);
+    }
+}
+
+/// An in place radix-2 FFT, or its inverse without the division by the length. The length must
+/// be a power of two.
+fn fft(re: &mut [f64], im: &mut [f64], inverse: bool) {
+    let len = re.len();
+    // put the input in bit reversed order, so each pass can work in place
+    let mut j = 0;
+    for i in 1..len {
+        let mut bit = len >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+
+    let sign = if inverse { 1.0 } else { -1.0 };
+    let mut size = 2;
+    while size <= len {
+        let angle = sign * TAU / size as f64;
+        for start in (0..len).step_by(size) {
+            for k in 0..size / 2 {
+                let (sin, cos) = (angle * k as f64).sin_cos();
+                let (a, b) = (start + k, start + k + size / 2);
+                let (b_re, b_im) = (re[b] * cos - im[b] * sin, re[b] * sin + im[b] * cos);
+                re[b] = re[a] - b_re;
+                im[b] = im[a] - b_im;
+                re[a] += b_re;
+                im[a] += b_im;
+            }
+        }
+        size *= 2;
+    }
+}
+
+/// How a wav file's samples are stored.
+struct WavFormat {
+    float: bool,
+    channels: usize,
+    bits: usize,
+}
+
+/// The first channel of a wav file's samples, from -1.0 to 1.0, and the frame size from its
+/// `clm ` chunk if it has one.
+fn read_wav(data: &[u8]) -> Result<(Vec<f64>, Option<usize>), WavetableError> {
+    let invalid = |message: &str| WavetableError::Format(message.to_string());
+    if data.len() < 12 || &data[..4] != b"RIFF" || &data[8..12] != b"WAVE" {
+        return Err(invalid("not a wav file"));
+    }
+    let (mut format, mut samples, mut frame_size) = (None, None, None);
+    let mut rest = &data[12..];
+    while rest.len() >= 8 {
+        let len = u32::from_le_bytes([rest[4], rest[5], rest[6], rest[7]]) as usize;
+        // some writers leave the length of the last chunk too long, so take what's there
+        let body = &rest[8..rest.len().min(8 + len)];
+        match &rest[..4] {
+            b"fmt " => format = Some(read_format(body)?),
+            b"data" => samples = Some(body),
+            b"clm " => frame_size = clm_frame_size(body),
+            _ => {}
+        }
+        // chunks are padded to an even length
+        rest = rest.get(8 + len + len % 2..).unwrap_or(&[]);
+    }
+    let format = format.ok_or_else(|| invalid("the file has no format chunk"))?;
+    let samples = samples.ok_or_else(|| invalid("the file has no audio"))?;
+
+    let size = format.bits / 8;
+    let samples = samples
+        .chunks_exact(format.channels * size)
+        .map(|block| match (format.float, &block[..size]) {
+            (false, &[a]) => (f64::from(a) - 128.0) / 128.0,
+            (false, &[a, b]) => f64::from(i16::from_le_bytes([a, b])) / 32768.0,
+            (false, &[a, b, c]) => f64::from(i32::from_le_bytes([0, a, b, c]) >> 8) / 8_388_608.0,
+            (false, &[a, b, c, d]) => f64::from(i32::from_le_bytes([a, b, c, d])) / 2_147_483_648.0,
+            (true, &[a, b, c, d]) => f64::from(f32::from_le_bytes([a, b, c, d])),
+            (_, bytes) => f64::from_le_bytes(bytes.try_into().unwrap_or_default()),
+        })
+        .map(|sample| if sample.is_finite() { sample } else { 0.0 })
+        .collect();
+    Ok((samples, frame_size))
+}
+
+fn read_format(body: &[u8]) -> Result<WavFormat, WavetableError> {
+    let u16_at = |offset: usize| body.get(offset..offset + 2).map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]));
+    let (Some(mut tag), Some(channels), Some(bits)) = (u16_at(0), u16_at(2), u16_at(14)) else {
+        return Err(WavetableError::Format("the format chunk is too short".to_string()));
+    };
+    // the extensible format keeps the real one in its sub format
+    if tag == 0xFFFE {
+        tag = u16_at(24).unwrap_or(tag);
+    }
+    let float = match (tag, bits) {
+        (1, 8 | 16 | 24 | 32) => false,
+        (3, 32 | 64) => true,
+        _ => return Err(WavetableError::Format(format!("unsupported sample format {} at {} bits", tag, bits))),
+    };
+    if channels == 0 {
+        return Err(WavetableError::Format("the file has no channels".to_string()));
+    }
+    Ok(WavFormat { float, channels: usize::from(channels), bits: usize::from(bits) })
+}
+
+/// The frame size from Serum's `clm ` chunk, which starts with `<!>` and the size, eg.
+/// `<!>2048 10000000 wavetable (www.xferrecords.com)`.
+fn clm_frame_size(body: &[u8]) -> Option<usize> {
+    let rest = body.strip_prefix(b"<!>")?;
+    let digits = rest.iter().take_while(|byte| byte.is_ascii_digit()).count();
+    std::str::from_utf8(&rest[..digits]).ok()?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A mono wav file, with a `clm ` chunk if `clm` is given.
+    fn wav(tag: u16, bits: u16, data: &[u8], clm: Option<&str>) -> Vec<u8> {
+        let mut chunks = Vec::new();
+        let mut chunk = |id: &[u8], body: &[u8]| {
+            chunks.extend_from_slice(id);
+            chunks.extend_from_slice(&(body.len() as u32).to_le_bytes());
+            chunks.extend_from_slice(body);
+            if body.len() % 2 == 1 {
+                chunks.push(0);
+            }
+        };
+        let block = bits / 8;
+        let mut format = Vec::new();
+        for value in [u32::from(tag) | 1 << 16, 44100, 44100 * u32::from(block), u32::from(block) | u32::from(bits) << 16] {
+            format.extend_from_slice(&value.to_le_bytes());
+        }
+        chunk(b"fmt ", &format);
+        if let Some(clm) = clm {
+            chunk(b"clm ", clm.as_bytes());
+        }
+        chunk(b"data", data);
+        let mut file = b"RIFF".to_vec();
+        file.extend_from_slice(&(chunks.len() as u32 + 4).to_le_bytes());
+        file.extend_from_slice(b"WAVE");
+        file.extend_from_slice(&chunks);
+        file
+    }
+
+    fn load(name: &str, data: &[u8]) -> Result<Wavetable, WavetableError> {
+        let path = std::env::temp_dir().join(format!("replicant-{}-{}.wav", name, std::process::id()));
+        fs::write(&path, data).unwrap();
+        let table = Wavetable::load(&path);
+        fs::remove_file(&path).unwrap();
+        table
+    }
+
+    fn sine(len: usize, harmonic: usize) -> impl Iterator<Item = f64> {
+        (0..len).map(move |i| (i as f64 / len as f64 * harmonic as f64 * TAU).sin())
+    }
+
+    #[test]
+    fn test_fft_finds_the_harmonics() {
+        let mut re: Vec<f64> = sine(64, 3).collect();
+        let mut im = vec![0.0; 64];
+        fft(&mut re, &mut im, false);
+        // a sine's energy is all in its own harmonic, and its mirror image
+        assert!((im[3] + 32.0).abs() < 1e-9 && (im[61] - 32.0).abs() < 1e-9);
+        assert!(re.iter().all(|bin| bin.abs() < 1e-9));
+
+        fft(&mut re, &mut im, true);
+        assert!(re.iter().zip(sine(64, 3)).all(|(a, b)| (a / 64.0 - b).abs() < 1e-9));
+    }
+
+    #[test]
+    fn test_loads_serum_wavetables() {
+        // two 256 sample frames in 16 bits: a sine, then the same upside down
+        let data: Vec<u8> = sine(256, 1)
+            .chain(sine(256, 1).map(|sample| -sample))
+            .flat_map(|sample| ((sample * 32767.0) as i16).to_le_bytes())
+            .collect();
+        let table = load("serum", &wav(1, 16, &data, Some("<!>256 10000000 wavetable"))).unwrap();
+        assert_eq!(table.frames, 2);
+        assert!((table.at(0.0).sample(0.25) - 1.0).abs() < 1e-3);
+        assert!((table.at(1.0).sample(0.25) + 1.0).abs() < 1e-3);
+        // half way, the two cancel out
+        assert!(table.at(0.5).sample(0.25).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_loads_single_cycles() {
+        // 600 float samples, which aren't a whole number of Serum frames
+        let data: Vec<u8> = sine(600, 1).flat_map(|sample| (sample as f32 * 0.5).to_le_bytes()).collect();
+        let table = load("single", &wav(3, 32, &data, None)).unwrap();
+        assert_eq!(table.frames, 1);
+        // scaled up to full level
+        assert!((table.at(0.7).sample(0.25) - 1.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_rejects_what_it_cant_read() {
+        assert!(matches!(load("text", b"not a wav file"), Err(WavetableError::Format(_))));
+        assert!(matches!(load("adpcm", &wav(2, 4, &[0; 64], None)), Err(WavetableError::Format(_))));
+        assert!(matches!(load("empty", &wav(1, 16, &[], None)), Err(WavetableError::Format(_))));
+        let missing = std::env::temp_dir().join("replicant-no-such-wavetable.wav");
+        assert!(matches!(Wavetable::load(&missing), Err(WavetableError::Io(_))));
+    }
+
+    #[test]
+    fn test_high_notes_read_fewer_harmonics() {
+        // the fundamental, with a quieter 100th harmonic
+        let frame: Vec<f64> = sine(TABLE_SIZE, 1).zip(sine(TABLE_SIZE, 100)).map(|(a, b)| a + b * 0.5).collect();
+        let table = Wavetable::from_frames(&[frame]);
+        let scale = table.at(0.0).sample(0.25);
+        for i in 0..64 {
+            let phase = f64::from(i) / 64.0;
+            // low enough for the harmonic to stay below Nyquist, it's all there
+            let low = table.at(0.0).sample_bandlimited(phase, 1.0 / 1000.0);
+            assert!((low - table.at(0.0).sample(phase)).abs() < 1e-6);
+            // at 100 samples a cycle it would alias, so only the fundamental is left
+            let high = table.at(0.0).sample_bandlimited(phase, 1.0 / 100.0);
+            assert!((high - scale * (phase * TAU).sin()).abs() < 1e-3, "{} at {}", high, phase);
+        }
+        assert_eq!(level(1.0 / 100.0), 5);
+        assert_eq!(level(0.0), 0);
+        assert_eq!(level(0.5), LEVELS - 1);
+    }
+
+    #[test]
+    fn test_additive_harmonics() {
+        let mut table = Wavetable::additive(&[0.5, 0.0, 0.25]);
+        for i in 0..64 {
+            let phase = f64::from(i) / 64.0;
+            let expected = 0.5 * (phase * TAU).sin() + 0.25 * (phase * 3.0 * TAU).sin();
+            assert!((table.at(0.0).sample(phase) - expected).abs() < 1e-3);
+            // too high for the third harmonic, so only the fundamental is left
+            let high = table.at(0.0).sample_bandlimited(phase, 0.4);
+            assert!((high - 0.5 * (phase * TAU).sin()).abs() < 1e-3);
+        }
+
+        // every harmonic at full would peak well over 1.0, so it's scaled down
+        table.set_harmonics(&[1.0; NUM_HARMONICS]);
+        let peak = (0..TABLE_SIZE).map(|i| table.at(0.0).sample(i as f64 / TABLE_SIZE as f64).abs()).fold(0.0, f64::max);
+        assert!((peak - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_basic_table_sweeps_the_waveforms() {
+        let table = Wavetable::basic();
+        assert_eq!(table.frames, 4);
+        assert!((table.at(0.0).sample(0.25) / table.at(0.0).sample(0.25 + 1.0 / 16.0) - 1.0 / (TAU / 16.0).cos()).abs() < 1e-3);
+        // the square is high for the first half of its cycle
+        assert!(table.at(1.0).sample(0.25) > 0.8 && table.at(1.0).sample(0.75) < -0.8);
+    }
+}
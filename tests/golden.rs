@@ -0,0 +1,134 @@
+//! Renders a fixed phrase with each of the presets in tests/golden, and compares the loudness and
+//! brightness of the result against the references stored next to them, so changes to the
+//! envelopes, oscillators or effects can't silently change the sound.
+//!
+//! After a deliberate change to the sound, regenerate the references with:
+//!
+//!     REPLICANT_BLESS=1 cargo test --test golden
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use replicant::Synth;
+
+const SAMPLE_RATE: f32 = 48000.0;
+const LEN: usize = 96000;
+
+/// The features are measured over windows of this many samples.
+const WINDOW: usize = 1024;
+
+/// How far each feature may drift from its reference: a fraction of the larger of the two, plus a
+/// fixed amount for values near zero.
+const LEVEL_TOLERANCE: (f64, f64) = (0.01, 1e-4);
+const CROSSINGS_TOLERANCE: (f64, f64) = (0.03, 2.0);
+
+const BLESS_VAR: &str = "REPLICANT_BLESS";
+
+const NOTE_ON: u8 = 0x90;
+const NOTE_OFF: u8 = 0x80;
+
+/// A chord, then a melody whose notes overlap, then a rest for the release and effects to ring
+/// out. The note lengths aren't whole buffers, so events land part way through them.
+fn phrase() -> Vec<(u32, [u8; 3])> {
+    let mut events = Vec::new();
+    for &note in &[48, 55, 64] {
+        events.push((0, [NOTE_ON, note, 100]));
+        events.push((20000, [NOTE_OFF, note, 0]));
+    }
+    for (i, &note) in [60, 62, 64, 67, 72].iter().enumerate() {
+        let start = 24000 + i as u32 * 9000;
+        events.push((start, [NOTE_ON, note, 60 + i as u8 * 15]));
+        events.push((start + 10500, [NOTE_OFF, note, 0]));
+    }
+    events
+}
+
+/// The RMS level and the number of zero crossings of each channel, for each window.
+fn features(output: &[f32]) -> Vec<[f64; 4]> {
+    output.chunks(WINDOW * 2)
+        .map(|window| {
+            let mut features = [0.0; 4];
+            for channel in 0..2 {
+                let samples: Vec<f64> = window.iter().skip(channel).step_by(2).map(|&sample| f64::from(sample)).collect();
+                let power = samples.iter().map(|sample| sample * sample).sum::<f64>() / samples.len() as f64;
+                features[channel] = power.sqrt();
+                features[channel + 2] = samples.windows(2).filter(|pair| (pair[0] < 0.0) != (pair[1] < 0.0)).count() as f64;
+            }
+            features
+        })
+        .collect()
+}
+
+fn within(value: f64, reference: f64, (relative, absolute): (f64, f64)) -> bool {
+    (value - reference).abs() <= relative * value.abs().max(reference.abs()) + absolute
+}
+
+fn golden_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("golden")
+}
+
+fn check(name: &str) {
+    let dir = golden_dir();
+    let preset = fs::read(dir.join(format!("{}.json", name))).unwrap();
+    let mut synth = Synth::default();
+    synth.set_sample_rate(SAMPLE_RATE);
+    synth.import_preset(&preset).unwrap();
+    let features = features(&synth.render_midi(&phrase(), LEN));
+
+    let reference_path = dir.join(format!("{}.ref", name));
+    if std::env::var_os(BLESS_VAR).is_some() {
+        let lines: Vec<String> = features.iter()
+            .map(|window| window.iter().map(|value| format!("{:.6e}", value)).collect::<Vec<_>>().join(" "))
+            .collect();
+        fs::write(&reference_path, lines.join("\n") + "\n").unwrap();
+        return;
+    }
+
+    let reference = fs::read_to_string(&reference_path)
+        .unwrap_or_else(|err| panic!("no reference for {} ({}). Make one with {}=1", name, err, BLESS_VAR));
+    let reference: Vec<Vec<f64>> = reference.lines()
+        .map(|line| line.split_whitespace().map(|value| value.parse().unwrap()).collect())
+        .collect();
+    assert_eq!(features.len(), reference.len(), "{}: the render is a different length", name);
+
+    for (window, (features, reference)) in features.iter().zip(&reference).enumerate() {
+        let time = (window * WINDOW) as f32 / SAMPLE_RATE;
+        for channel in 0..2 {
+            let side = ["left", "right"][channel];
+            assert!(
+                within(features[channel], reference[channel], LEVEL_TOLERANCE),
+                "{}: the {} level at {:.2}s is {}, but was {}", name, side, time, features[channel], reference[channel]
+            );
+            assert!(
+                within(features[channel + 2], reference[channel + 2], CROSSINGS_TOLERANCE),
+                "{}: the {} brightness at {:.2}s has changed, from {} to {} zero crossings",
+                name, side, time, reference[channel + 2], features[channel + 2]
+            );
+        }
+    }
+}
+
+#[test]
+fn golden_sine() {
+    check("sine");
+}
+
+#[test]
+fn golden_filtered_saw() {
+    check("filtered_saw");
+}
+
+#[test]
+fn golden_unison_chorus() {
+    check("unison_chorus");
+}
+
+#[test]
+fn golden_fm_delay() {
+    check("fm_delay");
+}
+
+#[test]
+fn golden_mono_glide() {
+    check("mono_glide");
+}